@@ -18,11 +18,13 @@ use std::os;
 use test::test_main;
 
 mod tokenizer;
+mod tree_builder;
 
 fn main() {
     let mut tests = vec!();
 
     tests.extend(tokenizer::tests());
+    tests.extend(tree_builder::tests());
     // more to follow
 
     test_main(os::args().as_slice(), tests);
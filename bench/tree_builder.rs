@@ -0,0 +1,101 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::{io, os};
+use std::default::Default;
+use std::vec::MoveItems;
+
+use test::{black_box, Bencher, TestDesc, TestDescAndFn};
+use test::{DynTestName, DynBenchFn, TDynBenchFn};
+
+use html5ever::sink::rcdom::RcDom;
+use html5ever::tree_builder::TreeBuilderOpts;
+use html5ever::{parse, one_input, ParseOpts};
+
+struct Bench {
+    input: String,
+    opts: TreeBuilderOpts,
+}
+
+impl Bench {
+    fn new(name: &str, in_frameset: bool, opts: TreeBuilderOpts) -> Bench {
+        let mut path = os::self_exe_path().expect("can't get exe path");
+        path.push("../data/bench/");
+        path.push(name);
+        let mut file = io::File::open(&path).ok().expect("can't open file");
+        let file_input = file.read_to_string().ok().expect("can't read file");
+
+        // In a frameset, text is split at every change between whitespace
+        // and other characters, which is where `exact_text_splitting`
+        // makes a difference.
+        let input = if in_frameset {
+            let mut input = String::from_str("<frameset>");
+            input.push_str(file_input.as_slice());
+            input
+        } else {
+            file_input
+        };
+
+        Bench {
+            input: input,
+            opts: opts,
+        }
+    }
+}
+
+impl TDynBenchFn for Bench {
+    fn run(&self, bh: &mut Bencher) {
+        bh.iter(|| {
+            let dom: RcDom = parse(one_input(self.input.clone()), ParseOpts {
+                tree_builder: self.opts.clone(),
+                .. Default::default()
+            });
+            black_box(dom);
+        });
+    }
+}
+
+fn make_bench(name: &str, in_frameset: bool, opts: TreeBuilderOpts) -> TestDescAndFn {
+    TestDescAndFn {
+        desc: TestDesc {
+            name: DynTestName([
+                "parse ".to_string(),
+                name.to_string(),
+                (if in_frameset { " in a frameset" } else { "" }).to_string(),
+                (if opts.exact_text_splitting { "" } else { " (fast text)" }).to_string(),
+            ].concat().to_string()),
+            ignore: false,
+            should_fail: false,
+        },
+        testfn: DynBenchFn(box Bench::new(name, in_frameset, opts)),
+    }
+}
+
+pub fn tests() -> MoveItems<TestDescAndFn> {
+    let mut tests = vec!();
+
+    let fast_text = TreeBuilderOpts {
+        exact_text_splitting: false,
+        .. Default::default()
+    };
+
+    // Text-heavy pages spend much of their tree builder time on
+    // character tokens, including whitespace splitting.
+    for &file in ["lipsum.html", "lipsum-zh.html", "strong.html",
+                  "tiny-fragment.html", "small-fragment.html", "medium-fragment.html"].iter() {
+        tests.push(make_bench(file, false, Default::default()));
+    }
+
+    for &file in ["lipsum.html", "strong.html"].iter() {
+        tests.push(make_bench(file, true, Default::default()));
+        tests.push(make_bench(file, true, fast_text.clone()));
+    }
+
+    tests.into_iter()
+}
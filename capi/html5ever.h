@@ -12,6 +12,33 @@
 
 #include <stdlib.h>
 
+/* Bumped for any change here which breaks existing callers.
+ *
+ * Version 1 put the size field at the start of struct h5e_token_ops,
+ * which moved every callback.  Code built against an older header must
+ * be rebuilt; h5e_tokenizer_new rejects a size of 0. */
+#define H5E_ABI_VERSION 1
+
+struct h5e_version {
+    unsigned int major;
+    unsigned int minor;
+    unsigned int patch;
+    unsigned int abi;
+};
+
+/* The version of the library you're linked against.  Compare its abi
+ * field to H5E_ABI_VERSION. */
+struct h5e_version h5e_version(void);
+
+/* Flags for h5e_capabilities. */
+#define H5E_CAP_TOKENIZER    (1u << 0)
+#define H5E_CAP_TREE_BUILDER (1u << 1)
+#define H5E_CAP_ENCODINGS    (1u << 2)
+#define H5E_CAP_SPANS        (1u << 3)
+
+/* What this build of the library supports. */
+unsigned int h5e_capabilities(void);
+
 struct h5e_buf {
     unsigned char *data;
     size_t len;
@@ -19,7 +46,12 @@ struct h5e_buf {
 
 struct h5e_buf h5e_buf_from_cstr(const char *str);
 
+/* Set size to sizeof(struct h5e_token_ops).  Callbacks added in later
+ * versions go at the end, and are never called for a smaller struct.
+ * A size of 0 is an error. */
 struct h5e_token_ops {
+    size_t size;
+
     void (*do_doctype)(void *user, struct h5e_buf name,
         struct h5e_buf pub, struct h5e_buf sys, int force_quirks);
     void (*do_start_tag)(void *user, struct h5e_buf name,
@@ -31,6 +63,10 @@ struct h5e_token_ops {
     void (*do_null_char)(void *user);
     void (*do_eof)(void *user);
     void (*do_error)(void *user, struct h5e_buf message);
+
+    /* Part of a comment; the rest follows in more chunks and a final
+     * do_comment.  Only called if comment chunking is enabled. */
+    void (*do_comment_chunk)(void *user, struct h5e_buf text);
 };
 
 struct h5e_token_sink {
@@ -38,11 +74,36 @@ struct h5e_token_sink {
     void *user;
 };
 
+/* Status codes.  After H5E_FAILED, which means the library failed
+ * internally or was misused, the tokenizer is poisoned: every later call
+ * returns H5E_POISONED and does nothing.  It can still be freed.
+ *
+ * These functions may be called from any thread, including ones not
+ * started by Rust. */
+#define H5E_OK        0
+#define H5E_FAILED   -1
+#define H5E_POISONED -2
+
 struct h5e_tokenizer;
 
+/* Returns NULL on failure, or if the ops have a size of 0. */
 struct h5e_tokenizer *h5e_tokenizer_new(struct h5e_token_sink *sink);
 void h5e_tokenizer_free(struct h5e_tokenizer *tok);
-void h5e_tokenizer_feed(struct h5e_tokenizer *tok, struct h5e_buf buf);
-void h5e_tokenizer_end(struct h5e_tokenizer *tok);
+
+/* Input is UTF-8.  A chunk may end partway through a multibyte character;
+ * the tokenizer holds the partial bytes until the next call.  Sequences
+ * still incomplete at h5e_tokenizer_end become U+FFFD.
+ *
+ * Invalid sequences, including encoded surrogates (as found in CESU-8 and
+ * WTF-8), are replaced with U+FFFD and reported through do_error, ahead of
+ * the tokens from the same chunk. */
+int h5e_tokenizer_feed(struct h5e_tokenizer *tok, struct h5e_buf buf);
+
+/* After this, feeding or ending the tokenizer again is H5E_FAILED. */
+int h5e_tokenizer_end(struct h5e_tokenizer *tok);
+
+/* How many surrogates and other invalid sequences were replaced so far. */
+size_t h5e_tokenizer_surrogate_count(struct h5e_tokenizer *tok);
+size_t h5e_tokenizer_invalid_count(struct h5e_tokenizer *tok);
 
 #endif
@@ -49,6 +49,7 @@ void do_end_tag(void *user, struct h5e_buf name) {
 }
 
 struct h5e_token_ops ops = {
+    .size = sizeof(struct h5e_token_ops),
     .do_chars = do_chars,
     .do_start_tag = do_start_tag,
     .do_tag_attr = do_tag_attr,
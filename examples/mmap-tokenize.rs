@@ -0,0 +1,68 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Tokenize a file of any size in constant memory, by memory-mapping it,
+// and print some counts.  The file can be many HTML documents
+// concatenated together, as in a web archive.
+
+extern crate libc;
+extern crate html5ever;
+
+use std::{io, os, slice};
+use std::os::{MemoryMap, MapReadable, MapFd};
+use std::default::Default;
+
+use html5ever::tokenizer::{TokenSink, Token, TagToken, StartTag, CommentToken};
+use html5ever::tokenizer::{CharacterTokens, ParseError};
+use html5ever::driver::{tokenize_to, utf8_input};
+
+#[deriving(Default)]
+struct Counts {
+    start_tags: uint,
+    comments: uint,
+    chars: uint,
+    errors: uint,
+}
+
+impl TokenSink for Counts {
+    fn process_token(&mut self, token: Token) {
+        match token {
+            TagToken(ref t) if t.kind == StartTag => self.start_tags += 1,
+            CommentToken(_) => self.comments += 1,
+            CharacterTokens(ref s) => self.chars += s.as_slice().char_len(),
+            ParseError(_) => self.errors += 1,
+            _ => (),
+        }
+    }
+}
+
+fn main() {
+    let path = Path::new(os::args()[1].as_slice());
+    let size = io::fs::stat(&path).ok().expect("can't stat file").size as uint;
+
+    let fd = path.with_c_str(|p| unsafe { libc::open(p, libc::O_RDONLY, 0) });
+    if fd < 0 {
+        fail!("can't open file");
+    }
+    let map = MemoryMap::new(size, [MapReadable, MapFd(fd)])
+        .ok().expect("can't map file");
+
+    let mut counts: Counts = Default::default();
+    unsafe {
+        slice::raw::buf_as_slice(map.data() as *const u8, size, |bytes| {
+            // FIXME: The mapping saves reading the file, but the tokenizer
+            // still gets a copy of each chunk.  See `utf8_input`.
+            tokenize_to(&mut counts, utf8_input(bytes), Default::default());
+        });
+        libc::close(fd);
+    }
+
+    println!("{:u} start tags, {:u} comments, {:u} characters, {:u} parse errors",
+        counts.start_tags, counts.comments, counts.chars, counts.errors);
+}
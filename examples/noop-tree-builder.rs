@@ -49,8 +49,17 @@ impl TreeSink<uint> for Sink {
         self.names.find(&target).expect("not an element").clone()
     }
 
+    fn get_template_contents(&mut self, target: uint) -> uint {
+        // `create_element` reserved this id.
+        target + 1
+    }
+
     fn create_element(&mut self, name: QualName, _attrs: Vec<Attribute>) -> uint {
         let id = self.get_id();
+        if name.local.as_slice() == "template" {
+            // Save an id for its contents.
+            self.get_id();
+        }
         self.names.insert(id, name);
         id
     }
@@ -74,6 +83,7 @@ impl TreeSink<uint> for Sink {
     fn append_doctype_to_document(&mut self, _name: String, _public_id: String, _system_id: String) { }
     fn add_attrs_if_missing(&mut self, _target: uint, _attrs: Vec<Attribute>) { }
     fn remove_from_parent(&mut self, _target: uint) { }
+    fn reparent_children(&mut self, _node: uint, _new_parent: uint) { }
     fn mark_script_already_started(&mut self, _node: uint) { }
 }
 
@@ -0,0 +1,186 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Show what a browser builds from a snippet of markup.
+//!
+//! ```ignore
+//! assert_eq!(complete("<td>x", Default::default()).as_slice(),
+//!     "<table><tbody><tr><td>x</td></tr></tbody></table>");
+//! ```
+//!
+//! Some elements, such as `<td>`, are dropped unless they're in the
+//! right context, so the snippet is parsed as a fragment in the context
+//! its first start tag needs, and written out inside the elements that
+//! context implies.  A snippet which starts with a doctype, `<html>`,
+//! `<head>`, `<body>` or `<frameset>` is parsed as a document.
+
+use core::prelude::*;
+
+use tokenizer::{Tokenizer, TokenSink, Token, DoctypeToken, TagToken, StartTag};
+use sink::rcdom::RcDom;
+use driver::{ParseOpts, parse, parse_fragment, one_input};
+use serialize::{serialize, Serializable};
+
+use core::default::Default;
+use collections::vec::Vec;
+use collections::string::String;
+use std::io::MemWriter;
+
+use string_cache::{Atom, QualName};
+
+/// Options for `complete`.
+#[deriving(Clone, Default)]
+pub struct CompleteOpts {
+    /// Write out a whole document, with `<html>`, `<head>` and `<body>`,
+    /// rather than the snippet in the least context it needs?
+    /// Default: false
+    pub document: bool,
+
+    /// Options for parsing the snippet.
+    pub parse_opts: ParseOpts,
+}
+
+// Finds the doctype or start tag which a snippet starts with.
+struct FirstTag {
+    doctype: bool,
+    name: Option<Atom>,
+}
+
+impl TokenSink for FirstTag {
+    fn process_token(&mut self, token: Token) {
+        match token {
+            DoctypeToken(_) => self.doctype = true,
+            TagToken(ref tag) if tag.kind == StartTag => self.name = Some(tag.name.clone()),
+            _ => (),
+        }
+    }
+
+    fn aborted(&self) -> bool {
+        self.doctype || self.name.is_some()
+    }
+}
+
+// The elements a fragment starting with `name` must be in, outermost
+// first.  None for a document.
+fn context_for(name: &Atom) -> Option<Vec<Atom>> {
+    Some(match *name {
+        atom!(html) | atom!(head) | atom!(body) | atom!(frameset) => return None,
+        atom!(td) | atom!(th) => vec!(atom!(table), atom!(tbody), atom!(tr)),
+        atom!(tr) => vec!(atom!(table), atom!(tbody)),
+        atom!(tbody) | atom!(thead) | atom!(tfoot) | atom!(caption) | atom!(colgroup)
+            => vec!(atom!(table)),
+        atom!(col) => vec!(atom!(table), atom!(colgroup)),
+        atom!(option) | atom!(optgroup) => vec!(atom!(select)),
+        atom!(frame) => vec!(atom!(frameset)),
+        _ => vec!(),
+    })
+}
+
+fn to_html<T: Serializable>(node: &T) -> String {
+    let mut out = MemWriter::new();
+    serialize(&mut out, node, Default::default()).ok().expect("writing to memory failed");
+    String::from_utf8(out.unwrap()).unwrap()
+}
+
+/// Parse a snippet and write out what it becomes, adding the elements
+/// the parser implies.
+pub fn complete(snippet: &str, opts: CompleteOpts) -> String {
+    let mut first = FirstTag {
+        doctype: false,
+        name: None,
+    };
+    {
+        let mut tok = Tokenizer::new(&mut first, opts.parse_opts.tokenizer.clone());
+        tok.feed(String::from_str(snippet));
+        tok.end();
+    }
+
+    let context = match (first.doctype, first.name) {
+        (true, _) => None,
+        (false, Some(ref name)) => context_for(name),
+        (false, None) => Some(vec!()),
+    };
+    let context = match context {
+        Some(context) => context,
+        None => {
+            let dom: RcDom = parse(one_input(String::from_str(snippet)), opts.parse_opts);
+            return to_html(&dom);
+        }
+    };
+
+    let parent = match context.last() {
+        Some(name) => QualName::new(ns!(HTML), name.clone()),
+        None => qualname!(HTML, body),
+    };
+    let dom: RcDom = parse_fragment(one_input(String::from_str(snippet)),
+        parent, opts.parse_opts.clone());
+
+    // The fragment is the children of the root <html> element.
+    let mut out = String::new();
+    for name in context.iter() {
+        out.push_str(format!("<{}>", name.as_slice()).as_slice());
+    }
+    let root = dom.document.borrow().children[0].clone();
+    out.push_str(to_html(&root).as_slice());
+    for name in context.iter().rev() {
+        out.push_str(format!("</{}>", name.as_slice()).as_slice());
+    }
+
+    if opts.document {
+        let dom: RcDom = parse(one_input(out), opts.parse_opts);
+        to_html(&dom)
+    } else {
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{complete, CompleteOpts};
+
+    use core::default::Default;
+    use collections::string::String;
+
+    fn fragment(snippet: &str) -> String {
+        complete(snippet, Default::default())
+    }
+
+    fn document(snippet: &str) -> String {
+        complete(snippet, CompleteOpts {
+            document: true,
+            .. Default::default()
+        })
+    }
+
+    #[test]
+    fn fragments() {
+        assert_eq!(fragment("<td>x").as_slice(), "<table><tbody><tr><td>x</td></tr></tbody></table>");
+        assert_eq!(fragment("<tr><td>1<td>2").as_slice(),
+            "<table><tbody><tr><td>1</td><td>2</td></tr></tbody></table>");
+        assert_eq!(fragment("<col span=2>").as_slice(), "<table><colgroup><col span=\"2\"></colgroup></table>");
+        assert_eq!(fragment("<option>a<option>b").as_slice(),
+            "<select><option>a</option><option>b</option></select>");
+        assert_eq!(fragment("x<p>a<p>b").as_slice(), "x<p>a</p><p>b</p>");
+        assert_eq!(fragment("<!-- c --><b><i>x</b>").as_slice(), "<!-- c --><b><i>x</i></b>");
+    }
+
+    #[test]
+    fn documents() {
+        assert_eq!(document("<td>x").as_slice(), "<html><head></head><body>\
+            <table><tbody><tr><td>x</td></tr></tbody></table></body></html>");
+        assert_eq!(document("<title>t</title>x").as_slice(),
+            "<html><head><title>t</title></head><body>x</body></html>");
+        // Snippets which are documents are parsed as documents either way.
+        assert_eq!(fragment("<!DOCTYPE html><td>x").as_slice(),
+            "<!DOCTYPE html><html><head></head><body>x</body></html>");
+        assert_eq!(fragment("<body class=a>").as_slice(),
+            "<html><head></head><body class=\"a\"></body></html>");
+    }
+}
@@ -0,0 +1,289 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Saving and restoring a complete parser configuration.
+//!
+//! `dump` writes every option in a `ParseOpts` as one `key = value`
+//! line, and `load` reads that back.  The format is flat so that it's
+//! easy to store anywhere, including as a JSON object of strings.
+//!
+//! A dump records every option, not just those which differ from the
+//! defaults, so a later version with different defaults still gets the
+//! same settings.  Keys missing from the input get their defaults, which
+//! lets old dumps load after options are added.  Unknown keys are an
+//! error, since a setting we can't honor would make the results differ.
+//!
+//! `TokenizerOpts::initial_state` and `last_start_tag_name` are for the
+//! test runner and the fragment parsing driver, and are not saved.
+//! Neither is `atom_table`, which holds atoms rather than settings.
+
+use core::prelude::*;
+
+use driver::ParseOpts;
+use tokenizer::{DuplicateAttrPolicy, KeepFirst, KeepLast, KeepAll};
+
+use core::default::Default;
+use collections::string::String;
+use std::to_string::ToString;
+use std::from_str::{FromStr, from_str};
+
+/// The version of the format written by `dump`.  `load` rejects
+/// anything newer.
+pub static CONFIG_VERSION: uint = 1;
+
+fn show_bool(b: bool) -> String {
+    String::from_str(if b { "true" } else { "false" })
+}
+
+fn show_limit<T: ToString>(n: Option<T>) -> String {
+    match n {
+        Some(n) => n.to_string(),
+        None => String::from_str("none"),
+    }
+}
+
+fn show_policy(p: DuplicateAttrPolicy) -> String {
+    String::from_str(match p {
+        KeepFirst => "keep_first",
+        KeepLast => "keep_last",
+        KeepAll => "keep_all",
+    })
+}
+
+/// Write out every option, one `key = value` per line.
+pub fn dump(opts: &ParseOpts) -> String {
+    let tok = &opts.tokenizer;
+    let tb = &opts.tree_builder;
+
+    let lines = [
+        ("config_version", CONFIG_VERSION.to_string()),
+
+        ("tokenizer.exact_errors", show_bool(tok.exact_errors)),
+        ("tokenizer.discard_bom", show_bool(tok.discard_bom)),
+        ("tokenizer.duplicate_attrs", show_policy(tok.duplicate_attrs.clone())),
+        ("tokenizer.max_comment_len", show_limit(tok.max_comment_len)),
+        ("tokenizer.comment_chunk_len", show_limit(tok.comment_chunk_len)),
+        ("tokenizer.max_attr_name_len", show_limit(tok.max_attr_name_len)),
+        ("tokenizer.max_attr_count", show_limit(tok.max_attr_count)),
+        ("tokenizer.max_token_size", show_limit(tok.max_token_size)),
+        ("tokenizer.profile", show_bool(tok.profile)),
+        ("tokenizer.trace", show_bool(tok.trace)),
+        ("tokenizer.atom_stats", show_bool(tok.atom_stats)),
+        ("tokenizer.input_stats", show_bool(tok.input_stats)),
+        ("tokenizer.preserve_cr.text", show_bool(tok.preserve_cr.text)),
+        ("tokenizer.preserve_cr.attr_values", show_bool(tok.preserve_cr.attr_values)),
+        ("tokenizer.preserve_cr.comments", show_bool(tok.preserve_cr.comments)),
+        ("tokenizer.char_ref_diagnostics", show_bool(tok.char_ref_diagnostics)),
+        ("tokenizer.strict_char_refs", show_bool(tok.strict_char_refs)),
+        ("tokenizer.check_fast_path", show_bool(tok.check_fast_path)),
+        ("tokenizer.track_lines", show_bool(tok.track_lines)),
+        ("tokenizer.lazy_attrs", show_bool(tok.lazy_attrs)),
+        ("tokenizer.raw_doctype", show_bool(tok.raw_doctype)),
+        ("tokenizer.attr_spans", show_bool(tok.attr_spans)),
+
+        ("tree_builder.exact_errors", show_bool(tb.exact_errors)),
+        ("tree_builder.scripting_enabled", show_bool(tb.scripting_enabled)),
+        ("tree_builder.iframe_srcdoc", show_bool(tb.iframe_srcdoc)),
+        ("tree_builder.fragment", show_bool(tb.fragment)),
+        ("tree_builder.drop_doctype", show_bool(tb.drop_doctype)),
+        ("tree_builder.trace", show_bool(tb.trace)),
+        ("tree_builder.audit", show_bool(tb.audit)),
+        ("tree_builder.xml_islands", show_bool(tb.xml_islands)),
+        ("tree_builder.report_steps", show_bool(tb.report_steps)),
+        ("tree_builder.error_comments", show_bool(tb.error_comments)),
+        ("tree_builder.report_void_end_tags", show_bool(tb.report_void_end_tags)),
+        ("tree_builder.max_tree_depth", show_limit(tb.max_tree_depth)),
+        ("tree_builder.exact_text_splitting", show_bool(tb.exact_text_splitting)),
+
+        ("max_parse_millis", show_limit(opts.max_parse_millis)),
+        ("reorder_window", opts.reorder_window.to_string()),
+    ];
+
+    let mut out = String::new();
+    for &(ref key, ref value) in lines.iter() {
+        out.push_str(*key);
+        out.push_str(" = ");
+        out.push_str(value.as_slice());
+        out.push('\n');
+    }
+    out
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("expected true or false, got {:s}", value)),
+    }
+}
+
+fn parse_limit<T: FromStr>(value: &str) -> Result<Option<T>, String> {
+    if value == "none" {
+        return Ok(None);
+    }
+    match from_str(value) {
+        Some(n) => Ok(Some(n)),
+        None => Err(format!("expected a number or none, got {:s}", value)),
+    }
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T, String> {
+    match from_str(value) {
+        Some(n) => Ok(n),
+        None => Err(format!("expected a number, got {:s}", value)),
+    }
+}
+
+fn parse_policy(value: &str) -> Result<DuplicateAttrPolicy, String> {
+    match value {
+        "keep_first" => Ok(KeepFirst),
+        "keep_last" => Ok(KeepLast),
+        "keep_all" => Ok(KeepAll),
+        _ => Err(format!("unknown duplicate attribute policy {:s}", value)),
+    }
+}
+
+fn set_option(opts: &mut ParseOpts, key: &str, value: &str) -> Result<(), String> {
+    let tok = &mut opts.tokenizer;
+    let tb = &mut opts.tree_builder;
+
+    match key {
+        "config_version" => {
+            let version: Option<uint> = from_str(value);
+            match version {
+                Some(v) if v <= CONFIG_VERSION => (),
+                _ => return Err(format!("unsupported config_version {:s}", value)),
+            }
+        }
+
+        "tokenizer.exact_errors" => tok.exact_errors = try!(parse_bool(value)),
+        "tokenizer.discard_bom" => tok.discard_bom = try!(parse_bool(value)),
+        "tokenizer.duplicate_attrs" => tok.duplicate_attrs = try!(parse_policy(value)),
+        "tokenizer.max_comment_len" => tok.max_comment_len = try!(parse_limit(value)),
+        "tokenizer.comment_chunk_len" => tok.comment_chunk_len = try!(parse_limit(value)),
+        "tokenizer.max_attr_name_len" => tok.max_attr_name_len = try!(parse_limit(value)),
+        "tokenizer.max_attr_count" => tok.max_attr_count = try!(parse_limit(value)),
+        "tokenizer.max_token_size" => tok.max_token_size = try!(parse_limit(value)),
+        "tokenizer.profile" => tok.profile = try!(parse_bool(value)),
+        "tokenizer.trace" => tok.trace = try!(parse_bool(value)),
+        "tokenizer.atom_stats" => tok.atom_stats = try!(parse_bool(value)),
+        "tokenizer.input_stats" => tok.input_stats = try!(parse_bool(value)),
+        "tokenizer.preserve_cr.text" => tok.preserve_cr.text = try!(parse_bool(value)),
+        "tokenizer.preserve_cr.attr_values"
+            => tok.preserve_cr.attr_values = try!(parse_bool(value)),
+        "tokenizer.preserve_cr.comments" => tok.preserve_cr.comments = try!(parse_bool(value)),
+        "tokenizer.char_ref_diagnostics" => tok.char_ref_diagnostics = try!(parse_bool(value)),
+        "tokenizer.strict_char_refs" => tok.strict_char_refs = try!(parse_bool(value)),
+        "tokenizer.check_fast_path" => tok.check_fast_path = try!(parse_bool(value)),
+        "tokenizer.track_lines" => tok.track_lines = try!(parse_bool(value)),
+        "tokenizer.lazy_attrs" => tok.lazy_attrs = try!(parse_bool(value)),
+        "tokenizer.raw_doctype" => tok.raw_doctype = try!(parse_bool(value)),
+        "tokenizer.attr_spans" => tok.attr_spans = try!(parse_bool(value)),
+
+        "tree_builder.exact_errors" => tb.exact_errors = try!(parse_bool(value)),
+        "tree_builder.scripting_enabled" => tb.scripting_enabled = try!(parse_bool(value)),
+        "tree_builder.iframe_srcdoc" => tb.iframe_srcdoc = try!(parse_bool(value)),
+        "tree_builder.fragment" => tb.fragment = try!(parse_bool(value)),
+        "tree_builder.drop_doctype" => tb.drop_doctype = try!(parse_bool(value)),
+        "tree_builder.trace" => tb.trace = try!(parse_bool(value)),
+        "tree_builder.audit" => tb.audit = try!(parse_bool(value)),
+        "tree_builder.xml_islands" => tb.xml_islands = try!(parse_bool(value)),
+        "tree_builder.report_steps" => tb.report_steps = try!(parse_bool(value)),
+        "tree_builder.error_comments" => tb.error_comments = try!(parse_bool(value)),
+        "tree_builder.report_void_end_tags"
+            => tb.report_void_end_tags = try!(parse_bool(value)),
+        "tree_builder.max_tree_depth" => tb.max_tree_depth = try!(parse_limit(value)),
+        "tree_builder.exact_text_splitting"
+            => tb.exact_text_splitting = try!(parse_bool(value)),
+
+        "max_parse_millis" => opts.max_parse_millis = try!(parse_limit(value)),
+        "reorder_window" => opts.reorder_window = try!(parse_number(value)),
+
+        _ => return Err(format!("unknown option {:s}", key)),
+    }
+    Ok(())
+}
+
+/// Read options written by `dump`.  Blank lines and lines starting with
+/// `#` are ignored.  Errors name the offending line.
+pub fn load(text: &str) -> Result<ParseOpts, String> {
+    let mut opts: ParseOpts = Default::default();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("#") {
+            continue;
+        }
+
+        let (key, value) = match line.find('=') {
+            Some(n) => (line.slice_to(n).trim(), line.slice_from(n + 1).trim()),
+            None => return Err(format!("line {:u}: expected key = value", i + 1)),
+        };
+
+        match set_option(&mut opts, key, value) {
+            Ok(()) => (),
+            Err(e) => return Err(format!("line {:u}: {:s}", i + 1, e)),
+        }
+    }
+    Ok(opts)
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{dump, load};
+
+    use driver::ParseOpts;
+    use tokenizer::{TokenizerOpts, KeepAll};
+    use tree_builder::TreeBuilderOpts;
+
+    use core::default::Default;
+    use collections::string::String;
+
+    #[test]
+    fn round_trip() {
+        let opts = ParseOpts {
+            tokenizer: TokenizerOpts {
+                duplicate_attrs: KeepAll,
+                max_comment_len: Some(1024),
+                strict_char_refs: true,
+                .. Default::default()
+            },
+            tree_builder: TreeBuilderOpts {
+                scripting_enabled: false,
+                max_tree_depth: Some(512),
+                .. Default::default()
+            },
+            max_parse_millis: Some(500),
+            reorder_window: 8,
+        };
+
+        let text = dump(&opts);
+        let loaded = load(text.as_slice()).ok().expect("couldn't load dump");
+        assert_eq!(dump(&loaded), text);
+        assert_eq!(loaded.tokenizer.duplicate_attrs, KeepAll);
+        assert_eq!(loaded.tokenizer.max_comment_len, Some(1024));
+        assert!(!loaded.tree_builder.scripting_enabled);
+        assert_eq!(loaded.tree_builder.max_tree_depth, Some(512));
+        assert_eq!(loaded.max_parse_millis, Some(500));
+        assert_eq!(loaded.reorder_window, 8);
+    }
+
+    #[test]
+    fn load_errors() {
+        let err = |text: &str| load(text).err().expect("loaded bad config");
+
+        assert!(load("# nothing\n\n").is_ok());
+        assert!(load("tokenizer.exact_errors = true").ok().unwrap().tokenizer.exact_errors);
+        assert_eq!(err("config_version = 99"), String::from_str("line 1: unsupported config_version 99"));
+        assert_eq!(err("\nbogus = 1"), String::from_str("line 2: unknown option bogus"));
+        assert_eq!(err("tokenizer.trace = yes"),
+            String::from_str("line 1: expected true or false, got yes"));
+        assert_eq!(err("max_parse_millis"), String::from_str("line 1: expected key = value"));
+    }
+}
@@ -0,0 +1,439 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Run the html5lib tree construction tests against any `TreeSink`.
+//!
+//! A DOM which builds its tree through `TreeSink` can check that it
+//! ends up with the trees the spec requires.  Implement `SnapshotSink`
+//! to write the tree out in the tests' format, using `Snapshot`, then:
+//!
+//! ```ignore
+//! let cases = load_corpus(&Path::new("html5lib-tests/tree-construction")).unwrap();
+//! for failure in run_corpus(cases.as_slice(), || MyDom::new()).iter() {
+//!     println!("{}", failure);
+//! }
+//! ```
+//!
+//! The corpus is the `tree-construction` directory of
+//! [html5lib-tests](https://github.com/html5lib/html5lib-tests).  Each
+//! test gets a fresh sink.  Parse errors aren't compared, since the
+//! tree builder reports its own; only the tree is.
+//!
+//! To see how `RcDom` parsed something, in the same format, use
+//! `snapshot_rcdom`.  `load_file` reads a single `.dat` file.
+
+use core::prelude::*;
+
+use tokenizer::Attribute;
+use tree_builder::TreeSink;
+use sink::common::{Document, Doctype, Text, Comment, Element};
+use sink::rcdom::RcDom;
+use sink::rcdom;
+use driver::{ParseOpts, parse_to, parse_fragment_to, one_input};
+
+use core::fmt;
+use core::mem::replace;
+use core::default::Default;
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+use std::io::{File, IoResult};
+use std::io::fs::readdir;
+use std::path::{Path, GenericPath};
+
+use string_cache::{Atom, QualName};
+
+/// A tree construction test.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct TestCase {
+    /// The file the test came from, and its position there from 0.
+    pub file: String,
+    pub index: uint,
+
+    /// The input.
+    pub data: String,
+
+    /// For a fragment test, the context element, as `body` or
+    /// `svg path`.
+    pub context: Option<String>,
+
+    /// Whether to parse with scripting on, if the test says.
+    pub scripting: Option<bool>,
+
+    /// The tree, as `Snapshot` writes it.
+    pub expected: String,
+}
+
+/// A test whose tree came out wrong.
+#[deriving(PartialEq, Eq, Clone)]
+pub struct Failure {
+    pub case: TestCase,
+    pub got: String,
+}
+
+impl fmt::Show for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}\ninput: {}\ngot:\n{}\nexpected:\n{}\n",
+            self.case.file, self.case.index, self.case.data, self.got, self.case.expected)
+    }
+}
+
+/// A tree written out in the format of the tests' `#document`
+/// sections: a line for each node and attribute, indented two spaces
+/// for each level of depth.
+///
+/// The document's children are at depth 0, or for a fragment, the
+/// children of the root `<html>` element.
+pub struct Snapshot {
+    out: String,
+}
+
+impl Snapshot {
+    pub fn new() -> Snapshot {
+        Snapshot {
+            out: String::new(),
+        }
+    }
+
+    fn line_start(&mut self, depth: uint) {
+        self.out.push_str("| ");
+        self.out.grow(2 * depth, ' ');
+    }
+
+    pub fn doctype(&mut self, depth: uint, name: &str, public_id: &str, system_id: &str) {
+        self.line_start(depth);
+        self.out.push_str("<!DOCTYPE ");
+        self.out.push_str(name);
+        if !public_id.is_empty() || !system_id.is_empty() {
+            self.out.push_str(format!(" \"{}\" \"{}\"", public_id, system_id).as_slice());
+        }
+        self.out.push_str(">\n");
+    }
+
+    pub fn text(&mut self, depth: uint, text: &str) {
+        self.line_start(depth);
+        self.out.push_str(format!("\"{}\"\n", text).as_slice());
+    }
+
+    pub fn comment(&mut self, depth: uint, text: &str) {
+        self.line_start(depth);
+        self.out.push_str(format!("<!-- {} -->\n", text).as_slice());
+    }
+
+    /// An element and its attributes.  The children come next, one
+    /// level deeper.
+    pub fn element(&mut self, depth: uint, name: &QualName, attrs: &[Attribute]) {
+        self.line_start(depth);
+        self.out.push_str("<");
+        match name.ns {
+            ns!(SVG) => self.out.push_str("svg "),
+            ns!(MathML) => self.out.push_str("math "),
+            _ => (),
+        }
+        self.out.push_str(name.local.as_slice());
+        self.out.push_str(">\n");
+
+        // FIXME: sort by UTF-16 code unit
+        let mut attrs = attrs.to_vec();
+        attrs.sort_by(|x, y| x.name.local.cmp(&y.name.local));
+        for attr in attrs.iter() {
+            self.line_start(depth + 1);
+            match attr.name.ns {
+                ns!(XLink) => self.out.push_str("xlink "),
+                ns!(XML) => self.out.push_str("xml "),
+                ns!(XMLNS) => self.out.push_str("xmlns "),
+                _ => (),
+            }
+            self.out.push_str(format!("{}=\"{}\"\n",
+                attr.name.local.as_slice(), attr.value).as_slice());
+        }
+    }
+
+    /// The contents of the `<template>` at `depth`.  Its children come
+    /// next, two levels deeper than the template.
+    pub fn template_contents(&mut self, depth: uint) {
+        self.line_start(depth + 1);
+        self.out.push_str("content\n");
+    }
+
+    /// The tree as text, without the final newline.
+    pub fn finish(self) -> String {
+        let mut out = self.out;
+        let len = out.len();
+        if len > 0 {
+            out.truncate(len - 1);
+        }
+        out
+    }
+}
+
+/// A `TreeSink` whose tree can be compared with the tests'.
+pub trait SnapshotSink<Handle>: TreeSink<Handle> {
+    /// Write out the finished tree.  For a fragment, write out the
+    /// children of the root `<html>` element.
+    fn snapshot(&mut self, fragment: bool) -> String;
+}
+
+fn snapshot_rc(snap: &mut Snapshot, depth: uint, handle: &rcdom::Handle) {
+    let node = handle.borrow();
+    match node.node {
+        Document => fail!("should not reach Document"),
+        Doctype(ref name, ref public_id, ref system_id)
+            => snap.doctype(depth, name.as_slice(), public_id.as_slice(), system_id.as_slice()),
+        Text(ref text) => snap.text(depth, text.as_slice()),
+        Comment(ref text) => snap.comment(depth, text.as_slice()),
+        Element(ref name, ref attrs) => snap.element(depth, name, attrs.as_slice()),
+    }
+
+    match node.template_contents {
+        Some(ref contents) => {
+            snap.template_contents(depth);
+            for child in contents.borrow().children.iter() {
+                snapshot_rc(snap, depth + 2, child);
+            }
+        }
+        None => for child in node.children.iter() {
+            snapshot_rc(snap, depth + 1, child);
+        },
+    }
+}
+
+/// Write out a parsed `RcDom` the way the tests expect it.  For a
+/// fragment, write out the children of the root `<html>` element.
+pub fn snapshot_rcdom(dom: &RcDom, fragment: bool) -> String {
+    let root = if fragment {
+        dom.document.borrow().children[0].clone()
+    } else {
+        dom.document.clone()
+    };
+    let mut snap = Snapshot::new();
+    for child in root.borrow().children.iter() {
+        snapshot_rc(&mut snap, 0, child);
+    }
+    snap.finish()
+}
+
+impl SnapshotSink<rcdom::Handle> for RcDom {
+    fn snapshot(&mut self, fragment: bool) -> String {
+        snapshot_rcdom(self, fragment)
+    }
+}
+
+/// Read the tests in the text of a `.dat` file.
+pub fn read_tests(file: &str, text: &str) -> Vec<TestCase> {
+    let mut tests = vec!();
+    let mut fields: Vec<(String, String)> = vec!();
+    let mut key: Option<String> = None;
+    let mut val = String::new();
+
+    fn finish_test(file: &str, tests: &mut Vec<TestCase>, fields: Vec<(String, String)>) {
+        if fields.is_empty() {
+            return;
+        }
+        let get = |name: &str| fields.iter()
+            .find(|&&(ref k, _)| k.as_slice() == name)
+            .map(|&(_, ref v)| String::from_str(v.as_slice().trim_right_chars('\n')));
+        let scripting = match (get("script-on"), get("script-off")) {
+            (Some(_), _) => Some(true),
+            (_, Some(_)) => Some(false),
+            _ => None,
+        };
+        let index = tests.len();
+        tests.push(TestCase {
+            file: String::from_str(file),
+            index: index,
+            data: get("data").unwrap_or(String::new()),
+            context: get("document-fragment"),
+            scripting: scripting,
+            expected: get("document").unwrap_or(String::new()),
+        });
+    }
+
+    for line in text.split('\n') {
+        if line.starts_with("#") {
+            match key.take() {
+                Some(k) => fields.push((k, replace(&mut val, String::new()))),
+                None => (),
+            }
+            if line == "#data" {
+                finish_test(file, &mut tests, replace(&mut fields, vec!()));
+            }
+            key = Some(String::from_str(line.slice_from(1)));
+        } else {
+            val.push_str(line);
+            val.push('\n');
+        }
+    }
+    match key.take() {
+        Some(k) => fields.push((k, val)),
+        None => (),
+    }
+    finish_test(file, &mut tests, fields);
+    tests
+}
+
+/// Read the tests in a `.dat` file.  They're named for the file's
+/// name, without the directory.
+pub fn load_file(path: &Path) -> IoResult<Vec<TestCase>> {
+    let text = try!(File::open(path).read_to_string());
+    let name = path.filename_str().unwrap_or("");
+    Ok(read_tests(name, text.as_slice()))
+}
+
+/// Read every `.dat` file in a directory.
+pub fn load_corpus(dir: &Path) -> IoResult<Vec<TestCase>> {
+    let mut paths = try!(readdir(dir));
+    paths.sort_by(|a, b| a.as_vec().cmp(&b.as_vec()));
+    let mut tests = vec!();
+    for path in paths.iter() {
+        match path.filename_str() {
+            Some(name) if name.ends_with(".dat") => (),
+            _ => continue,
+        }
+        tests.extend(try!(load_file(path)).into_iter());
+    }
+    Ok(tests)
+}
+
+// The context element of a fragment test.  Contexts in foreign
+// namespaces look like "svg path".
+fn context_name(context: &str) -> QualName {
+    if context.starts_with("svg ") {
+        QualName::new(ns!(SVG), Atom::from_slice(context.slice_from(4)))
+    } else if context.starts_with("math ") {
+        QualName::new(ns!(MathML), Atom::from_slice(context.slice_from(5)))
+    } else {
+        QualName::new(ns!(HTML), Atom::from_slice(context))
+    }
+}
+
+/// Parse a test's input into `sink`, and compare the tree.
+pub fn run_test<Handle: Clone, Sink: SnapshotSink<Handle>>(case: &TestCase, sink: &mut Sink)
+        -> Result<(), Failure> {
+    let mut opts: ParseOpts = Default::default();
+    match case.scripting {
+        Some(s) => opts.tree_builder.scripting_enabled = s,
+        None => (),
+    }
+
+    match case.context {
+        None => {
+            parse_to(sink, one_input(case.data.clone()), opts);
+        }
+        Some(ref context) => {
+            let context = sink.create_element(context_name(context.as_slice()), vec!());
+            parse_fragment_to(sink, one_input(case.data.clone()), context, None, opts);
+        }
+    }
+
+    let got = sink.snapshot(case.context.is_some());
+    if got == case.expected {
+        Ok(())
+    } else {
+        Err(Failure {
+            case: case.clone(),
+            got: got,
+        })
+    }
+}
+
+/// Run each test on a new sink from `new_sink`, and return the
+/// failures.
+pub fn run_corpus<Handle: Clone, Sink: SnapshotSink<Handle>>(cases: &[TestCase],
+        new_sink: || -> Sink) -> Vec<Failure> {
+    let mut failures = vec!();
+    for case in cases.iter() {
+        let mut sink = new_sink();
+        match run_test(case, &mut sink) {
+            Ok(()) => (),
+            Err(f) => failures.push(f),
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{read_tests, run_corpus, snapshot_rcdom};
+
+    use sink::rcdom::RcDom;
+    use driver::{parse, one_input};
+
+    use core::default::Default;
+    use collections::string::String;
+
+    static TESTS: &'static str = "\
+#data
+<p a=1 b=2>x<!--c-->
+#errors
+(1,4): expected-doctype-but-got-start-tag
+#document
+| <html>
+|   <head>
+|   <body>
+|     <p>
+|       a=\"1\"
+|       b=\"2\"
+|       \"x\"
+|       <!-- c -->
+
+#data
+<template><b></b></template><svg><path/></svg>
+#errors
+#document-fragment
+div
+#document
+| <template>
+|   content
+|     <b>
+| <svg svg>
+|   <svg path>
+
+#data
+<noscript><i></i></noscript>
+#errors
+#script-off
+#document-fragment
+body
+#document
+| <noscript>
+|   <b>
+";
+
+    #[test]
+    fn corpus() {
+        let cases = read_tests("test.dat", TESTS);
+        assert_eq!(cases.len(), 3);
+        assert_eq!(cases[1].context, Some(String::from_str("div")));
+        assert_eq!(cases[2].scripting, Some(false));
+
+        // The last test expects the wrong tree.
+        let failures = run_corpus(cases.as_slice(), || -> RcDom { Default::default() });
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].case.index, 2);
+        assert_eq!(failures[0].got.as_slice(), "| <noscript>\n|   <i>");
+    }
+
+    #[test]
+    fn rcdom() {
+        let dom: RcDom = parse(one_input(String::from_str(
+            "<!DOCTYPE html><title>t</title><math><mi xlink:href=x>")), Default::default());
+        assert_eq!(snapshot_rcdom(&dom, false).as_slice(), "\
+| <!DOCTYPE html>
+| <html>
+|   <head>
+|     <title>
+|       \"t\"
+|   <body>
+|     <math math>
+|       <math mi>
+|         xlink href=\"x\"");
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Turning bytes into text for the parser.
+//!
+//! `driver::decoded_input` feeds bytes through any `Decoder`.  The only
+//! one here is `Utf8Decoder`; to support other encodings, wrap your
+//! platform's converters (ICU, the Windows code page functions, ...) in
+//! a `Decoder`, and use `sniff::decide_encoding` to choose one.
+
+use core::prelude::*;
+
+use core::char;
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+use collections::str::{MaybeOwned, Slice};
+
+/// Malformed input, which a `Decoder` replaced with U+FFFD.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct DecodeError {
+    /// Where the bad bytes start, counting from the first byte given to
+    /// the decoder.
+    pub position: uint,
+
+    /// What was wrong with them.
+    pub message: MaybeOwned<'static>,
+}
+
+/// A streaming converter from some encoding to Unicode.
+///
+/// Input arrives in arbitrary pieces, so a character may be split across
+/// calls to `decode`.  The decoder keeps back the start of such a
+/// character until it sees the rest.  Malformed input becomes U+FFFD,
+/// as the spec requires, and is also reported in `errors`.
+pub trait Decoder {
+    /// Decode some bytes, appending the text to `output`.
+    fn decode(&mut self, input: &[u8], output: &mut String, errors: &mut Vec<DecodeError>);
+
+    /// There's no more input.  Anything kept back is malformed.
+    fn finish(&mut self, output: &mut String, errors: &mut Vec<DecodeError>);
+}
+
+/// A `Decoder` for UTF-8, following the decoder in the
+/// [Encoding](https://encoding.spec.whatwg.org/#utf-8-decoder) standard.
+pub struct Utf8Decoder {
+    code_point: u32,
+    bytes_needed: uint,
+    bytes_seen: uint,
+
+    /// The range allowed for the next continuation byte.  It's narrower
+    /// than usual after some lead bytes, to rule out overlong forms,
+    /// surrogates and values past U+10FFFF.
+    lower: u8,
+    upper: u8,
+
+    /// Bytes decoded before the current call to `decode`.
+    offset: uint,
+
+    /// Where the character being decoded started.
+    char_start: uint,
+}
+
+impl Utf8Decoder {
+    pub fn new() -> Utf8Decoder {
+        Utf8Decoder {
+            code_point: 0,
+            bytes_needed: 0,
+            bytes_seen: 0,
+            lower: 0x80,
+            upper: 0xBF,
+            offset: 0,
+            char_start: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.code_point = 0;
+        self.bytes_needed = 0;
+        self.bytes_seen = 0;
+        self.lower = 0x80;
+        self.upper = 0xBF;
+    }
+
+    fn malformed(&mut self, output: &mut String, errors: &mut Vec<DecodeError>) {
+        self.reset();
+        output.push('\ufffd');
+        errors.push(DecodeError {
+            position: self.char_start,
+            message: Slice("Invalid UTF-8"),
+        });
+    }
+}
+
+impl Decoder for Utf8Decoder {
+    fn decode(&mut self, input: &[u8], output: &mut String, errors: &mut Vec<DecodeError>) {
+        output.reserve_additional(input.len());
+
+        let mut i = 0;
+        while i < input.len() {
+            let b = input[i];
+
+            if self.bytes_needed == 0 {
+                self.char_start = self.offset + i;
+                i += 1;
+                match b {
+                    0x00...0x7F => output.push(b as char),
+                    0xC2...0xDF => {
+                        self.bytes_needed = 1;
+                        self.code_point = (b & 0x1F) as u32;
+                    }
+                    0xE0...0xEF => {
+                        if b == 0xE0 { self.lower = 0xA0; }
+                        if b == 0xED { self.upper = 0x9F; }
+                        self.bytes_needed = 2;
+                        self.code_point = (b & 0xF) as u32;
+                    }
+                    0xF0...0xF4 => {
+                        if b == 0xF0 { self.lower = 0x90; }
+                        if b == 0xF4 { self.upper = 0x8F; }
+                        self.bytes_needed = 3;
+                        self.code_point = (b & 0x7) as u32;
+                    }
+                    _ => self.malformed(output, errors),
+                }
+                continue;
+            }
+
+            if b < self.lower || b > self.upper {
+                // Leave the byte for the next character, which it may
+                // well start.
+                self.malformed(output, errors);
+                continue;
+            }
+
+            i += 1;
+            self.lower = 0x80;
+            self.upper = 0xBF;
+            self.code_point = (self.code_point << 6) | (b & 0x3F) as u32;
+            self.bytes_seen += 1;
+            if self.bytes_seen == self.bytes_needed {
+                output.push(char::from_u32(self.code_point).expect("decoded a bad code point"));
+                self.reset();
+            }
+        }
+
+        self.offset += input.len();
+    }
+
+    fn finish(&mut self, output: &mut String, errors: &mut Vec<DecodeError>) {
+        if self.bytes_needed != 0 {
+            self.malformed(output, errors);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{Decoder, Utf8Decoder, DecodeError};
+
+    use collections::vec::Vec;
+    use collections::string::String;
+    use collections::str::Slice;
+
+    fn decode(pieces: &[&[u8]]) -> (String, Vec<uint>) {
+        let mut decoder = Utf8Decoder::new();
+        let mut out = String::new();
+        let mut errors = vec!();
+        for piece in pieces.iter() {
+            decoder.decode(*piece, &mut out, &mut errors);
+        }
+        decoder.finish(&mut out, &mut errors);
+        (out, errors.into_iter().map(|e: DecodeError| e.position).collect())
+    }
+
+    #[test]
+    fn split_chars() {
+        let bytes = "a☃b😀".as_bytes();
+        for i in range(0, bytes.len() + 1) {
+            let (out, errors) = decode(&[bytes.slice_to(i), bytes.slice_from(i)]);
+            assert_eq!(out.as_slice(), "a☃b😀");
+            assert!(errors.is_empty());
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        // A bad continuation byte starts the next character.
+        assert_eq!(decode(&[b"a\xe2\x98x"]), (String::from_str("a\ufffdx"), vec!(1)));
+
+        // Surrogates and overlong forms are one error per byte.
+        assert_eq!(decode(&[b"\xed\xa0\x80"]),
+            (String::from_str("\ufffd\ufffd\ufffd"), vec!(0, 1, 2)));
+        assert_eq!(decode(&[b"\xc0\xafz"]), (String::from_str("\ufffd\ufffdz"), vec!(0, 1)));
+
+        // Positions count across pieces, and input can end mid-character.
+        assert_eq!(decode(&[b"ab", b"\xff", b"\xf0\x9f"]),
+            (String::from_str("ab\ufffd\ufffd"), vec!(2, 3)));
+    }
+
+    #[test]
+    fn error_message() {
+        let mut decoder = Utf8Decoder::new();
+        let mut out = String::new();
+        let mut errors = vec!();
+        decoder.decode(b"\x80", &mut out, &mut errors);
+        assert_eq!(errors, vec!(DecodeError { position: 0, message: Slice("Invalid UTF-8") }));
+    }
+}
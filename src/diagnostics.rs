@@ -0,0 +1,772 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Sinks which print what the tokenizer and tree builder are doing.
+//!
+//! These are the printers from the `tokenize` and `print-tree-actions`
+//! examples.  The output format is meant for people, but it is kept
+//! stable so that it can be compared in tests.
+//!
+//! There are also tree sinks for testing: `NumberingSink` records tree
+//! actions, and `ScriptedSink` injects faults into another sink.
+
+use core::prelude::*;
+
+use tokenizer::{Attribute, TokenSink, Token, ParseError, ParseErrorKind};
+use tokenizer::{CharacterTokens, NullCharacterToken, TagToken, StartTag, EndTag};
+use tree_builder::{TreeSink, QuirksMode, NodeOrText, AppendNode, AppendText};
+use tree_builder::{ElemProvenance, AuditIssue, ImgWithoutAlt, UnlabeledLabel};
+use tree_builder::{LabelForMissingId, DuplicateId};
+use tree_builder::{InsertionMode, StepOutcome, SplitStatus, ScriptAction};
+use tree_builder;
+
+use core::char;
+use collections::{MutableSeq, MutableMap};
+use collections::vec::Vec;
+use collections::string::String;
+use collections::str::MaybeOwned;
+use std::io::{Writer, IoResult, LineBufferedWriter};
+use std::io::stdio::{stdout, StdWriter};
+use std::collections::hashmap::HashMap;
+
+use string_cache::{Atom, QualName};
+
+/// Write to `$self.out`, remembering the first error.
+macro_rules! out ( ($self_:ident, $($arg:tt)*) => (
+    if $self_.result.is_ok() {
+        $self_.result = write!(&mut $self_.out, $($arg)*);
+    }
+))
+
+/// A `TreeSink` which prints each tree modification, one per line.
+///
+/// Nodes are identified by a `uint` id; the document is `0`.  A
+/// template's contents have the id after the template's.
+pub struct TreeActionsPrinter<W> {
+    out: W,
+    result: IoResult<()>,
+    next_id: uint,
+    names: HashMap<uint, QualName>,
+}
+
+impl<W: Writer> TreeActionsPrinter<W> {
+    pub fn new(out: W) -> TreeActionsPrinter<W> {
+        TreeActionsPrinter {
+            out: out,
+            result: Ok(()),
+            next_id: 1,
+            names: HashMap::new(),
+        }
+    }
+
+    /// Get back the writer, or the first error we saw when writing to it.
+    pub fn into_inner(self) -> IoResult<W> {
+        let TreeActionsPrinter { out, result, .. } = self;
+        result.map(|_| out)
+    }
+
+    fn get_id(&mut self) -> uint {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+}
+
+/// A `TreeActionsPrinter` which writes to standard output.
+pub fn print_tree_actions_sink() -> TreeActionsPrinter<LineBufferedWriter<StdWriter>> {
+    TreeActionsPrinter::new(stdout())
+}
+
+impl<W: Writer> TreeSink<uint> for TreeActionsPrinter<W> {
+    fn parse_error(&mut self, msg: MaybeOwned<'static>) {
+        out!(self, "Parse error: {:s}\n", msg);
+    }
+
+    fn get_document(&mut self) -> uint {
+        0
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        out!(self, "Set quirks mode to {}\n", mode);
+    }
+
+    fn same_node(&self, x: uint, y: uint) -> bool {
+        x == y
+    }
+
+    fn elem_name(&self, target: uint) -> QualName {
+        self.names.find(&target).expect("not an element").clone()
+    }
+
+    fn get_template_contents(&mut self, target: uint) -> uint {
+        target + 1
+    }
+
+    fn create_element(&mut self, name: QualName, _attrs: Vec<Attribute>) -> uint {
+        let id = self.get_id();
+        out!(self, "Created {} as {:u}\n", name, id);
+        if name == qualname!(HTML, template) {
+            // The next id is for its contents.
+            self.get_id();
+        }
+        self.names.insert(id, name);
+        id
+    }
+
+    fn create_comment(&mut self, text: String) -> uint {
+        let id = self.get_id();
+        out!(self, "Created comment \"{:s}\" as {:u}\n", text.escape_default(), id);
+        id
+    }
+
+    fn append(&mut self, parent: uint, child: NodeOrText<uint>) {
+        match child {
+            AppendNode(n)
+                => out!(self, "Append node {:u} to {:u}\n", n, parent),
+            AppendText(t)
+                => out!(self, "Append text to {:u}: \"{:s}\"\n", parent, t.escape_default()),
+        }
+    }
+
+    fn append_before_sibling(&mut self,
+            sibling: uint,
+            new_node: NodeOrText<uint>) -> Result<(), NodeOrText<uint>> {
+        match new_node {
+            AppendNode(n)
+                => out!(self, "Append node {:u} before {:u}\n", n, sibling),
+            AppendText(t)
+                => out!(self, "Append text before {:u}: \"{:s}\"\n", sibling, t.escape_default()),
+        }
+
+        // `sibling` will have a parent unless a script moved it, and we're
+        // not running scripts.  Therefore we can aways return `Ok(())`.
+        Ok(())
+    }
+
+    fn append_doctype_to_document(&mut self, name: String, public_id: String, system_id: String) {
+        out!(self, "Append doctype: {:s} {:s} {:s}\n", name, public_id, system_id);
+    }
+
+    fn add_attrs_if_missing(&mut self, target: uint, attrs: Vec<Attribute>) {
+        out!(self, "Add missing attributes to {:u}:\n", target);
+        for attr in attrs.into_iter() {
+            out!(self, "    {} = {}\n", attr.name, attr.value);
+        }
+    }
+
+    fn remove_from_parent(&mut self, target: uint) {
+        out!(self, "Remove {:u} from parent\n", target);
+    }
+
+    fn reparent_children(&mut self, node: uint, new_parent: uint) {
+        out!(self, "Move children of {:u} to {:u}\n", node, new_parent);
+    }
+
+    fn mark_script_already_started(&mut self, node: uint) {
+        out!(self, "Mark script {:u} as already started\n", node);
+    }
+}
+
+/// A `TokenSink` which prints each token.  Runs of characters are
+/// collected onto a single line.
+///
+/// Call `finish` after the last token, to end any run of characters.
+pub struct TokenPrinter<W> {
+    out: W,
+    result: IoResult<()>,
+    in_char_run: bool,
+
+    /// Highlight tag and attribute names with ANSI escape codes?
+    pub color: bool,
+}
+
+impl<W: Writer> TokenPrinter<W> {
+    pub fn new(out: W) -> TokenPrinter<W> {
+        TokenPrinter {
+            out: out,
+            result: Ok(()),
+            in_char_run: false,
+            color: false,
+        }
+    }
+
+    /// End any run of characters, and get back the writer or the first
+    /// error we saw when writing to it.
+    pub fn finish(mut self) -> IoResult<W> {
+        self.is_char(false);
+        let TokenPrinter { out, result, .. } = self;
+        result.map(|_| out)
+    }
+
+    fn is_char(&mut self, is_char: bool) {
+        match (self.in_char_run, is_char) {
+            (false, true ) => out!(self, "CHAR : \""),
+            (true,  false) => out!(self, "\"\n"),
+            _ => (),
+        }
+        self.in_char_run = is_char;
+    }
+
+    fn do_char(&mut self, c: char) {
+        self.is_char(true);
+        let mut escaped = String::new();
+        char::escape_default(c, |d| escaped.push(d));
+        out!(self, "{:s}", escaped);
+    }
+
+    fn esc(&self, code: &'static str) -> &'static str {
+        if self.color { code } else { "" }
+    }
+}
+
+impl<W: Writer> TokenSink for TokenPrinter<W> {
+    fn process_token(&mut self, token: Token) {
+        match token {
+            CharacterTokens(b) => {
+                for c in b.as_slice().chars() {
+                    self.do_char(c);
+                }
+            }
+            NullCharacterToken => self.do_char('\0'),
+            TagToken(tag) => {
+                self.is_char(false);
+                let (reset, green, red) = (self.esc("\x1b[0m"),
+                    self.esc("\x1b[32m"), self.esc("\x1b[31m"));
+                let (cyan, blue) = (self.esc("\x1b[36m"), self.esc("\x1b[34m"));
+
+                // This is not proper HTML serialization, of course.
+                match tag.kind {
+                    StartTag => out!(self, "TAG  : <{:s}{:s}{:s}", green, tag.name.as_slice(), reset),
+                    EndTag   => out!(self, "TAG  : <{:s}/{:s}{:s}", red, tag.name.as_slice(), reset),
+                }
+                for attr in tag.attrs.iter() {
+                    out!(self, " {:s}{:s}{:s}='{:s}{:s}{:s}'",
+                        cyan, attr.name.local.as_slice(), reset,
+                        blue, attr.value, reset);
+                }
+                if tag.self_closing {
+                    out!(self, " {:s}/{:s}", red, reset);
+                }
+                out!(self, ">\n");
+            }
+            ParseError(err) => {
+                self.is_char(false);
+                out!(self, "ERROR: {:s}\n", err);
+            }
+            _ => {
+                self.is_char(false);
+                out!(self, "OTHER: {}\n", token);
+            }
+        }
+    }
+}
+
+/// A node handle from a `NumberingSink`: the wrapped sink's handle, plus
+/// a sequential id.
+#[deriving(Clone, Show)]
+pub struct Numbered<Handle> {
+    pub id: uint,
+    pub handle: Handle,
+}
+
+/// A tree modification, as recorded by `NumberingSink`.  Nodes are
+/// identified by their `Numbered::id`.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum TreeAction {
+    ReportedError(String),
+    SetQuirks(QuirksMode),
+    CreatedElement(uint, QualName, Vec<Attribute>),
+    CreatedComment(uint, String),
+    AppendedNode(uint, uint),
+    AppendedText(uint, String),
+    InsertedNodeBefore(uint, uint),
+    InsertedTextBefore(uint, String),
+    AppendedDoctype(String, String, String),
+    AddedAttrs(uint, Vec<Attribute>),
+    Removed(uint),
+    ReparentedChildren(uint, uint),
+    MarkedScriptStarted(uint),
+}
+
+/// A `TreeSink` which wraps another, numbering each node it creates and
+/// recording every tree modification.
+///
+/// Ids are assigned in order of creation, starting with `0` for the
+/// document, so they are stable from run to run.  This makes the
+/// recorded `actions` suitable for golden tests.
+pub struct NumberingSink<Handle, Sink> {
+    sink: Sink,
+    next_id: uint,
+    document: Option<Numbered<Handle>>,
+
+    /// Ids of template contents, by the id of their template.
+    template_contents: HashMap<uint, uint>,
+
+    /// Every tree modification so far, in order.
+    pub actions: Vec<TreeAction>,
+}
+
+impl<Handle: Clone, Sink: TreeSink<Handle>> NumberingSink<Handle, Sink> {
+    pub fn new(sink: Sink) -> NumberingSink<Handle, Sink> {
+        NumberingSink {
+            sink: sink,
+            next_id: 1,
+            document: None,
+            template_contents: HashMap::new(),
+            actions: vec!(),
+        }
+    }
+
+    /// Assign an id to a handle from the wrapped sink, e.g. for the
+    /// context element when parsing a fragment.
+    pub fn wrap(&mut self, handle: Handle) -> Numbered<Handle> {
+        let id = self.next_id;
+        self.next_id += 1;
+        Numbered {
+            id: id,
+            handle: handle,
+        }
+    }
+
+    /// Get back the wrapped sink and the recorded actions.
+    pub fn unwrap(self) -> (Sink, Vec<TreeAction>) {
+        (self.sink, self.actions)
+    }
+}
+
+fn unwrap_node_or_text<Handle>(child: NodeOrText<Numbered<Handle>>)
+        -> (NodeOrText<Handle>, Option<uint>) {
+    match child {
+        AppendNode(n) => (AppendNode(n.handle), Some(n.id)),
+        AppendText(t) => (AppendText(t), None),
+    }
+}
+
+impl<Handle: Clone, Sink: TreeSink<Handle>> TreeSink<Numbered<Handle>>
+        for NumberingSink<Handle, Sink> {
+    fn parse_error(&mut self, msg: MaybeOwned<'static>) {
+        self.actions.push(ReportedError(String::from_str(msg.as_slice())));
+        self.sink.parse_error(msg);
+    }
+
+    fn classified_parse_error(&mut self, kind: ParseErrorKind, msg: MaybeOwned<'static>) {
+        self.actions.push(ReportedError(String::from_str(msg.as_slice())));
+        self.sink.classified_parse_error(kind, msg);
+    }
+
+    fn get_document(&mut self) -> Numbered<Handle> {
+        match self.document {
+            Some(ref doc) => return doc.clone(),
+            None => (),
+        }
+        let doc = Numbered {
+            id: 0,
+            handle: self.sink.get_document(),
+        };
+        self.document = Some(doc.clone());
+        doc
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.actions.push(SetQuirks(mode));
+        self.sink.set_quirks_mode(mode);
+    }
+
+    fn query_quirks_override(&mut self, mode: QuirksMode) -> Option<QuirksMode> {
+        self.sink.query_quirks_override(mode)
+    }
+
+    fn same_node(&self, x: Numbered<Handle>, y: Numbered<Handle>) -> bool {
+        x.id == y.id
+    }
+
+    fn elem_name(&self, target: Numbered<Handle>) -> QualName {
+        self.sink.elem_name(target.handle)
+    }
+
+    fn get_template_contents(&mut self, target: Numbered<Handle>) -> Numbered<Handle> {
+        let handle = self.sink.get_template_contents(target.handle);
+        let known = self.template_contents.find(&target.id).map(|&id| id);
+        let id = match known {
+            Some(id) => id,
+            None => {
+                let id = self.wrap(handle.clone()).id;
+                self.template_contents.insert(target.id, id);
+                id
+            }
+        };
+        Numbered {
+            id: id,
+            handle: handle,
+        }
+    }
+
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Numbered<Handle> {
+        let handle = self.sink.create_element(name.clone(), attrs.clone());
+        let node = self.wrap(handle);
+        self.actions.push(CreatedElement(node.id, name, attrs));
+        node
+    }
+
+    fn create_comment(&mut self, text: String) -> Numbered<Handle> {
+        let handle = self.sink.create_comment(text.clone());
+        let node = self.wrap(handle);
+        self.actions.push(CreatedComment(node.id, text));
+        node
+    }
+
+    fn append(&mut self, parent: Numbered<Handle>, child: NodeOrText<Numbered<Handle>>) {
+        let (child, id) = unwrap_node_or_text(child);
+        self.actions.push(match (id, &child) {
+            (Some(id), _) => AppendedNode(parent.id, id),
+            (None, &AppendText(ref t)) => AppendedText(parent.id, t.clone()),
+            (None, &AppendNode(_)) => unreachable!(),
+        });
+        self.sink.append(parent.handle, child);
+    }
+
+    fn append_text_with_status(&mut self, parent: Numbered<Handle>, text: String,
+            status: SplitStatus) {
+        self.actions.push(AppendedText(parent.id, text.clone()));
+        self.sink.append_text_with_status(parent.handle, text, status);
+    }
+
+    fn append_before_sibling(&mut self,
+            sibling: Numbered<Handle>,
+            new_node: NodeOrText<Numbered<Handle>>)
+            -> Result<(), NodeOrText<Numbered<Handle>>> {
+        let (child, id) = unwrap_node_or_text(new_node);
+        let action = match (id, &child) {
+            (Some(id), _) => InsertedNodeBefore(sibling.id, id),
+            (None, &AppendText(ref t)) => InsertedTextBefore(sibling.id, t.clone()),
+            (None, &AppendNode(_)) => unreachable!(),
+        };
+        match self.sink.append_before_sibling(sibling.handle, child) {
+            Ok(()) => {
+                self.actions.push(action);
+                Ok(())
+            }
+            Err(AppendNode(handle)) => Err(AppendNode(Numbered {
+                id: id.expect("node became text"),
+                handle: handle,
+            })),
+            Err(AppendText(t)) => Err(AppendText(t)),
+        }
+    }
+
+    fn append_doctype_to_document(&mut self, name: String, public_id: String, system_id: String) {
+        self.actions.push(AppendedDoctype(name.clone(), public_id.clone(), system_id.clone()));
+        self.sink.append_doctype_to_document(name, public_id, system_id);
+    }
+
+    fn append_raw_doctype_to_document(&mut self, name: String, public_id: String,
+            system_id: String, raw: String) {
+        self.actions.push(AppendedDoctype(name.clone(), public_id.clone(), system_id.clone()));
+        self.sink.append_raw_doctype_to_document(name, public_id, system_id, raw);
+    }
+
+    fn add_attrs_if_missing(&mut self, target: Numbered<Handle>, attrs: Vec<Attribute>) {
+        self.actions.push(AddedAttrs(target.id, attrs.clone()));
+        self.sink.add_attrs_if_missing(target.handle, attrs);
+    }
+
+    fn remove_from_parent(&mut self, target: Numbered<Handle>) {
+        self.actions.push(Removed(target.id));
+        self.sink.remove_from_parent(target.handle);
+    }
+
+    fn reparent_children(&mut self, node: Numbered<Handle>, new_parent: Numbered<Handle>) {
+        self.actions.push(ReparentedChildren(node.id, new_parent.id));
+        self.sink.reparent_children(node.handle, new_parent.handle);
+    }
+
+    fn mark_script_already_started(&mut self, node: Numbered<Handle>) {
+        self.actions.push(MarkedScriptStarted(node.id));
+        self.sink.mark_script_already_started(node.handle);
+    }
+
+    fn complete_script(&mut self, node: Numbered<Handle>) -> ScriptAction {
+        self.sink.complete_script(node.handle)
+    }
+
+    fn element_provenance(&mut self, elem: Numbered<Handle>, provenance: ElemProvenance) {
+        self.sink.element_provenance(elem.handle, provenance);
+    }
+
+    fn step_taken(&mut self, mode: InsertionMode, token: &tree_builder::Token, outcome: StepOutcome) {
+        self.sink.step_taken(mode, token, outcome);
+    }
+
+    fn audit(&mut self, issue: AuditIssue<Numbered<Handle>>) {
+        self.sink.audit(match issue {
+            ImgWithoutAlt(n) => ImgWithoutAlt(n.handle),
+            UnlabeledLabel(n) => UnlabeledLabel(n.handle),
+            LabelForMissingId(n, id) => LabelForMissingId(n.handle, id),
+            DuplicateId(n, id) => DuplicateId(n.handle, id),
+        });
+    }
+
+    fn void_end_tag_ignored(&mut self, name: Atom) {
+        self.sink.void_end_tag_ignored(name);
+    }
+
+    fn aborted(&self) -> bool {
+        self.sink.aborted()
+    }
+}
+
+/// A misbehavior for `ScriptedSink` to inject.  Calls are counted from 0.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum Fault {
+    /// Fail the nth call to `append_before_sibling`, as if the sibling
+    /// had no parent, without passing it on to the wrapped sink.
+    FailAppendBeforeSibling(uint),
+
+    /// Start returning true from `aborted` after n calls which modify
+    /// the tree.
+    AbortAfter(uint),
+}
+
+/// A `TreeSink` which wraps another and injects scripted faults, for
+/// checking how the tree builder copes with the less common outcomes
+/// the `TreeSink` contract allows.
+pub struct ScriptedSink<Sink> {
+    sink: Sink,
+    faults: Vec<Fault>,
+    before_sibling_calls: uint,
+    modifications: uint,
+
+    /// Faults which have taken effect, in order.
+    pub injected: Vec<Fault>,
+}
+
+impl<Sink> ScriptedSink<Sink> {
+    pub fn new(sink: Sink, faults: Vec<Fault>) -> ScriptedSink<Sink> {
+        ScriptedSink {
+            sink: sink,
+            faults: faults,
+            before_sibling_calls: 0,
+            modifications: 0,
+            injected: vec!(),
+        }
+    }
+
+    /// Get back the wrapped sink.
+    pub fn unwrap(self) -> Sink {
+        self.sink
+    }
+
+    fn modified(&mut self) {
+        self.modifications += 1;
+        let n = self.modifications;
+        if self.faults.contains(&AbortAfter(n)) {
+            self.injected.push(AbortAfter(n));
+        }
+    }
+}
+
+impl<Handle, Sink: TreeSink<Handle>> TreeSink<Handle> for ScriptedSink<Sink> {
+    fn parse_error(&mut self, msg: MaybeOwned<'static>) {
+        self.sink.parse_error(msg);
+    }
+
+    fn classified_parse_error(&mut self, kind: ParseErrorKind, msg: MaybeOwned<'static>) {
+        self.sink.classified_parse_error(kind, msg);
+    }
+
+    fn get_document(&mut self) -> Handle {
+        self.sink.get_document()
+    }
+
+    fn same_node(&self, x: Handle, y: Handle) -> bool {
+        self.sink.same_node(x, y)
+    }
+
+    fn elem_name(&self, target: Handle) -> QualName {
+        self.sink.elem_name(target)
+    }
+
+    fn get_template_contents(&mut self, target: Handle) -> Handle {
+        self.sink.get_template_contents(target)
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.sink.set_quirks_mode(mode);
+    }
+
+    fn query_quirks_override(&mut self, mode: QuirksMode) -> Option<QuirksMode> {
+        self.sink.query_quirks_override(mode)
+    }
+
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle {
+        self.sink.create_element(name, attrs)
+    }
+
+    fn create_comment(&mut self, text: String) -> Handle {
+        self.sink.create_comment(text)
+    }
+
+    fn append(&mut self, parent: Handle, child: NodeOrText<Handle>) {
+        self.modified();
+        self.sink.append(parent, child);
+    }
+
+    fn append_text_with_status(&mut self, parent: Handle, text: String, status: SplitStatus) {
+        self.modified();
+        self.sink.append_text_with_status(parent, text, status);
+    }
+
+    fn append_before_sibling(&mut self,
+            sibling: Handle,
+            new_node: NodeOrText<Handle>) -> Result<(), NodeOrText<Handle>> {
+        let n = self.before_sibling_calls;
+        self.before_sibling_calls += 1;
+        if self.faults.contains(&FailAppendBeforeSibling(n)) {
+            self.injected.push(FailAppendBeforeSibling(n));
+            return Err(new_node);
+        }
+        self.modified();
+        self.sink.append_before_sibling(sibling, new_node)
+    }
+
+    fn append_doctype_to_document(&mut self, name: String, public_id: String, system_id: String) {
+        self.modified();
+        self.sink.append_doctype_to_document(name, public_id, system_id);
+    }
+
+    fn append_raw_doctype_to_document(&mut self, name: String, public_id: String,
+            system_id: String, raw: String) {
+        self.modified();
+        self.sink.append_raw_doctype_to_document(name, public_id, system_id, raw);
+    }
+
+    fn add_attrs_if_missing(&mut self, target: Handle, attrs: Vec<Attribute>) {
+        self.modified();
+        self.sink.add_attrs_if_missing(target, attrs);
+    }
+
+    fn remove_from_parent(&mut self, target: Handle) {
+        self.modified();
+        self.sink.remove_from_parent(target);
+    }
+
+    fn reparent_children(&mut self, node: Handle, new_parent: Handle) {
+        self.modified();
+        self.sink.reparent_children(node, new_parent);
+    }
+
+    fn mark_script_already_started(&mut self, node: Handle) {
+        self.sink.mark_script_already_started(node);
+    }
+
+    fn complete_script(&mut self, node: Handle) -> ScriptAction {
+        self.sink.complete_script(node)
+    }
+
+    fn element_provenance(&mut self, elem: Handle, provenance: ElemProvenance) {
+        self.sink.element_provenance(elem, provenance);
+    }
+
+    fn step_taken(&mut self, mode: InsertionMode, token: &tree_builder::Token, outcome: StepOutcome) {
+        self.sink.step_taken(mode, token, outcome);
+    }
+
+    fn audit(&mut self, issue: AuditIssue<Handle>) {
+        self.sink.audit(issue);
+    }
+
+    fn void_end_tag_ignored(&mut self, name: Atom) {
+        self.sink.void_end_tag_ignored(name);
+    }
+
+    fn aborted(&self) -> bool {
+        let n = self.modifications;
+        self.faults.iter().any(|f| match *f {
+            AbortAfter(limit) => n >= limit,
+            _ => false,
+        }) || self.sink.aborted()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{TokenPrinter, TreeActionsPrinter, NumberingSink};
+    use super::{CreatedElement, AppendedNode, AppendedText};
+    use super::{ScriptedSink, Fault, FailAppendBeforeSibling, AbortAfter};
+
+    use sink::rcdom::RcDom;
+    use serialize::serialize;
+
+    use driver::{tokenize_to, parse_to, one_input};
+
+    use core::default::Default;
+    use collections::string::String;
+    use std::io::MemWriter;
+
+    fn tokens(input: &str) -> String {
+        let mut sink = TokenPrinter::new(MemWriter::new());
+        tokenize_to(&mut sink, one_input(String::from_str(input)), Default::default());
+        let out = sink.finish().ok().expect("write failed").unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn token_printer() {
+        assert_eq!(tokens("<a href='x'>hi\n</a>").as_slice(),
+            "TAG  : <a href='x'>\nCHAR : \"hi\\n\"\nTAG  : </a>\nOTHER: EOFToken\n");
+    }
+
+    #[test]
+    fn tree_actions_printer() {
+        let mut sink = TreeActionsPrinter::new(MemWriter::new());
+        parse_to(&mut sink, one_input(String::from_str("<!DOCTYPE html>x")), Default::default());
+        let out = sink.into_inner().ok().expect("write failed").unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.as_slice().starts_with("Append doctype: html  \n"));
+        assert!(out.as_slice().contains("Append text to 3: \"x\"\n"));
+    }
+
+    #[test]
+    fn numbering_sink() {
+        let mut sink = NumberingSink::new(RcDom::default());
+        parse_to(&mut sink, one_input(String::from_str("<!DOCTYPE html>x")), Default::default());
+        let (_, actions) = sink.unwrap();
+        // actions[0] and [1] are the doctype and quirks mode.
+        assert_eq!(actions[2], CreatedElement(1, qualname!(HTML, html), vec!()));
+        assert_eq!(actions[3], AppendedNode(0, 1));
+        assert_eq!(actions.last(), Some(&AppendedText(3, String::from_str("x"))));
+    }
+
+    fn scripted(input: &str, faults: Vec<Fault>) -> (String, Vec<Fault>) {
+        let mut sink = ScriptedSink::new(RcDom::default(), faults);
+        parse_to(&mut sink, one_input(String::from_str(input)), Default::default());
+        let injected = sink.injected.clone();
+        let dom = sink.unwrap();
+        let mut out = MemWriter::new();
+        serialize(&mut out, &dom.document, Default::default()).ok().expect("write failed");
+        (String::from_utf8(out.unwrap()).unwrap(), injected)
+    }
+
+    #[test]
+    fn failed_foster_parenting() {
+        // With no parent for the table, foster-parented text goes at the
+        // end of the element before it.
+        let (out, injected) = scripted("<table>x</table>", vec!(FailAppendBeforeSibling(0)));
+        assert_eq!(out.as_slice(), "<html><head></head><body><table></table>x</body></html>");
+        assert_eq!(injected, vec!(FailAppendBeforeSibling(0)));
+    }
+
+    #[test]
+    fn scripted_abort() {
+        let (out, injected) = scripted("<p>a</p><p>b</p>", vec!(AbortAfter(4)));
+        assert_eq!(out.as_slice(), "<html><head></head><body><p></p></body></html>");
+        assert_eq!(injected, vec!(AbortAfter(4)));
+    }
+}
@@ -12,17 +12,117 @@
 use core::prelude::*;
 
 use tokenizer::{TokenizerOpts, Tokenizer, TokenSink};
+use tokenizer::{SecurityHooks, SecurityScanner};
 use tree_builder::{TreeBuilderOpts, TreeBuilder, TreeSink};
+use decoder::{Decoder, Utf8Decoder, DecodeError};
 
+use core::cmp;
+use core::mem;
 use core::default::Default;
 use core::option;
+use alloc::boxed::Box;
+use collections::{Deque, RingBuf, Map, MutableMap};
+use collections::treemap::TreeMap;
+use collections::vec::Vec;
 use collections::string::String;
+use collections::str::Slice;
+
+use string_cache::QualName;
 
 /// Convenience function to turn a single `String` into an iterator.
 pub fn one_input(x: String) -> option::Item<String> {
     Some(x).into_iter()
 }
 
+/// Input for the parser from a byte slice holding UTF-8, such as a
+/// memory-mapped file.
+///
+/// The bytes are decoded a chunk at a time, so memory use stays the same
+/// however large the input is.  Invalid UTF-8 is replaced with U+FFFD.
+///
+/// FIXME: This isn't zero-copy.  Each chunk is copied into a `String`,
+/// since the tokenizer only takes owned input.  Feeding it slices of
+/// `bytes` needs a tokenizer input queue which can borrow.
+///
+/// ## Example
+///
+/// ```rust
+/// tokenize_to(&mut sink, utf8_input(mapped_bytes), Default::default());
+/// ```
+pub fn utf8_input<'a>(bytes: &'a [u8]) -> Utf8Input<'a> {
+    decoded_input(bytes, Utf8Decoder::new())
+}
+
+/// Iterator returned by `utf8_input`.
+pub type Utf8Input<'a> = DecodedInput<'a, Utf8Decoder>;
+
+/// Input for the parser from a byte slice in any encoding, which
+/// `decoder` understands.
+///
+/// Like `utf8_input`, this decodes a chunk at a time.  Malformed input is
+/// replaced with U+FFFD, and recorded so that it can be reported after
+/// parsing:
+///
+/// ```rust
+/// let mut input = decoded_input(bytes, MyShiftJisDecoder::new());
+/// let dom: RcDom = parse(input.by_ref(), Default::default());
+/// for err in input.errors().iter() { ... }
+/// ```
+pub fn decoded_input<'a, D: Decoder>(bytes: &'a [u8], decoder: D) -> DecodedInput<'a, D> {
+    DecodedInput {
+        bytes: bytes,
+        pos: 0,
+        decoder: decoder,
+        errors: vec!(),
+        finished: false,
+    }
+}
+
+/// Iterator returned by `decoded_input`.
+pub struct DecodedInput<'a, D> {
+    bytes: &'a [u8],
+    pos: uint,
+    decoder: D,
+    errors: Vec<DecodeError>,
+
+    /// Have we called `Decoder::finish`?
+    finished: bool,
+}
+
+impl<'a, D> DecodedInput<'a, D> {
+    /// Malformed input found so far.
+    pub fn errors<'b>(&'b self) -> &'b [DecodeError] {
+        self.errors.as_slice()
+    }
+}
+
+/// Roughly how many bytes `DecodedInput` decodes at once.
+static INPUT_CHUNK_SIZE: uint = 64 * 1024;
+
+impl<'a, D: Decoder> Iterator<String> for DecodedInput<'a, D> {
+    fn next(&mut self) -> Option<String> {
+        // A chunk may decode to nothing, if it ends partway through a
+        // character, so go on until there's some text.
+        while !self.finished {
+            let mut out = String::new();
+            let rest = self.bytes.slice_from(self.pos);
+            if rest.is_empty() {
+                self.decoder.finish(&mut out, &mut self.errors);
+                self.finished = true;
+            } else {
+                let end = cmp::min(INPUT_CHUNK_SIZE, rest.len());
+                self.decoder.decode(rest.slice_to(end), &mut out, &mut self.errors);
+                self.pos += end;
+            }
+
+            if !out.is_empty() {
+                return Some(out);
+            }
+        }
+        None
+    }
+}
+
 /// Tokenize and send results to a `TokenSink`.
 ///
 /// ## Example
@@ -54,10 +154,82 @@ pub struct ParseOpts {
 
     /// Tree builder options.
     pub tree_builder: TreeBuilderOpts,
+
+    /// Stop parsing after about this many milliseconds, and finish the
+    /// tree with whatever input has been parsed so far.  The clock is
+    /// checked between chunks of input, so parsing may overrun slightly.
+    /// Default: None (no limit)
+    pub max_parse_millis: Option<u64>,
+
+    /// How far ahead of the next expected chunk `Parser::feed_sequenced`
+    /// accepts a chunk, holding it until the gap is filled.  Default: 0
+    /// (chunks must arrive in order)
+    pub reorder_window: uint,
+}
+
+/// Whether parsing got through all of the input.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum ParseStatus {
+    /// All input was parsed.
+    ParseFinished,
+
+    /// `ParseOpts::max_parse_millis` ran out.  The tree holds only the
+    /// start of the document, and the sink was sent a parse error saying
+    /// so.
+    ParseTimedOut,
+
+    /// The sink stopped parsing, by returning true from `aborted`.
+    ParseAborted,
+}
+
+// Feed input to the tokenizer, stopping early if the deadline passes.
+fn feed_until<'sink, Sink: TokenSink, It: Iterator<String>>(
+        tok: &mut Tokenizer<'sink, Sink>,
+        mut input: It,
+        max_millis: Option<u64>) -> ParseStatus {
+
+    let max_millis = match max_millis {
+        None => {
+            for s in input {
+                tok.feed(s);
+                if tok.is_aborted() {
+                    return ParseAborted;
+                }
+            }
+            return ParseFinished;
+        }
+        Some(m) => m,
+    };
+
+    let deadline = ::time::precise_time_ns() + max_millis * 1000000;
+    for s in input {
+        let buf = s.as_slice();
+        let mut pos = 0;
+        while pos < buf.len() {
+            if ::time::precise_time_ns() >= deadline {
+                return ParseTimedOut;
+            }
+            let mut end = cmp::min(pos + PUMP_CHUNK_SIZE, buf.len());
+            while !buf.is_char_boundary(end) {
+                end += 1;
+            }
+            tok.feed(String::from_str(buf.slice(pos, end)));
+            if tok.is_aborted() {
+                return ParseAborted;
+            }
+            pos = end;
+        }
+    }
+    ParseFinished
 }
 
+static TIMEOUT_ERROR: &'static str = "Parse time limit exceeded; document truncated";
+
 /// Parse and send results to a `TreeSink`.
 ///
+/// Returns `ParseTimedOut` if `opts.max_parse_millis` ran out, or
+/// `ParseAborted` if the sink stopped parsing.
+///
 /// ## Example
 ///
 /// ```rust
@@ -70,15 +242,136 @@ pub fn parse_to<
         It: Iterator<String>
     >(
         sink: &mut Sink,
-        mut input: It,
-        opts: ParseOpts) {
+        input: It,
+        opts: ParseOpts) -> ParseStatus {
 
-    let mut tb  = TreeBuilder::new(sink, opts.tree_builder);
-    let mut tok = Tokenizer::new(&mut tb, opts.tokenizer);
-    for s in input {
-        tok.feed(s);
+    let status = {
+        let mut tb  = TreeBuilder::new(&mut *sink, opts.tree_builder);
+        let mut tok = Tokenizer::new(&mut tb, opts.tokenizer);
+        let status = feed_until(&mut tok, input, opts.max_parse_millis);
+        tok.end();
+        status
+    };
+    if status == ParseTimedOut {
+        sink.parse_error(Slice(TIMEOUT_ERROR));
     }
-    tok.end();
+    status
+}
+
+/// Parse and send results to a `TreeSink`, passing each token by a
+/// `SecurityHooks` first.  Lines are tracked, whatever `opts` says.
+///
+/// Returns `ParseAborted` if a hook rejected the document.  The tree
+/// then holds only what came before the rejected token.
+pub fn parse_scanned_to<
+        Handle: Clone,
+        Sink: TreeSink<Handle>,
+        Hooks: SecurityHooks,
+        It: Iterator<String>
+    >(
+        sink: &mut Sink,
+        hooks: &mut Hooks,
+        input: It,
+        opts: ParseOpts) -> ParseStatus {
+
+    let status = {
+        let tb = TreeBuilder::new(&mut *sink, opts.tree_builder);
+        let mut scanner = SecurityScanner::new(tb, hooks);
+        let tok_opts = TokenizerOpts {
+            track_lines: true,
+            .. opts.tokenizer
+        };
+        let mut tok = Tokenizer::new(&mut scanner, tok_opts);
+        let status = feed_until(&mut tok, input, opts.max_parse_millis);
+        tok.end();
+        status
+    };
+    if status == ParseTimedOut {
+        sink.parse_error(Slice(TIMEOUT_ERROR));
+    }
+    status
+}
+
+/// Parse an HTML fragment and send results to a `TreeSink`.
+///
+/// The input is parsed as the contents of `context_elem`, as for
+/// `innerHTML`.  `form_elem` is the nearest `<form>` ancestor of the
+/// context element, if any.  The resulting nodes are children of a
+/// new root `<html>` element.
+///
+/// ## Example
+///
+/// ```rust
+/// let mut sink = MySink;
+/// let context = sink.create_element(qualname!(HTML, body), vec!());
+/// parse_fragment_to(&mut sink, one_input(my_str), context, None, Default::default());
+/// ```
+pub fn parse_fragment_to<
+        Handle: Clone,
+        Sink: TreeSink<Handle>,
+        It: Iterator<String>
+    >(
+        sink: &mut Sink,
+        input: It,
+        context_elem: Handle,
+        form_elem: Option<Handle>,
+        opts: ParseOpts) -> ParseStatus {
+
+    let status = {
+        let mut tb = TreeBuilder::new_for_fragment(&mut *sink, context_elem, form_elem,
+            opts.tree_builder);
+        let tok_opts = TokenizerOpts {
+            initial_state: Some(tb.tokenizer_state_for_context_elem()),
+            .. opts.tokenizer
+        };
+        let mut tok = Tokenizer::new(&mut tb, tok_opts);
+        let status = feed_until(&mut tok, input, opts.max_parse_millis);
+        tok.end();
+        status
+    };
+    if status == ParseTimedOut {
+        sink.parse_error(Slice(TIMEOUT_ERROR));
+    }
+    status
+}
+
+/// Parse the `srcdoc` attribute of an `<iframe>` as a document, and send
+/// results to a `TreeSink`.
+///
+/// This sets `TreeBuilderOpts::iframe_srcdoc`, as the spec requires for
+/// such documents: a missing DOCTYPE is not a parse error, and the
+/// document is never in quirks or limited-quirks mode, whatever DOCTYPE
+/// it has.  `TreeBuilderOpts::fragment` is turned off, since a `srcdoc`
+/// is a whole document.
+///
+/// To parse a fragment in the context of an element inside a `srcdoc`
+/// document, use `parse_fragment_to` as usual.  Fragments are always
+/// parsed in no-quirks mode, which is the mode such a document has, so
+/// `iframe_srcdoc` makes no difference there.
+///
+/// ## Example
+///
+/// ```rust
+/// let mut sink = MySink;
+/// parse_iframe_srcdoc_to(&mut sink, srcdoc_value, Default::default());
+/// ```
+pub fn parse_iframe_srcdoc_to<
+        Handle: Clone,
+        Sink: TreeSink<Handle>
+    >(
+        sink: &mut Sink,
+        srcdoc: String,
+        opts: ParseOpts) -> ParseStatus {
+
+    let opts = ParseOpts {
+        tree_builder: TreeBuilderOpts {
+            iframe_srcdoc: true,
+            fragment: false,
+            .. opts.tree_builder
+        },
+        .. opts
+    };
+    parse_to(sink, one_input(srcdoc), opts)
 }
 
 /// Results which can be extracted from a `TreeSink`.
@@ -91,6 +384,9 @@ pub trait ParseResult<Sink> {
 
 /// Parse into a type which implements `ParseResult`.
 ///
+/// If parsing may stop early, because of `ParseOpts::max_parse_millis`
+/// or a sink which aborts, use `parse_with_status` to find out.
+///
 /// ## Example
 ///
 /// ```rust
@@ -105,7 +401,595 @@ pub fn parse<
         input: It,
         opts: ParseOpts) -> Output {
 
+    let (output, _) = parse_with_status(input, opts);
+    output
+}
+
+/// Like `parse`, but also say whether parsing got through all of the
+/// input.
+///
+/// ## Example
+///
+/// ```rust
+/// let (dom, status): (RcDom, ParseStatus) = parse_with_status(one_input(my_str), opts);
+/// if status == ParseTimedOut { ... }
+/// ```
+pub fn parse_with_status<
+        Handle: Clone,
+        Sink: Default + TreeSink<Handle>,
+        Output: ParseResult<Sink>,
+        It: Iterator<String>
+    >(
+        input: It,
+        opts: ParseOpts) -> (Output, ParseStatus) {
+
+    let mut sink: Sink = Default::default();
+    let status = parse_to(&mut sink, input, opts);
+    (ParseResult::get_result(sink), status)
+}
+
+/// Parse a fragment into a type which implements `ParseResult`.
+///
+/// The context element is created by the sink, with no attributes and no
+/// `<form>` ancestor.
+///
+/// ## Example
+///
+/// ```rust
+/// let dom: RcDom = parse_fragment(one_input(my_str), qualname!(HTML, body), Default::default());
+/// ```
+pub fn parse_fragment<
+        Handle: Clone,
+        Sink: Default + TreeSink<Handle>,
+        Output: ParseResult<Sink>,
+        It: Iterator<String>
+    >(
+        input: It,
+        context: QualName,
+        opts: ParseOpts) -> Output {
+
+    let (output, _) = parse_fragment_with_status(input, context, opts);
+    output
+}
+
+/// Like `parse_fragment`, but also say whether parsing got through all
+/// of the input.
+pub fn parse_fragment_with_status<
+        Handle: Clone,
+        Sink: Default + TreeSink<Handle>,
+        Output: ParseResult<Sink>,
+        It: Iterator<String>
+    >(
+        input: It,
+        context: QualName,
+        opts: ParseOpts) -> (Output, ParseStatus) {
+
+    let mut sink: Sink = Default::default();
+    let context_elem = sink.create_element(context, vec!());
+    let status = parse_fragment_to(&mut sink, input, context_elem, None, opts);
+    (ParseResult::get_result(sink), status)
+}
+
+/// Parse an `<iframe>`'s `srcdoc` into a type which implements
+/// `ParseResult`.  See `parse_iframe_srcdoc_to`.
+///
+/// ## Example
+///
+/// ```rust
+/// let dom: RcDom = parse_iframe_srcdoc(srcdoc_value, Default::default());
+/// ```
+pub fn parse_iframe_srcdoc<
+        Handle: Clone,
+        Sink: Default + TreeSink<Handle>,
+        Output: ParseResult<Sink>
+    >(
+        srcdoc: String,
+        opts: ParseOpts) -> Output {
+
     let mut sink: Sink = Default::default();
-    parse_to(&mut sink, input, opts);
+    parse_iframe_srcdoc_to(&mut sink, srcdoc, opts);
     ParseResult::get_result(sink)
 }
+
+/// A parser which takes input as it arrives, e.g. from the network, and
+/// owns the sink it builds a tree in.
+///
+/// `ParseOpts::max_parse_millis` is ignored, since the caller decides
+/// when input is fed.
+///
+/// ## Example
+///
+/// ```rust
+/// let sink: RcDom = Default::default();
+/// let mut parser = Parser::new(sink, Default::default());
+/// for chunk in response.chunks() {
+///     parser.feed(chunk);
+/// }
+/// let dom: RcDom = parser.finish();
+/// ```
+pub struct Parser<Handle, Sink: 'static> {
+    // The tokenizer points into the tree builder, which points into the
+    // sink.  Each is boxed so that it doesn't move, and they're declared
+    // in this order so that they're dropped in this order.
+    tok: Box<Tokenizer<'static, TreeBuilder<'static, Handle, Sink>>>,
+    tb: Box<TreeBuilder<'static, Handle, Sink>>,
+    sink: Box<Sink>,
+
+    /// The number of the next chunk `feed_sequenced` will parse.
+    next_seq: u64,
+
+    /// Chunks which arrived before that one, by number.
+    held: TreeMap<u64, String>,
+
+    reorder_window: uint,
+}
+
+impl<Handle: Clone, Sink: TreeSink<Handle>> Parser<Handle, Sink> {
+    pub fn new(sink: Sink, opts: ParseOpts) -> Parser<Handle, Sink> {
+        let mut sink = box sink;
+        // The borrows live as long as the boxes, which `Parser` keeps
+        // together until `finish` drops them in order.
+        let sink_ref: &'static mut Sink = unsafe { mem::transmute(&mut *sink) };
+        let mut tb = box TreeBuilder::new(sink_ref, opts.tree_builder);
+        let tb_ref: &'static mut TreeBuilder<'static, Handle, Sink>
+            = unsafe { mem::transmute(&mut *tb) };
+        Parser {
+            tok: box Tokenizer::new(tb_ref, opts.tokenizer),
+            tb: tb,
+            sink: sink,
+            next_seq: 0,
+            held: TreeMap::new(),
+            reorder_window: opts.reorder_window,
+        }
+    }
+
+    /// Parse some more input.  It may end anywhere, even in the middle of
+    /// a tag.
+    pub fn feed(&mut self, input: &str) {
+        if !input.is_empty() {
+            self.tok.feed(String::from_str(input));
+        }
+    }
+
+    /// Parse the chunk numbered `seq`, counting from 0.  A chunk which
+    /// arrives early, by up to `ParseOpts::reorder_window`, is held until
+    /// the chunks before it have been parsed.
+    ///
+    /// Fails on a chunk which was already fed, or which is too far ahead,
+    /// rather than parse the document out of order.  Don't mix this with
+    /// `feed`.
+    pub fn feed_sequenced(&mut self, seq: u64, input: &str) {
+        if seq < self.next_seq || self.held.contains_key(&seq) {
+            fail!("chunk {} was fed twice", seq);
+        }
+        if seq - self.next_seq > self.reorder_window as u64 {
+            fail!("chunk {} arrived too far ahead of chunk {}", seq, self.next_seq);
+        }
+        if seq > self.next_seq {
+            self.held.insert(seq, String::from_str(input));
+            return;
+        }
+
+        self.feed(input);
+        self.next_seq += 1;
+        loop {
+            let next = self.next_seq;
+            match self.held.pop(&next) {
+                Some(chunk) => self.feed(chunk.as_slice()),
+                None => break,
+            }
+            self.next_seq += 1;
+        }
+    }
+
+    /// Did the sink stop parsing, by returning true from `aborted`?
+    /// Further input is ignored if so.
+    pub fn is_aborted(&self) -> bool {
+        self.tok.is_aborted()
+    }
+
+    /// Stop parsing until `resume`.  Input fed meanwhile is kept.
+    pub fn pause(&mut self) {
+        self.tok.pause();
+    }
+
+    /// Is parsing paused, by `pause` or because the sink returned `Pause`
+    /// from `complete_script`?
+    pub fn is_paused(&self) -> bool {
+        self.tok.is_paused()
+    }
+
+    /// Insert input at the insertion point, as for `document.write` from
+    /// a script: after the `</script>` which paused parsing, and after
+    /// anything written before.
+    pub fn write(&mut self, input: &str) {
+        if !input.is_empty() {
+            self.tok.insert_at_current_position(String::from_str(input));
+        }
+    }
+
+    /// Carry on parsing, after `pause` or once the script which paused
+    /// parsing has finished.  This parses what the script wrote, then the
+    /// input fed meanwhile, until something pauses parsing again.
+    pub fn resume(&mut self) {
+        self.tok.sink_mut().script_completed();
+        self.tok.resume();
+    }
+
+    /// Signal the end of input, and get the result from the sink.
+    ///
+    /// Fails if `feed_sequenced` is still holding chunks, since one
+    /// before them never arrived.
+    pub fn finish<Output: ParseResult<Sink>>(mut self) -> Output {
+        if !self.held.is_empty() {
+            fail!("chunk {} never arrived", self.next_seq);
+        }
+        self.tok.end();
+        let Parser { tok, tb, sink, .. } = self;
+        drop(tok);
+        drop(tb);
+        ParseResult::get_result(*sink)
+    }
+}
+
+/// The result of `PumpParser::pump`.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum PumpStatus {
+    /// All input fed so far has been parsed, but `end` hasn't been called.
+    NeedsInput,
+
+    /// The time limit was reached with input still left to parse.
+    Working,
+
+    /// Parsing is finished, or the sink aborted it.
+    Complete,
+}
+
+/// How much input to hand the tokenizer between checks of the clock.
+static PUMP_CHUNK_SIZE: uint = 4096;
+
+/// A parser which does a bounded amount of work each time it is pumped,
+/// e.g. from a GUI event loop.
+///
+/// All parser state is kept between calls to `pump`.  The sink is usually
+/// a `TreeBuilder`.
+///
+/// ## Example
+///
+/// ```rust
+/// let mut tb = TreeBuilder::new(&mut sink, Default::default());
+/// let mut parser = PumpParser::new(&mut tb, Default::default());
+/// parser.feed(my_str);
+/// parser.end();
+/// while parser.pump(10) != Complete {
+///     handle_ui_events();
+/// }
+/// ```
+pub struct PumpParser<'sink, Sink:'sink> {
+    tok: Tokenizer<'sink, Sink>,
+
+    /// Input not yet given to the tokenizer.
+    input: RingBuf<String>,
+
+    /// How far into the front buffer of `input` we've got.
+    pos: uint,
+
+    /// Has `end` been called?
+    input_ended: bool,
+
+    /// Has the tokenizer seen EOF?
+    complete: bool,
+}
+
+impl<'sink, Sink: TokenSink> PumpParser<'sink, Sink> {
+    pub fn new(sink: &'sink mut Sink, opts: TokenizerOpts) -> PumpParser<'sink, Sink> {
+        PumpParser {
+            tok: Tokenizer::new(sink, opts),
+            input: RingBuf::new(),
+            pos: 0,
+            input_ended: false,
+            complete: false,
+        }
+    }
+
+    /// Queue more input.  No parsing happens until the next `pump`.
+    pub fn feed(&mut self, input: String) {
+        assert!(!self.input_ended, "input after end()");
+        if !input.is_empty() {
+            self.input.push(input);
+        }
+    }
+
+    /// Signal that there is no more input.
+    pub fn end(&mut self) {
+        self.input_ended = true;
+    }
+
+    /// Parse for up to about `max_millis` milliseconds.
+    ///
+    /// Input is handed to the tokenizer in small chunks and the clock is
+    /// checked between them, so a single pump may overrun slightly.
+    pub fn pump(&mut self, max_millis: u64) -> PumpStatus {
+        if self.complete {
+            return Complete;
+        }
+
+        let deadline = ::time::precise_time_ns() + max_millis * 1000000;
+        loop {
+            let chunk = match self.input.front() {
+                None => {
+                    if !self.input_ended {
+                        return NeedsInput;
+                    }
+                    self.tok.end();
+                    self.complete = true;
+                    return Complete;
+                }
+                Some(buf) => {
+                    let buf = buf.as_slice();
+                    let mut end = cmp::min(self.pos + PUMP_CHUNK_SIZE, buf.len());
+                    while !buf.is_char_boundary(end) {
+                        end += 1;
+                    }
+                    let chunk = String::from_str(buf.slice(self.pos, end));
+                    self.pos = end;
+                    if end == buf.len() {
+                        self.pos = 0;
+                    }
+                    chunk
+                }
+            };
+            if self.pos == 0 {
+                self.input.pop_front();
+            }
+
+            self.tok.feed(chunk);
+            if self.tok.is_aborted() {
+                self.complete = true;
+                return Complete;
+            }
+
+            if ::time::precise_time_ns() >= deadline {
+                return if self.input.is_empty() && !self.input_ended {
+                    NeedsInput
+                } else {
+                    Working
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{utf8_input, decoded_input, parse, one_input, parse_iframe_srcdoc};
+    use super::{parse_with_status, parse_fragment_with_status, TIMEOUT_ERROR};
+    use super::{ParseStatus, ParseFinished, ParseTimedOut};
+    use super::{Parser, ParseOpts, INPUT_CHUNK_SIZE};
+    use super::{PumpParser, NeedsInput, Working, Complete};
+
+    use decoder::{Decoder, DecodeError};
+    use serialize::serialize;
+    use sink::rcdom::RcDom;
+    use tree_builder::{TreeBuilder, Quirks, NoQuirks};
+
+    use core::default::Default;
+
+    use collections::MutableSeq;
+    use collections::vec::Vec;
+    use collections::string::String;
+    use collections::str::Slice;
+    use std::io::MemWriter;
+
+    fn to_html(dom: &RcDom) -> String {
+        let mut out = MemWriter::new();
+        serialize(&mut out, dom, Default::default()).ok().expect("write failed");
+        String::from_utf8(out.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn utf8_input_keeps_chars_whole() {
+        // Put a three-byte character across the chunk boundary.
+        let mut input = String::from_char(INPUT_CHUNK_SIZE - 1, 'x');
+        input.push_str("☃y");
+        let chunks: Vec<String> = utf8_input(input.as_bytes()).collect();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].len(), INPUT_CHUNK_SIZE - 1);
+        assert_eq!(chunks[1].as_slice(), "☃y");
+    }
+
+    #[test]
+    fn utf8_input_invalid() {
+        let chunks: Vec<String> = utf8_input(b"a\xffb").collect();
+        assert_eq!(chunks, vec!(String::from_str("a�b")));
+    }
+
+    /// ASCII, with anything else rejected.
+    struct AsciiDecoder {
+        offset: uint,
+    }
+
+    impl Decoder for AsciiDecoder {
+        fn decode(&mut self, input: &[u8], output: &mut String, errors: &mut Vec<DecodeError>) {
+            for (i, &b) in input.iter().enumerate() {
+                if b < 0x80 {
+                    output.push(b as char);
+                } else {
+                    output.push('\ufffd');
+                    errors.push(DecodeError {
+                        position: self.offset + i,
+                        message: Slice("Not ASCII"),
+                    });
+                }
+            }
+            self.offset += input.len();
+        }
+
+        fn finish(&mut self, _output: &mut String, _errors: &mut Vec<DecodeError>) { }
+    }
+
+    #[test]
+    fn custom_decoder() {
+        let mut input = decoded_input(b"a\xe9b", AsciiDecoder { offset: 0 });
+        let chunks: Vec<String> = input.by_ref().collect();
+        assert_eq!(chunks, vec!(String::from_str("a\ufffdb")));
+        let expected = [DecodeError { position: 1, message: Slice("Not ASCII") }];
+        assert_eq!(input.errors(), expected.as_slice());
+    }
+
+    #[test]
+    fn iframe_srcdoc() {
+        let input = "<p>hi";
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        assert_eq!(dom.quirks_mode, Quirks);
+        assert_eq!(dom.errors.len(), 1);
+
+        let dom: RcDom = parse_iframe_srcdoc(String::from_str(input), Default::default());
+        assert_eq!(dom.quirks_mode, NoQuirks);
+        assert!(dom.errors.is_empty());
+
+        // Even a DOCTYPE which asks for quirks doesn't get them.
+        let dom: RcDom = parse_iframe_srcdoc(
+            String::from_str("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 3.2//EN\">"),
+            Default::default());
+        assert_eq!(dom.quirks_mode, NoQuirks);
+    }
+
+    #[test]
+    fn incremental() {
+        let chunks = ["<!DOCTYPE html><p cl", "ass=x>a", "b</p><!-", "-c--><textarea>\n", "d"];
+
+        let sink: RcDom = Default::default();
+        let mut parser = Parser::new(sink, Default::default());
+        for chunk in chunks.iter() {
+            parser.feed(*chunk);
+        }
+        assert!(!parser.is_aborted());
+        let dom: RcDom = parser.finish();
+
+        let whole: RcDom = parse(one_input(chunks.as_slice().concat()), Default::default());
+        assert_eq!(to_html(&dom), to_html(&whole));
+        assert_eq!(dom.errors.len(), whole.errors.len());
+    }
+
+    fn parse_sequenced(chunks: &[(u64, &str)], window: uint) -> String {
+        let sink: RcDom = Default::default();
+        let mut parser = Parser::new(sink, ParseOpts {
+            reorder_window: window,
+            .. Default::default()
+        });
+        for &(seq, chunk) in chunks.iter() {
+            parser.feed_sequenced(seq, chunk);
+        }
+        let dom: RcDom = parser.finish();
+        to_html(&dom)
+    }
+
+    #[test]
+    fn sequenced() {
+        let in_order = parse_sequenced(&[(0, "<p>a"), (1, "<b>b"), (2, "c</b>"), (3, "d")], 0);
+        assert_eq!(in_order.as_slice(), "<html><head></head><body><p>a<b>bc</b>d</p></body></html>");
+        assert_eq!(parse_sequenced(&[(2, "c</b>"), (1, "<b>b"), (3, "d"), (0, "<p>a")], 3),
+            in_order);
+    }
+
+    #[test]
+    #[should_fail]
+    fn sequenced_outside_window() {
+        parse_sequenced(&[(2, "c</b>"), (0, "<p>a"), (1, "<b>b")], 1);
+    }
+
+    #[test]
+    #[should_fail]
+    fn sequenced_twice() {
+        parse_sequenced(&[(0, "<p>a"), (0, "<p>a")], 4);
+    }
+
+    #[test]
+    #[should_fail]
+    fn sequenced_gap() {
+        parse_sequenced(&[(0, "<p>a"), (2, "c</b>")], 4);
+    }
+
+    #[test]
+    fn pause_and_write() {
+        let sink: RcDom = Default::default();
+        let mut parser = Parser::new(sink, Default::default());
+        parser.feed("<!DOCTYPE html><p>a");
+        parser.pause();
+        assert!(parser.is_paused());
+
+        // Written input goes before input fed while paused.
+        parser.feed("b</p>");
+        parser.write("<i>x</i>");
+        parser.resume();
+        assert!(!parser.is_paused());
+
+        let dom: RcDom = parser.finish();
+        assert_eq!(to_html(&dom).as_slice(),
+            "<!DOCTYPE html><html><head></head><body><p>a<i>x</i>b</p></body></html>");
+    }
+
+    #[test]
+    fn pump_parser() {
+        let mut input = String::from_str("<!DOCTYPE html><table>");
+        for i in range(0u, 1000) {
+            input.push_str(format!("<tr><td>{}<b>x", i).as_slice());
+        }
+        let pieces = [input.as_slice().slice_to(5000), input.as_slice().slice(5000, 5001),
+            input.as_slice().slice_from(5001)];
+
+        let mut sink: RcDom = Default::default();
+        {
+            let mut tb = TreeBuilder::new(&mut sink, Default::default());
+            let mut parser = PumpParser::new(&mut tb, Default::default());
+            let mut working = 0u;
+            for piece in pieces.iter() {
+                parser.feed(String::from_str(*piece));
+                loop {
+                    match parser.pump(0) {
+                        Working => working += 1,
+                        NeedsInput => break,
+                        Complete => fail!("complete before end()"),
+                    }
+                }
+            }
+            // Pumping with no time at all still makes progress.
+            assert!(working > 0);
+
+            parser.end();
+            assert_eq!(parser.pump(0), Complete);
+            assert_eq!(parser.pump(0), Complete);
+        }
+
+        let whole: RcDom = parse(one_input(input.clone()), Default::default());
+        assert_eq!(to_html(&sink), to_html(&whole));
+        assert_eq!(sink.errors.len(), whole.errors.len());
+    }
+
+    #[test]
+    fn max_parse_millis() {
+        let input = "<!DOCTYPE html><p>a";
+        let (dom, status): (RcDom, ParseStatus) = parse_with_status(
+            one_input(String::from_str(input)), Default::default());
+        assert_eq!(status, ParseFinished);
+        assert!(dom.errors.is_empty());
+
+        // With no time at all, nothing is parsed, but the tree is still
+        // finished.
+        let opts = ParseOpts {
+            max_parse_millis: Some(0),
+            .. Default::default()
+        };
+        let (dom, status): (RcDom, ParseStatus) = parse_with_status(
+            one_input(String::from_str(input)), opts.clone());
+        assert_eq!(status, ParseTimedOut);
+        assert_eq!(to_html(&dom).as_slice(), "<html><head></head><body></body></html>");
+        assert_eq!(dom.errors.last().map(|e| e.as_slice()), Some(TIMEOUT_ERROR));
+
+        let (_, status): (RcDom, ParseStatus) = parse_fragment_with_status(
+            one_input(String::from_str(input)), qualname!(HTML, body), opts);
+        assert_eq!(status, ParseTimedOut);
+    }
+}
@@ -15,7 +15,11 @@ use core::kinds::marker::ContravariantLifetime;
 use collections::str::MaybeOwned;
 use collections::string::String;
 
+use alloc::boxed::Box;
 use libc::{size_t, c_int, c_char, strlen};
+use rustrt::local::Local;
+use rustrt::task::Task;
+use rustrt::unwind;
 
 use string_cache::Atom;
 
@@ -37,6 +41,10 @@ impl h5e_buf {
         buf_as_slice(self.data, self.len as uint,
             |bytes| f(from_utf8(bytes)))
     }
+
+    pub unsafe fn with_bytes<R>(&self, f: |&[u8]| -> R) -> R {
+        buf_as_slice(self.data, self.len as uint, f)
+    }
 }
 
 pub struct LifetimeBuf<'a> {
@@ -106,3 +114,30 @@ pub fn c_bool(x: bool) -> c_int {
         true => 1,
     }
 }
+
+/// Status codes returned by entry points which can fail.
+pub static H5E_OK: c_int = 0;
+
+/// The library failed internally.  The handle is now unusable.
+pub static H5E_FAILED: c_int = -1;
+
+/// The handle is unusable, because of an earlier `H5E_FAILED`.
+pub static H5E_POISONED: c_int = -2;
+
+/// Run `f`, stopping any failure from unwinding into C.  Returns false
+/// if `f` failed.  Every entry point which could fail goes through this.
+///
+/// Failing needs a Rust task, or the process aborts.  A thread made in C
+/// has none, so we set one up for the length of the call.
+pub fn catch_failure(f: ||) -> bool {
+    if Local::exists(None::<Task>) {
+        return unsafe { unwind::try(f).is_ok() };
+    }
+
+    let mut ok = false;
+    let task: Box<Task> = box Task::new(None, None);
+    task.run(|| {
+        ok = unsafe { unwind::try(|| f()).is_ok() };
+    }).destroy();
+    ok
+}
@@ -12,19 +12,31 @@
 use core::prelude::*;
 
 use for_c::common::{LifetimeBuf, AsLifetimeBuf, h5e_buf, c_bool};
+use for_c::common::{catch_failure, H5E_OK, H5E_FAILED, H5E_POISONED};
+use util::utf8::{Utf8Chunker, DecodeStats};
 
-use tokenizer::{TokenSink, Token, Doctype, Tag, ParseError, DoctypeToken};
-use tokenizer::{CommentToken, CharacterTokens, NullCharacterToken};
+use tokenizer::{TokenSink, Token, Doctype, TagView, ParseError, DoctypeToken};
+use tokenizer::SurrogateInInputStream;
+use tokenizer::{CommentToken, CommentChunkToken, CharacterTokens, NullCharacterToken};
 use tokenizer::{TagToken, StartTag, EndTag, EOFToken, Tokenizer};
 
 use core::mem;
 use core::default::Default;
 use alloc::boxed::Box;
 use collections::String;
+use collections::str::Slice;
 use libc::{c_void, c_int, size_t};
 
+/// Callbacks for tokens.  The layout is part of the ABI.
+///
+/// `size` is the size of the caller's struct, so that a caller built
+/// against an older header, with fewer callbacks, still works.  We never
+/// look at callbacks past that size.  Zero is rejected, since it would
+/// skip every callback; it almost certainly means `size` wasn't set.
 #[repr(C)]
 pub struct h5e_token_ops {
+    size: size_t,
+
     do_doctype: Option<extern "C" fn(user: *mut c_void, name: h5e_buf,
         public: h5e_buf, system: h5e_buf, force_quirks: c_int)>,
 
@@ -38,6 +50,7 @@ pub struct h5e_token_ops {
     do_null_char:     Option<extern "C" fn(user: *mut c_void)>,
     do_eof:           Option<extern "C" fn(user: *mut c_void)>,
     do_error:         Option<extern "C" fn(user: *mut c_void, message: h5e_buf)>,
+    do_comment_chunk: Option<extern "C" fn(user: *mut c_void, text: h5e_buf)>,
 }
 
 #[repr(C)]
@@ -46,17 +59,55 @@ pub struct h5e_token_sink {
     user: *mut c_void,
 }
 
+// Call a callback, if the caller's `h5e_token_ops` is big enough to
+// have it.
+macro_rules! call ( ($sink:expr, $name:ident $(, $arg:expr)*) => (
+    unsafe {
+        let (ops_ptr, user) = ($sink.ops, $sink.user);
+        let ops = &*ops_ptr;
+        let offset = (&ops.$name as *const _ as uint) - (ops_ptr as uint);
+        if offset + mem::size_of_val(&ops.$name) <= ops.size as uint {
+            match ops.$name {
+                None => (),
+                Some(f) => f(user $(, $arg)*),
+            }
+        }
+    }
+))
+
 impl TokenSink for h5e_token_sink {
-    fn process_token(&mut self, token: Token) {
-        macro_rules! call ( ($name:ident $(, $arg:expr)*) => (
-            unsafe {
-                match (*self.ops).$name {
-                    None => (),
-                    Some(f) => f(self.user $(, $arg)*),
+    fn wants_tag_views(&self) -> bool {
+        true
+    }
+
+    fn process_tag_view(&mut self, tag: TagView) {
+        let name = tag.name.as_lifetime_buf();
+        match tag.kind {
+            StartTag => {
+                call!(self, do_start_tag, name.get(), c_bool(tag.self_closing),
+                    tag.attrs.len() as size_t);
+                for attr in tag.attrs.iter() {
+                    // All attribute names from the tokenizer are local.
+                    assert!(attr.name.ns == ns!(""));
+                    let name = attr.name.local.as_lifetime_buf();
+                    let value = attr.value.as_lifetime_buf();
+                    call!(self, do_tag_attr, name.get(), value.get());
                 }
             }
-        ))
+            EndTag => call!(self, do_end_tag, name.get()),
+        }
+    }
+
+    fn wants_text_slices(&self) -> bool {
+        true
+    }
+
+    fn process_text(&mut self, text: &str) {
+        let text = LifetimeBuf::from_str(text);
+        call!(self, do_chars, text.get());
+    }
 
+    fn process_token(&mut self, token: Token) {
         fn opt_str_to_buf<'a>(s: &'a Option<String>) -> LifetimeBuf<'a> {
             match *s {
                 None => LifetimeBuf::null(),
@@ -65,78 +116,161 @@ impl TokenSink for h5e_token_sink {
         }
 
         match token {
-            DoctypeToken(Doctype { name, public_id, system_id, force_quirks }) => {
+            DoctypeToken(Doctype { name, public_id, system_id, force_quirks, .. }) => {
                 let name = opt_str_to_buf(&name);
                 let public_id = opt_str_to_buf(&public_id);
                 let system_id = opt_str_to_buf(&system_id);
-                call!(do_doctype, name.get(), public_id.get(), system_id.get(),
+                call!(self, do_doctype, name.get(), public_id.get(), system_id.get(),
                     c_bool(force_quirks));
             }
 
-            TagToken(Tag { kind, name, self_closing, attrs }) => {
-                let name = name.as_lifetime_buf();
-                match kind {
-                    StartTag => {
-                        call!(do_start_tag, name.get(), c_bool(self_closing),
-                            attrs.len() as size_t);
-                        for attr in attrs.into_iter() {
-                            // All attribute names from the tokenizer are local.
-                            assert!(attr.name.ns == ns!(""));
-                            let name = attr.name.local.as_lifetime_buf();
-                            let value = attr.value.as_lifetime_buf();
-                            call!(do_tag_attr, name.get(), value.get());
-                        }
-                    }
-                    EndTag => call!(do_end_tag, name.get()),
-                }
-            }
+            TagToken(ref tag) => self.process_tag_view(tag.view()),
 
             CommentToken(text) => {
                 let text = text.as_lifetime_buf();
-                call!(do_comment, text.get());
+                call!(self, do_comment, text.get());
+            }
+
+            CommentChunkToken(text) => {
+                let text = text.as_lifetime_buf();
+                call!(self, do_comment_chunk, text.get());
             }
 
             CharacterTokens(text) => {
                 let text = text.as_lifetime_buf();
-                call!(do_chars, text.get());
+                call!(self, do_chars, text.get());
             }
 
-            NullCharacterToken => call!(do_null_char),
+            NullCharacterToken => call!(self, do_null_char),
 
-            EOFToken => call!(do_eof),
+            EOFToken => call!(self, do_eof),
 
             ParseError(msg) => {
                 let msg = msg.as_lifetime_buf();
-                call!(do_error, msg.get());
+                call!(self, do_error, msg.get());
             }
         }
     }
 }
 
+/// A tokenizer, plus the tail of any character split across calls to
+/// `h5e_tokenizer_feed`.
+struct CTokenizer<'sink> {
+    tok: Tokenizer<'sink, h5e_token_sink>,
+    chunker: Utf8Chunker,
+
+    /// The tokenizer's sink, for reporting decoding errors.  Only used
+    /// between calls into the tokenizer.
+    sink: *mut h5e_token_sink,
+
+    /// Did an earlier call fail?  The tokenizer may then be in any
+    /// state, so we refuse to go on.
+    poisoned: bool,
+
+    /// Has `h5e_tokenizer_end` been called?
+    ended: bool,
+}
+
+impl<'sink> CTokenizer<'sink> {
+    /// Run `f` on the tokenizer, unless an earlier call failed, and
+    /// return a status code.  Any call after `h5e_tokenizer_end` fails.
+    fn guard(&mut self, f: |&mut CTokenizer<'sink>|) -> c_int {
+        if self.poisoned {
+            return H5E_POISONED;
+        }
+        if catch_failure(|| {
+            if self.ended {
+                fail!("tokenizer used after h5e_tokenizer_end");
+            }
+            f(&mut *self)
+        }) {
+            H5E_OK
+        } else {
+            self.poisoned = true;
+            H5E_FAILED
+        }
+    }
+
+    /// Decode a chunk, reporting a parse error for each bad sequence.
+    unsafe fn decode(&mut self, decode: |&mut Utf8Chunker| -> String) -> String {
+        let before = self.chunker.stats.clone();
+        let out = decode(&mut self.chunker);
+        let DecodeStats { surrogates, invalid } = self.chunker.stats;
+        for _ in range(before.surrogates, surrogates) {
+            (*self.sink).classified_parse_error(SurrogateInInputStream,
+                Slice("Surrogate code point in input"));
+        }
+        for _ in range(before.invalid, invalid) {
+            (*self.sink).process_token(ParseError(Slice("Invalid UTF-8 in input")));
+        }
+        out
+    }
+}
+
 pub type h5e_tokenizer_ptr = *const ();
 
+/// Returns null if creating the tokenizer failed, or if the sink's ops
+/// have a size of 0.
 #[no_mangle]
 pub unsafe extern "C" fn h5e_tokenizer_new(sink: *mut h5e_token_sink) -> h5e_tokenizer_ptr {
-    let tok: Box<Tokenizer<h5e_token_sink>>
-        = box Tokenizer::new(mem::transmute::<_, &mut h5e_token_sink>(sink),
-            Default::default());
-
-    mem::transmute(tok)
+    let mut result = RawPtr::null();
+    if (*(*sink).ops).size == 0 {
+        return result;
+    }
+    catch_failure(|| {
+        let tok: Box<CTokenizer> = box CTokenizer {
+            tok: Tokenizer::new(mem::transmute::<_, &mut h5e_token_sink>(sink),
+                Default::default()),
+            chunker: Utf8Chunker::new(),
+            sink: sink,
+            poisoned: false,
+            ended: false,
+        };
+        result = mem::transmute(tok);
+    });
+    result
 }
 
+/// Free the tokenizer, even if it's poisoned.
 #[no_mangle]
 pub unsafe extern "C" fn h5e_tokenizer_free(tok: h5e_tokenizer_ptr) {
-    let _: Box<Tokenizer<h5e_token_sink>> = mem::transmute(tok);
+    catch_failure(|| {
+        let _: Box<CTokenizer> = mem::transmute(tok);
+    });
+}
+
+/// Feed a chunk of UTF-8.  The chunk may end in the middle of a
+/// character; the remaining bytes are expected in the next chunk.
+#[no_mangle]
+pub unsafe extern "C" fn h5e_tokenizer_feed(tok: h5e_tokenizer_ptr, buf: h5e_buf) -> c_int {
+    let tok: &mut CTokenizer = mem::transmute(tok);
+    tok.guard(|tok| {
+        let input = tok.decode(|c| buf.with_bytes(|b| c.push(b)));
+        tok.tok.feed(input);
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn h5e_tokenizer_end(tok: h5e_tokenizer_ptr) -> c_int {
+    let tok: &mut CTokenizer = mem::transmute(tok);
+    tok.guard(|tok| {
+        let rest = tok.decode(|c| c.finish());
+        tok.tok.feed(rest);
+        tok.tok.end();
+        tok.ended = true;
+    })
 }
 
+/// The number of encoded surrogates replaced with U+FFFD so far.
 #[no_mangle]
-pub unsafe extern "C" fn h5e_tokenizer_feed(tok: h5e_tokenizer_ptr, buf: h5e_buf) {
-    let tok: &mut Tokenizer<h5e_token_sink> = mem::transmute(tok);
-    tok.feed(buf.with_slice(|s| String::from_str(s)));
+pub unsafe extern "C" fn h5e_tokenizer_surrogate_count(tok: h5e_tokenizer_ptr) -> size_t {
+    let tok: &CTokenizer = mem::transmute(tok);
+    tok.chunker.stats.surrogates as size_t
 }
 
+/// The number of other invalid UTF-8 sequences replaced with U+FFFD so far.
 #[no_mangle]
-pub unsafe extern "C" fn h5e_tokenizer_end(tok: h5e_tokenizer_ptr) {
-    let tok: &mut Tokenizer<h5e_token_sink> = mem::transmute(tok);
-    tok.end();
+pub unsafe extern "C" fn h5e_tokenizer_invalid_count(tok: h5e_tokenizer_ptr) -> size_t {
+    let tok: &CTokenizer = mem::transmute(tok);
+    tok.chunker.stats.invalid as size_t
 }
@@ -0,0 +1,68 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Runtime version and feature detection, so that a C program can cope
+//! with whichever build of the library it's linked against.
+
+#![allow(non_camel_case_types)]
+
+use libc::c_uint;
+
+/// Bumped whenever a struct or function in `html5ever.h` changes in a
+/// way that breaks existing callers.  Adding a callback to the end of
+/// `h5e_token_ops` doesn't count; see `h5e_token_ops::size`.
+///
+/// Version 1 added that `size` field, at the start of the struct, so
+/// every callback moved.  A caller still zeroing the struct the old way
+/// and leaving `size` out gets an error from `h5e_tokenizer_new`, rather
+/// than silently getting no callbacks.
+pub static H5E_ABI_VERSION: c_uint = 1;
+
+// The library version, from Cargo.toml.  This build isn't made by
+// Cargo, so we can't ask it.
+static VERSION_MAJOR: c_uint = 0;
+static VERSION_MINOR: c_uint = 0;
+static VERSION_PATCH: c_uint = 0;
+
+/// The tokenizer API: `h5e_tokenizer_new` and friends.
+pub static H5E_CAP_TOKENIZER: c_uint = 1 << 0;
+
+/// The tree builder API.  Not yet available.
+pub static H5E_CAP_TREE_BUILDER: c_uint = 1 << 1;
+
+/// Input in encodings other than UTF-8.  Not yet available.
+pub static H5E_CAP_ENCODINGS: c_uint = 1 << 2;
+
+/// Source positions for tokens.  Not yet available.
+pub static H5E_CAP_SPANS: c_uint = 1 << 3;
+
+#[repr(C)]
+pub struct h5e_version {
+    major: c_uint,
+    minor: c_uint,
+    patch: c_uint,
+    abi: c_uint,
+}
+
+/// The version of the library, and of its ABI.
+#[no_mangle]
+pub extern "C" fn h5e_version() -> h5e_version {
+    h5e_version {
+        major: VERSION_MAJOR,
+        minor: VERSION_MINOR,
+        patch: VERSION_PATCH,
+        abi: H5E_ABI_VERSION,
+    }
+}
+
+/// The `H5E_CAP_*` flags for what this build supports.
+#[no_mangle]
+pub extern "C" fn h5e_capabilities() -> c_uint {
+    H5E_CAP_TOKENIZER
+}
@@ -30,6 +30,9 @@ extern crate std;
 #[cfg(for_c)]
 extern crate libc;
 
+#[cfg(for_c)]
+extern crate rustrt;
+
 #[phase(plugin, link)]
 extern crate collections;
 
@@ -37,6 +40,9 @@ extern crate collections;
 #[phase(plugin, link)]
 extern crate log;
 
+#[cfg(not(for_c))]
+extern crate arena;
+
 #[phase(plugin, link)]
 extern crate debug;
 
@@ -58,7 +64,12 @@ extern crate phf;
 extern crate time;
 
 pub use tokenizer::Attribute;
-pub use driver::{one_input, ParseOpts, parse_to, parse};
+pub use driver::{one_input, ParseOpts, parse_to, parse, parse_fragment_to, parse_fragment};
+pub use driver::{parse_with_status, parse_fragment_with_status};
+pub use driver::{utf8_input, decoded_input};
+pub use driver::{parse_iframe_srcdoc_to, parse_iframe_srcdoc};
+pub use driver::Parser;
+pub use driver::{ParseStatus, ParseFinished, ParseTimedOut, ParseAborted};
 
 #[cfg(not(for_c))]
 pub use serialize::serialize;
@@ -70,6 +81,11 @@ mod util {
 
     pub mod str;
     pub mod smallcharset;
+
+    // Only the C API uses this, but it's built everywhere so that the
+    // tests run.
+    #[allow(dead_code)]
+    pub mod utf8;
 }
 
 pub mod tokenizer;
@@ -83,15 +99,41 @@ pub mod serialize;
 pub mod sink {
     pub mod common;
     pub mod rcdom;
+    pub mod arcdom;
     pub mod owned_dom;
+    pub mod arena_dom;
+    pub mod transform;
+    pub mod query;
+    pub mod xpath;
+    pub mod diff;
+    pub mod patch;
+    pub mod metadata;
+    pub mod watch;
 }
 
 pub mod driver;
+pub mod decoder;
+pub mod sniff;
+pub mod prescan;
+
+#[cfg(not(for_c))]
+pub mod config;
+
+#[cfg(not(for_c))]
+pub mod conformance;
+
+#[cfg(not(for_c))]
+pub mod complete;
+
+// Not `debug`, which would clash with the `debug` crate.
+#[cfg(not(for_c))]
+pub mod diagnostics;
 
 #[cfg(for_c)]
 pub mod for_c {
     pub mod common;
     pub mod tokenizer;
+    pub mod version;
 }
 
 /// A fake `std` module so that `deriving` and other macros will work.
@@ -0,0 +1,289 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Finding what a document will fetch, ahead of the parser.
+//!
+//! While the parser waits, e.g. for a script to be fetched and run, the
+//! input buffered after that point can be scanned for the scripts,
+//! stylesheets and images it refers to, so they can be fetched in the
+//! meantime, as browsers' speculative preload scanners do.  `prescan`
+//! runs a second tokenizer which builds no tree, skips the attributes of
+//! tags it doesn't care about, and doesn't copy text.  It can run in
+//! another task:
+//!
+//! ```ignore
+//! let buffered = String::from_str(rest_of_input);
+//! spawn(proc() {
+//!     let mut hints = vec!();
+//!     prescan(buffered.as_slice(), &mut hints);
+//!     tx.send(hints);
+//! });
+//! ```
+//!
+//! Without a tree builder, the tokenizer state after each start tag is
+//! guessed from the tag's name, so that e.g. markup written inside a
+//! `<script>` isn't reported.  The guess can be wrong inside `<svg>` or
+//! `<math>`.  Everything found is a hint: the real parse may not use it.
+
+use core::prelude::*;
+
+use tokenizer::{TokenSink, Token, TagToken, Tag, TagKind, StartTag};
+use tokenizer::{Tokenizer, TokenizerOpts};
+use tokenizer::states;
+use sniff::charset_from_content;
+use util::str::{AsciiExt, is_ascii_whitespace};
+
+use core::default::Default;
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+
+use string_cache::{Atom, QualName};
+
+/// What kind of resource a URL refers to.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum ResourceKind {
+    /// `<script src>`.
+    Script,
+
+    /// `<link rel=stylesheet href>`.
+    Stylesheet,
+
+    /// `<img src>`.
+    Image,
+
+    /// `<link rel=preload href>`.
+    Preload,
+}
+
+/// Receives what a prescan finds.
+pub trait PrescanSink {
+    /// A resource the document refers to.  The URL is as written, less
+    /// any leading and trailing whitespace; resolve it against the
+    /// document's URL, or the `base_url` reported before it.
+    fn resource(&mut self, kind: ResourceKind, url: String);
+
+    /// The first `<base href>`.  By default it's ignored.
+    fn base_url(&mut self, _url: String) { }
+
+    /// The encoding label from a `<meta charset>` or `<meta http-equiv=
+    /// content-type content=...>`.  It hasn't been checked against the
+    /// known encodings.  By default it's ignored.
+    fn charset(&mut self, _label: String) { }
+}
+
+/// One thing a prescan found, for collecting them in a `Vec`.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum Hint {
+    ResourceHint(ResourceKind, String),
+    BaseUrlHint(String),
+    CharsetHint(String),
+}
+
+impl PrescanSink for Vec<Hint> {
+    fn resource(&mut self, kind: ResourceKind, url: String) {
+        self.push(ResourceHint(kind, url));
+    }
+
+    fn base_url(&mut self, url: String) {
+        self.push(BaseUrlHint(url));
+    }
+
+    fn charset(&mut self, label: String) {
+        self.push(CharsetHint(label));
+    }
+}
+
+/// Wraps a `PrescanSink` so that it can be given to the tokenizer.  Set
+/// `TokenizerOpts::lazy_attrs`, or use `prescan`, to skip the attributes
+/// of other tags.
+pub struct Prescanner<'a, Sink: 'a> {
+    sink: &'a mut Sink,
+    next_state: Option<states::State>,
+    seen_base: bool,
+}
+
+fn attr_value<'a>(tag: &'a Tag, name: Atom) -> Option<&'a str> {
+    tag.attrs.iter()
+        .find(|a| a.name == QualName::new(ns!(""), name.clone()))
+        .map(|a| a.value.as_slice())
+}
+
+// An attribute holding a URL, without the whitespace around it.
+fn url_attr(tag: &Tag, name: Atom) -> Option<String> {
+    match attr_value(tag, name).map(|v| v.trim_chars(is_ascii_whitespace)) {
+        Some("") | None => None,
+        Some(url) => Some(String::from_str(url)),
+    }
+}
+
+fn has_link_type(tag: &Tag, link_type: &str) -> bool {
+    attr_value(tag, atom!(rel)).map_or(false, |rel| {
+        rel.split(|c: char| is_ascii_whitespace(c))
+            .any(|r| r.eq_ignore_ascii_case(link_type))
+    })
+}
+
+impl<'a, Sink: PrescanSink> Prescanner<'a, Sink> {
+    pub fn new(sink: &'a mut Sink) -> Prescanner<'a, Sink> {
+        Prescanner {
+            sink: sink,
+            next_state: None,
+            seen_base: false,
+        }
+    }
+
+    fn start_tag(&mut self, tag: &Tag) {
+        let found = match tag.name {
+            atom!(script) => url_attr(tag, atom!(src)).map(|u| (Script, u)),
+            atom!(img) => url_attr(tag, atom!(src)).map(|u| (Image, u)),
+            atom!(link) if has_link_type(tag, "stylesheet")
+                => url_attr(tag, atom!(href)).map(|u| (Stylesheet, u)),
+            atom!(link) if has_link_type(tag, "preload")
+                => url_attr(tag, atom!(href)).map(|u| (Preload, u)),
+
+            atom!(base) if !self.seen_base => {
+                match url_attr(tag, atom!(href)) {
+                    Some(url) => {
+                        self.seen_base = true;
+                        self.sink.base_url(url);
+                    }
+                    None => (),
+                }
+                None
+            }
+
+            atom!(meta) => {
+                self.meta(tag);
+                None
+            }
+
+            _ => None,
+        };
+        match found {
+            Some((kind, url)) => self.sink.resource(kind, url),
+            None => (),
+        }
+
+        // A browser's prescanner runs with scripting on.
+        self.next_state = states::State::after_start_tag(&tag.name, true);
+    }
+
+    fn meta(&mut self, tag: &Tag) {
+        let label = match attr_value(tag, atom!(charset)) {
+            Some(charset) => Some(String::from_str(charset.trim_chars(is_ascii_whitespace))),
+            None => {
+                let pragma = attr_value(tag, Atom::from_slice("http-equiv"))
+                    .map_or(false, |v| v.eq_ignore_ascii_case("content-type"));
+                match attr_value(tag, atom!(content)) {
+                    Some(content) if pragma => charset_from_content(content.as_bytes())
+                        .and_then(|label| String::from_utf8(label).ok()),
+                    _ => None,
+                }
+            }
+        };
+        match label {
+            Some(ref label) if label.is_empty() => (),
+            Some(label) => self.sink.charset(label),
+            None => (),
+        }
+    }
+}
+
+impl<'a, Sink: PrescanSink> TokenSink for Prescanner<'a, Sink> {
+    fn process_token(&mut self, token: Token) {
+        match token {
+            TagToken(ref tag) if tag.kind == StartTag => self.start_tag(tag),
+            _ => (),
+        }
+    }
+
+    // Text is never looked at, so don't copy it.
+    fn wants_text_slices(&self) -> bool {
+        true
+    }
+
+    fn process_text(&mut self, _text: &str) { }
+
+    fn query_state_change(&mut self) -> Option<states::State> {
+        self.next_state.take()
+    }
+
+    fn wants_attrs(&mut self, kind: TagKind, name: &str) -> bool {
+        kind == StartTag && match name {
+            "script" | "img" | "link" | "base" | "meta" => true,
+            _ => false,
+        }
+    }
+}
+
+/// Scan `input`, which the parser hasn't reached yet, for resources and
+/// charset declarations.
+pub fn prescan<Sink: PrescanSink>(input: &str, sink: &mut Sink) {
+    let mut scanner = Prescanner::new(sink);
+    let mut tok = Tokenizer::new(&mut scanner, TokenizerOpts {
+        lazy_attrs: true,
+        .. Default::default()
+    });
+    tok.feed(String::from_str(input));
+    tok.end();
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{prescan, Hint, ResourceHint, BaseUrlHint, CharsetHint};
+    use super::{Script, Stylesheet, Image, Preload};
+
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    fn hints(input: &str) -> Vec<Hint> {
+        let mut hints = vec!();
+        prescan(input, &mut hints);
+        hints
+    }
+
+    fn s(s: &str) -> String {
+        String::from_str(s)
+    }
+
+    #[test]
+    fn resources() {
+        assert_eq!(hints("<script src=a.js></script><link rel='Preload stylesheet' href=' b.css '>\
+                          <img src=c.png alt=x><link rel=preload href=d.woff><img>\
+                          <link rel=icon href=e.ico><base href=/x/><base href=/y/>"), vec!(
+            ResourceHint(Script, s("a.js")),
+            ResourceHint(Stylesheet, s("b.css")),
+            ResourceHint(Image, s("c.png")),
+            ResourceHint(Preload, s("d.woff")),
+            BaseUrlHint(s("/x/")),
+        ));
+    }
+
+    #[test]
+    fn raw_text() {
+        // Markup in script, style, title and noscript isn't markup.
+        assert_eq!(hints("<script>document.write('<img src=no1.png>')</script>\
+                          <style>/* <link rel=stylesheet href=no2.css> */</style>\
+                          <title><img src=no3.png></title>\
+                          <noscript><img src=no4.png></noscript>\
+                          <img src=yes.png>"),
+            vec!(ResourceHint(Image, s("yes.png"))));
+    }
+
+    #[test]
+    fn charsets() {
+        assert_eq!(hints("<meta charset=' utf-8'>\
+                          <meta http-equiv=Content-Type content='text/html; charset=koi8-r'>\
+                          <meta content='text/html; charset=big5'>\
+                          <meta charset=''>"),
+            vec!(CharsetHint(s("utf-8")), CharsetHint(s("koi8-r"))));
+    }
+}
@@ -9,10 +9,13 @@
 
 use core::prelude::*;
 
-use std::io::{Writer, IoResult};
+use std::io::{Writer, IoResult, IoError, InvalidInput};
 use core::default::Default;
 use collections::MutableSeq;
 use collections::vec::Vec;
+use collections::string::String;
+
+use util::str::{is_ascii_whitespace, AsciiExt};
 
 use string_cache::{Atom, QualName};
 
@@ -21,38 +24,291 @@ pub trait Serializable {
     fn serialize<'wr, Wr: Writer>(&self, serializer: &mut Serializer<'wr, Wr>, incl_self: bool) -> IoResult<()>;
 }
 
+/// Write out `node`'s children, or a whole document when given a DOM such
+/// as `RcDom`.
 pub fn serialize<Wr: Writer, T: Serializable>
     (writer: &mut Wr, node: &T, opts: SerializeOpts) -> IoResult<()> {
 
+    let incl_self = opts.traversal_scope == IncludeNode;
     let mut ser = Serializer::new(writer, opts);
-    node.serialize(&mut ser, false)
+    try!(node.serialize(&mut ser, incl_self));
+    ser.finish()
 }
 
 pub struct SerializeOpts {
     /// Is scripting enabled?
     pub scripting_enabled: bool,
+
+    /// Leave out end tags which the spec allows to be omitted, such as
+    /// `</li>` and `</p>`, when the content which follows makes that
+    /// safe.  Default: false
+    pub omit_optional_tags: bool,
+
+    /// What to do with comments which can't be written verbatim.
+    /// Default: `CommentVerbatim`
+    pub comment_policy: CommentPolicy,
+
+    /// What to do with the text of a raw text element, such as
+    /// `<script>`, which can't be written verbatim.
+    /// Default: `RawTextVerbatim`
+    pub raw_text_policy: RawTextPolicy,
+
+    /// Write the doctype, if there is one?  Default: true
+    pub emit_doctype: bool,
+
+    /// Pretty-print, putting elements on their own lines, indented by
+    /// this many spaces per level?  This adds and drops whitespace, so
+    /// the document will parse differently; it's for reading.  Nothing
+    /// changes inside `<pre>`, `<textarea>` or raw text elements.
+    /// Default: None
+    pub indent: Option<uint>,
+
+    /// Write the node passed to `serialize`, or only its children?  A
+    /// document has no tags of its own, so the whole document is written
+    /// either way.  Default: `ChildrenOnly`
+    pub traversal_scope: TraversalScope,
 }
 
 impl Default for SerializeOpts {
     fn default() -> SerializeOpts {
         SerializeOpts {
             scripting_enabled: true,
+            omit_optional_tags: false,
+            comment_policy: CommentVerbatim,
+            raw_text_policy: RawTextVerbatim,
+            emit_doctype: true,
+            indent: None,
+            traversal_scope: ChildrenOnly,
+        }
+    }
+}
+
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum TraversalScope {
+    IncludeNode,
+    ChildrenOnly,
+}
+
+/// How to serialize a comment whose text would end the comment early or
+/// otherwise not parse back the same: text containing `--`, ending with
+/// `-`, or starting with `>` or `->`.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum CommentPolicy {
+    /// Write the text unchanged, as the spec's algorithm does.
+    CommentVerbatim,
+
+    /// Fail serialization with an `InvalidInput` error.
+    CommentError,
+
+    /// Insert U+200B ZERO WIDTH SPACE where needed, e.g. `-\u200b-`.
+    CommentEscape,
+
+    /// Insert an ASCII space where needed, e.g. `- -`.
+    CommentRewrite,
+}
+
+/// How to serialize the text of a raw text element which contains what
+/// looks like its own end tag, e.g. `</script` inside `<script>`, and so
+/// would end the element early when parsed.  Each text node is checked
+/// on its own.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum RawTextPolicy {
+    /// Write the text unchanged, as the spec's algorithm does.
+    RawTextVerbatim,
+
+    /// Fail serialization with an `InvalidInput` error.
+    RawTextError,
+
+    /// Insert a backslash after the `<`, e.g. `<\/script`, which means
+    /// the same inside JavaScript and CSS strings.
+    RawTextEscape,
+}
+
+/// Where `text` has `</name` followed by something which ends a tag name,
+/// the positions of the `/`.
+fn raw_text_end_tags(text: &str, name: &str) -> Vec<uint> {
+    let bytes = text.as_bytes();
+    let mut found = vec!();
+    let mut i = 0;
+    loop {
+        let start = match text.slice_from(i).find_str("</") {
+            Some(n) => i + n + 2,
+            None => return found,
+        };
+        let end = start + name.len();
+        if end <= bytes.len() && bytes.slice(start, end).eq_ignore_ascii_case(name.as_bytes()) {
+            match bytes.get(end) {
+                None | Some(&b'\t') | Some(&b'\n') | Some(&b'\x0C') | Some(&b' ')
+                    | Some(&b'/') | Some(&b'>') => found.push(start - 1),
+                _ => (),
+            }
+        }
+        i = start;
+    }
+}
+
+fn comment_is_safe(text: &str) -> bool {
+    !(text.starts_with(">") || text.starts_with("->")
+        || text.contains("--") || text.ends_with("-"))
+}
+
+/// Break up every problematic sequence in a comment with `sep`.
+fn fix_comment(text: &str, sep: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    if text.starts_with(">") || text.starts_with("->") {
+        out.push_str(sep);
+    }
+    let mut prev_dash = false;
+    for c in text.chars() {
+        if c == '-' && prev_dash {
+            out.push_str(sep);
         }
+        out.push(c);
+        prev_dash = c == '-';
     }
+    if prev_dash {
+        out.push_str(sep);
+    }
+    out
 }
 
+/// What comes after an end tag, for deciding whether it can be omitted.
+enum Following<'a> {
+    FollowedByElem(&'a Atom),
+    FollowedByText(&'a str),
+    FollowedByComment,
+    FollowedByOther,
+
+    /// No more content in the parent element.
+    FollowedByNothing,
+}
+
+//§ optional-tags
+fn can_omit_end_tag(name: &Atom, parent: Option<&Atom>, next: Following) -> bool {
+    let starts_with_ws = |text: &str|
+        text.chars().next().map_or(false, is_ascii_whitespace);
+
+    match (name.clone(), next) {
+        (atom!(html), FollowedByComment) | (atom!(body), FollowedByComment) => false,
+        (atom!(html), _) | (atom!(body), _) => true,
+
+        (atom!(head), FollowedByComment)
+            | (atom!(colgroup), FollowedByComment)
+            | (atom!(caption), FollowedByComment) => false,
+        (atom!(head), FollowedByText(t))
+            | (atom!(colgroup), FollowedByText(t))
+            | (atom!(caption), FollowedByText(t)) => !starts_with_ws(t),
+        (atom!(head), _) | (atom!(colgroup), _) | (atom!(caption), _) => true,
+
+        (atom!(li), FollowedByElem(&atom!(li))) => true,
+
+        (atom!(dt), FollowedByElem(&atom!(dt)))
+            | (atom!(dt), FollowedByElem(&atom!(dd))) => true,
+
+        (atom!(dd), FollowedByElem(&atom!(dd)))
+            | (atom!(dd), FollowedByElem(&atom!(dt))) => true,
+
+        (atom!(p), FollowedByElem(next)) => match *next {
+            atom!(address) | atom!(article) | atom!(aside) | atom!(blockquote)
+            | atom!(details) | atom!(div) | atom!(dl) | atom!(fieldset)
+            | atom!(figcaption) | atom!(figure) | atom!(footer) | atom!(form)
+            | atom!(h1) | atom!(h2) | atom!(h3) | atom!(h4) | atom!(h5) | atom!(h6)
+            | atom!(header) | atom!(hgroup) | atom!(hr) | atom!(main) | atom!(menu)
+            | atom!(nav) | atom!(ol) | atom!(p) | atom!(pre) | atom!(section)
+            | atom!(table) | atom!(ul) => true,
+            _ => false,
+        },
+
+        // Omitting </p> at the end of these would let the paragraph
+        // swallow the parent's following siblings when reparsed.
+        (atom!(p), FollowedByNothing) => match parent {
+            None | Some(&atom!(a)) | Some(&atom!(audio)) | Some(&atom!(del))
+                | Some(&atom!(ins)) | Some(&atom!(map)) | Some(&atom!(noscript))
+                | Some(&atom!(video)) => false,
+            Some(_) => true,
+        },
+
+        (atom!(rt), FollowedByElem(&atom!(rt)))
+            | (atom!(rt), FollowedByElem(&atom!(rp)))
+            | (atom!(rp), FollowedByElem(&atom!(rt)))
+            | (atom!(rp), FollowedByElem(&atom!(rp))) => true,
+
+        (atom!(optgroup), FollowedByElem(&atom!(optgroup))) => true,
+
+        (atom!(option), FollowedByElem(&atom!(option)))
+            | (atom!(option), FollowedByElem(&atom!(optgroup))) => true,
+
+        (atom!(thead), FollowedByElem(&atom!(tbody)))
+            | (atom!(thead), FollowedByElem(&atom!(tfoot)))
+            | (atom!(tbody), FollowedByElem(&atom!(tbody)))
+            | (atom!(tbody), FollowedByElem(&atom!(tfoot))) => true,
+
+        (atom!(tr), FollowedByElem(&atom!(tr))) => true,
+
+        (atom!(td), FollowedByElem(&atom!(td)))
+            | (atom!(td), FollowedByElem(&atom!(th)))
+            | (atom!(th), FollowedByElem(&atom!(td)))
+            | (atom!(th), FollowedByElem(&atom!(th))) => true,
+
+        (atom!(li), FollowedByNothing) | (atom!(dd), FollowedByNothing)
+            | (atom!(rt), FollowedByNothing) | (atom!(rp), FollowedByNothing)
+            | (atom!(optgroup), FollowedByNothing) | (atom!(option), FollowedByNothing)
+            | (atom!(tbody), FollowedByNothing) | (atom!(tfoot), FollowedByNothing)
+            | (atom!(tr), FollowedByNothing) | (atom!(td), FollowedByNothing)
+            | (atom!(th), FollowedByNothing) => true,
+
+        _ => false,
+    }
+}
+//§ END
+
 struct ElemInfo {
     html_name: Option<Atom>,
     ignore_children: bool,
     processed_first_child: bool,
+
+    /// Inside an element whose whitespace matters, such as `<pre>`, so
+    /// pretty-printing leaves it alone.
+    keep_whitespace: bool,
+
+    /// Has an element child, so pretty-printing puts the end tag on a
+    /// line of its own.
+    has_elem_child: bool,
+}
+
+impl ElemInfo {
+    fn new(html_name: Option<Atom>, ignore_children: bool, keep_whitespace: bool) -> ElemInfo {
+        ElemInfo {
+            html_name: html_name,
+            ignore_children: ignore_children,
+            processed_first_child: false,
+            keep_whitespace: keep_whitespace,
+            has_elem_child: false,
+        }
+    }
 }
 
 pub type AttrRef<'a> = (&'a QualName, &'a str);
 
+/// How much output to collect before passing it to the `Writer`.
+static BUFFER_SIZE: uint = 8192;
+
 pub struct Serializer<'wr, Wr:'wr> {
     writer: &'wr mut Wr,
     opts: SerializeOpts,
+
+    /// Output not yet passed to `writer`.  Many small writes go here,
+    /// rather than each one paying the `Writer`'s per-call cost.
+    buf: String,
+
     stack: Vec<ElemInfo>,
+
+    /// An end tag we may be able to omit, depending on what comes next,
+    /// and whether to pretty-print it on its own line.
+    pending_end: Option<(Atom, bool)>,
+
+    /// Has any output been passed to `writer`?
+    wrote_any: bool,
 }
 
 impl<'wr, Wr: Writer> Serializer<'wr, Wr> {
@@ -60,30 +316,118 @@ impl<'wr, Wr: Writer> Serializer<'wr, Wr> {
         Serializer {
             writer: writer,
             opts: opts,
-            stack: vec!(ElemInfo {
-                html_name: None,
-                ignore_children: false,
-                processed_first_child: false,
-            }),
+            buf: String::with_capacity(BUFFER_SIZE),
+            stack: vec!(ElemInfo::new(None, false, false)),
+            pending_end: None,
+            wrote_any: false,
+        }
+    }
+
+    /// Write any end tag we held back, unless `next` makes it safe to
+    /// leave out.
+    fn flush_end(&mut self, next: Following) -> IoResult<()> {
+        let (name, own_line) = unwrap_or_return!(self.pending_end.take(), Ok(()));
+        let omit = {
+            let parent = self.parent().html_name.clone();
+            can_omit_end_tag(&name, parent.as_ref(), next)
+        };
+        if omit {
+            return Ok(());
+        }
+        self.write_end_tag(name.as_slice(), own_line)
+    }
+
+    fn write_end_tag(&mut self, name: &str, own_line: bool) -> IoResult<()> {
+        if own_line {
+            try!(self.write_indent());
+        }
+        try!(self.write_str("</"));
+        try!(self.write_str(name));
+        self.write_char('>')
+    }
+
+    /// When pretty-printing, start a new line indented for a child of the
+    /// current parent, unless whitespace matters there.
+    fn indent_for_child(&mut self) -> IoResult<()> {
+        if self.opts.indent.is_none() || self.parent().keep_whitespace {
+            return Ok(());
+        }
+        self.write_indent()
+    }
+
+    // A new line, indented to the depth of the current parent's children.
+    fn write_indent(&mut self) -> IoResult<()> {
+        let width = match self.opts.indent {
+            Some(n) => n * (self.stack.len() - 1),
+            None => return Ok(()),
+        };
+        if self.wrote_any || !self.buf.is_empty() {
+            try!(self.write_char('\n'));
+        }
+        for _ in range(0, width) {
+            try!(self.write_char(' '));
+        }
+        Ok(())
+    }
+
+    /// Write anything still pending at the end of serialization.
+    fn finish(&mut self) -> IoResult<()> {
+        try!(self.flush_end(FollowedByNothing));
+        self.flush()
+    }
+
+    /// Pass all buffered output to the `Writer`.  This happens
+    /// automatically when the buffer fills up and at the end of
+    /// serialization.  It doesn't flush the `Writer` itself.
+    pub fn flush(&mut self) -> IoResult<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        try!(self.writer.write(self.buf.as_bytes()));
+        self.buf.truncate(0);
+        self.wrote_any = true;
+        Ok(())
+    }
+
+    fn flush_if_full(&mut self) -> IoResult<()> {
+        if self.buf.len() >= BUFFER_SIZE {
+            self.flush()
+        } else {
+            Ok(())
         }
     }
 
+    fn write_str(&mut self, s: &str) -> IoResult<()> {
+        self.buf.push_str(s);
+        self.flush_if_full()
+    }
+
+    fn write_char(&mut self, c: char) -> IoResult<()> {
+        self.buf.push(c);
+        self.flush_if_full()
+    }
+
     fn parent<'a>(&'a mut self) -> &'a mut ElemInfo {
         self.stack.last_mut().expect("no parent ElemInfo")
     }
 
     fn write_escaped(&mut self, text: &str, attr_mode: bool) -> IoResult<()> {
-        for c in text.chars() {
-            try!(match c {
-                '&' => self.writer.write_str("&amp;"),
-                '\xA0' => self.writer.write_str("&nbsp;"),
-                '"' if attr_mode => self.writer.write_str("&quot;"),
-                '<' if !attr_mode => self.writer.write_str("&lt;"),
-                '>' if !attr_mode => self.writer.write_str("&gt;"),
-                c => self.writer.write_char(c),
-            });
+        // Copy runs of characters which need no escaping in one go.
+        let mut run_start = 0;
+        for (i, c) in text.char_indices() {
+            let escaped = match c {
+                '&' => "&amp;",
+                '\xA0' => "&nbsp;",
+                '"' if attr_mode => "&quot;",
+                '<' if !attr_mode => "&lt;",
+                '>' if !attr_mode => "&gt;",
+                _ => continue,
+            };
+            self.buf.push_str(text.slice(run_start, i));
+            self.buf.push_str(escaped);
+            run_start = i + c.len_utf8_bytes();
         }
-        Ok(())
+        self.write_str(text.slice_from(run_start))
     }
 
     pub fn start_elem<'a, AttrIter: Iterator<AttrRef<'a>>>(
@@ -91,32 +435,41 @@ impl<'wr, Wr: Writer> Serializer<'wr, Wr> {
         name: QualName,
         mut attrs: AttrIter) -> IoResult<()> {
 
+        // SVG and MathML elements are written with just their local name,
+        // like HTML ones, but none of the HTML special cases apply.
         let html_name = match name.ns {
             ns!(HTML) => Some(name.local.clone()),
+            ns!(SVG) | ns!(MathML) => None,
             _ => fail!("FIXME: Handle qualified tag names"),
         };
 
+        try!(self.flush_end(FollowedByElem(&name.local)));
+
         if self.parent().ignore_children {
-            self.stack.push(ElemInfo {
-                html_name: html_name,
-                ignore_children: true,
-                processed_first_child: false,
-            });
+            self.stack.push(ElemInfo::new(html_name, true, true));
             return Ok(());
         }
 
-        try!(self.writer.write_char('<'));
-        try!(self.writer.write_str(name.local.as_slice()));
+        try!(self.indent_for_child());
+        try!(self.write_char('<'));
+        try!(self.write_str(name.local.as_slice()));
         for (name, value) in attrs {
-            try!(self.writer.write_char(' '));
-            // FIXME: qualified names
-            assert!(name.ns == ns!(""));
-            try!(self.writer.write_str(name.local.as_slice()));
-            try!(self.writer.write_str("=\""));
+            try!(self.write_char(' '));
+            match name.ns {
+                ns!("") => (),
+                ns!(XML) => try!(self.write_str("xml:")),
+                ns!(XMLNS) => if name.local.as_slice() != "xmlns" {
+                    try!(self.write_str("xmlns:"));
+                },
+                ns!(XLink) => try!(self.write_str("xlink:")),
+                _ => fail!("FIXME: Handle attributes in other namespaces"),
+            }
+            try!(self.write_str(name.local.as_slice()));
+            try!(self.write_str("=\""));
             try!(self.write_escaped(value, true));
-            try!(self.writer.write_char('"'));
+            try!(self.write_char('"'));
         }
-        try!(self.writer.write_char('>'));
+        try!(self.write_char('>'));
 
         let ignore_children = name.ns == ns!(HTML) && match name.local {
             atom!(area) | atom!(base) | atom!(basefont) | atom!(bgsound) | atom!(br)
@@ -127,30 +480,83 @@ impl<'wr, Wr: Writer> Serializer<'wr, Wr> {
             _ => false,
         };
 
+        let keep_whitespace = match html_name {
+            Some(atom!(pre)) | Some(atom!(textarea)) | Some(atom!(listing))
+            | Some(atom!(plaintext)) => true,
+            _ => self.raw_text_name(&html_name).is_some(),
+        };
+        let keep_whitespace = keep_whitespace || self.parent().keep_whitespace;
+
         self.parent().processed_first_child = true;
+        self.parent().has_elem_child = true;
 
-        self.stack.push(ElemInfo {
-            html_name: html_name,
-            ignore_children: ignore_children,
-            processed_first_child: false,
-        });
+        self.stack.push(ElemInfo::new(html_name, ignore_children, keep_whitespace));
 
         Ok(())
     }
 
     pub fn end_elem(&mut self, name: QualName) -> IoResult<()> {
+        try!(self.flush_end(FollowedByNothing));
         let info = self.stack.pop().expect("no ElemInfo");
         if info.ignore_children {
             return Ok(());
         }
 
+        let own_line = self.opts.indent.is_some() && info.has_elem_child && !info.keep_whitespace;
+        if self.opts.omit_optional_tags && name.ns == ns!(HTML) {
+            self.pending_end = Some((name.local, own_line));
+            return Ok(());
+        }
+
         // FIXME: Handle qualified tag names
-        try!(self.writer.write_str("</"));
-        try!(self.writer.write_str(name.local.as_slice()));
-        self.writer.write_char('>')
+        self.write_end_tag(name.local.as_slice(), own_line)
+    }
+
+    /// If the element is one whose text isn't escaped, its name.
+    fn raw_text_name(&self, html_name: &Option<Atom>) -> Option<Atom> {
+        match *html_name {
+            Some(atom!(style)) | Some(atom!(script)) | Some(atom!(xmp))
+            | Some(atom!(iframe)) | Some(atom!(noembed)) | Some(atom!(noframes))
+                => html_name.clone(),
+            Some(atom!(noscript)) if self.opts.scripting_enabled => html_name.clone(),
+            _ => None,
+        }
+    }
+
+    // The text of a raw text element, following `opts.raw_text_policy`.
+    fn write_raw_text(&mut self, text: &str, name: Atom) -> IoResult<()> {
+        if self.opts.raw_text_policy == RawTextVerbatim {
+            return self.write_str(text);
+        }
+        let slashes = raw_text_end_tags(text, name.as_slice());
+        if slashes.is_empty() {
+            return self.write_str(text);
+        }
+        if self.opts.raw_text_policy == RawTextError {
+            return Err(IoError {
+                kind: InvalidInput,
+                desc: "raw text contains its own end tag",
+                detail: None,
+            });
+        }
+        let mut start = 0;
+        for &i in slashes.iter() {
+            try!(self.write_str(text.slice(start, i)));
+            try!(self.write_char('\\'));
+            start = i;
+        }
+        self.write_str(text.slice_from(start))
     }
 
     pub fn write_text(&mut self, text: &str) -> IoResult<()> {
+        // Pretty-printing supplies its own whitespace.
+        if self.opts.indent.is_some() && !self.parent().keep_whitespace
+                && text.chars().all(is_ascii_whitespace) {
+            return Ok(());
+        }
+
+        try!(self.flush_end(FollowedByText(text)));
+
         let prepend_lf = text.starts_with("\n") && {
             let parent = self.parent();
             !parent.processed_first_child && match parent.html_name {
@@ -160,35 +566,191 @@ impl<'wr, Wr: Writer> Serializer<'wr, Wr> {
         };
 
         if prepend_lf {
-            try!(self.writer.write_char('\n'));
+            try!(self.write_char('\n'));
         }
 
-        let escape = match self.parent().html_name {
-            Some(atom!(style)) | Some(atom!(script)) | Some(atom!(xmp))
-            | Some(atom!(iframe)) | Some(atom!(noembed)) | Some(atom!(noframes))
-            | Some(atom!(plaintext)) => false,
-
-            Some(atom!(noscript)) => !self.opts.scripting_enabled,
+        let parent_name = self.parent().html_name.clone();
+        match self.raw_text_name(&parent_name) {
+            Some(name) => self.write_raw_text(text, name),
+            None if parent_name == Some(atom!(plaintext)) => self.write_str(text),
+            None => self.write_escaped(text, false),
+        }
+    }
 
-            _ => true,
+    pub fn write_comment(&mut self, text: &str) -> IoResult<()> {
+        try!(self.flush_end(FollowedByComment));
+        let fixed = if comment_is_safe(text) {
+            None
+        } else {
+            match self.opts.comment_policy {
+                CommentVerbatim => None,
+                CommentError => return Err(IoError {
+                    kind: InvalidInput,
+                    desc: "comment text can't be serialized",
+                    detail: None,
+                }),
+                CommentEscape => Some(fix_comment(text, "\u200b")),
+                CommentRewrite => Some(fix_comment(text, " ")),
+            }
         };
+        let text = match fixed {
+            Some(ref fixed) => fixed.as_slice(),
+            None => text,
+        };
+        try!(self.indent_for_child());
+        try!(self.write_str("<!--"));
+        try!(self.write_str(text));
+        self.write_str("-->")
+    }
+
+    pub fn write_doctype(&mut self, name: &str) -> IoResult<()> {
+        if !self.opts.emit_doctype {
+            return Ok(());
+        }
+        try!(self.flush_end(FollowedByOther));
+        try!(self.write_str("<!DOCTYPE "));
+        try!(self.write_str(name));
+        self.write_char('>')
+    }
+
+    /// Write a doctype exactly as it appeared in the source, as kept by
+    /// `TokenizerOpts::raw_doctype`.
+    pub fn write_raw_doctype(&mut self, raw: &str) -> IoResult<()> {
+        if !self.opts.emit_doctype {
+            return Ok(());
+        }
+        try!(self.flush_end(FollowedByOther));
+        self.write_str(raw)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{serialize, Serializer, SerializeOpts, AttrRef, fix_comment};
+    use super::{IncludeNode, RawTextPolicy, RawTextVerbatim, RawTextError, RawTextEscape};
+
+    use driver::{parse, one_input};
+    use sink::rcdom::RcDom;
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+    use std::io::{MemWriter, IoResult};
+
+    fn reserialize(input: &str, omit: bool) -> String {
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        let mut out = MemWriter::new();
+        serialize(&mut out, &dom.document, SerializeOpts {
+            omit_optional_tags: omit,
+            .. Default::default()
+        }).ok().expect("serialization failed");
+        String::from_utf8(out.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn omit_list_and_paragraph_ends() {
+        assert_eq!(reserialize("<ul><li>a<li>b</ul><p>x</p><div></div>", true).as_slice(),
+            "<html><head><body><ul><li>a<li>b</ul><p>x<div></div>");
+    }
 
-        if escape {
-            self.write_escaped(text, false)
+    #[test]
+    fn keep_unsafe_ends() {
+        // </p> before text, and </p> at the end of <a>, must stay.
+        assert_eq!(reserialize("<p>x</p>y<a><p>z</p></a>", true).as_slice(),
+            "<html><head><body><p>x</p>y<a><p>z</p></a>");
+    }
+
+    #[test]
+    fn keep_all_by_default() {
+        assert_eq!(reserialize("<ul><li>a</ul>", false).as_slice(),
+            "<html><head></head><body><ul><li>a</li></ul></body></html>");
+    }
+
+    #[test]
+    fn escape_runs() {
+        assert_eq!(reserialize("<p title='a\"b&amp;'>x&lt;\u00a0y</p>", false).as_slice(),
+            "<html><head></head><body><p title=\"a&quot;b&amp;\">x&lt;&nbsp;y</p></body></html>");
+    }
+
+    #[test]
+    fn larger_than_buffer() {
+        let text = String::from_char(20000, 'x');
+        let out = reserialize(text.as_slice(), false);
+        assert_eq!(out.len(), text.len() + "<html><head></head><body></body></html>".len());
+    }
+
+    fn serialize_with(input: &str, body_only: bool, opts: SerializeOpts) -> String {
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        let mut out = MemWriter::new();
+        if body_only {
+            let html = dom.document.borrow().children[1].clone();
+            let body = html.borrow().children[1].clone();
+            serialize(&mut out, &body, opts).ok().expect("serialization failed");
         } else {
-            self.writer.write_str(text)
+            serialize(&mut out, &dom, opts).ok().expect("serialization failed");
         }
+        String::from_utf8(out.unwrap()).unwrap()
     }
 
-    pub fn write_comment(&mut self, text: &str) -> IoResult<()> {
-        try!(self.writer.write_str("<!--"));
-        try!(self.writer.write_str(text));
-        self.writer.write_str("-->")
+    #[test]
+    fn doctype_and_scope() {
+        let input = "<!DOCTYPE html><p>x";
+        assert_eq!(serialize_with(input, false, Default::default()).as_slice(),
+            "<!DOCTYPE html><html><head></head><body><p>x</p></body></html>");
+        assert_eq!(serialize_with(input, false, SerializeOpts {
+            emit_doctype: false,
+            traversal_scope: IncludeNode,
+            .. Default::default()
+        }).as_slice(), "<html><head></head><body><p>x</p></body></html>");
+        assert_eq!(serialize_with(input, true, SerializeOpts {
+            traversal_scope: IncludeNode,
+            .. Default::default()
+        }).as_slice(), "<body><p>x</p></body>");
+        assert_eq!(serialize_with(input, true, Default::default()).as_slice(), "<p>x</p>");
     }
 
-    pub fn write_doctype(&mut self, name: &str) -> IoResult<()> {
-        try!(self.writer.write_str("<!DOCTYPE "));
-        try!(self.writer.write_str(name));
-        self.writer.write_char('\n')
+    #[test]
+    fn indent() {
+        let out = serialize_with("<!DOCTYPE html><div><p>a</p>\n<pre>\n x\n</pre><!--c--></div>",
+            false, SerializeOpts { indent: Some(2), .. Default::default() });
+        assert_eq!(out.as_slice(), concat!(
+            "<!DOCTYPE html>\n<html>\n  <head></head>\n  <body>\n    <div>\n",
+            "      <p>a</p>\n      <pre> x\n</pre>\n      <!--c-->\n",
+            "    </div>\n  </body>\n</html>"));
+    }
+
+    fn script(text: &str, policy: RawTextPolicy) -> IoResult<String> {
+        let mut out = MemWriter::new();
+        {
+            let mut ser = Serializer::new(&mut out, SerializeOpts {
+                raw_text_policy: policy,
+                .. Default::default()
+            });
+            let attrs: Vec<AttrRef> = vec!();
+            try!(ser.start_elem(qualname!(HTML, script), attrs.into_iter()));
+            try!(ser.write_text(text));
+            try!(ser.end_elem(qualname!(HTML, script)));
+            try!(ser.finish());
+        }
+        Ok(String::from_utf8(out.unwrap()).unwrap())
+    }
+
+    #[test]
+    fn raw_text_policy() {
+        let text = "a = '</script>'; b = '</SCRIPT '; c = '</scripts>'";
+        assert_eq!(script(text, RawTextVerbatim).unwrap().as_slice(),
+            "<script>a = '</script>'; b = '</SCRIPT '; c = '</scripts>'</script>");
+        assert_eq!(script(text, RawTextEscape).unwrap().as_slice(),
+            "<script>a = '<\\/script>'; b = '<\\/SCRIPT '; c = '</scripts>'</script>");
+        assert!(script(text, RawTextError).is_err());
+        assert!(script("c = '</scripts>'", RawTextError).is_ok());
+    }
+
+    #[test]
+    fn fix_comments() {
+        assert_eq!(fix_comment("a--b", " ").as_slice(), "a- -b");
+        assert_eq!(fix_comment("a---b-", " ").as_slice(), "a- - -b- ");
+        assert_eq!(fix_comment("->x", "\u200b").as_slice(), "\u200b->x");
     }
 }
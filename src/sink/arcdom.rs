@@ -0,0 +1,412 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A DOM like `RcDom`, but with atomic reference counting.
+//!
+//! Parsing still happens in one task.  Afterwards, handles can be sent to
+//! other tasks, which share the tree without copying it.  Each node is
+//! behind a `RWLock`, so any number of tasks can read a node at once.
+//! The whole `ArcDom` can be sent too, so a worker task can parse and
+//! hand the result to another.  Prefer `RcDom` if the tree never leaves
+//! the parsing task, or `OwnedDom` if only one task at a time needs it.
+
+use core::prelude::*;
+
+use sink::common::{NodeEnum, Document, Doctype, Text, Comment, Element};
+use sink::common::ParseOutput;
+
+use tokenizer::Attribute;
+use tree_builder::{TreeSink, QuirksMode, NodeOrText, AppendNode, AppendText};
+use tree_builder;
+use serialize::{Serializable, Serializer};
+use driver::ParseResult;
+
+use core::default::Default;
+use core::mem::replace;
+use alloc::arc::{Arc, Weak};
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+use collections::str::MaybeOwned;
+use std::io::{Writer, IoResult};
+use std::sync::RWLock;
+
+use string_cache::QualName;
+
+/// A DOM node.
+pub struct Node {
+    pub node: NodeEnum,
+    pub parent: Option<WeakHandle>,
+
+    /// Child nodes, in document order.
+    pub children: Vec<Handle>,
+
+    /// The "script already started" flag.
+    ///
+    /// Not meaningful for nodes other than HTML `<script>`.
+    pub script_already_started: bool,
+
+    /// The doctype as written in the source, if the tokenizer kept it.
+    ///
+    /// Not meaningful for nodes other than `Doctype`.
+    pub raw_doctype: Option<String>,
+
+    /// The template contents, for a HTML `<template>`.  This is a
+    /// `Document` node standing in for a document fragment.
+    pub template_contents: Option<Handle>,
+}
+
+impl Node {
+    fn new(node: NodeEnum) -> Node {
+        Node {
+            node: node,
+            parent: None,
+            children: vec!(),
+            script_already_started: false,
+            raw_doctype: None,
+            template_contents: None,
+        }
+    }
+
+    /// The child at index `n`, if there is one.
+    pub fn nth_child(&self, n: uint) -> Option<Handle> {
+        self.children.as_slice().get(n).map(|h| h.clone())
+    }
+
+    pub fn first_child(&self) -> Option<Handle> {
+        self.children.as_slice().get(0).map(|h| h.clone())
+    }
+
+    pub fn last_child(&self) -> Option<Handle> {
+        self.children.as_slice().last().map(|h| h.clone())
+    }
+}
+
+/// Reference to a DOM node.
+pub type Handle = Arc<RWLock<Node>>;
+
+/// Weak reference to a DOM node, used for parent pointers.
+pub type WeakHandle = Weak<RWLock<Node>>;
+
+fn same_node(x: &Handle, y: &Handle) -> bool {
+    (&**x as *const RWLock<Node>) == (&**y as *const RWLock<Node>)
+}
+
+fn new_node(node: NodeEnum) -> Handle {
+    Arc::new(RWLock::new(Node::new(node)))
+}
+
+fn append(new_parent: &Handle, child: Handle) {
+    new_parent.write().children.push(child.clone());
+    let mut child = child.write();
+    assert!(child.parent.is_none());
+    child.parent = Some(new_parent.downgrade());
+}
+
+fn get_parent_and_index(target: &Handle) -> Option<(Handle, uint)> {
+    let parent = unwrap_or_return!(target.read().parent.as_ref(), None)
+        .upgrade().expect("dangling weak pointer");
+    let i = parent.read().children.iter().position(|n| same_node(n, target));
+    match i {
+        Some(i) => Some((parent, i)),
+        None => fail!("have parent but couldn't find in parent's children!"),
+    }
+}
+
+fn append_to_existing_text(prev: &Handle, text: &str) -> bool {
+    match prev.write().node {
+        Text(ref mut existing) => {
+            existing.push_str(text);
+            true
+        }
+        _ => false,
+    }
+}
+
+fn remove_from_parent(target: &Handle) {
+    {
+        let (parent, i) = unwrap_or_return!(get_parent_and_index(target), ());
+        parent.write().children.remove(i).expect("not found!");
+    }
+
+    target.write().parent = None;
+}
+
+/// The DOM itself; the result of parsing.
+pub struct ArcDom {
+    /// The `Document` itself.
+    pub document: Handle,
+
+    /// Errors that occurred during parsing.
+    pub errors: Vec<MaybeOwned<'static>>,
+
+    /// The document's quirks mode.
+    pub quirks_mode: QuirksMode,
+}
+
+impl TreeSink<Handle> for ArcDom {
+    fn parse_error(&mut self, msg: MaybeOwned<'static>) {
+        self.errors.push(msg);
+    }
+
+    fn get_document(&mut self) -> Handle {
+        self.document.clone()
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.quirks_mode = mode;
+    }
+
+    fn same_node(&self, x: Handle, y: Handle) -> bool {
+        same_node(&x, &y)
+    }
+
+    fn elem_name(&self, target: Handle) -> QualName {
+        match target.read().node {
+            Element(ref name, _) => name.clone(),
+            _ => fail!("not an element!"),
+        }
+    }
+
+    fn get_template_contents(&mut self, target: Handle) -> Handle {
+        target.read().template_contents.clone().expect("not a template element!")
+    }
+
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle {
+        let is_template = name == qualname!(HTML, template);
+        let elem = new_node(Element(name, attrs));
+        if is_template {
+            elem.write().template_contents = Some(new_node(Document));
+        }
+        elem
+    }
+
+    fn create_comment(&mut self, text: String) -> Handle {
+        new_node(Comment(text))
+    }
+
+    fn append(&mut self, parent: Handle, child: NodeOrText<Handle>) {
+        // Append to an existing Text node if we have one.
+        match child {
+            AppendText(ref text) => match parent.read().children.last() {
+                Some(h) => if append_to_existing_text(h, text.as_slice()) { return; },
+                _ => (),
+            },
+            _ => (),
+        }
+
+        append(&parent, match child {
+            AppendText(text) => new_node(Text(text)),
+            AppendNode(node) => node
+        });
+    }
+
+    fn append_before_sibling(&mut self,
+            sibling: Handle,
+            child: NodeOrText<Handle>) -> Result<(), NodeOrText<Handle>> {
+        let (parent, i) = unwrap_or_return!(get_parent_and_index(&sibling), Err(child));
+
+        let child = match (child, i) {
+            // No previous node.
+            (AppendText(text), 0) => new_node(Text(text)),
+
+            // Look for a text node before the insertion point.
+            (AppendText(text), i) => {
+                let prev = parent.read().children[i-1].clone();
+                if append_to_existing_text(&prev, text.as_slice()) {
+                    return Ok(());
+                }
+                new_node(Text(text))
+            }
+
+            // The tree builder promises we won't have a text node after
+            // the insertion point.
+
+            // Any other kind of node.
+            (AppendNode(node), _) => node,
+        };
+
+        let has_parent = child.read().parent.is_some();
+        if has_parent {
+            remove_from_parent(&child);
+        }
+
+        child.write().parent = Some(parent.downgrade());
+        parent.write().children.insert(i, child);
+        Ok(())
+    }
+
+    fn append_doctype_to_document(&mut self, name: String, public_id: String, system_id: String) {
+        append(&self.document, new_node(Doctype(name, public_id, system_id)));
+    }
+
+    fn append_raw_doctype_to_document(&mut self, name: String, public_id: String,
+            system_id: String, raw: String) {
+        let node = new_node(Doctype(name, public_id, system_id));
+        node.write().raw_doctype = Some(raw);
+        append(&self.document, node);
+    }
+
+    fn add_attrs_if_missing(&mut self, target: Handle, mut attrs: Vec<Attribute>) {
+        let mut node = target.write();
+        let existing = match node.node {
+            Element(_, ref mut attrs) => attrs,
+            _ => return,
+        };
+
+        // FIXME: quadratic time
+        attrs.retain(|attr|
+            !existing.iter().any(|e| e.name == attr.name));
+        existing.extend(attrs.into_iter());
+    }
+
+    fn remove_from_parent(&mut self, target: Handle) {
+        remove_from_parent(&target);
+    }
+
+    fn reparent_children(&mut self, node: Handle, new_parent: Handle) {
+        let children = replace(&mut node.write().children, vec!());
+        for child in children.into_iter() {
+            child.write().parent = None;
+            append(&new_parent, child);
+        }
+    }
+
+    fn mark_script_already_started(&mut self, node: Handle) {
+        node.write().script_already_started = true;
+    }
+}
+
+impl Default for ArcDom {
+    fn default() -> ArcDom {
+        ArcDom {
+            document: new_node(Document),
+            errors: vec!(),
+            quirks_mode: tree_builder::NoQuirks,
+        }
+    }
+}
+
+impl ParseResult<ArcDom> for ArcDom {
+    fn get_result(sink: ArcDom) -> ArcDom {
+        sink
+    }
+}
+
+impl ParseOutput<Handle> for ArcDom {
+    fn errors<'a>(&'a self) -> &'a [MaybeOwned<'static>] {
+        self.errors.as_slice()
+    }
+
+    fn quirks_mode(&self) -> QuirksMode {
+        self.quirks_mode.clone()
+    }
+
+    fn document<'a>(&'a self) -> &'a Handle {
+        &self.document
+    }
+}
+
+impl Serializable for Handle {
+    fn serialize<'wr, Wr: Writer>(&self, serializer: &mut Serializer<'wr, Wr>, incl_self: bool) -> IoResult<()> {
+        let node = self.read();
+        match (incl_self, &node.node) {
+            (_, &Element(ref name, ref attrs)) => {
+                if incl_self {
+                    try!(serializer.start_elem(name.clone(),
+                        attrs.iter().map(|at| (&at.name, at.value.as_slice()))));
+                }
+
+                // A template's children are in its contents.
+                match node.template_contents {
+                    Some(ref contents) => try!(contents.serialize(serializer, false)),
+                    None => for handle in node.children.iter() {
+                        try!(handle.serialize(serializer, true));
+                    },
+                }
+
+                if incl_self {
+                    try!(serializer.end_elem(name.clone()));
+                }
+                Ok(())
+            }
+
+            (_, &Document) => {
+                for handle in node.children.iter() {
+                    try!(handle.serialize(serializer, true));
+                }
+                Ok(())
+            }
+
+            (false, _) => Ok(()),
+
+            (true, &Doctype(ref name, _, _)) => match node.raw_doctype {
+                Some(ref raw) => serializer.write_raw_doctype(raw.as_slice()),
+                None => serializer.write_doctype(name.as_slice()),
+            },
+            (true, &Text(ref text)) => serializer.write_text(text.as_slice()),
+            (true, &Comment(ref text)) => serializer.write_comment(text.as_slice()),
+        }
+    }
+}
+
+/// Serializes the whole document, so `serialize(&mut writer, &dom, opts)`
+/// works.
+impl Serializable for ArcDom {
+    fn serialize<'wr, Wr: Writer>(&self, serializer: &mut Serializer<'wr, Wr>, incl_self: bool) -> IoResult<()> {
+        self.document.serialize(serializer, incl_self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{ArcDom, Handle};
+
+    use sink::common::Element;
+    use driver::{parse, one_input};
+
+    use core::default::Default;
+    use collections::string::String;
+    use std::comm::channel;
+    use std::task::spawn;
+
+    fn count_elements(node: &Handle) -> uint {
+        let node = node.read();
+        let me = match node.node {
+            Element(..) => 1,
+            _ => 0,
+        };
+        node.children.iter().fold(me, |n, c| n + count_elements(c))
+    }
+
+    #[test]
+    fn share_between_tasks() {
+        let dom: ArcDom = parse(one_input(String::from_str("<p>a<p>b")), Default::default());
+        let (tx, rx) = channel();
+        for _ in range(0, 2u) {
+            let (doc, tx) = (dom.document.clone(), tx.clone());
+            spawn(proc() tx.send(count_elements(&doc)));
+        }
+
+        // html, head, body, p, p
+        assert_eq!((rx.recv(), rx.recv()), (5, 5));
+    }
+
+    #[test]
+    fn parse_in_another_task() {
+        let (tx, rx) = channel();
+        spawn(proc() {
+            let dom: ArcDom = parse(one_input(String::from_str("<p>a<p>b")), Default::default());
+            tx.send(dom);
+        });
+        let dom = rx.recv();
+        assert_eq!(count_elements(&dom.document), 5);
+    }
+}
@@ -0,0 +1,541 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A DOM whose nodes live in an arena.
+//!
+//! The caller owns a `TypedArena`, and every node is allocated from it,
+//! so building the tree allocates memory in large blocks and nodes end
+//! up close together.  A `Handle` is a plain reference into the arena,
+//! and all the nodes are freed at once when the arena is dropped.
+//!
+//! ```ignore
+//! let arena = TypedArena::new();
+//! let mut dom = ArenaDom::new(&arena);
+//! parse_to(&mut dom, one_input(input), Default::default());
+//! for child in dom.document.children() {
+//!     ...
+//! }
+//! ```
+//!
+//! Nodes are linked to their parent and siblings rather than holding a
+//! `Vec` of children.  A node removed from the tree stays in the arena
+//! until the arena is dropped.
+
+use core::prelude::*;
+
+use sink::common::{NodeEnum, Document, Doctype, Text, Comment, Element};
+use sink::common::ParseOutput;
+
+use tokenizer::Attribute;
+use tree_builder::{TreeSink, QuirksMode, NodeOrText, AppendNode, AppendText};
+use tree_builder;
+use serialize::{Serializable, Serializer};
+
+use core::cell::{Cell, RefCell};
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+use collections::str::MaybeOwned;
+use std::io::{Writer, IoResult};
+use arena::TypedArena;
+
+use string_cache::QualName;
+
+/// A DOM node, allocated in an arena.
+pub struct Node<'arena> {
+    /// What kind of node this is.  Text and attributes can change
+    /// while the tree is built.
+    pub node: RefCell<NodeEnum>,
+
+    /// The "script already started" flag.
+    ///
+    /// Not meaningful for nodes other than HTML `<script>`.
+    pub script_already_started: Cell<bool>,
+
+    /// The doctype as written in the source, if the tokenizer kept it.
+    ///
+    /// Not meaningful for nodes other than `Doctype`.
+    pub raw_doctype: Option<String>,
+
+    /// The template contents, for a HTML `<template>`.  This is a
+    /// `Document` node standing in for a document fragment.
+    pub template_contents: Option<Handle<'arena>>,
+
+    parent: Cell<Option<Handle<'arena>>>,
+    first_child: Cell<Option<Handle<'arena>>>,
+    last_child: Cell<Option<Handle<'arena>>>,
+    previous_sibling: Cell<Option<Handle<'arena>>>,
+    next_sibling: Cell<Option<Handle<'arena>>>,
+}
+
+/// Reference to a DOM node.
+pub type Handle<'arena> = &'arena Node<'arena>;
+
+/// Iterator over a node's children, from `Node::children`.
+pub struct Children<'arena> {
+    next: Option<Handle<'arena>>,
+}
+
+impl<'arena> Iterator<Handle<'arena>> for Children<'arena> {
+    fn next(&mut self) -> Option<Handle<'arena>> {
+        let node = unwrap_or_return!(self.next, None);
+        self.next = node.next_sibling.get();
+        Some(node)
+    }
+}
+
+fn same_node<'a>(x: Handle<'a>, y: Handle<'a>) -> bool {
+    (x as *const Node<'a>) == (y as *const Node<'a>)
+}
+
+impl<'arena> Node<'arena> {
+    fn new(node: NodeEnum) -> Node<'arena> {
+        Node {
+            node: RefCell::new(node),
+            script_already_started: Cell::new(false),
+            raw_doctype: None,
+            template_contents: None,
+            parent: Cell::new(None),
+            first_child: Cell::new(None),
+            last_child: Cell::new(None),
+            previous_sibling: Cell::new(None),
+            next_sibling: Cell::new(None),
+        }
+    }
+
+    pub fn parent(&self) -> Option<Handle<'arena>> {
+        self.parent.get()
+    }
+
+    pub fn first_child(&self) -> Option<Handle<'arena>> {
+        self.first_child.get()
+    }
+
+    pub fn last_child(&self) -> Option<Handle<'arena>> {
+        self.last_child.get()
+    }
+
+    pub fn previous_sibling(&self) -> Option<Handle<'arena>> {
+        self.previous_sibling.get()
+    }
+
+    pub fn next_sibling(&self) -> Option<Handle<'arena>> {
+        self.next_sibling.get()
+    }
+
+    /// The node's children, in document order.
+    pub fn children(&self) -> Children<'arena> {
+        Children {
+            next: self.first_child.get(),
+        }
+    }
+
+    /// Detach the node from its parent, if it has one.
+    pub fn remove_from_parent(&self) {
+        let parent = self.parent.get();
+        let prev = self.previous_sibling.get();
+        let next = self.next_sibling.get();
+        match (prev, parent) {
+            (Some(prev), _) => prev.next_sibling.set(next),
+            (None, Some(parent)) => parent.first_child.set(next),
+            (None, None) => (),
+        }
+        match (next, parent) {
+            (Some(next), _) => next.previous_sibling.set(prev),
+            (None, Some(parent)) => parent.last_child.set(prev),
+            (None, None) => (),
+        }
+        self.parent.set(None);
+        self.previous_sibling.set(None);
+        self.next_sibling.set(None);
+    }
+
+    /// Make `child` the last child of this node, detaching it from its
+    /// old parent first.  Fails if `child` is this node or one of its
+    /// ancestors.
+    pub fn append_child(&'arena self, child: Handle<'arena>) {
+        check_insertion(self, child);
+        child.remove_from_parent();
+        append(self, child);
+    }
+
+    /// Insert `child` just before this node, detaching it from its old
+    /// parent first.  Fails if this node has no parent, or if `child` is
+    /// one of its ancestors.
+    pub fn insert_before(&'arena self, child: Handle<'arena>) {
+        if same_node(self, child) {
+            return;
+        }
+        let parent = self.parent.get().expect("sibling has no parent");
+        check_insertion(parent, child);
+        child.remove_from_parent();
+        insert_before(self, child);
+    }
+}
+
+// Fail if putting `child` under `parent` would make a cycle.
+fn check_insertion<'arena>(parent: Handle<'arena>, child: Handle<'arena>) {
+    let mut node = parent;
+    loop {
+        if same_node(node, child) {
+            fail!("can't insert a node into itself or its descendant");
+        }
+        node = unwrap_or_return!(node.parent.get(), ());
+    }
+}
+
+// Link a node with no parent in as the last child of `parent`.
+fn append<'arena>(parent: Handle<'arena>, child: Handle<'arena>) {
+    assert!(child.parent.get().is_none());
+    child.parent.set(Some(parent));
+    match parent.last_child.get() {
+        Some(last) => {
+            last.next_sibling.set(Some(child));
+            child.previous_sibling.set(Some(last));
+        }
+        None => parent.first_child.set(Some(child)),
+    }
+    parent.last_child.set(Some(child));
+}
+
+// Link a node with no parent in just before `sibling`, which has one.
+fn insert_before<'arena>(sibling: Handle<'arena>, child: Handle<'arena>) {
+    assert!(child.parent.get().is_none());
+    let parent = sibling.parent.get().unwrap();
+    let prev = sibling.previous_sibling.get();
+    child.parent.set(Some(parent));
+    child.previous_sibling.set(prev);
+    child.next_sibling.set(Some(sibling));
+    sibling.previous_sibling.set(Some(child));
+    match prev {
+        Some(prev) => prev.next_sibling.set(Some(child)),
+        None => parent.first_child.set(Some(child)),
+    }
+}
+
+fn append_to_existing_text<'arena>(prev: Handle<'arena>, text: &str) -> bool {
+    match prev.node.borrow_mut().deref_mut() {
+        &Text(ref mut existing) => {
+            existing.push_str(text);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// The DOM itself; the result of parsing.
+pub struct ArenaDom<'arena> {
+    arena: &'arena TypedArena<Node<'arena>>,
+
+    /// The `Document` itself.
+    pub document: Handle<'arena>,
+
+    /// Errors that occurred during parsing.
+    pub errors: Vec<MaybeOwned<'static>>,
+
+    /// The document's quirks mode.
+    pub quirks_mode: QuirksMode,
+}
+
+impl<'arena> ArenaDom<'arena> {
+    /// An empty document, whose nodes will be allocated from `arena`.
+    pub fn new(arena: &'arena TypedArena<Node<'arena>>) -> ArenaDom<'arena> {
+        let document: Handle<'arena> = arena.alloc(Node::new(Document));
+        ArenaDom {
+            arena: arena,
+            document: document,
+            errors: vec!(),
+            quirks_mode: tree_builder::NoQuirks,
+        }
+    }
+
+    fn new_node(&self, node: Node<'arena>) -> Handle<'arena> {
+        self.arena.alloc(node)
+    }
+}
+
+impl<'arena> TreeSink<Handle<'arena>> for ArenaDom<'arena> {
+    fn parse_error(&mut self, msg: MaybeOwned<'static>) {
+        self.errors.push(msg);
+    }
+
+    fn get_document(&mut self) -> Handle<'arena> {
+        self.document
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.quirks_mode = mode;
+    }
+
+    fn same_node(&self, x: Handle<'arena>, y: Handle<'arena>) -> bool {
+        same_node(x, y)
+    }
+
+    fn elem_name(&self, target: Handle<'arena>) -> QualName {
+        match *target.node.borrow() {
+            Element(ref name, _) => name.clone(),
+            _ => fail!("not an element!"),
+        }
+    }
+
+    fn get_template_contents(&mut self, target: Handle<'arena>) -> Handle<'arena> {
+        target.template_contents.expect("not a template element!")
+    }
+
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle<'arena> {
+        let contents = if name == qualname!(HTML, template) {
+            Some(self.new_node(Node::new(Document)))
+        } else {
+            None
+        };
+        let mut elem = Node::new(Element(name, attrs));
+        elem.template_contents = contents;
+        self.new_node(elem)
+    }
+
+    fn create_comment(&mut self, text: String) -> Handle<'arena> {
+        self.new_node(Node::new(Comment(text)))
+    }
+
+    fn append(&mut self, parent: Handle<'arena>, child: NodeOrText<Handle<'arena>>) {
+        let child = match child {
+            // Append to an existing Text node if we have one.
+            AppendText(text) => match parent.last_child.get() {
+                Some(last) if append_to_existing_text(last, text.as_slice()) => return,
+                _ => self.new_node(Node::new(Text(text))),
+            },
+            AppendNode(node) => node,
+        };
+        append(parent, child);
+    }
+
+    fn append_before_sibling(&mut self,
+            sibling: Handle<'arena>,
+            child: NodeOrText<Handle<'arena>>) -> Result<(), NodeOrText<Handle<'arena>>> {
+        if sibling.parent.get().is_none() {
+            return Err(child);
+        }
+
+        let child = match child {
+            // Look for a text node before the insertion point.  The tree
+            // builder promises we won't have one after it.
+            AppendText(text) => match sibling.previous_sibling.get() {
+                Some(prev) if append_to_existing_text(prev, text.as_slice()) => return Ok(()),
+                _ => self.new_node(Node::new(Text(text))),
+            },
+
+            // Any other kind of node.
+            AppendNode(node) => node,
+        };
+
+        child.remove_from_parent();
+        insert_before(sibling, child);
+        Ok(())
+    }
+
+    fn append_doctype_to_document(&mut self, name: String, public_id: String, system_id: String) {
+        let node = self.new_node(Node::new(Doctype(name, public_id, system_id)));
+        append(self.document, node);
+    }
+
+    fn append_raw_doctype_to_document(&mut self, name: String, public_id: String,
+            system_id: String, raw: String) {
+        let mut node = Node::new(Doctype(name, public_id, system_id));
+        node.raw_doctype = Some(raw);
+        let node = self.new_node(node);
+        append(self.document, node);
+    }
+
+    fn add_attrs_if_missing(&mut self, target: Handle<'arena>, mut attrs: Vec<Attribute>) {
+        let mut node = target.node.borrow_mut();
+        // FIXME: mozilla/rust#15609
+        let existing = match node.deref_mut() {
+            &Element(_, ref mut attrs) => attrs,
+            _ => return,
+        };
+
+        // FIXME: quadratic time
+        attrs.retain(|attr|
+            !existing.iter().any(|e| e.name == attr.name));
+        existing.extend(attrs.into_iter());
+    }
+
+    fn remove_from_parent(&mut self, target: Handle<'arena>) {
+        target.remove_from_parent();
+    }
+
+    fn reparent_children(&mut self, node: Handle<'arena>, new_parent: Handle<'arena>) {
+        loop {
+            let child = unwrap_or_return!(node.first_child.get(), ());
+            child.remove_from_parent();
+            append(new_parent, child);
+        }
+    }
+
+    fn mark_script_already_started(&mut self, node: Handle<'arena>) {
+        node.script_already_started.set(true);
+    }
+}
+
+impl<'arena> ParseOutput<Handle<'arena>> for ArenaDom<'arena> {
+    fn errors<'a>(&'a self) -> &'a [MaybeOwned<'static>] {
+        self.errors.as_slice()
+    }
+
+    fn quirks_mode(&self) -> QuirksMode {
+        self.quirks_mode.clone()
+    }
+
+    fn document<'a>(&'a self) -> &'a Handle<'arena> {
+        &self.document
+    }
+}
+
+impl<'arena> Serializable for Node<'arena> {
+    fn serialize<'wr, Wr: Writer>(&self, serializer: &mut Serializer<'wr, Wr>, incl_self: bool) -> IoResult<()> {
+        match (incl_self, &*self.node.borrow()) {
+            (_, &Element(ref name, ref attrs)) => {
+                if incl_self {
+                    try!(serializer.start_elem(name.clone(),
+                        attrs.iter().map(|at| (&at.name, at.value.as_slice()))));
+                }
+
+                // A template's children are in its contents.
+                match self.template_contents {
+                    Some(contents) => try!(contents.serialize(serializer, false)),
+                    None => for child in self.children() {
+                        try!(child.serialize(serializer, true));
+                    },
+                }
+
+                if incl_self {
+                    try!(serializer.end_elem(name.clone()));
+                }
+                Ok(())
+            }
+
+            (_, &Document) => {
+                for child in self.children() {
+                    try!(child.serialize(serializer, true));
+                }
+                Ok(())
+            }
+
+            (false, _) => Ok(()),
+
+            (true, &Doctype(ref name, _, _)) => match self.raw_doctype {
+                Some(ref raw) => serializer.write_raw_doctype(raw.as_slice()),
+                None => serializer.write_doctype(name.as_slice()),
+            },
+            (true, &Text(ref text)) => serializer.write_text(text.as_slice()),
+            (true, &Comment(ref text)) => serializer.write_comment(text.as_slice()),
+        }
+    }
+}
+
+/// Serializes the whole document, so `serialize(&mut writer, &dom, opts)`
+/// works.
+impl<'arena> Serializable for ArenaDom<'arena> {
+    fn serialize<'wr, Wr: Writer>(&self, serializer: &mut Serializer<'wr, Wr>, incl_self: bool) -> IoResult<()> {
+        self.document.serialize(serializer, incl_self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{ArenaDom, Handle, same_node};
+
+    use sink::common::Text;
+    use driver::{parse_to, one_input};
+    use serialize::serialize;
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+    use std::io::MemWriter;
+    use arena::TypedArena;
+
+    fn to_html<'arena>(dom: &ArenaDom<'arena>) -> String {
+        let mut writer = MemWriter::new();
+        serialize(&mut writer, dom, Default::default()).unwrap();
+        String::from_utf8(writer.unwrap()).unwrap()
+    }
+
+    fn body<'arena>(dom: &ArenaDom<'arena>) -> Handle<'arena> {
+        dom.document.first_child().unwrap().last_child().unwrap()
+    }
+
+    #[test]
+    fn parse_and_serialize() {
+        let arena = TypedArena::new();
+        let mut dom = ArenaDom::new(&arena);
+        parse_to(&mut dom, one_input(String::from_str(
+            "<!DOCTYPE html><title>x</title><table>a<tr>b<td>c</table>\
+             <b>1<p>2</b>3<template><i>t</i></template>")),
+            Default::default());
+        assert!(dom.errors.len() > 0);
+        // Foster parented text is merged, the adoption agency moves
+        // children, and template contents are kept apart.
+        assert_eq!(to_html(&dom).as_slice(),
+            "<!DOCTYPE html><html><head><title>x</title></head><body>ab\
+             <table><tbody><tr><td>c</td></tr></tbody></table>\
+             <b>1</b><p><b>2</b>3<template><i>t</i></template></p></body></html>");
+
+        let body = body(&dom);
+        let texts: Vec<String> = body.children().filter_map(|n| match *n.node.borrow() {
+            Text(ref t) => Some(t.clone()),
+            _ => None,
+        }).collect();
+        assert_eq!(texts, vec!(String::from_str("ab")));
+        let template = body.last_child().unwrap().last_child().unwrap();
+        assert!(template.first_child().is_none());
+        assert!(template.template_contents.unwrap().first_child().is_some());
+    }
+
+    #[test]
+    fn mutation() {
+        let arena = TypedArena::new();
+        let mut dom = ArenaDom::new(&arena);
+        parse_to(&mut dom, one_input(String::from_str("<a></a><b></b><c><i></i></c>")),
+            Default::default());
+        let body = body(&dom);
+        let a = body.first_child().unwrap();
+        let b = a.next_sibling().unwrap();
+        let c = b.next_sibling().unwrap();
+        let i = c.first_child().unwrap();
+
+        a.append_child(i);
+        assert!(c.first_child().is_none() && c.last_child().is_none());
+        assert_eq!(to_html(&dom).as_slice(), "<html><head></head><body>\
+            <a><i></i></a><b></b><c></c></body></html>");
+
+        b.insert_before(c);
+        assert!(same_node(body.last_child().unwrap(), b));
+        // Moving a node within its parent.
+        c.insert_before(b);
+        a.remove_from_parent();
+        a.remove_from_parent();
+        assert_eq!(to_html(&dom).as_slice(), "<html><head></head><body>\
+            <b></b><c></c></body></html>");
+        assert!(b.previous_sibling().is_none() && a.parent().is_none());
+        assert!(same_node(i.parent().unwrap(), a));
+    }
+
+    #[test]
+    #[should_fail]
+    fn no_cycles() {
+        let arena = TypedArena::new();
+        let mut dom = ArenaDom::new(&arena);
+        parse_to(&mut dom, one_input(String::from_str("<a><b></b></a>")), Default::default());
+        let a = body(&dom).first_child().unwrap();
+        let b = a.first_child().unwrap();
+        b.append_child(a);
+    }
+}
@@ -8,9 +8,11 @@
 // except according to those terms.
 
 use tokenizer::Attribute;
+use tree_builder::QuirksMode;
 
 use collections::vec::Vec;
 use collections::string::String;
+use collections::str::MaybeOwned;
 use string_cache::QualName;
 
 /// The different kinds of nodes in the DOM.
@@ -32,3 +34,17 @@ pub enum NodeEnum {
     Element(QualName, Vec<Attribute>),
 }
 
+/// The result of parsing a document, for code which works with any of
+/// the bundled DOMs.
+///
+/// `Doc` is how the DOM represents its `Document` node.
+pub trait ParseOutput<Doc> {
+    /// Errors that occurred during parsing.
+    fn errors<'a>(&'a self) -> &'a [MaybeOwned<'static>];
+
+    /// The document's quirks mode.
+    fn quirks_mode(&self) -> QuirksMode;
+
+    /// The `Document` node.
+    fn document<'a>(&'a self) -> &'a Doc;
+}
@@ -0,0 +1,298 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Structural differences between two documents.
+//!
+//! The documents are compared as the tree builder left them, so input
+//! which differs only in ways the parser smooths over, such as omitted
+//! end tags or attribute quoting, compares equal.
+//!
+//! Children are matched up by a longest common subsequence over their
+//! kind and element name.  Matched nodes are compared in turn, and the
+//! rest are inserted or removed, except that a removed subtree which
+//! reappears unchanged elsewhere is reported as moved.  A `<template>`'s
+//! contents stand in for its children.
+
+use core::prelude::*;
+
+use sink::common::{NodeEnum, Document, Doctype, Text, Comment, Element};
+use sink::rcdom::{RcDom, Handle};
+use driver::{parse, one_input, ParseOpts};
+use tokenizer::Attribute;
+
+use core::cmp;
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+
+use string_cache::QualName;
+
+/// Where a node is: the index of each ancestor among its parent's
+/// children, from the document down, then the node's own.
+pub type Path = Vec<uint>;
+
+/// One difference between two documents.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum Change {
+    /// A node only in the new document, at this path there.
+    Inserted(Path),
+
+    /// A node only in the old document, at this path there.
+    Removed(Path),
+
+    /// An unchanged subtree at different places, old then new.
+    Moved(Path, Path),
+
+    /// An element's attribute was added, removed or changed.  The path
+    /// is in the new document; the values are old then new.
+    AttrChanged(Path, QualName, Option<String>, Option<String>),
+
+    /// A text or comment node's contents changed.  The path is in the
+    /// new document; the texts are old then new.
+    TextChanged(Path, String, String),
+}
+
+/// Parse two documents and list their differences.
+pub fn diff_html(old: &str, new: &str, opts: ParseOpts) -> Vec<Change> {
+    let old: RcDom = parse(one_input(String::from_str(old)), opts.clone());
+    let new: RcDom = parse(one_input(String::from_str(new)), opts);
+    diff(&old.document, &new.document)
+}
+
+/// List the differences between two trees.
+pub fn diff(old: &Handle, new: &Handle) -> Vec<Change> {
+    let mut differ = Differ {
+        changes: vec!(),
+        removed: vec!(),
+        inserted: vec!(),
+    };
+    differ.compare(old, new, &vec!(), &vec!());
+    differ.finish()
+}
+
+struct Differ {
+    changes: Vec<Change>,
+
+    /// Unmatched subtrees, which may turn out to have moved.
+    removed: Vec<(Path, Handle)>,
+    inserted: Vec<(Path, Handle)>,
+}
+
+fn children(node: &Handle) -> Vec<Handle> {
+    let node = node.borrow();
+    match node.template_contents {
+        Some(ref contents) => contents.borrow().children.clone(),
+        None => node.children.clone(),
+    }
+}
+
+fn child_path(path: &Path, i: uint) -> Path {
+    let mut path = path.clone();
+    path.push(i);
+    path
+}
+
+// Could the nodes be versions of one another?
+fn same_kind(a: &NodeEnum, b: &NodeEnum) -> bool {
+    match (a, b) {
+        (&Document, &Document) | (&Text(_), &Text(_)) | (&Comment(_), &Comment(_)) => true,
+        (&Doctype(ref a, _, _), &Doctype(ref b, _, _)) => a == b,
+        (&Element(ref a, _), &Element(ref b, _)) => a == b,
+        _ => false,
+    }
+}
+
+fn find_attr<'a>(attrs: &'a [Attribute], name: &QualName) -> Option<&'a Attribute> {
+    attrs.iter().find(|a| a.name == *name)
+}
+
+fn same_attrs(a: &[Attribute], b: &[Attribute]) -> bool {
+    a.len() == b.len() && a.iter().all(|x| find_attr(b, &x.name) == Some(x))
+}
+
+// Are the subtrees identical, up to attribute order?
+fn same_tree(a: &Handle, b: &Handle) -> bool {
+    let same_node = match (&a.borrow().node, &b.borrow().node) {
+        (&Element(ref an, ref aa), &Element(ref bn, ref ba))
+            => an == bn && same_attrs(aa.as_slice(), ba.as_slice()),
+        (&Text(ref a), &Text(ref b)) | (&Comment(ref a), &Comment(ref b)) => a == b,
+        (&Doctype(ref an, ref ap, ref as_), &Doctype(ref bn, ref bp, ref bs))
+            => an == bn && ap == bp && as_ == bs,
+        (&Document, &Document) => true,
+        _ => false,
+    };
+    if !same_node {
+        return false;
+    }
+    let (a, b) = (children(a), children(b));
+    a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| same_tree(x, y))
+}
+
+// Pairs of indices into `old` and `new`, in order, for a longest common
+// subsequence of nodes of the same kind.
+fn match_children(old: &[Handle], new: &[Handle]) -> Vec<(uint, uint)> {
+    let (n, m) = (old.len(), new.len());
+    // lcs[i * (m+1) + j] is the length for old[i..] and new[j..].
+    let mut lcs = Vec::from_elem((n + 1) * (m + 1), 0u);
+    for i in range(0, n).rev() {
+        for j in range(0, m).rev() {
+            let here = if same_kind(&old[i].borrow().node, &new[j].borrow().node) {
+                lcs[(i + 1) * (m + 1) + j + 1] + 1
+            } else {
+                cmp::max(lcs[(i + 1) * (m + 1) + j], lcs[i * (m + 1) + j + 1])
+            };
+            *lcs.get_mut(i * (m + 1) + j) = here;
+        }
+    }
+
+    let mut pairs = vec!();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if same_kind(&old[i].borrow().node, &new[j].borrow().node)
+                && lcs[i * (m + 1) + j] == lcs[(i + 1) * (m + 1) + j + 1] + 1 {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lcs[(i + 1) * (m + 1) + j] >= lcs[i * (m + 1) + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+impl Differ {
+    // Compare two nodes of the same kind, and their descendants.
+    fn compare(&mut self, old: &Handle, new: &Handle, old_path: &Path, new_path: &Path) {
+        match (&old.borrow().node, &new.borrow().node) {
+            (&Element(_, ref old_attrs), &Element(_, ref new_attrs))
+                => self.compare_attrs(old_attrs.as_slice(), new_attrs.as_slice(), new_path),
+
+            (&Text(ref a), &Text(ref b)) | (&Comment(ref a), &Comment(ref b)) if a != b
+                => self.changes.push(TextChanged(new_path.clone(), a.clone(), b.clone())),
+
+            _ => (),
+        }
+
+        let old_children = children(old);
+        let new_children = children(new);
+        let mut pairs = match_children(old_children.as_slice(), new_children.as_slice());
+
+        // Unmatched children come before each matched pair, or at the end.
+        pairs.push((old_children.len(), new_children.len()));
+        let (mut i, mut j) = (0, 0);
+        for &(oi, nj) in pairs.iter() {
+            for k in range(i, oi) {
+                self.removed.push((child_path(old_path, k), old_children[k].clone()));
+            }
+            for k in range(j, nj) {
+                self.inserted.push((child_path(new_path, k), new_children[k].clone()));
+            }
+            if oi < old_children.len() {
+                self.compare(&old_children[oi], &new_children[nj],
+                    &child_path(old_path, oi), &child_path(new_path, nj));
+            }
+            i = oi + 1;
+            j = nj + 1;
+        }
+    }
+
+    fn compare_attrs(&mut self, old: &[Attribute], new: &[Attribute], path: &Path) {
+        for attr in old.iter() {
+            match find_attr(new, &attr.name) {
+                Some(n) if n.value == attr.value => (),
+                n => self.changes.push(AttrChanged(path.clone(), attr.name.clone(),
+                    Some(attr.value.clone()), n.map(|n| n.value.clone()))),
+            }
+        }
+        for attr in new.iter() {
+            if find_attr(old, &attr.name).is_none() {
+                self.changes.push(AttrChanged(path.clone(), attr.name.clone(),
+                    None, Some(attr.value.clone())));
+            }
+        }
+    }
+
+    // Pair up removed and inserted subtrees which are identical, and
+    // report the rest.
+    fn finish(self) -> Vec<Change> {
+        let Differ { mut changes, removed, mut inserted } = self;
+        for (old_path, old) in removed.into_iter() {
+            match inserted.iter().position(|&(_, ref new)| same_tree(&old, new)) {
+                Some(k) => {
+                    let (new_path, _) = inserted.remove(k).unwrap();
+                    changes.push(Moved(old_path, new_path));
+                }
+                None => changes.push(Removed(old_path)),
+            }
+        }
+        for (new_path, _) in inserted.into_iter() {
+            changes.push(Inserted(new_path));
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{diff_html, Change, Inserted, Removed, Moved, AttrChanged, TextChanged};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    use string_cache::QualName;
+
+    fn d(old: &str, new: &str) -> Vec<Change> {
+        diff_html(old, new, Default::default())
+    }
+
+    fn s(x: &str) -> String {
+        String::from_str(x)
+    }
+
+    #[test]
+    fn same() {
+        // Differences in the source which the parser smooths over.
+        assert_eq!(d("<p class=x>a<p>b", "<p class='x'>a</p><p>b</p>"), vec!());
+    }
+
+    #[test]
+    fn changes() {
+        // Body is [0, 1] under the document and <html>.
+        assert_eq!(d("<p>a</p><i>b</i>", "<p>c</p><i>b</i><hr>"), vec!(
+            TextChanged(vec!(0, 1, 0, 0), s("a"), s("c")),
+            Inserted(vec!(0, 1, 2))));
+
+        assert_eq!(d("<a href=x title=t>", "<a href=y id=i>"), vec!(
+            AttrChanged(vec!(0, 1, 0), QualName::new(ns!(""), atom!(href)),
+                Some(s("x")), Some(s("y"))),
+            AttrChanged(vec!(0, 1, 0), QualName::new(ns!(""), atom!(title)),
+                Some(s("t")), None),
+            AttrChanged(vec!(0, 1, 0), QualName::new(ns!(""), atom!(id)),
+                None, Some(s("i")))));
+
+        assert_eq!(d("<p>a</p><!--x--><div></div>", "<div></div>"), vec!(
+            Removed(vec!(0, 1, 0)),
+            Removed(vec!(0, 1, 1))));
+    }
+
+    #[test]
+    fn moved() {
+        assert_eq!(d("<ul><li>1<li>2</ul><p>x</p>", "<p>x</p><ul><li>1<li>2</ul>"), vec!(
+            Moved(vec!(0, 1, 0), vec!(0, 1, 1))));
+
+        // A moved subtree must be unchanged.
+        assert_eq!(d("<b>1</b><i>2</i>", "<i>2</i><b>3</b>"), vec!(
+            Removed(vec!(0, 1, 0)),
+            Inserted(vec!(0, 1, 1))));
+    }
+}
@@ -0,0 +1,307 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Collecting document metadata while the tree is built.
+//!
+//! `MetadataSink` wraps any `TreeSink` and watches for `<title>`,
+//! `<meta>` and `<link rel=canonical>` as they are created, so there's no
+//! need to walk the finished tree to find them.
+
+use core::prelude::*;
+
+use tokenizer::{Attribute, ParseErrorKind};
+use tree_builder::{TreeSink, QuirksMode, NodeOrText, AppendText};
+use tree_builder::{ElemProvenance, AuditIssue, InsertionMode, Token, StepOutcome, SplitStatus};
+use tree_builder::ScriptAction;
+use driver::ParseResult;
+use util::str::{AsciiExt, is_ascii_whitespace};
+
+use core::default::Default;
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+use collections::str::MaybeOwned;
+
+use string_cache::{Atom, QualName};
+
+/// Metadata found in a document.
+#[deriving(PartialEq, Eq, Clone, Show, Default)]
+pub struct Metadata {
+    /// Text of the first `<title>` element.
+    pub title: Option<String>,
+
+    /// `(name, content)` for each `<meta name=... content=...>`, in
+    /// document order.
+    pub meta: Vec<(String, String)>,
+
+    /// `href` of the first `<link rel=canonical>`.
+    pub canonical: Option<String>,
+
+    /// `(property, content)` for each `<meta property="og:..."
+    /// content=...>`, in document order.  The property keeps its `og:`
+    /// prefix.
+    pub open_graph: Vec<(String, String)>,
+}
+
+fn attr_value<'a>(attrs: &'a [Attribute], name: Atom) -> Option<&'a str> {
+    attrs.iter()
+        .find(|a| a.name == QualName::new(ns!(""), name.clone()))
+        .map(|a| a.value.as_slice())
+}
+
+impl Metadata {
+    fn saw_element(&mut self, name: &QualName, attrs: &[Attribute]) {
+        if name.ns != ns!(HTML) {
+            return;
+        }
+
+        match name.local {
+            atom!(meta) => {
+                let content = match attr_value(attrs, atom!(content)) {
+                    Some(c) => String::from_str(c),
+                    None => return,
+                };
+                match attr_value(attrs, atom!(name)) {
+                    Some(n) => self.meta.push((String::from_str(n), content.clone())),
+                    None => (),
+                }
+                match attr_value(attrs, Atom::from_slice("property")) {
+                    Some(p) if p.to_ascii_lower().as_slice().starts_with("og:")
+                        => self.open_graph.push((String::from_str(p), content)),
+                    _ => (),
+                }
+            }
+
+            atom!(link) if self.canonical.is_none() => {
+                let canonical = attr_value(attrs, atom!(rel)).map_or(false, |rel| {
+                    rel.split(|c: char| is_ascii_whitespace(c))
+                        .any(|r| r.eq_ignore_ascii_case("canonical"))
+                });
+                if canonical {
+                    self.canonical = attr_value(attrs, atom!(href)).map(|h| String::from_str(h));
+                }
+            }
+
+            _ => (),
+        }
+    }
+}
+
+/// A `TreeSink` which wraps another, collecting `Metadata` on the way.
+pub struct MetadataSink<Handle, Sink> {
+    sink: Sink,
+    metadata: Metadata,
+
+    /// The first `<title>` element, whose text we're collecting.
+    title: Option<Handle>,
+}
+
+impl<Handle: Clone, Sink: TreeSink<Handle>> MetadataSink<Handle, Sink> {
+    pub fn new(sink: Sink) -> MetadataSink<Handle, Sink> {
+        MetadataSink {
+            sink: sink,
+            metadata: Default::default(),
+            title: None,
+        }
+    }
+
+    /// The metadata found so far.
+    pub fn metadata<'a>(&'a self) -> &'a Metadata {
+        &self.metadata
+    }
+
+    /// Get back the wrapped sink and the metadata.
+    pub fn unwrap(self) -> (Sink, Metadata) {
+        (self.sink, self.metadata)
+    }
+
+    fn is_title(&self, node: &Handle) -> bool {
+        match self.title {
+            Some(ref title) => self.sink.same_node(title.clone(), node.clone()),
+            None => false,
+        }
+    }
+
+    fn saw_text(&mut self, parent: &Handle, text: &str) {
+        if self.is_title(parent) {
+            match self.metadata.title {
+                Some(ref mut title) => title.push_str(text),
+                None => (),
+            }
+        }
+    }
+}
+
+impl<Handle: Clone, Sink: TreeSink<Handle> + Default> Default for MetadataSink<Handle, Sink> {
+    fn default() -> MetadataSink<Handle, Sink> {
+        MetadataSink::new(Default::default())
+    }
+}
+
+/// Parse into any `ParseResult` along with its `Metadata`:
+///
+/// ```ignore
+/// let (dom, metadata): (RcDom, Metadata) = parse(one_input(my_str), Default::default());
+/// ```
+impl<Handle, Sink, Output: ParseResult<Sink>> ParseResult<MetadataSink<Handle, Sink>>
+        for (Output, Metadata) {
+    fn get_result(sink: MetadataSink<Handle, Sink>) -> (Output, Metadata) {
+        let MetadataSink { sink, metadata, .. } = sink;
+        (ParseResult::get_result(sink), metadata)
+    }
+}
+
+impl<Handle: Clone, Sink: TreeSink<Handle>> TreeSink<Handle> for MetadataSink<Handle, Sink> {
+    fn parse_error(&mut self, msg: MaybeOwned<'static>) {
+        self.sink.parse_error(msg);
+    }
+
+    fn classified_parse_error(&mut self, kind: ParseErrorKind, msg: MaybeOwned<'static>) {
+        self.sink.classified_parse_error(kind, msg);
+    }
+
+    fn get_document(&mut self) -> Handle {
+        self.sink.get_document()
+    }
+
+    fn same_node(&self, x: Handle, y: Handle) -> bool {
+        self.sink.same_node(x, y)
+    }
+
+    fn elem_name(&self, target: Handle) -> QualName {
+        self.sink.elem_name(target)
+    }
+
+    fn get_template_contents(&mut self, target: Handle) -> Handle {
+        self.sink.get_template_contents(target)
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.sink.set_quirks_mode(mode);
+    }
+
+    fn query_quirks_override(&mut self, mode: QuirksMode) -> Option<QuirksMode> {
+        self.sink.query_quirks_override(mode)
+    }
+
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle {
+        self.metadata.saw_element(&name, attrs.as_slice());
+        let is_title = self.title.is_none() && name == qualname!(HTML, title);
+        let elem = self.sink.create_element(name, attrs);
+        if is_title {
+            self.title = Some(elem.clone());
+            self.metadata.title = Some(String::new());
+        }
+        elem
+    }
+
+    fn create_comment(&mut self, text: String) -> Handle {
+        self.sink.create_comment(text)
+    }
+
+    fn append(&mut self, parent: Handle, child: NodeOrText<Handle>) {
+        match child {
+            AppendText(ref text) => self.saw_text(&parent, text.as_slice()),
+            _ => (),
+        }
+        self.sink.append(parent, child);
+    }
+
+    fn append_text_with_status(&mut self, parent: Handle, text: String, status: SplitStatus) {
+        self.saw_text(&parent, text.as_slice());
+        self.sink.append_text_with_status(parent, text, status);
+    }
+
+    fn append_before_sibling(&mut self,
+            sibling: Handle,
+            new_node: NodeOrText<Handle>) -> Result<(), NodeOrText<Handle>> {
+        self.sink.append_before_sibling(sibling, new_node)
+    }
+
+    fn append_doctype_to_document(&mut self, name: String, public_id: String, system_id: String) {
+        self.sink.append_doctype_to_document(name, public_id, system_id);
+    }
+
+    fn append_raw_doctype_to_document(&mut self, name: String, public_id: String,
+            system_id: String, raw: String) {
+        self.sink.append_raw_doctype_to_document(name, public_id, system_id, raw);
+    }
+
+    fn add_attrs_if_missing(&mut self, target: Handle, attrs: Vec<Attribute>) {
+        self.sink.add_attrs_if_missing(target, attrs);
+    }
+
+    fn remove_from_parent(&mut self, target: Handle) {
+        self.sink.remove_from_parent(target);
+    }
+
+    fn reparent_children(&mut self, node: Handle, new_parent: Handle) {
+        self.sink.reparent_children(node, new_parent);
+    }
+
+    fn mark_script_already_started(&mut self, node: Handle) {
+        self.sink.mark_script_already_started(node);
+    }
+
+    fn complete_script(&mut self, node: Handle) -> ScriptAction {
+        self.sink.complete_script(node)
+    }
+
+    fn element_provenance(&mut self, elem: Handle, provenance: ElemProvenance) {
+        self.sink.element_provenance(elem, provenance);
+    }
+
+    fn step_taken(&mut self, mode: InsertionMode, token: &Token, outcome: StepOutcome) {
+        self.sink.step_taken(mode, token, outcome);
+    }
+
+    fn audit(&mut self, issue: AuditIssue<Handle>) {
+        self.sink.audit(issue);
+    }
+
+    fn void_end_tag_ignored(&mut self, name: Atom) {
+        self.sink.void_end_tag_ignored(name);
+    }
+
+    fn aborted(&self) -> bool {
+        self.sink.aborted()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::MetadataSink;
+
+    use sink::rcdom::RcDom;
+    use driver::{parse_to, one_input};
+
+    use core::default::Default;
+    use collections::string::String;
+
+    #[test]
+    fn collect() {
+        let dom: RcDom = Default::default();
+        let mut sink = MetadataSink::new(dom);
+        parse_to(&mut sink, one_input(String::from_str(concat!(
+            "<title>Hello &amp; welcome</title>",
+            "<meta name=description content='A page'>",
+            "<meta property='og:title' content=Hi>",
+            "<link rel='Canonical alternate' href='/a'><link rel=canonical href='/b'>",
+            "<body><title>Not this</title>"))), Default::default());
+        let (_, metadata) = sink.unwrap();
+
+        assert_eq!(metadata.title, Some(String::from_str("Hello & welcome")));
+        assert_eq!(metadata.meta, vec!((String::from_str("description"),
+            String::from_str("A page"))));
+        assert_eq!(metadata.open_graph, vec!((String::from_str("og:title"),
+            String::from_str("Hi"))));
+        assert_eq!(metadata.canonical, Some(String::from_str("/a")));
+    }
+}
@@ -17,10 +17,15 @@
 //! build a different type and then transmute to the public `Node`.
 //! This is believed to be memory safe, but if you want to be extra
 //! careful you can use `RcDom` instead.
+//!
+//! The handles used while parsing can't leave the parsing task, but the
+//! finished `OwnedDom` owns its nodes outright and can be sent to another
+//! task without copying, e.g. from a worker task which did the parsing.
 
 use core::prelude::*;
 
 use sink::common::{NodeEnum, Document, Doctype, Text, Comment, Element};
+use sink::common::ParseOutput;
 
 use tokenizer::Attribute;
 use tree_builder::{TreeSink, QuirksMode, NodeOrText, AppendNode, AppendText};
@@ -48,6 +53,9 @@ struct SquishyNode {
     node: NodeEnum,
     parent: Handle,
     children: Vec<Handle>,
+
+    /// Null unless this is a HTML `<template>`.
+    template_contents: Handle,
 }
 
 impl SquishyNode {
@@ -56,6 +64,7 @@ impl SquishyNode {
             node: node,
             parent: Handle::null(),
             children: vec!(),
+            template_contents: Handle::null(),
         }
     }
 }
@@ -199,8 +208,18 @@ impl TreeSink<Handle> for Sink {
         }
     }
 
+    fn get_template_contents(&mut self, target: Handle) -> Handle {
+        assert!(!target.template_contents.is_null(), "not a template element!");
+        target.template_contents
+    }
+
     fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle {
-        self.new_node(Element(name, attrs))
+        let is_template = name == qualname!(HTML, template);
+        let mut elem = self.new_node(Element(name, attrs));
+        if is_template {
+            elem.template_contents = self.new_node(Document);
+        }
+        elem
     }
 
     fn create_comment(&mut self, text: String) -> Handle {
@@ -279,6 +298,14 @@ impl TreeSink<Handle> for Sink {
         target.parent = Handle::null();
     }
 
+    fn reparent_children(&mut self, mut node: Handle, new_parent: Handle) {
+        let children = mem::replace(&mut node.children, vec!());
+        for mut child in children.into_iter() {
+            child.parent = Handle::null();
+            append(new_parent, child);
+        }
+    }
+
     fn mark_script_already_started(&mut self, _node: Handle) { }
 }
 
@@ -286,6 +313,10 @@ pub struct Node {
     pub node: NodeEnum,
     _parent_not_accessible: uint,
     pub children: Vec<Box<Node>>,
+
+    /// The template contents, for a HTML `<template>`.  This is a
+    /// `Document` node standing in for a document fragment.
+    pub template_contents: Option<Box<Node>>,
 }
 
 pub struct OwnedDom {
@@ -301,6 +332,9 @@ impl ParseResult<Sink> for OwnedDom {
             for &child in node.deref().children.iter() {
                 walk(live, child);
             }
+            if !node.template_contents.is_null() {
+                walk(live, node.template_contents);
+            }
         }
 
         // Collect addresses of all the nodes that made it into the final tree.
@@ -319,7 +353,7 @@ impl ParseResult<Sink> for OwnedDom {
             }
         }
 
-        let old_addrs = addrs_of!(sink.document: node, parent, children);
+        let old_addrs = addrs_of!(sink.document: node, parent, children, template_contents);
 
         // Transmute the root to a Node, finalizing the transfer of ownership.
         let document = unsafe {
@@ -327,7 +361,8 @@ impl ParseResult<Sink> for OwnedDom {
         };
 
         // FIXME: do this assertion statically
-        let new_addrs = addrs_of!(document: node, _parent_not_accessible, children);
+        let new_addrs = addrs_of!(document: node, _parent_not_accessible, children,
+            template_contents);
         assert_eq!(old_addrs, new_addrs);
 
         OwnedDom {
@@ -338,6 +373,20 @@ impl ParseResult<Sink> for OwnedDom {
     }
 }
 
+impl ParseOutput<Node> for OwnedDom {
+    fn errors<'a>(&'a self) -> &'a [MaybeOwned<'static>] {
+        self.errors.as_slice()
+    }
+
+    fn quirks_mode(&self) -> QuirksMode {
+        self.quirks_mode.clone()
+    }
+
+    fn document<'a>(&'a self) -> &'a Node {
+        &*self.document
+    }
+}
+
 impl Serializable for Node {
     fn serialize<'wr, Wr: Writer>(&self,
             serializer: &mut Serializer<'wr, Wr>,
@@ -350,8 +399,12 @@ impl Serializable for Node {
                         attrs.iter().map(|at| (&at.name, at.value.as_slice()))));
                 }
 
-                for child in self.children.iter() {
-                    try!(child.serialize(serializer, true));
+                // A template's children are in its contents.
+                match self.template_contents {
+                    Some(ref contents) => try!(contents.serialize(serializer, false)),
+                    None => for child in self.children.iter() {
+                        try!(child.serialize(serializer, true));
+                    },
                 }
 
                 if incl_self {
@@ -360,7 +413,7 @@ impl Serializable for Node {
                 Ok(())
             }
 
-            (false, &Document) => {
+            (_, &Document) => {
                 for child in self.children.iter() {
                     try!(child.serialize(serializer, true));
                 }
@@ -372,8 +425,74 @@ impl Serializable for Node {
             (true, &Doctype(ref name, _, _)) => serializer.write_doctype(name.as_slice()),
             (true, &Text(ref text)) => serializer.write_text(text.as_slice()),
             (true, &Comment(ref text)) => serializer.write_comment(text.as_slice()),
+        }
+    }
+}
 
-            (true, &Document) => fail!("Can't serialize Document node itself"),
+/// Serializes the whole document, so `serialize(&mut writer, &dom, opts)`
+/// works.
+impl Serializable for OwnedDom {
+    fn serialize<'wr, Wr: Writer>(&self, serializer: &mut Serializer<'wr, Wr>, incl_self: bool) -> IoResult<()> {
+        self.document.serialize(serializer, incl_self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::OwnedDom;
+
+    use sink::common::{ParseOutput, Document};
+    use sink::rcdom::RcDom;
+    use driver::{parse, one_input};
+    use serialize::serialize;
+    use tree_builder::{QuirksMode, Quirks, NoQuirks};
+
+    use core::default::Default;
+    use collections::string::String;
+    use std::comm::channel;
+    use std::io::MemWriter;
+    use std::task::spawn;
+
+    #[test]
+    fn parse_in_another_task() {
+        let (tx, rx) = channel();
+        spawn(proc() {
+            let dom: OwnedDom = parse(one_input(String::from_str("<p>a<p>b")), Default::default());
+            tx.send(dom);
+        });
+        let dom: OwnedDom = rx.recv();
+        let mut writer = MemWriter::new();
+        serialize(&mut writer, &dom, Default::default()).unwrap();
+        assert_eq!(String::from_utf8(writer.unwrap()).unwrap().as_slice(),
+            "<html><head></head><body><p>a</p><p>b</p></body></html>");
+    }
+
+    // Written against the trait, as generic code would be.
+    fn errors_and_quirks<Doc, Out: ParseOutput<Doc>>(out: &Out) -> (uint, QuirksMode) {
+        (out.errors().len(), out.quirks_mode())
+    }
+
+    #[test]
+    fn parse_output() {
+        // No DOCTYPE, and an end tag with nothing to close.
+        let input = "<p>a</p></p>";
+        let owned: OwnedDom = parse(one_input(String::from_str(input)), Default::default());
+        let rc: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        assert_eq!(errors_and_quirks(&owned), (2, Quirks));
+        assert_eq!(errors_and_quirks(&rc), (2, Quirks));
+
+        let doc = owned.document();
+        match doc.node {
+            Document => (),
+            _ => fail!("not a document"),
         }
+        assert_eq!(doc.children.len(), 1);
+        assert_eq!(rc.document().borrow().children.len(), 1);
+
+        let owned: OwnedDom = parse(one_input(String::from_str("<!DOCTYPE html>")),
+            Default::default());
+        assert_eq!(errors_and_quirks(&owned), (0, NoQuirks));
+        assert_eq!(owned.document().children.len(), 2);
     }
 }
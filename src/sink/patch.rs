@@ -0,0 +1,430 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A stream of changes which mirrors the tree as it's built.
+//!
+//! `PatchSink` doesn't build a tree.  It sends each change the tree
+//! builder makes to a `PatchReceiver` as a `Patch`, which names nodes by
+//! number, so that a tree elsewhere, such as in a remote renderer or a
+//! virtual DOM, can follow along.  A `Patch` owns all its data, so it
+//! can be sent to another task.  `Replayer` applies patches to any
+//! `TreeSink`.
+//!
+//! ```ignore
+//! let mut sink = PatchSink::new(vec!());
+//! parse_to(&mut sink, one_input(input), Default::default());
+//! for patch in sink.unwrap().into_iter() {
+//!     ...
+//! }
+//! ```
+//!
+//! The document is node 0, and other nodes are numbered from 1 as they
+//! are created.  Text nodes aren't numbered.  Text is appended to a
+//! parent, or inserted before a sibling, and is merged into a text node
+//! already there; after that it only moves with `ReparentChildren`.
+
+use core::prelude::*;
+
+use tokenizer::Attribute;
+use tree_builder::{TreeSink, QuirksMode, NodeOrText, AppendNode, AppendText};
+use tree_builder;
+
+use core::mem::replace;
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+use collections::str::MaybeOwned;
+use std::comm::Sender;
+
+use string_cache::QualName;
+
+/// Identifies a node in a patch stream.
+pub type NodeId = uint;
+
+/// The `Document` node.
+pub static DOCUMENT: NodeId = 0;
+
+/// One change to the tree.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum Patch {
+    /// Create an element, outside the tree.
+    CreateElement(NodeId, QualName, Vec<Attribute>),
+
+    /// Create the contents of a HTML `<template>` element: a document
+    /// fragment which holds what the parser puts inside it.  The element
+    /// comes first, then the fragment.
+    CreateTemplateContents(NodeId, NodeId),
+
+    /// Create a comment, outside the tree.
+    CreateComment(NodeId, String),
+
+    /// Append a doctype, with its name, public id and system id, to the
+    /// document.
+    AppendDoctype(String, String, String),
+
+    /// Append a node with no parent as the last child of another.  The
+    /// parent comes first.
+    AppendChild(NodeId, NodeId),
+
+    /// Append text to a node, merging it into the node's last child if
+    /// that's text.
+    AppendTextChild(NodeId, String),
+
+    /// Insert a node just before a sibling, detaching it from its parent
+    /// first.  The sibling comes first.
+    InsertBefore(NodeId, NodeId),
+
+    /// Insert text just before a sibling, merging it into the sibling's
+    /// previous sibling if that's text.
+    InsertTextBefore(NodeId, String),
+
+    /// Add an attribute which the element doesn't have.
+    SetAttribute(NodeId, QualName, String),
+
+    /// Detach a node from its parent.
+    RemoveNode(NodeId),
+
+    /// Move all of one node's children, text included, to the end of
+    /// another's, keeping their order.
+    ReparentChildren(NodeId, NodeId),
+
+    /// Set the document's quirks mode.
+    SetQuirksMode(QuirksMode),
+}
+
+/// Somewhere to send patches.
+pub trait PatchReceiver {
+    fn receive(&mut self, patch: Patch);
+}
+
+impl PatchReceiver for Vec<Patch> {
+    fn receive(&mut self, patch: Patch) {
+        self.push(patch);
+    }
+}
+
+impl PatchReceiver for Sender<Patch> {
+    fn receive(&mut self, patch: Patch) {
+        self.send(patch);
+    }
+}
+
+// What the sink remembers about a node, to answer the tree builder.
+// Children are tracked only as far as we need to keep parents right.
+struct NodeInfo {
+    name: Option<QualName>,
+    attr_names: Vec<QualName>,
+    template_contents: Option<NodeId>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+impl NodeInfo {
+    fn new(name: Option<QualName>) -> NodeInfo {
+        NodeInfo {
+            name: name,
+            attr_names: vec!(),
+            template_contents: None,
+            parent: None,
+            children: vec!(),
+        }
+    }
+}
+
+/// A `TreeSink` which sends patches to a `PatchReceiver`.
+pub struct PatchSink<R> {
+    receiver: R,
+    nodes: Vec<NodeInfo>,
+
+    /// Errors that occurred during parsing.
+    pub errors: Vec<MaybeOwned<'static>>,
+
+    /// The document's quirks mode.
+    pub quirks_mode: QuirksMode,
+}
+
+impl<R: PatchReceiver> PatchSink<R> {
+    pub fn new(receiver: R) -> PatchSink<R> {
+        PatchSink {
+            receiver: receiver,
+            nodes: vec!(NodeInfo::new(None)),
+            errors: vec!(),
+            quirks_mode: tree_builder::NoQuirks,
+        }
+    }
+
+    /// Get back the receiver.
+    pub fn unwrap(self) -> R {
+        self.receiver
+    }
+
+    fn new_node(&mut self, name: Option<QualName>) -> NodeId {
+        self.nodes.push(NodeInfo::new(name));
+        self.nodes.len() - 1
+    }
+
+    fn detach(&mut self, id: NodeId) {
+        let parent = unwrap_or_return!(self.nodes.get_mut(id).parent.take(), ());
+        let siblings = &mut self.nodes.get_mut(parent).children;
+        let i = siblings.iter().position(|&c| c == id).expect("not found!");
+        siblings.remove(i);
+    }
+}
+
+impl<R: PatchReceiver> TreeSink<NodeId> for PatchSink<R> {
+    fn parse_error(&mut self, msg: MaybeOwned<'static>) {
+        self.errors.push(msg);
+    }
+
+    fn get_document(&mut self) -> NodeId {
+        DOCUMENT
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.receiver.receive(SetQuirksMode(mode.clone()));
+        self.quirks_mode = mode;
+    }
+
+    fn same_node(&self, x: NodeId, y: NodeId) -> bool {
+        x == y
+    }
+
+    fn elem_name(&self, target: NodeId) -> QualName {
+        self.nodes[target].name.clone().expect("not an element!")
+    }
+
+    fn get_template_contents(&mut self, target: NodeId) -> NodeId {
+        self.nodes[target].template_contents.expect("not a template element!")
+    }
+
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> NodeId {
+        let is_template = name == qualname!(HTML, template);
+        let id = self.new_node(Some(name.clone()));
+        self.nodes.get_mut(id).attr_names = attrs.iter().map(|a| a.name.clone()).collect();
+        self.receiver.receive(CreateElement(id, name, attrs));
+        if is_template {
+            let contents = self.new_node(None);
+            self.nodes.get_mut(id).template_contents = Some(contents);
+            self.receiver.receive(CreateTemplateContents(id, contents));
+        }
+        id
+    }
+
+    fn create_comment(&mut self, text: String) -> NodeId {
+        let id = self.new_node(None);
+        self.receiver.receive(CreateComment(id, text));
+        id
+    }
+
+    fn append(&mut self, parent: NodeId, child: NodeOrText<NodeId>) {
+        match child {
+            AppendText(text) => self.receiver.receive(AppendTextChild(parent, text)),
+            AppendNode(child) => {
+                assert!(self.nodes[child].parent.is_none());
+                self.nodes.get_mut(child).parent = Some(parent);
+                self.nodes.get_mut(parent).children.push(child);
+                self.receiver.receive(AppendChild(parent, child));
+            }
+        }
+    }
+
+    fn append_before_sibling(&mut self,
+            sibling: NodeId,
+            child: NodeOrText<NodeId>) -> Result<(), NodeOrText<NodeId>> {
+        let parent = unwrap_or_return!(self.nodes[sibling].parent, Err(child));
+        match child {
+            AppendText(text) => self.receiver.receive(InsertTextBefore(sibling, text)),
+            AppendNode(child) => {
+                self.detach(child);
+                let i = self.nodes[parent].children.iter().position(|&c| c == sibling).unwrap();
+                self.nodes.get_mut(parent).children.insert(i, child);
+                self.nodes.get_mut(child).parent = Some(parent);
+                self.receiver.receive(InsertBefore(sibling, child));
+            }
+        }
+        Ok(())
+    }
+
+    fn append_doctype_to_document(&mut self, name: String, public_id: String, system_id: String) {
+        self.receiver.receive(AppendDoctype(name, public_id, system_id));
+    }
+
+    fn add_attrs_if_missing(&mut self, target: NodeId, attrs: Vec<Attribute>) {
+        for attr in attrs.into_iter() {
+            if self.nodes[target].attr_names.contains(&attr.name) {
+                continue;
+            }
+            self.nodes.get_mut(target).attr_names.push(attr.name.clone());
+            self.receiver.receive(SetAttribute(target, attr.name, attr.value));
+        }
+    }
+
+    fn remove_from_parent(&mut self, target: NodeId) {
+        self.detach(target);
+        self.receiver.receive(RemoveNode(target));
+    }
+
+    fn reparent_children(&mut self, node: NodeId, new_parent: NodeId) {
+        let children = replace(&mut self.nodes.get_mut(node).children, vec!());
+        for &child in children.iter() {
+            self.nodes.get_mut(child).parent = Some(new_parent);
+        }
+        self.nodes.get_mut(new_parent).children.extend(children.into_iter());
+        self.receiver.receive(ReparentChildren(node, new_parent));
+    }
+
+    fn mark_script_already_started(&mut self, _node: NodeId) { }
+}
+
+/// Applies patches to a `TreeSink`, keeping track of which of its nodes
+/// each number refers to.
+pub struct Replayer<Handle> {
+    handles: Vec<Option<Handle>>,
+}
+
+impl<Handle: Clone> Replayer<Handle> {
+    pub fn new() -> Replayer<Handle> {
+        Replayer {
+            handles: vec!(),
+        }
+    }
+
+    fn get(&self, id: NodeId) -> Handle {
+        match self.handles.as_slice().get(id) {
+            Some(&Some(ref h)) => h.clone(),
+            _ => fail!("patch refers to unknown node {}", id),
+        }
+    }
+
+    fn set(&mut self, id: NodeId, handle: Handle) {
+        while self.handles.len() <= id {
+            self.handles.push(None);
+        }
+        *self.handles.get_mut(id) = Some(handle);
+    }
+
+    /// Make the change `patch` describes to `sink`.  The first patch
+    /// must be the first of a stream.  Fails if the patches aren't from
+    /// one `PatchSink`, in order.
+    pub fn apply<Sink: TreeSink<Handle>>(&mut self, sink: &mut Sink, patch: Patch) {
+        if self.handles.is_empty() {
+            let document = sink.get_document();
+            self.set(DOCUMENT, document);
+        }
+
+        match patch {
+            CreateElement(id, name, attrs) => {
+                let elem = sink.create_element(name, attrs);
+                self.set(id, elem);
+            }
+            CreateTemplateContents(id, contents) => {
+                let elem = self.get(id);
+                let handle = sink.get_template_contents(elem);
+                self.set(contents, handle);
+            }
+            CreateComment(id, text) => {
+                let comment = sink.create_comment(text);
+                self.set(id, comment);
+            }
+            AppendDoctype(name, public_id, system_id)
+                => sink.append_doctype_to_document(name, public_id, system_id),
+            AppendChild(parent, child) => sink.append(self.get(parent), AppendNode(self.get(child))),
+            AppendTextChild(parent, text) => sink.append(self.get(parent), AppendText(text)),
+            InsertBefore(sibling, child) => {
+                let child = AppendNode(self.get(child));
+                if sink.append_before_sibling(self.get(sibling), child).is_err() {
+                    fail!("patch inserts before node {}, which has no parent", sibling);
+                }
+            }
+            InsertTextBefore(sibling, text) => {
+                if sink.append_before_sibling(self.get(sibling), AppendText(text)).is_err() {
+                    fail!("patch inserts before node {}, which has no parent", sibling);
+                }
+            }
+            SetAttribute(id, name, value) => sink.add_attrs_if_missing(self.get(id),
+                vec!(Attribute { name: name, value: value })),
+            RemoveNode(id) => sink.remove_from_parent(self.get(id)),
+            ReparentChildren(node, new_parent)
+                => sink.reparent_children(self.get(node), self.get(new_parent)),
+            SetQuirksMode(mode) => sink.set_quirks_mode(mode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{PatchSink, Replayer, Patch, CreateElement, CreateComment, AppendDoctype};
+    use super::{AppendChild, AppendTextChild, SetQuirksMode};
+
+    use tokenizer::Attribute;
+    use tree_builder::NoQuirks;
+    use sink::rcdom::RcDom;
+    use driver::{parse_to, parse, one_input};
+    use serialize::serialize;
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+    use std::io::MemWriter;
+
+    fn patches(input: &str) -> Vec<Patch> {
+        let mut sink = PatchSink::new(vec!());
+        parse_to(&mut sink, one_input(String::from_str(input)), Default::default());
+        sink.unwrap()
+    }
+
+    fn to_html(dom: &RcDom) -> String {
+        let mut writer = MemWriter::new();
+        serialize(&mut writer, dom, Default::default()).unwrap();
+        String::from_utf8(writer.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn stream() {
+        let class = Attribute {
+            name: qualname!("", class),
+            value: String::from_str("a"),
+        };
+        let s = |s: &str| String::from_str(s);
+        assert_eq!(patches("<!DOCTYPE html><p class=a>x</p><!--c-->"), vec!(
+            AppendDoctype(s("html"), s(""), s("")),
+            SetQuirksMode(NoQuirks),
+            CreateElement(1, qualname!(HTML, html), vec!()),
+            AppendChild(0, 1),
+            CreateElement(2, qualname!(HTML, head), vec!()),
+            AppendChild(1, 2),
+            CreateElement(3, qualname!(HTML, body), vec!()),
+            AppendChild(1, 3),
+            CreateElement(4, qualname!(HTML, p), vec!(class)),
+            AppendChild(3, 4),
+            AppendTextChild(4, s("x")),
+            CreateComment(5, s("c")),
+            AppendChild(3, 5),
+        ));
+    }
+
+    #[test]
+    fn replay() {
+        // Foster parenting, the adoption agency, templates and attributes
+        // added to <html>.
+        let input = "<html a=1><table>x<tr>y<td>1</td></tr></table>\
+                     <b>1<p>2<i>3</b>4</i><template><td>t</template>\
+                     <html a=2 b=3><!--c-->";
+        let mut replayer = Replayer::new();
+        let mut dom: RcDom = Default::default();
+        for patch in patches(input).into_iter() {
+            replayer.apply(&mut dom, patch);
+        }
+
+        let direct: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        assert_eq!(to_html(&dom), to_html(&direct));
+        assert_eq!(dom.quirks_mode, direct.quirks_mode);
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Searching a finished parse tree.
+//!
+//! `QueryNode` is implemented for `RcDom`'s and `ArenaDom`'s `Handle`s
+//! and for references to `OwnedDom`'s `Node`:
+//!
+//! ```ignore
+//! for link in dom.document.find_all_by_tag(atom!(a)).iter() {
+//!     match link.get_attribute(atom!(href)) {
+//!         Some(href) => println!("{}", href),
+//!         None => (),
+//!     }
+//! }
+//! ```
+//!
+//! As in the DOM, a `<template>`'s contents aren't among its
+//! descendants.
+
+use core::prelude::*;
+
+use sink::common::{NodeEnum, Element};
+use sink::rcdom::Handle;
+use sink::rcdom::Node as RcNode;
+use sink::owned_dom::Node;
+use sink::arena_dom::Handle as ArenaHandle;
+use sink::arena_dom::Node as ArenaNode;
+
+use core::cell::RefCell;
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+
+use string_cache::Atom;
+
+/// A node in one of the bundled DOMs.
+pub trait QueryNode: Clone {
+    /// Call `f` on what kind of node this is.
+    fn with_node<R>(&self, f: |&NodeEnum| -> R) -> R;
+
+    /// The node's children, in document order.
+    fn children(&self) -> Vec<Self>;
+
+    /// A number which identifies the node, among the nodes which exist
+    /// at the time.  It's the node's address.
+    fn node_id(&self) -> uint;
+
+    /// All the node's descendants, not including itself, in document
+    /// order.
+    fn descendants(&self) -> Descendants<Self> {
+        let mut stack = self.children();
+        stack.reverse();
+        Descendants {
+            stack: stack,
+        }
+    }
+
+    /// The descendant elements with local name `name`, in any namespace,
+    /// in document order.
+    fn find_all_by_tag(&self, name: Atom) -> Vec<Self> {
+        self.descendants().filter(|node| node.with_node(|n| match *n {
+            Element(ref qn, _) => qn.local == name,
+            _ => false,
+        })).collect()
+    }
+
+    /// The value of the element's attribute `name`, which has no
+    /// namespace.  None for other kinds of node.
+    fn get_attribute(&self, name: Atom) -> Option<String> {
+        self.with_node(|n| match *n {
+            Element(_, ref attrs) => attrs.iter()
+                .find(|a| a.name.ns == ns!("") && a.name.local == name)
+                .map(|a| a.value.clone()),
+            _ => None,
+        })
+    }
+}
+
+/// Iterator over a node's descendants, from `QueryNode::descendants`.
+pub struct Descendants<N> {
+    // Nodes still to visit, the next one last.
+    stack: Vec<N>,
+}
+
+impl<N: QueryNode> Iterator<N> for Descendants<N> {
+    fn next(&mut self) -> Option<N> {
+        let node = unwrap_or_return!(self.stack.pop(), None);
+        let mut children = node.children();
+        children.reverse();
+        self.stack.extend(children.into_iter());
+        Some(node)
+    }
+}
+
+impl QueryNode for Handle {
+    fn with_node<R>(&self, f: |&NodeEnum| -> R) -> R {
+        f(&self.borrow().node)
+    }
+
+    fn children(&self) -> Vec<Handle> {
+        self.borrow().children.clone()
+    }
+
+    fn node_id(&self) -> uint {
+        &**self as *const RefCell<RcNode> as uint
+    }
+}
+
+impl<'a> QueryNode for &'a Node {
+    fn with_node<R>(&self, f: |&NodeEnum| -> R) -> R {
+        f(&self.node)
+    }
+
+    fn children(&self) -> Vec<&'a Node> {
+        self.children.iter().map(|c| &**c).collect()
+    }
+
+    fn node_id(&self) -> uint {
+        *self as *const Node as uint
+    }
+}
+
+impl<'arena> QueryNode for ArenaHandle<'arena> {
+    fn with_node<R>(&self, f: |&NodeEnum| -> R) -> R {
+        f(&*self.node.borrow())
+    }
+
+    fn children(&self) -> Vec<ArenaHandle<'arena>> {
+        (*self).children().collect()
+    }
+
+    fn node_id(&self) -> uint {
+        *self as *const ArenaNode<'arena> as uint
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::QueryNode;
+
+    use sink::rcdom::RcDom;
+    use sink::owned_dom::OwnedDom;
+    use sink::arena_dom::ArenaDom;
+    use driver::{parse, parse_to, one_input};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+    use arena::TypedArena;
+
+    static INPUT: &'static str = "<a href=x>1</a><div><a>2</a><svg><a href=y></a></svg></div>";
+
+    #[test]
+    fn rcdom() {
+        let dom: RcDom = parse(one_input(String::from_str(INPUT)), Default::default());
+        assert_eq!(dom.document.descendants().count(), 10);
+
+        let links = dom.document.find_all_by_tag(atom!(a));
+        let hrefs: Vec<Option<String>> = links.iter().map(|a| a.get_attribute(atom!(href))).collect();
+        assert_eq!(hrefs, vec!(Some(String::from_str("x")), None, Some(String::from_str("y"))));
+        assert_eq!(dom.document.get_attribute(atom!(href)), None);
+    }
+
+    #[test]
+    fn owned_dom() {
+        let dom: OwnedDom = parse(one_input(String::from_str(INPUT)), Default::default());
+        let document = &*dom.document;
+        assert_eq!(document.descendants().count(), 10);
+
+        let links = document.find_all_by_tag(atom!(a));
+        let hrefs: Vec<Option<String>> = links.iter().map(|a| a.get_attribute(atom!(href))).collect();
+        assert_eq!(hrefs, vec!(Some(String::from_str("x")), None, Some(String::from_str("y"))));
+    }
+
+    #[test]
+    fn arena_dom() {
+        let arena = TypedArena::new();
+        let mut dom = ArenaDom::new(&arena);
+        parse_to(&mut dom, one_input(String::from_str(INPUT)), Default::default());
+        assert_eq!(dom.document.descendants().count(), 10);
+
+        let links = dom.document.find_all_by_tag(atom!(a));
+        let hrefs: Vec<Option<String>> = links.iter().map(|a| a.get_attribute(atom!(href))).collect();
+        assert_eq!(hrefs, vec!(Some(String::from_str("x")), None, Some(String::from_str("y"))));
+    }
+}
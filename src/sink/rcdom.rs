@@ -11,10 +11,15 @@
 //!
 //! This is sufficient as a static parse tree, but don't build a
 //! web browser using it. :)
+//!
+//! The tree can be rearranged after parsing with `append_child`,
+//! `insert_before`, `replace_with` and `remove_from_parent`, which keep
+//! the parent pointers up to date.
 
 use core::prelude::*;
 
 use sink::common::{NodeEnum, Document, Doctype, Text, Comment, Element};
+use sink::common::ParseOutput;
 
 use tokenizer::Attribute;
 use tree_builder::{TreeSink, QuirksMode, NodeOrText, AppendNode, AppendText};
@@ -23,6 +28,7 @@ use serialize::{Serializable, Serializer};
 use driver::ParseResult;
 
 use core::cell::RefCell;
+use core::mem::replace;
 use core::default::Default;
 use alloc::rc::{Rc, Weak};
 use collections::MutableSeq;
@@ -37,12 +43,24 @@ use string_cache::QualName;
 pub struct Node {
     pub node: NodeEnum,
     pub parent: Option<WeakHandle>,
+
+    /// Child nodes, in document order.  The tree builder only ever
+    /// inserts and removes nodes, so the order never changes otherwise.
     pub children: Vec<Handle>,
 
     /// The "script already started" flag.
     ///
     /// Not meaningful for nodes other than HTML `<script>`.
     pub script_already_started: bool,
+
+    /// The doctype as written in the source, if the tokenizer kept it.
+    ///
+    /// Not meaningful for nodes other than `Doctype`.
+    pub raw_doctype: Option<String>,
+
+    /// The template contents, for a HTML `<template>`.  This is a
+    /// `Document` node standing in for a document fragment.
+    pub template_contents: Option<Handle>,
 }
 
 impl Node {
@@ -52,8 +70,50 @@ impl Node {
             parent: None,
             children: vec!(),
             script_already_started: false,
+            raw_doctype: None,
+            template_contents: None,
         }
     }
+
+    /// The child at index `n`, if there is one.
+    pub fn nth_child(&self, n: uint) -> Option<Handle> {
+        self.children.as_slice().get(n).map(|h| h.clone())
+    }
+
+    pub fn first_child(&self) -> Option<Handle> {
+        self.children.as_slice().get(0).map(|h| h.clone())
+    }
+
+    pub fn last_child(&self) -> Option<Handle> {
+        self.children.as_slice().last().map(|h| h.clone())
+    }
+
+    /// This node's position among its parent's children, if it has a
+    /// parent.  This is a linear search of the parent's children.
+    pub fn index(&self) -> Option<uint> {
+        let parent = unwrap_or_return!(self.parent.as_ref(), None)
+            .upgrade().expect("dangling weak pointer");
+        let parent = parent.borrow();
+        let me = self as *const Node;
+        let index = parent.children.iter().position(|n| (&*n.borrow() as *const Node) == me);
+        index
+    }
+
+    pub fn next_sibling(&self) -> Option<Handle> {
+        self.sibling(|i| Some(i + 1))
+    }
+
+    pub fn previous_sibling(&self) -> Option<Handle> {
+        self.sibling(|i| if i == 0 { None } else { Some(i - 1) })
+    }
+
+    fn sibling(&self, step: |uint| -> Option<uint>) -> Option<Handle> {
+        let i = unwrap_or_return!(self.index(), None);
+        let j = unwrap_or_return!(step(i), None);
+        let parent = self.parent.as_ref().unwrap().upgrade().unwrap();
+        let parent = parent.borrow();
+        parent.nth_child(j)
+    }
 }
 
 /// Reference to a DOM node.
@@ -82,9 +142,8 @@ fn get_parent_and_index(target: &Handle) -> Option<(Handle, uint)> {
     let child = target.borrow();
     let parent = unwrap_or_return!(child.parent.as_ref(), None)
         .upgrade().expect("dangling weak pointer");
-    match parent.borrow_mut().children.iter().enumerate()
-                .find(|&(_, n)| same_node(n, target)) {
-        Some((i, _)) => Some((parent, i)),
+    match child.index() {
+        Some(i) => Some((parent, i)),
         None => fail!("have parent but couldn't find in parent's children!"),
     }
 }
@@ -99,7 +158,8 @@ fn append_to_existing_text(prev: &Handle, text: &str) -> bool {
     }
 }
 
-fn remove_from_parent(target: &Handle) {
+/// Detach a node from its parent, if it has one.
+pub fn remove_from_parent(target: &Handle) {
     {
         let (parent, i) = unwrap_or_return!(get_parent_and_index(target), ());
         parent.borrow_mut().children.remove(i).expect("not found!");
@@ -109,6 +169,59 @@ fn remove_from_parent(target: &Handle) {
     (*child).parent = None;
 }
 
+// Fail if putting `child` under `parent` would make a cycle.
+fn check_insertion(parent: &Handle, child: &Handle) {
+    let mut node = parent.clone();
+    loop {
+        if same_node(&node, child) {
+            fail!("can't insert a node into itself or its descendant");
+        }
+        let next = match node.borrow().parent {
+            Some(ref weak) => weak.upgrade().expect("dangling weak pointer"),
+            None => return,
+        };
+        node = next;
+    }
+}
+
+/// Make `child` the last child of `parent`, detaching it from its old
+/// parent first.  Fails if `child` is `parent` or one of its ancestors.
+pub fn append_child(parent: &Handle, child: Handle) {
+    check_insertion(parent, &child);
+    remove_from_parent(&child);
+    append(parent, child);
+}
+
+/// Insert `child` just before `sibling`, detaching it from its old
+/// parent first.  Fails if `sibling` has no parent, or if `child` is
+/// one of its ancestors.
+pub fn insert_before(sibling: &Handle, child: Handle) {
+    if same_node(sibling, &child) {
+        return;
+    }
+    {
+        let (parent, _) = get_parent_and_index(sibling).expect("sibling has no parent");
+        check_insertion(&parent, &child);
+    }
+
+    // Look for the sibling after detaching, in case `child` came before
+    // it in the same parent.
+    remove_from_parent(&child);
+    let (parent, i) = get_parent_and_index(sibling).unwrap();
+    child.borrow_mut().parent = Some(parent.downgrade());
+    parent.borrow_mut().children.insert(i, child);
+}
+
+/// Put `new` in the place of `target`, which is left without a parent.
+/// Fails as `insert_before` does.
+pub fn replace_with(target: &Handle, new: Handle) {
+    if same_node(target, &new) {
+        return;
+    }
+    insert_before(target, new);
+    remove_from_parent(target);
+}
+
 /// The DOM itself; the result of parsing.
 pub struct RcDom {
     /// The `Document` itself.
@@ -145,8 +258,17 @@ impl TreeSink<Handle> for RcDom {
         }
     }
 
+    fn get_template_contents(&mut self, target: Handle) -> Handle {
+        target.borrow().template_contents.clone().expect("not a template element!")
+    }
+
     fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle {
-        new_node(Element(name, attrs))
+        let is_template = name == qualname!(HTML, template);
+        let elem = new_node(Element(name, attrs));
+        if is_template {
+            elem.borrow_mut().template_contents = Some(new_node(Document));
+        }
+        elem
     }
 
     fn create_comment(&mut self, text: String) -> Handle {
@@ -208,6 +330,13 @@ impl TreeSink<Handle> for RcDom {
         append(&self.document, new_node(Doctype(name, public_id, system_id)));
     }
 
+    fn append_raw_doctype_to_document(&mut self, name: String, public_id: String,
+            system_id: String, raw: String) {
+        let node = new_node(Doctype(name, public_id, system_id));
+        node.borrow_mut().raw_doctype = Some(raw);
+        append(&self.document, node);
+    }
+
     fn add_attrs_if_missing(&mut self, target: Handle, mut attrs: Vec<Attribute>) {
         let mut node = target.borrow_mut();
         // FIXME: mozilla/rust#15609
@@ -226,6 +355,14 @@ impl TreeSink<Handle> for RcDom {
         remove_from_parent(&target);
     }
 
+    fn reparent_children(&mut self, node: Handle, new_parent: Handle) {
+        let children = replace(&mut node.borrow_mut().children, vec!());
+        for child in children.into_iter() {
+            child.borrow_mut().parent = None;
+            append(&new_parent, child);
+        }
+    }
+
     fn mark_script_already_started(&mut self, node: Handle) {
         node.borrow_mut().script_already_started = true;
     }
@@ -247,6 +384,20 @@ impl ParseResult<RcDom> for RcDom {
     }
 }
 
+impl ParseOutput<Handle> for RcDom {
+    fn errors<'a>(&'a self) -> &'a [MaybeOwned<'static>] {
+        self.errors.as_slice()
+    }
+
+    fn quirks_mode(&self) -> QuirksMode {
+        self.quirks_mode.clone()
+    }
+
+    fn document<'a>(&'a self) -> &'a Handle {
+        &self.document
+    }
+}
+
 impl Serializable for Handle {
     fn serialize<'wr, Wr: Writer>(&self, serializer: &mut Serializer<'wr, Wr>, incl_self: bool) -> IoResult<()> {
         let node = self.borrow();
@@ -257,8 +408,12 @@ impl Serializable for Handle {
                         attrs.iter().map(|at| (&at.name, at.value.as_slice()))));
                 }
 
-                for handle in node.children.iter() {
-                    try!(handle.clone().serialize(serializer, true));
+                // A template's children are in its contents.
+                match node.template_contents {
+                    Some(ref contents) => try!(contents.serialize(serializer, false)),
+                    None => for handle in node.children.iter() {
+                        try!(handle.clone().serialize(serializer, true));
+                    },
                 }
 
                 if incl_self {
@@ -267,7 +422,7 @@ impl Serializable for Handle {
                 Ok(())
             }
 
-            (false, &Document) => {
+            (_, &Document) => {
                 for handle in node.children.iter() {
                     try!(handle.clone().serialize(serializer, true));
                 }
@@ -276,11 +431,144 @@ impl Serializable for Handle {
 
             (false, _) => Ok(()),
 
-            (true, &Doctype(ref name, _, _)) => serializer.write_doctype(name.as_slice()),
+            (true, &Doctype(ref name, _, _)) => match node.raw_doctype {
+                Some(ref raw) => serializer.write_raw_doctype(raw.as_slice()),
+                None => serializer.write_doctype(name.as_slice()),
+            },
             (true, &Text(ref text)) => serializer.write_text(text.as_slice()),
             (true, &Comment(ref text)) => serializer.write_comment(text.as_slice()),
+        }
+    }
+}
 
-            (true, &Document) => fail!("Can't serialize Document node itself"),
+/// Serializes the whole document, so `serialize(&mut writer, &dom, opts)`
+/// works.
+impl Serializable for RcDom {
+    fn serialize<'wr, Wr: Writer>(&self, serializer: &mut Serializer<'wr, Wr>, incl_self: bool) -> IoResult<()> {
+        self.document.serialize(serializer, incl_self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{RcDom, Handle};
+    use super::{append_child, insert_before, replace_with, remove_from_parent};
+
+    use sink::common::Text;
+    use driver::{parse, one_input, ParseOpts};
+    use tokenizer::TokenizerOpts;
+    use serialize::serialize;
+
+    use core::default::Default;
+    use collections::string::String;
+    use std::io::MemWriter;
+
+    fn text(node: Option<Handle>) -> String {
+        let node = node.expect("no node");
+        let node = node.borrow();
+        match node.node {
+            Text(ref t) => t.clone(),
+            _ => fail!("not a text node"),
         }
     }
+
+    #[test]
+    fn child_access() {
+        let dom: RcDom = parse(one_input(String::from_str("a<b></b>c")), Default::default());
+        let html = dom.document.borrow().first_child().unwrap();
+        let body = html.borrow().last_child().unwrap();
+        let body = body.borrow();
+        assert_eq!(body.children.len(), 3);
+
+        let b = body.nth_child(1).unwrap();
+        assert_eq!(b.borrow().index(), Some(1));
+        assert_eq!(text(b.borrow().previous_sibling()).as_slice(), "a");
+        assert_eq!(text(b.borrow().next_sibling()).as_slice(), "c");
+        assert!(body.nth_child(3).is_none());
+
+        let a = body.first_child().unwrap();
+        assert!(a.borrow().previous_sibling().is_none());
+        assert!(body.last_child().unwrap().borrow().next_sibling().is_none());
+        assert!(dom.document.borrow().next_sibling().is_none());
+    }
+
+    fn body(dom: &RcDom) -> Handle {
+        let html = dom.document.borrow().first_child().unwrap();
+        let body = html.borrow().last_child().unwrap();
+        body
+    }
+
+    fn to_html(node: &Handle) -> String {
+        let mut writer = MemWriter::new();
+        serialize(&mut writer, node, Default::default()).unwrap();
+        String::from_utf8(writer.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn mutation() {
+        let dom: RcDom = parse(one_input(String::from_str("<a></a><b></b><c><i></i></c>")),
+            Default::default());
+        let body = body(&dom);
+        let a = body.borrow().nth_child(0).unwrap();
+        let b = body.borrow().nth_child(1).unwrap();
+        let c = body.borrow().nth_child(2).unwrap();
+        let i = c.borrow().first_child().unwrap();
+
+        append_child(&a, i.clone());
+        assert!(c.borrow().children.is_empty());
+        assert_eq!(to_html(&body).as_slice(), "<a><i></i></a><b></b><c></c>");
+
+        insert_before(&b, c.clone());
+        assert_eq!(to_html(&body).as_slice(), "<a><i></i></a><c></c><b></b>");
+        assert_eq!(c.borrow().index(), Some(1));
+
+        // Moving a node within its parent.
+        insert_before(&c, b.clone());
+        assert_eq!(to_html(&body).as_slice(), "<a><i></i></a><b></b><c></c>");
+
+        replace_with(&a, i.clone());
+        assert!(a.borrow().parent.is_none() && a.borrow().children.is_empty());
+        assert_eq!(to_html(&body).as_slice(), "<i></i><b></b><c></c>");
+
+        remove_from_parent(&b);
+        remove_from_parent(&b);
+        assert_eq!(to_html(&body).as_slice(), "<i></i><c></c>");
+        assert_eq!(c.borrow().index(), Some(1));
+    }
+
+    #[test]
+    #[should_fail]
+    fn no_cycles() {
+        let dom: RcDom = parse(one_input(String::from_str("<a><b></b></a>")), Default::default());
+        let body = body(&dom);
+        let a = body.borrow().first_child().unwrap();
+        let b = a.borrow().first_child().unwrap();
+        append_child(&b, a.clone());
+    }
+
+    #[test]
+    fn serialize_dom() {
+        let dom: RcDom = parse(one_input(String::from_str("<!DOCTYPE html><title>x</title>a&lt;b")),
+            Default::default());
+        let mut writer = MemWriter::new();
+        serialize(&mut writer, &dom, Default::default()).unwrap();
+        assert_eq!(String::from_utf8(writer.unwrap()).unwrap().as_slice(),
+            "<!DOCTYPE html><html><head><title>x</title></head><body>a&lt;b</body></html>");
+    }
+
+    #[test]
+    fn serialize_raw_doctype() {
+        let opts = ParseOpts {
+            tokenizer: TokenizerOpts { raw_doctype: true, .. Default::default() },
+            .. Default::default()
+        };
+        let input = "<!doctype HTML PUBLIC \"-//W3C//DTD HTML 4.01//EN\" [junk] ><p>x";
+        let dom: RcDom = parse(one_input(String::from_str(input)), opts);
+        let mut writer = MemWriter::new();
+        serialize(&mut writer, &dom, Default::default()).unwrap();
+        assert_eq!(String::from_utf8(writer.unwrap()).unwrap().as_slice(),
+            "<!doctype HTML PUBLIC \"-//W3C//DTD HTML 4.01//EN\" [junk] >\
+             <html><head></head><body><p>x</p></body></html>");
+    }
 }
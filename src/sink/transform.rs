@@ -0,0 +1,471 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Passes which rewrite a finished parse tree.
+//!
+//! Each pass implements `Transform` for both of the bundled DOMs, and
+//! takes care of the parent pointers in `RcDom`.  Passes can be chained
+//! with a `Pipeline`:
+//!
+//! ```ignore
+//! let pipeline = Pipeline::new()
+//!     .then(RemoveEmptyText { whitespace: true })
+//!     .then(Unwrap { names: vec!(atom!(font)) });
+//! pipeline.run(&mut dom);
+//! ```
+
+use core::prelude::*;
+
+use sink::common::{NodeEnum, Text, Element};
+use sink::rcdom::{RcDom, Handle};
+use sink::owned_dom::{OwnedDom, Node};
+
+use tokenizer::Attribute;
+use util::str::is_ascii_whitespace;
+
+use core::mem;
+use alloc::boxed::Box;
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+
+use string_cache::Atom;
+
+/// A pass over a DOM subtree.
+///
+/// The node passed in is the root of the subtree and is never itself
+/// removed or replaced; passes only rearrange its descendants.
+pub trait Transform {
+    /// Rewrite the descendants of an `RcDom` node.
+    fn transform_rc(&self, node: &Handle);
+
+    /// Rewrite the descendants of an `OwnedDom` node.
+    fn transform_owned(&self, node: &mut Node);
+}
+
+/// A DOM which `Transform`s can be applied to.
+pub trait Transformable {
+    /// Apply a transformation to the whole document.
+    fn apply(&mut self, transform: &Transform);
+}
+
+impl Transformable for RcDom {
+    fn apply(&mut self, transform: &Transform) {
+        transform.transform_rc(&self.document);
+    }
+}
+
+impl Transformable for OwnedDom {
+    fn apply(&mut self, transform: &Transform) {
+        transform.transform_owned(&mut *self.document);
+    }
+}
+
+/// A sequence of passes, run in order.
+///
+/// A `Pipeline` is itself a `Transform`, so pipelines can be nested.
+pub struct Pipeline {
+    passes: Vec<Box<Transform + 'static>>,
+}
+
+impl Pipeline {
+    /// Create an empty pipeline.
+    pub fn new() -> Pipeline {
+        Pipeline {
+            passes: vec!(),
+        }
+    }
+
+    /// Add a pass to the end of the pipeline.
+    pub fn then<T: Transform + 'static>(mut self, pass: T) -> Pipeline {
+        self.passes.push(box pass as Box<Transform + 'static>);
+        self
+    }
+
+    /// Run every pass over a whole document.
+    pub fn run<Dom: Transformable>(&self, dom: &mut Dom) {
+        dom.apply(self);
+    }
+}
+
+impl Transform for Pipeline {
+    fn transform_rc(&self, node: &Handle) {
+        for pass in self.passes.iter() {
+            pass.transform_rc(node);
+        }
+    }
+
+    fn transform_owned(&self, node: &mut Node) {
+        for pass in self.passes.iter() {
+            pass.transform_owned(node);
+        }
+    }
+}
+
+// Detach the children of an `RcDom` node, so they can be rebuilt.
+fn take_rc_children(node: &Handle) -> Vec<Handle> {
+    mem::replace(&mut node.borrow_mut().children, vec!())
+}
+
+// Install a new list of children, fixing up their parent pointers.
+fn set_rc_children(node: &Handle, children: Vec<Handle>) {
+    for child in children.iter() {
+        child.borrow_mut().parent = Some(node.downgrade());
+    }
+    node.borrow_mut().children = children;
+}
+
+fn is_html_elem(node: &NodeEnum, names: &[Atom]) -> bool {
+    match *node {
+        Element(ref name, _) => name.ns == ns!(HTML) && names.contains(&name.local),
+        _ => false,
+    }
+}
+
+/// Remove text nodes with no content.
+pub struct RemoveEmptyText {
+    /// Also remove text nodes which consist only of ASCII whitespace.
+    /// Note that this changes the rendering of `<pre>` and friends.
+    pub whitespace: bool,
+}
+
+impl RemoveEmptyText {
+    fn is_empty(&self, node: &NodeEnum) -> bool {
+        match *node {
+            Text(ref text) => if self.whitespace {
+                text.as_slice().chars().all(is_ascii_whitespace)
+            } else {
+                text.is_empty()
+            },
+            _ => false,
+        }
+    }
+}
+
+impl Transform for RemoveEmptyText {
+    fn transform_rc(&self, node: &Handle) {
+        let mut kept = vec!();
+        for child in take_rc_children(node).into_iter() {
+            let empty = self.is_empty(&child.borrow().node);
+            if empty {
+                child.borrow_mut().parent = None;
+            } else {
+                self.transform_rc(&child);
+                kept.push(child);
+            }
+        }
+        set_rc_children(node, kept);
+    }
+
+    fn transform_owned(&self, node: &mut Node) {
+        node.children.retain(|child| !self.is_empty(&child.node));
+        for child in node.children.iter_mut() {
+            self.transform_owned(&mut **child);
+        }
+    }
+}
+
+/// Replace HTML elements with the given local names by their children.
+pub struct Unwrap {
+    pub names: Vec<Atom>,
+}
+
+impl Transform for Unwrap {
+    fn transform_rc(&self, node: &Handle) {
+        let mut new_children = vec!();
+        for child in take_rc_children(node).into_iter() {
+            // Work bottom-up, so nested matches are unwrapped too.
+            self.transform_rc(&child);
+            let unwrap = is_html_elem(&child.borrow().node, self.names.as_slice());
+            if unwrap {
+                child.borrow_mut().parent = None;
+                new_children.extend(take_rc_children(&child).into_iter());
+            } else {
+                new_children.push(child);
+            }
+        }
+        set_rc_children(node, new_children);
+        merge_rc_text(node);
+    }
+
+    fn transform_owned(&self, node: &mut Node) {
+        let mut new_children = vec!();
+        for mut child in mem::replace(&mut node.children, vec!()).into_iter() {
+            self.transform_owned(&mut *child);
+            if is_html_elem(&child.node, self.names.as_slice()) {
+                new_children.extend(mem::replace(&mut child.children, vec!()).into_iter());
+            } else {
+                new_children.push(child);
+            }
+        }
+        node.children = new_children;
+        merge_owned_text(node);
+    }
+}
+
+/// Replace every occurrence of `pattern` with `replacement` in the values
+/// of attributes named `name` (with no namespace).
+pub struct RewriteAttr {
+    pub name: Atom,
+    pub pattern: String,
+    pub replacement: String,
+}
+
+impl RewriteAttr {
+    fn rewrite(&self, node: &mut NodeEnum) {
+        let attrs = match *node {
+            Element(_, ref mut attrs) => attrs,
+            _ => return,
+        };
+
+        for attr in attrs.iter_mut() {
+            if attr.name.ns == ns!("") && attr.name.local == self.name
+                && attr.value.as_slice().contains(self.pattern.as_slice()) {
+                attr.value = attr.value.as_slice().replace(
+                    self.pattern.as_slice(), self.replacement.as_slice());
+            }
+        }
+    }
+}
+
+impl Transform for RewriteAttr {
+    fn transform_rc(&self, node: &Handle) {
+        self.rewrite(&mut node.borrow_mut().node);
+        for child in node.borrow().children.iter() {
+            self.transform_rc(child);
+        }
+    }
+
+    fn transform_owned(&self, node: &mut Node) {
+        self.rewrite(&mut node.node);
+        for child in node.children.iter_mut() {
+            self.transform_owned(&mut **child);
+        }
+    }
+}
+
+/// Remove the lines which wrap the contents of HTML `<script>` and
+/// `<style>` elements in a CDATA section, for XHTML compatibility, e.g.
+///
+/// ```ignore
+/// <script>//<![CDATA[
+/// run();
+/// //]]></script>
+/// ```
+///
+/// A line is removed if it consists only of `<![CDATA[` or `]]>`, along
+/// with whitespace and the comment punctuation used to hide them from
+/// scripts and old browsers.  Other lines are left alone, even if they
+/// contain those markers.
+pub struct StripCdataWrappers;
+
+// Is this line nothing but a CDATA marker, hidden inside comments?
+fn is_cdata_wrapper(line: &str) -> bool {
+    let rest = line.replace("<![CDATA[", "").as_slice().replace("]]>", "");
+    rest.len() < line.len() && rest.as_slice().chars()
+        .all(|c| is_ascii_whitespace(c) || "/*<!->".contains_char(c))
+}
+
+// Text with the wrapper lines removed, or `None` if there are none.
+fn strip_cdata_lines(text: &str) -> Option<String> {
+    if !text.contains("<![CDATA[") && !text.contains("]]>") {
+        return None;
+    }
+
+    let lines: Vec<&str> = text.split('\n').collect();
+    let mut out = String::with_capacity(text.len());
+    let mut changed = false;
+    for (i, line) in lines.iter().enumerate() {
+        if is_cdata_wrapper(*line) {
+            changed = true;
+            continue;
+        }
+        out.push_str(*line);
+        if i + 1 < lines.len() {
+            out.push('\n');
+        }
+    }
+
+    if changed { Some(out) } else { None }
+}
+
+impl StripCdataWrappers {
+    fn strip(&self, parent: &NodeEnum, node: &mut NodeEnum) {
+        if !is_html_elem(parent, &[atom!(script), atom!(style)]) {
+            return;
+        }
+        match *node {
+            Text(ref mut text) => match strip_cdata_lines(text.as_slice()) {
+                Some(stripped) => *text = stripped,
+                None => (),
+            },
+            _ => (),
+        }
+    }
+}
+
+impl Transform for StripCdataWrappers {
+    fn transform_rc(&self, node: &Handle) {
+        let node = node.borrow();
+        for child in node.children.iter() {
+            self.strip(&node.node, &mut child.borrow_mut().node);
+            self.transform_rc(child);
+        }
+    }
+
+    fn transform_owned(&self, node: &mut Node) {
+        for child in node.children.iter_mut() {
+            self.strip(&node.node, &mut child.node);
+            self.transform_owned(&mut **child);
+        }
+    }
+}
+
+/// Merge adjacent sibling HTML elements which have the same name (one of
+/// `names`) and the same attributes, e.g. `<b>x</b><b>y</b>` becomes
+/// `<b>xy</b>`.
+pub struct MergeAdjacent {
+    pub names: Vec<Atom>,
+}
+
+impl MergeAdjacent {
+    fn can_merge(&self, prev: &NodeEnum, next: &NodeEnum) -> bool {
+        match (prev, next) {
+            (&Element(ref n1, ref a1), &Element(ref n2, ref a2)) => {
+                n1 == n2 && is_html_elem(prev, self.names.as_slice()) && same_attrs(a1, a2)
+            }
+            _ => false,
+        }
+    }
+}
+
+// Attribute order doesn't matter, but we're not going to see enough
+// attributes here to make sorting worthwhile.
+fn same_attrs(x: &Vec<Attribute>, y: &Vec<Attribute>) -> bool {
+    x.len() == y.len() && x.iter().all(|a| y.contains(a))
+}
+
+impl Transform for MergeAdjacent {
+    fn transform_rc(&self, node: &Handle) {
+        let mut new_children: Vec<Handle> = vec!();
+        for child in take_rc_children(node).into_iter() {
+            let merge = match new_children.last() {
+                Some(prev) => self.can_merge(&prev.borrow().node, &child.borrow().node),
+                None => false,
+            };
+            if merge {
+                let prev = new_children.last().unwrap();
+                child.borrow_mut().parent = None;
+                let moved = take_rc_children(&child);
+                let mut all = take_rc_children(prev);
+                all.extend(moved.into_iter());
+                set_rc_children(prev, all);
+            } else {
+                new_children.push(child);
+            }
+        }
+        set_rc_children(node, new_children);
+
+        // Recurse after merging, so the combined children get merged too.
+        for child in node.borrow().children.iter() {
+            self.transform_rc(child);
+        }
+        merge_rc_text(node);
+    }
+
+    fn transform_owned(&self, node: &mut Node) {
+        let mut new_children: Vec<Box<Node>> = vec!();
+        for mut child in mem::replace(&mut node.children, vec!()).into_iter() {
+            let merge = match new_children.last() {
+                Some(prev) => self.can_merge(&prev.node, &child.node),
+                None => false,
+            };
+            if merge {
+                let prev = new_children.last_mut().unwrap();
+                let moved = mem::replace(&mut child.children, vec!());
+                prev.children.extend(moved.into_iter());
+            } else {
+                new_children.push(child);
+            }
+        }
+        node.children = new_children;
+
+        for child in node.children.iter_mut() {
+            self.transform_owned(&mut **child);
+        }
+        merge_owned_text(node);
+    }
+}
+
+// Unwrapping and merging can leave text nodes next to each other.
+// Join them so the result looks like something the parser would produce.
+
+fn merge_rc_text(node: &Handle) {
+    let mut new_children: Vec<Handle> = vec!();
+    for child in take_rc_children(node).into_iter() {
+        let appended = match (new_children.last(), &child.borrow().node) {
+            (Some(prev), &Text(ref text)) => match prev.borrow_mut().node {
+                Text(ref mut existing) => {
+                    existing.push_str(text.as_slice());
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        };
+        if appended {
+            child.borrow_mut().parent = None;
+        } else {
+            new_children.push(child);
+        }
+    }
+    set_rc_children(node, new_children);
+}
+
+fn merge_owned_text(node: &mut Node) {
+    let mut new_children: Vec<Box<Node>> = vec!();
+    for child in mem::replace(&mut node.children, vec!()).into_iter() {
+        let appended = match (new_children.last_mut(), &child.node) {
+            (Some(prev), &Text(ref text)) => match prev.node {
+                Text(ref mut existing) => {
+                    existing.push_str(text.as_slice());
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        };
+        if !appended {
+            new_children.push(child);
+        }
+    }
+    node.children = new_children;
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::strip_cdata_lines;
+
+    use collections::string::String;
+
+    fn strip(text: &str) -> Option<String> {
+        strip_cdata_lines(text)
+    }
+
+    #[test]
+    fn cdata_wrappers() {
+        assert_eq!(strip("//<![CDATA[\nrun();\n//]]>"), Some(String::from_str("run();\n")));
+        assert_eq!(strip("\n/*<![CDATA[*/\nb {}\n/*]]>*/\n"), Some(String::from_str("\nb {}\n")));
+        assert_eq!(strip("<!--//--><![CDATA[//><!--\nx\n//--><!]]>"),
+            Some(String::from_str("x\n")));
+        assert_eq!(strip("run();"), None);
+        assert_eq!(strip("var s = ']]>';"), None);
+    }
+}
@@ -0,0 +1,261 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Callbacks for particular elements, as soon as they are created.
+//!
+//! `WatchSink` wraps any `TreeSink`.  Each registered `ElementWatcher`
+//! sees the elements with the name it asked for, with their attributes,
+//! when the start tag is processed.  A watcher can stop the parse there,
+//! so that e.g. finding a `<meta http-equiv=refresh>` doesn't cost
+//! parsing the rest of the document.
+
+use core::prelude::*;
+
+use tokenizer::{Attribute, ParseErrorKind};
+use tree_builder::{TreeSink, QuirksMode, NodeOrText};
+use tree_builder::{ElemProvenance, AuditIssue, InsertionMode, Token, StepOutcome, SplitStatus};
+use tree_builder::ScriptAction;
+
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+use collections::str::MaybeOwned;
+
+use string_cache::{Atom, QualName};
+
+/// Whether to go on parsing after an `ElementWatcher` is called.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum WatchResult {
+    KeepParsing,
+    StopParsing,
+}
+
+/// Something interested in elements with a particular name.
+pub trait ElementWatcher<Handle> {
+    /// An element was created.  It has not been inserted into the tree
+    /// yet.  If this returns `StopParsing`, the element is inserted and
+    /// then parsing stops, as if the sink had aborted.
+    fn element_created(&mut self, elem: &Handle, name: &QualName,
+        attrs: &[Attribute]) -> WatchResult;
+}
+
+/// A `TreeSink` which wraps another, calling `ElementWatcher`s.
+pub struct WatchSink<'w, Handle, Sink> {
+    sink: Sink,
+    watchers: Vec<(QualName, &'w mut (ElementWatcher<Handle> + 'w))>,
+    stopped: bool,
+}
+
+impl<'w, Handle, Sink: TreeSink<Handle>> WatchSink<'w, Handle, Sink> {
+    pub fn new(sink: Sink) -> WatchSink<'w, Handle, Sink> {
+        WatchSink {
+            sink: sink,
+            watchers: vec!(),
+            stopped: false,
+        }
+    }
+
+    /// Call `watcher` for each element named `name`.  Watchers are called
+    /// in the order they were added.
+    pub fn watch(&mut self, name: QualName, watcher: &'w mut (ElementWatcher<Handle> + 'w)) {
+        self.watchers.push((name, watcher));
+    }
+
+    /// Did a watcher stop the parse?
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Get back the wrapped sink.
+    pub fn unwrap(self) -> Sink {
+        self.sink
+    }
+}
+
+impl<'w, Handle, Sink: TreeSink<Handle>> TreeSink<Handle> for WatchSink<'w, Handle, Sink> {
+    fn parse_error(&mut self, msg: MaybeOwned<'static>) {
+        self.sink.parse_error(msg);
+    }
+
+    fn classified_parse_error(&mut self, kind: ParseErrorKind, msg: MaybeOwned<'static>) {
+        self.sink.classified_parse_error(kind, msg);
+    }
+
+    fn get_document(&mut self) -> Handle {
+        self.sink.get_document()
+    }
+
+    fn same_node(&self, x: Handle, y: Handle) -> bool {
+        self.sink.same_node(x, y)
+    }
+
+    fn elem_name(&self, target: Handle) -> QualName {
+        self.sink.elem_name(target)
+    }
+
+    fn get_template_contents(&mut self, target: Handle) -> Handle {
+        self.sink.get_template_contents(target)
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.sink.set_quirks_mode(mode);
+    }
+
+    fn query_quirks_override(&mut self, mode: QuirksMode) -> Option<QuirksMode> {
+        self.sink.query_quirks_override(mode)
+    }
+
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle {
+        if self.watchers.is_empty() {
+            return self.sink.create_element(name, attrs);
+        }
+
+        // The element takes ownership of the attributes, so watchers get
+        // a copy.  Only pay for that when someone is watching.
+        let watched = self.watchers.iter().any(|&(ref n, _)| *n == name);
+        let copy = if watched { attrs.clone() } else { vec!() };
+        let elem = self.sink.create_element(name.clone(), attrs);
+
+        if watched {
+            for pair in self.watchers.iter_mut() {
+                let (ref n, ref mut watcher) = *pair;
+                if *n == name && !self.stopped {
+                    if watcher.element_created(&elem, &name, copy.as_slice()) == StopParsing {
+                        self.stopped = true;
+                    }
+                }
+            }
+        }
+        elem
+    }
+
+    fn create_comment(&mut self, text: String) -> Handle {
+        self.sink.create_comment(text)
+    }
+
+    fn append(&mut self, parent: Handle, child: NodeOrText<Handle>) {
+        self.sink.append(parent, child);
+    }
+
+    fn append_text_with_status(&mut self, parent: Handle, text: String, status: SplitStatus) {
+        self.sink.append_text_with_status(parent, text, status);
+    }
+
+    fn append_before_sibling(&mut self,
+            sibling: Handle,
+            new_node: NodeOrText<Handle>) -> Result<(), NodeOrText<Handle>> {
+        self.sink.append_before_sibling(sibling, new_node)
+    }
+
+    fn append_doctype_to_document(&mut self, name: String, public_id: String, system_id: String) {
+        self.sink.append_doctype_to_document(name, public_id, system_id);
+    }
+
+    fn append_raw_doctype_to_document(&mut self, name: String, public_id: String,
+            system_id: String, raw: String) {
+        self.sink.append_raw_doctype_to_document(name, public_id, system_id, raw);
+    }
+
+    fn add_attrs_if_missing(&mut self, target: Handle, attrs: Vec<Attribute>) {
+        self.sink.add_attrs_if_missing(target, attrs);
+    }
+
+    fn remove_from_parent(&mut self, target: Handle) {
+        self.sink.remove_from_parent(target);
+    }
+
+    fn reparent_children(&mut self, node: Handle, new_parent: Handle) {
+        self.sink.reparent_children(node, new_parent);
+    }
+
+    fn mark_script_already_started(&mut self, node: Handle) {
+        self.sink.mark_script_already_started(node);
+    }
+
+    fn complete_script(&mut self, node: Handle) -> ScriptAction {
+        self.sink.complete_script(node)
+    }
+
+    fn element_provenance(&mut self, elem: Handle, provenance: ElemProvenance) {
+        self.sink.element_provenance(elem, provenance);
+    }
+
+    fn step_taken(&mut self, mode: InsertionMode, token: &Token, outcome: StepOutcome) {
+        self.sink.step_taken(mode, token, outcome);
+    }
+
+    fn audit(&mut self, issue: AuditIssue<Handle>) {
+        self.sink.audit(issue);
+    }
+
+    fn void_end_tag_ignored(&mut self, name: Atom) {
+        self.sink.void_end_tag_ignored(name);
+    }
+
+    fn aborted(&self) -> bool {
+        self.stopped || self.sink.aborted()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{WatchSink, ElementWatcher, WatchResult, KeepParsing, StopParsing};
+
+    use tokenizer::Attribute;
+    use sink::rcdom::{RcDom, Handle};
+    use driver::{parse_to, one_input};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    use string_cache::{Atom, QualName};
+
+    struct Refresh {
+        urls: Vec<String>,
+    }
+
+    impl ElementWatcher<Handle> for Refresh {
+        fn element_created(&mut self, _elem: &Handle, _name: &QualName,
+                attrs: &[Attribute]) -> WatchResult {
+            let is_refresh = attrs.iter().any(|a|
+                a.name.local == Atom::from_slice("http-equiv") && a.value.as_slice() == "refresh");
+            if !is_refresh {
+                return KeepParsing;
+            }
+            match attrs.iter().find(|a| a.name == qualname!("", content)) {
+                Some(a) => self.urls.push(a.value.clone()),
+                None => (),
+            }
+            StopParsing
+        }
+    }
+
+    #[test]
+    fn stop_at_refresh() {
+        let mut refresh = Refresh { urls: vec!() };
+        let dom = {
+            let mut sink = WatchSink::new(RcDom::default());
+            sink.watch(qualname!(HTML, meta), &mut refresh);
+            parse_to(&mut sink, one_input(String::from_str(concat!(
+                "<meta charset=utf-8><meta http-equiv=refresh content='0; url=/a'>",
+                "<meta http-equiv=refresh content='0; url=/b'><body><p>Never parsed"))),
+                Default::default());
+            assert!(sink.stopped());
+            sink.unwrap()
+        };
+
+        assert_eq!(refresh.urls, vec!(String::from_str("0; url=/a")));
+
+        // The <html> element has only a <head>.
+        let html = dom.document.borrow().children[0].clone();
+        assert_eq!(html.borrow().children.len(), 1);
+    }
+}
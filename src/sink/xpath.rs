@@ -0,0 +1,355 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A small subset of XPath, for picking nodes out of a parse tree.
+//!
+//! ```ignore
+//! let cells = XPath::compile("/html/body//table[2]/tbody/tr/td[@class='price']")
+//!     .unwrap().select(&dom.document);
+//! ```
+//!
+//! A path is a series of steps separated by `/`, or by `//` to look at
+//! all descendants.  A step is an element name, `*` for any element,
+//! `text()` for text nodes, or `.` for the node itself.  Steps other
+//! than `.` may have predicates:
+//!
+//! * `[2]`, the second match among each parent's children, counting
+//!   from 1, and `[last()]`;
+//! * `[@name]`, elements with an attribute `name`;
+//! * `[@name='value']` or `[@name="value"]`.
+//!
+//! Predicates apply in turn, so `td[@class='x'][1]` is the first of the
+//! `td`s with that class.  Names match an element's local name in any
+//! namespace, and attributes with no namespace.  The path starts from
+//! the node `select` is given, whether or not it begins with `/`.
+//!
+//! Remember that the tree builder adds elements the source may leave
+//! out, such as `<tbody>`.  Results come in document order, each once.
+
+use core::prelude::*;
+
+use sink::common::{Text, Element};
+use sink::query::QueryNode;
+
+use core::char;
+use collections::{MutableSeq, MutableMap, MutableSet};
+use collections::vec::Vec;
+use collections::treemap::{TreeMap, TreeSet};
+use collections::string::String;
+use std::from_str::from_str;
+
+use string_cache::Atom;
+
+enum NodeTest {
+    NameTest(Atom),
+    AnyElementTest,
+    TextTest,
+    SelfTest,
+}
+
+enum Predicate {
+    Position(uint),
+    Last,
+    HasAttr(Atom),
+    AttrEquals(Atom, String),
+}
+
+struct Step {
+    /// Reached by `//`?
+    descendants: bool,
+    test: NodeTest,
+    predicates: Vec<Predicate>,
+}
+
+/// A compiled path.
+pub struct XPath {
+    steps: Vec<Step>,
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_' || c == ':' || c == '.'
+}
+
+// A cursor over the text of a path.
+struct PathParser<'a> {
+    text: &'a str,
+    pos: uint,
+}
+
+impl<'a> PathParser<'a> {
+    fn peek(&self) -> Option<char> {
+        if self.pos < self.text.len() {
+            Some(self.text.char_at(self.pos))
+        } else {
+            None
+        }
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if self.text.slice_from(self.pos).starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), String> {
+        if self.eat(s) {
+            Ok(())
+        } else {
+            Err(format!("expected {} at offset {}", s, self.pos))
+        }
+    }
+
+    fn take_while(&mut self, pred: |char| -> bool) -> &'a str {
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                Some(c) if pred(c) => self.pos += char::len_utf8_bytes(c),
+                _ => break,
+            }
+        }
+        self.text.slice(start, self.pos)
+    }
+
+    fn name(&mut self) -> Result<Atom, String> {
+        match self.take_while(is_name_char) {
+            "" => Err(format!("expected a name at offset {}", self.pos)),
+            name => Ok(Atom::from_slice(name)),
+        }
+    }
+
+    fn predicate(&mut self) -> Result<Predicate, String> {
+        if self.eat("last()") {
+            return Ok(Last);
+        }
+        if self.eat("@") {
+            let name = try!(self.name());
+            if !self.eat("=") {
+                return Ok(HasAttr(name));
+            }
+            let quote = match self.peek() {
+                Some(q) if q == '\'' || q == '"' => q,
+                _ => return Err(format!("expected a quoted value at offset {}", self.pos)),
+            };
+            self.pos += 1;
+            let value = String::from_str(self.take_while(|c| c != quote));
+            try!(self.expect(if quote == '"' { "\"" } else { "'" }));
+            return Ok(AttrEquals(name, value));
+        }
+        let start = self.pos;
+        match from_str(self.take_while(|c| c.is_digit())) {
+            Some(n) if n > 0 => Ok(Position(n)),
+            _ => Err(format!("expected a predicate at offset {}", start)),
+        }
+    }
+
+    fn step(&mut self, descendants: bool) -> Result<Step, String> {
+        let test = if self.eat("text()") {
+            TextTest
+        } else if self.eat("*") {
+            AnyElementTest
+        } else if self.eat(".") {
+            SelfTest
+        } else {
+            NameTest(try!(self.name()))
+        };
+
+        let mut predicates = vec!();
+        while self.eat("[") {
+            match test {
+                SelfTest => return Err(String::from_str("`.` can't have predicates")),
+                _ => (),
+            }
+            predicates.push(try!(self.predicate()));
+            try!(self.expect("]"));
+        }
+
+        Ok(Step {
+            descendants: descendants,
+            test: test,
+            predicates: predicates,
+        })
+    }
+}
+
+impl NodeTest {
+    fn matches<N: QueryNode>(&self, node: &N) -> bool {
+        node.with_node(|n| match (self, n) {
+            (&NameTest(ref name), &Element(ref qn, _)) => qn.local == *name,
+            (&AnyElementTest, &Element(..)) | (&TextTest, &Text(_)) => true,
+            _ => false,
+        })
+    }
+}
+
+impl Predicate {
+    // Filter the nodes one step found under one parent.
+    fn apply<N: QueryNode>(&self, nodes: Vec<N>) -> Vec<N> {
+        match *self {
+            Position(n) => nodes.into_iter().skip(n - 1).take(1).collect(),
+            Last => nodes.into_iter().last().into_iter().collect(),
+            HasAttr(ref name) => nodes.into_iter()
+                .filter(|n| n.get_attribute(name.clone()).is_some()).collect(),
+            AttrEquals(ref name, ref value) => nodes.into_iter()
+                .filter(|n| n.get_attribute(name.clone()).as_ref() == Some(value)).collect(),
+        }
+    }
+}
+
+impl XPath {
+    /// Parse a path.  The error says what was wrong, and where.
+    pub fn compile(path: &str) -> Result<XPath, String> {
+        let mut parser = PathParser { text: path, pos: 0 };
+        let mut steps = vec!();
+        let mut descendants = parser.eat("//");
+        if !descendants {
+            parser.eat("/");
+        }
+        loop {
+            steps.push(try!(parser.step(descendants)));
+            if parser.peek().is_none() {
+                break;
+            }
+            descendants = parser.eat("//");
+            if !descendants {
+                try!(parser.expect("/"));
+            }
+        }
+        Ok(XPath { steps: steps })
+    }
+
+    /// The nodes the path leads to from `node`.
+    pub fn select<N: QueryNode>(&self, node: &N) -> Vec<N> {
+        let mut context = vec!(node.clone());
+        for step in self.steps.iter() {
+            // `//` from more than one node can reach a node twice.
+            let mut seen = TreeSet::new();
+            let mut found: Vec<N> = vec!();
+            for start in context.iter() {
+                let mut origins = vec!(start.clone());
+                if step.descendants {
+                    origins.extend(start.descendants());
+                }
+                for origin in origins.iter() {
+                    let mut matches = match step.test {
+                        SelfTest => vec!(origin.clone()),
+                        _ => origin.children().into_iter()
+                            .filter(|n| step.test.matches(n)).collect(),
+                    };
+                    for pred in step.predicates.iter() {
+                        matches = pred.apply(matches);
+                    }
+                    for n in matches.into_iter() {
+                        if seen.insert(n.node_id()) {
+                            found.push(n);
+                        }
+                    }
+                }
+            }
+            context = found;
+        }
+
+        // After `//`, nested nodes can be found out of order, e.g. by
+        // `//div/p` with one `<div>` in another.
+        if context.len() > 1 && self.steps.iter().any(|s| s.descendants) {
+            let mut order = TreeMap::new();
+            order.insert(node.node_id(), 0u);
+            for (i, n) in node.descendants().enumerate() {
+                order.insert(n.node_id(), i + 1);
+            }
+            let mut keyed: Vec<(uint, N)> = context.into_iter()
+                .map(|n| (*order.find(&n.node_id()).unwrap(), n)).collect();
+            keyed.sort_by(|&(i, _), &(j, _)| i.cmp(&j));
+            context = keyed.into_iter().map(|(_, n)| n).collect();
+        }
+        context
+    }
+}
+
+/// Compile `path` and select from `node` with it.
+pub fn select<N: QueryNode>(node: &N, path: &str) -> Result<Vec<N>, String> {
+    XPath::compile(path).map(|p| p.select(node))
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{XPath, select};
+
+    use sink::query::QueryNode;
+    use sink::common::{Text, Element};
+    use sink::rcdom::{RcDom, Handle};
+    use sink::owned_dom::OwnedDom;
+    use driver::{parse, one_input};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    static INPUT: &'static str = "\
+        <table><tr><td>a</td></tr></table>\
+        <table><tr><td class=x>b</td><td>c</td></tr>\
+               <tr><td class=x>d</td><td class=y>e</td></tr></table>\
+        <div><div><p>f</p></div><p>g</p></div>";
+
+    // What each node says about itself: an element's name, or a text
+    // node's text.
+    fn describe<N: QueryNode>(nodes: Vec<N>) -> Vec<String> {
+        nodes.iter().map(|n| n.with_node(|n| match *n {
+            Element(ref name, _) => String::from_str(name.local.as_slice()),
+            Text(ref text) => text.clone(),
+            _ => String::from_str("?"),
+        })).collect()
+    }
+
+    fn rc(path: &str) -> Vec<String> {
+        let dom: RcDom = parse(one_input(String::from_str(INPUT)), Default::default());
+        let nodes: Vec<Handle> = select(&dom.document, path).unwrap();
+        describe(nodes)
+    }
+
+    fn texts(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| String::from_str(*s)).collect()
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(rc("/html/body/table"), texts(&["table", "table"]));
+        assert_eq!(rc("/html/body//table[2]//td/text()"), texts(&["b", "c", "d", "e"]));
+        assert_eq!(rc("//tr[last()]/td[1]/text()"), texts(&["a", "d"]));
+        assert_eq!(rc("//td[@class][2]/text()"), texts(&["e"]));
+        assert_eq!(rc("//td[@class='x']/text()"), texts(&["b", "d"]));
+        assert_eq!(rc("//td[@class=\"y\"]/./text()"), texts(&["e"]));
+        assert_eq!(rc("html/body/*[3]/*"), texts(&["div", "p"]));
+        // Nested matches are found once each, in document order.
+        assert_eq!(rc("//div//p/text()"), texts(&["f", "g"]));
+        assert_eq!(rc("//div/p/text()"), texts(&["f", "g"]));
+        assert_eq!(rc("/html/body/span"), texts(&[]));
+    }
+
+    #[test]
+    fn owned_dom() {
+        let dom: OwnedDom = parse(one_input(String::from_str(INPUT)), Default::default());
+        let path = XPath::compile("//table[2]/tbody/tr[2]/td").unwrap();
+        assert_eq!(describe(path.select(&&*dom.document)), texts(&["td", "td"]));
+    }
+
+    #[test]
+    fn errors() {
+        let err = |path: &str| XPath::compile(path).err().expect("compiled a bad path");
+        assert_eq!(err("/html/").as_slice(), "expected a name at offset 6");
+        assert_eq!(err("//td[0]").as_slice(), "expected a predicate at offset 5");
+        assert_eq!(err("//td[@class='x]").as_slice(), "expected ' at offset 15");
+        assert_eq!(err("a[1").as_slice(), "expected ] at offset 3");
+        assert_eq!(err("a b").as_slice(), "expected / at offset 1");
+        assert!(XPath::compile(".[1]").is_err());
+    }
+}
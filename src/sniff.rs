@@ -0,0 +1,379 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Deciding whether unlabeled bytes are HTML, following the HTML part of
+//! the [MIME Sniffing](https://mimesniff.spec.whatwg.org/) standard, and
+//! which character encoding they declare.
+
+use core::prelude::*;
+
+use util::str::AsciiExt;
+
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+
+//§ rules-for-identifying-an-unknown-mime-type
+// Each pattern must be followed by a tag-terminating byte.
+static HTML_PATTERNS: &'static [&'static [u8]] = &[
+    b"<!DOCTYPE HTML", b"<HTML", b"<HEAD", b"<SCRIPT", b"<IFRAME", b"<H1",
+    b"<DIV", b"<FONT", b"<TABLE", b"<A", b"<STYLE", b"<TITLE", b"<B",
+    b"<BODY", b"<BR", b"<P", b"<!--",
+];
+
+fn is_whitespace_byte(b: u8) -> bool {
+    match b {
+        b'\t' | b'\n' | b'\x0C' | b'\r' | b' ' => true,
+        _ => false,
+    }
+}
+
+fn is_tag_terminating_byte(b: u8) -> bool {
+    b == b' ' || b == b'>'
+}
+
+/// Would the MIME sniffing algorithm identify these bytes, the start of a
+/// resource with an unknown type, as `text/html`?
+///
+/// Only the first 512 bytes (the resource header) are examined.  This
+/// doesn't check for byte order marks or binary data; anything which
+/// doesn't start with one of a fixed set of HTML tags is rejected.
+pub fn looks_like_html(bytes: &[u8]) -> bool {
+    let header = bytes.slice_to(if bytes.len() < 512 { bytes.len() } else { 512 });
+    let start = match header.iter().position(|&b| !is_whitespace_byte(b)) {
+        Some(i) => i,
+        None => return false,
+    };
+    let header = header.slice_from(start);
+
+    HTML_PATTERNS.iter().any(|pat| {
+        let n = pat.len();
+        header.len() > n
+            && header.slice_to(n).eq_ignore_ascii_case(*pat)
+            && is_tag_terminating_byte(header[n])
+    })
+}
+//§ END
+
+/// Where a character encoding label came from.  Earlier sources take
+/// precedence over later ones.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum EncodingSource {
+    /// A byte order mark at the start of the input.
+    FromBom,
+
+    /// The transport layer, e.g. the `charset` parameter of an HTTP
+    /// `Content-Type` header.
+    FromTransport,
+
+    /// A `<meta charset>` or `<meta http-equiv=Content-Type>` within the
+    /// first 1024 bytes.
+    FromMeta,
+}
+
+/// A declared encoding which lost to one from a source with higher
+/// precedence.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct EncodingConflict {
+    pub source: EncodingSource,
+
+    /// The label, trimmed and lowercased.
+    pub label: String,
+}
+
+/// Which declared encoding applies to a document, and which other
+/// declarations disagreed with it.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct EncodingDecision {
+    /// The winning label and where it came from, or `None` if no
+    /// encoding was declared at all.
+    pub declared: Option<(EncodingSource, String)>,
+
+    /// Declarations with a different label from the winner, in order
+    /// of precedence.
+    pub conflicts: Vec<EncodingConflict>,
+}
+
+//§ determining-the-character-encoding
+/// Decide which declared encoding applies to a document, given its first
+/// bytes and the charset from the transport layer, if any.
+///
+/// Labels are compared after trimming and lowercasing; aliases such as
+/// `latin1` and `iso-8859-1` aren't resolved, so they count as a
+/// conflict.  The bytes aren't decoded; that's up to the caller.
+pub fn decide_encoding(bytes: &[u8], transport: Option<&str>) -> EncodingDecision {
+    let normalize = |label: &str| label.trim().to_ascii_lower();
+
+    let mut found = vec!();
+    match bom_encoding(bytes) {
+        Some(label) => found.push((FromBom, String::from_str(label))),
+        None => (),
+    }
+    match transport {
+        Some(label) => found.push((FromTransport, normalize(label))),
+        None => (),
+    }
+    match prescan_meta(bytes) {
+        Some(label) => found.push((FromMeta, normalize(label.as_slice()))),
+        None => (),
+    }
+
+    let mut found = found.into_iter();
+    let declared = found.next();
+    let conflicts = match declared {
+        None => vec!(),
+        Some((_, ref winner)) => found
+            .filter(|&(_, ref label)| label != winner)
+            .map(|(source, label)| EncodingConflict {
+                source: source,
+                label: label,
+            })
+            .collect(),
+    };
+
+    EncodingDecision {
+        declared: declared,
+        conflicts: conflicts,
+    }
+}
+
+fn bom_encoding(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\xEF\xBB\xBF") {
+        Some("utf-8")
+    } else if bytes.starts_with(b"\xFE\xFF") {
+        Some("utf-16be")
+    } else if bytes.starts_with(b"\xFF\xFE") {
+        Some("utf-16le")
+    } else {
+        None
+    }
+}
+
+fn is_ascii_letter(b: u8) -> bool {
+    match b {
+        b'a' ... b'z' | b'A' ... b'Z' => true,
+        _ => false,
+    }
+}
+
+fn starts_with_ignore_case(bytes: &[u8], pat: &[u8]) -> bool {
+    bytes.len() >= pat.len() && bytes.slice_to(pat.len()).eq_ignore_ascii_case(pat)
+}
+
+fn find(bytes: &[u8], pat: &[u8]) -> Option<uint> {
+    range(0, bytes.len()).find(|&i| bytes.slice_from(i).starts_with(pat))
+}
+
+fn skip_whitespace(bytes: &[u8], i: &mut uint) {
+    while *i < bytes.len() && is_whitespace_byte(bytes[*i]) {
+        *i += 1;
+    }
+}
+
+//§ concept-get-attributes-when-sniffing
+// Get the next attribute of a tag, with its name lowercased.  Returns
+// `None` at the end of the tag.
+fn get_attribute(bytes: &[u8], i: &mut uint) -> Option<(Vec<u8>, Vec<u8>)> {
+    while *i < bytes.len() && (is_whitespace_byte(bytes[*i]) || bytes[*i] == b'/') {
+        *i += 1;
+    }
+    if *i >= bytes.len() || bytes[*i] == b'>' {
+        return None;
+    }
+
+    let start = *i;
+    *i += 1;
+    while *i < bytes.len() && match bytes[*i] {
+        b'=' | b'/' | b'>' => false,
+        b => !is_whitespace_byte(b),
+    } {
+        *i += 1;
+    }
+    let name = bytes.slice(start, *i).to_ascii_lower();
+
+    skip_whitespace(bytes, i);
+    if *i >= bytes.len() || bytes[*i] != b'=' {
+        return Some((name, vec!()));
+    }
+    *i += 1;
+    skip_whitespace(bytes, i);
+    if *i >= bytes.len() {
+        return None;
+    }
+
+    let value = match bytes[*i] {
+        quote @ b'"' | quote @ b'\'' => {
+            let start = *i + 1;
+            let len = unwrap_or_return!(bytes.slice_from(start).iter().position(|&b| b == quote),
+                None);
+            *i = start + len + 1;
+            bytes.slice(start, start + len)
+        }
+        _ => {
+            let start = *i;
+            while *i < bytes.len() && bytes[*i] != b'>' && !is_whitespace_byte(bytes[*i]) {
+                *i += 1;
+            }
+            bytes.slice(start, *i)
+        }
+    };
+    Some((name, value.to_ascii_lower()))
+}
+
+//§ extracting-character-encodings-from-meta-elements
+/// The encoding label in the `content` of a `<meta http-equiv=content-type>`,
+/// e.g. `koi8-r` from `text/html; charset=koi8-r`, if there is one.
+pub fn charset_from_content(content: &[u8]) -> Option<Vec<u8>> {
+    let mut i = unwrap_or_return!(find(content, b"charset"), None) + 7;
+    skip_whitespace(content, &mut i);
+    if i >= content.len() || content[i] != b'=' {
+        // Look for another "charset" after this one.
+        return charset_from_content(content.slice_from(i));
+    }
+    i += 1;
+    skip_whitespace(content, &mut i);
+    if i >= content.len() {
+        return None;
+    }
+    match content[i] {
+        quote @ b'"' | quote @ b'\'' => {
+            let rest = content.slice_from(i + 1);
+            rest.iter().position(|&b| b == quote).map(|end| rest.slice_to(end).to_vec())
+        }
+        _ => {
+            let rest = content.slice_from(i);
+            let end = rest.iter()
+                .position(|&b| b == b';' || is_whitespace_byte(b))
+                .unwrap_or(rest.len());
+            Some(rest.slice_to(end).to_vec())
+        }
+    }
+}
+
+//§ prescan-a-byte-stream-to-determine-its-encoding
+// Look for an encoding declared by a `<meta>` in the first 1024 bytes.
+fn prescan_meta(bytes: &[u8]) -> Option<String> {
+    let bytes = bytes.slice_to(if bytes.len() < 1024 { bytes.len() } else { 1024 });
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = bytes.slice_from(i);
+        if rest.starts_with(b"<!--") {
+            // Move to the '>' which ends the comment.
+            i += 2 + unwrap_or_return!(find(rest.slice_from(2), b"-->"), None) + 2;
+        } else if starts_with_ignore_case(rest, b"<meta") && rest.len() > 5
+                && (is_whitespace_byte(rest[5]) || rest[5] == b'/') {
+            i += 5;
+            let mut charset = None;
+            let mut pragma = false;
+            let mut content_charset = None;
+            loop {
+                let (name, value) = match get_attribute(bytes, &mut i) {
+                    Some(attr) => attr,
+                    None => break,
+                };
+                let name = name.as_slice();
+                if name == b"charset" && charset.is_none() {
+                    charset = Some(value);
+                } else if name == b"http-equiv" && value.as_slice() == b"content-type" {
+                    pragma = true;
+                } else if name == b"content" && content_charset.is_none() {
+                    content_charset = charset_from_content(value.as_slice());
+                }
+            }
+            let found = match charset {
+                Some(c) => Some(c),
+                None if pragma => content_charset,
+                None => None,
+            };
+            match found {
+                Some(label) => return String::from_utf8(label).ok(),
+                None => (),
+            }
+        } else if rest.len() > 1 && rest[0] == b'<' && is_ascii_letter(rest[1])
+                || rest.len() > 2 && rest.starts_with(b"</") && is_ascii_letter(rest[2]) {
+            // Skip the tag name, then its attributes, which may contain '>'.
+            while i < bytes.len() && bytes[i] != b'>' && !is_whitespace_byte(bytes[i]) {
+                i += 1;
+            }
+            while get_attribute(bytes, &mut i).is_some() { }
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            i += unwrap_or_return!(find(rest, b">"), None);
+        }
+        i += 1;
+    }
+    None
+}
+//§ END
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{looks_like_html, decide_encoding, EncodingDecision, EncodingConflict};
+    use super::{FromBom, FromTransport, FromMeta};
+
+    use collections::string::String;
+
+    test_eq!(doctype, looks_like_html(b"\n  <!doctype html>"), true)
+    test_eq!(tag, looks_like_html(b"<p>hi"), true)
+    test_eq!(tag_with_space, looks_like_html(b"<Table border=1>"), true)
+    test_eq!(comment, looks_like_html(b"<!-- x -->"), true)
+    test_eq!(no_terminator, looks_like_html(b"<bx>"), false)
+    test_eq!(at_end, looks_like_html(b"<html"), false)
+    test_eq!(json, looks_like_html(b"{\"a\": 1}"), false)
+    test_eq!(xml, looks_like_html(b"<?xml version=\"1.0\"?>"), false)
+    test_eq!(unknown_tag, looks_like_html(b"<section>"), false)
+    test_eq!(empty, looks_like_html(b"  "), false)
+
+    fn declared(bytes: &[u8], transport: Option<&str>) -> Option<String> {
+        decide_encoding(bytes, transport).declared.map(|(_, label)| label)
+    }
+
+    test_eq!(meta_charset, declared(b"<meta charset='UTF-8'>", None),
+        Some(String::from_str("utf-8")))
+    test_eq!(meta_http_equiv,
+        declared(b"<meta http-equiv=Content-Type content='text/html; charset=Shift_JIS'>", None),
+        Some(String::from_str("shift_jis")))
+    test_eq!(meta_needs_pragma,
+        declared(b"<meta content='text/html; charset=Shift_JIS'>", None), None)
+    test_eq!(meta_in_comment, declared(b"<!-- <meta charset=big5> -->", None), None)
+    test_eq!(meta_after_tags,
+        declared(b"<html lang='<meta charset=x>'><head><meta charset=koi8-r>", None),
+        Some(String::from_str("koi8-r")))
+
+    #[test]
+    fn meta_past_1024() {
+        let mut input = String::from_char(1020, ' ');
+        input.push_str("<meta charset=big5>");
+        assert_eq!(declared(input.as_bytes(), None), None);
+    }
+
+    test_eq!(transport_only, declared(b"<p>", Some(" ISO-8859-2 ")),
+        Some(String::from_str("iso-8859-2")))
+    test_eq!(nothing, declared(b"<p>", None), None)
+
+    #[test]
+    fn conflicts() {
+        let input = b"\xEF\xBB\xBF<meta charset=windows-1252><p>";
+        assert_eq!(decide_encoding(input, Some("utf-8")), EncodingDecision {
+            declared: Some((FromBom, String::from_str("utf-8"))),
+            conflicts: vec!(EncodingConflict {
+                source: FromMeta,
+                label: String::from_str("windows-1252"),
+            }),
+        });
+
+        let input = b"<meta charset=windows-1252>";
+        assert_eq!(decide_encoding(input, Some("euc-kr")).conflicts, vec!(EncodingConflict {
+            source: FromMeta,
+            label: String::from_str("windows-1252"),
+        }));
+        assert_eq!(decide_encoding(input, Some("euc-kr")).declared,
+            Some((FromTransport, String::from_str("euc-kr"))));
+    }
+}
@@ -0,0 +1,80 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Statistics on the names the tokenizer interns as atoms.
+
+use core::prelude::*;
+
+use collections::{MutableSeq, MutableMap};
+use collections::vec::Vec;
+use collections::string::String;
+use collections::treemap::TreeMap;
+
+/// Counts of the tag and attribute names interned by one tokenizer.
+///
+/// The interning table itself belongs to `string_cache` and is shared by
+/// every parser in the process, so this can't say how much memory the
+/// table holds.  It does say which names a document brought in and how
+/// often, which is what decides the table's growth.  Names which are
+/// built into `string_cache` as static atoms are counted too, although
+/// they cost nothing to intern.
+pub struct AtomStats {
+    counts: TreeMap<String, uint>,
+}
+
+impl AtomStats {
+    pub fn new() -> AtomStats {
+        AtomStats {
+            counts: TreeMap::new(),
+        }
+    }
+
+    /// Count one interning of `name`.
+    pub fn record(&mut self, name: &str) {
+        let name = String::from_str(name);
+        let found = match self.counts.find_mut(&name) {
+            Some(n) => {
+                *n += 1;
+                true
+            }
+            None => false,
+        };
+        if !found {
+            self.counts.insert(name, 1);
+        }
+    }
+
+    /// Number of distinct names interned.
+    pub fn distinct(&self) -> uint {
+        self.counts.len()
+    }
+
+    /// Total number of names interned, counting repeats.
+    pub fn total(&self) -> uint {
+        self.counts.values().fold(0, |a, &n| a + n)
+    }
+
+    /// Total size in bytes of the distinct names.
+    pub fn total_bytes(&self) -> uint {
+        self.counts.keys().fold(0, |a, k| a + k.len())
+    }
+
+    /// The `n` most frequently interned names, most frequent first.
+    pub fn most_frequent(&self, n: uint) -> Vec<(String, uint)> {
+        let mut all: Vec<(String, uint)> = self.counts.iter()
+            .map(|(k, &v)| (k.clone(), v)).collect();
+        // Ties are broken by name, so the output is deterministic.
+        all.sort_by(|&(ref k1, v1), &(ref k2, v2)| match v2.cmp(&v1) {
+            Equal => k1.cmp(k2),
+            ord => ord,
+        });
+        all.truncate(n);
+        all
+    }
+}
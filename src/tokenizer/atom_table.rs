@@ -0,0 +1,76 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Names to intern once, before tokenizing.
+
+use core::prelude::*;
+
+use collections::MutableMap;
+use collections::string::String;
+use collections::treemap::TreeMap;
+
+use string_cache::Atom;
+
+/// A set of tag and attribute names, interned when the table is built.
+///
+/// A name which isn't built into `string_cache` as a static atom is
+/// interned in a table shared by the whole process, behind a lock.  A
+/// tokenizer given an `AtomTable` through `TokenizerOpts::atom_table`
+/// looks names up here first, so an application's own vocabulary, such
+/// as custom elements like `<amp-img>`, is as cheap as the built-in
+/// names.  The table holds its atoms for as long as it lives, and one
+/// table can be shared by many tokenizers.
+///
+/// ```ignore
+/// let table = Arc::new(AtomTable::new(&["amp-img", "amp-ad", "layout"]));
+/// let opts = TokenizerOpts { atom_table: Some(table.clone()), .. Default::default() };
+/// ```
+pub struct AtomTable {
+    atoms: TreeMap<String, Atom>,
+}
+
+impl AtomTable {
+    pub fn new(names: &[&str]) -> AtomTable {
+        let mut atoms = TreeMap::new();
+        for &name in names.iter() {
+            atoms.insert(String::from_str(name), Atom::from_slice(name));
+        }
+        AtomTable {
+            atoms: atoms,
+        }
+    }
+
+    /// The atom for `name`, if it's in the table.
+    pub fn get(&self, name: &str) -> Option<Atom> {
+        self.atoms.find_with(|k| name.cmp(&k.as_slice())).map(|a| a.clone())
+    }
+
+    /// Number of names in the table.
+    pub fn len(&self) -> uint {
+        self.atoms.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::AtomTable;
+
+    use string_cache::Atom;
+
+    #[test]
+    fn lookup() {
+        let table = AtomTable::new(&["amp-img", "x-button", "div", "amp-img"]);
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.get("amp-img"), Some(Atom::from_slice("amp-img")));
+        assert_eq!(table.get("div"), Some(atom!(div)));
+        assert_eq!(table.get("amp"), None);
+        assert_eq!(table.get("AMP-IMG"), None);
+    }
+}
@@ -16,6 +16,7 @@ use collections::string::String;
 use collections::{MutableSeq, Deque};
 use collections::dlist::DList;
 
+#[deriving(Clone, PartialEq)]
 struct Buffer {
     /// Byte position within the buffer.
     pub pos: uint,
@@ -32,12 +33,17 @@ pub enum SetResult {
 
 /// A queue of owned string buffers, which supports incrementally
 /// consuming characters.
+#[deriving(Clone, PartialEq)]
 pub struct BufferQueue {
     /// Buffers to process.
     buffers: DList<Buffer>,
 
     /// Number of available characters.
     available: uint,
+
+    /// Number of bytes taken out so far, less any put back with
+    /// `unconsume`.
+    consumed: uint,
 }
 
 impl BufferQueue {
@@ -46,9 +52,23 @@ impl BufferQueue {
         BufferQueue {
             buffers: DList::new(),
             available: 0,
+            consumed: 0,
         }
     }
 
+    /// Put back characters which were taken out, at the beginning of the
+    /// queue.  Unlike `push_front`, this counts them as not yet consumed.
+    pub fn unconsume(&mut self, buf: String) {
+        self.consumed -= buf.len();
+        self.push_front(buf);
+    }
+
+    /// How many bytes have been taken out of the queue.  Buffers added
+    /// with `push_front` count once they're taken out.
+    pub fn consumed(&self) -> uint {
+        self.consumed
+    }
+
     /// Add a buffer to the beginning of the queue.
     pub fn push_front(&mut self, buf: String) {
         if buf.len() == 0 {
@@ -109,10 +129,13 @@ impl BufferQueue {
                     let new_pos = *pos + n;
                     let out = String::from_str(buf.as_slice().slice(*pos, new_pos));
                     *pos = new_pos;
-                    self.available -= n;
+                    // `n` counts bytes, but `available` counts characters.
+                    self.available -= out.as_slice().char_len();
+                    self.consumed += n;
                     (Some(NotFromSet(out)), new_pos >= buf.len())
                 } else {
                     let CharRange { ch, next } = buf.as_slice().char_range_at(*pos);
+                    self.consumed += next - *pos;
                     *pos = next;
                     self.available -= 1;
                     (Some(FromSet(ch)), next >= buf.len())
@@ -129,6 +152,37 @@ impl BufferQueue {
         result
     }
 
+    /// The characters at the front of the queue which aren't in `set`,
+    /// as far as the end of the first buffer, without taking them out.
+    /// Empty if the next character is in the set, or there is none.
+    pub fn peek_except_from<'a>(&'a self, set: SmallCharSet) -> &'a str {
+        match self.buffers.front() {
+            Some(&Buffer { pos, ref buf }) => {
+                let n = set.nonmember_prefix_len(buf.as_slice().slice_from(pos));
+                buf.as_slice().slice(pos, pos + n)
+            }
+            None => "",
+        }
+    }
+
+    /// Take out the first `n` bytes, which must be whole characters in
+    /// the first buffer, as after `peek_except_from`.
+    pub fn advance(&mut self, n: uint) {
+        let now_empty = match self.buffers.front_mut() {
+            Some(&Buffer { ref mut pos, ref buf }) => {
+                self.available -= buf.as_slice().slice(*pos, *pos + n).char_len();
+                self.consumed += n;
+                *pos += n;
+                *pos >= buf.len()
+            }
+            None => fail!("advance past the end of a BufferQueue"),
+        };
+
+        if now_empty {
+            self.buffers.pop_front();
+        }
+    }
+
     fn account_new(&mut self, buf: &str) {
         // FIXME: We could pass through length from the initial [u8] -> String
         // conversion, which already must re-encode or at least scan for UTF-8
@@ -148,6 +202,7 @@ impl Iterator<char> for BufferQueue {
             None => (None, false),
             Some(&Buffer { ref mut pos, ref buf }) => {
                 let CharRange { ch, next } = buf.as_slice().char_range_at(*pos);
+                self.consumed += next - *pos;
                 *pos = next;
                 self.available -= 1;
                 (Some(ch), next >= buf.len())
@@ -229,6 +284,37 @@ mod test {
         assert_eq!(pop(), None);
     }
 
+    #[test]
+    fn pop_except_set_counts_chars() {
+        let mut bq = BufferQueue::new();
+        bq.push_back(String::from_str("a\u00e9\u00e9&\u4e2dz"), 0);
+        let pop = || bq.pop_except_from(small_char_set!('&'));
+        assert_eq!(pop(), Some(NotFromSet(String::from_str("a\u00e9\u00e9"))));
+        // Five bytes, but three characters.
+        assert!(bq.has(3) && !bq.has(4));
+        assert_eq!(pop(), Some(FromSet('&')));
+        assert!(bq.has(2) && !bq.has(3));
+        assert_eq!(pop(), Some(NotFromSet(String::from_str("\u4e2dz"))));
+        assert!(!bq.has(1));
+        assert_eq!(pop(), None);
+    }
+
+    #[test]
+    fn can_peek_except_set() {
+        let mut bq = BufferQueue::new();
+        bq.push_back(String::from_str("a\u00e9&b"), 0);
+        bq.push_back(String::from_str("cd"), 0);
+        assert_eq!(bq.peek_except_from(small_char_set!('&')), "a\u00e9");
+        bq.advance(3);
+        assert_eq!(bq.peek_except_from(small_char_set!('&')), "");
+        assert_eq!(bq.next(), Some('&'));
+        assert_eq!(bq.peek_except_from(small_char_set!('&')), "b");
+        bq.advance(1);
+        assert_eq!(bq.consumed(), 5);
+        assert!(bq.has(2) && !bq.has(3));
+        assert_eq!(bq.peek_except_from(small_char_set!('&')), "cd");
+    }
+
     #[test]
     fn can_push_truncated() {
         let mut bq = BufferQueue::new();
@@ -238,4 +324,23 @@ mod test {
         assert_eq!(bq.next(), Some('c'));
         assert_eq!(bq.next(), None);
     }
+
+    #[test]
+    fn counts_consumed_bytes() {
+        let mut bq = BufferQueue::new();
+        bq.push_back(String::from_str("a\u00e9b&c"), 0);
+        assert_eq!(bq.next(), Some('a'));
+        assert_eq!(bq.next(), Some('\u00e9'));
+        assert_eq!(bq.consumed(), 3);
+
+        assert_eq!(bq.pop_except_from(small_char_set!('&')), Some(NotFromSet(String::from_str("b"))));
+        assert_eq!(bq.pop_except_from(small_char_set!('&')), Some(FromSet('&')));
+        assert_eq!(bq.consumed(), 5);
+
+        bq.unconsume(String::from_str("b&"));
+        assert_eq!(bq.consumed(), 3);
+        bq.push_front(String::from_str("xy"));
+        assert_eq!(bq.pop_front(3), Some(String::from_str("xyb")));
+        assert_eq!(bq.consumed(), 6);
+    }
 }
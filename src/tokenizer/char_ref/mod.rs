@@ -10,12 +10,24 @@
 use core::prelude::*;
 
 use super::{Tokenizer, TokenSink};
+use super::interface::{CharRefDiagnostic, CharRefIssue, CharRefMissingSemicolon};
+use super::interface::{CharRefNoDigits, CharRefOutOfRange, CharRefControl};
+use super::interface::{CharRefNoncharacter, CharRefUnknownName, CharRefEqualsInAttribute};
+use super::interface::CharRefSurrogate;
+use super::interface::MissingSemicolonAfterCharacterReference;
+use super::interface::{AbsenceOfDigitsInNumericCharacterReference, OtherError};
+use super::interface::{CharacterReferenceOutsideUnicodeRange, NullCharacterReference};
+use super::interface::{SurrogateCharacterReference, ControlCharacterReference};
+use super::interface::{NoncharacterCharacterReference, UnknownNamedCharacterReference};
 
 use util::str::{is_ascii_alnum, empty_str};
 
+use core::mem;
 use core::char::{to_digit, from_u32};
+use collections::MutableSeq;
 use collections::str::Slice;
 use collections::string::String;
+use collections::vec::Vec;
 
 mod data;
 
@@ -28,12 +40,22 @@ pub struct CharRef {
     pub num_chars: u8,
 }
 
+impl Clone for CharRef {
+    fn clone(&self) -> CharRef {
+        CharRef {
+            chars: self.chars,
+            num_chars: self.num_chars,
+        }
+    }
+}
+
 pub enum Status {
     Stuck,
     Progress,
     Done,
 }
 
+#[deriving(Clone)]
 enum State {
     Begin,
     Octothorpe,
@@ -43,6 +65,10 @@ enum State {
     BogusName,
 }
 
+/// Tokenizes one character reference.  When the input runs out partway
+/// through, `step` returns `Stuck` and keeps what it has read, so a
+/// reference split between chunks comes out as if it were fed whole.
+#[deriving(Clone)]
 pub struct CharRefTokenizer {
     state: State,
     addnl_allowed: Option<char>,
@@ -56,12 +82,19 @@ pub struct CharRefTokenizer {
     name_buf_opt: Option<String>,
     name_match: Option<&'static [u32, ..2]>,
     name_len: uint,
+
+    /// Collect `CharRefDiagnostic`s?
+    diagnostics: bool,
+
+    /// The reference as consumed so far, if collecting diagnostics.
+    raw: String,
+    issues: Vec<CharRefIssue>,
 }
 
 impl CharRefTokenizer {
     // NB: We assume that we have an additional allowed character iff we're
     // tokenizing in an attribute value.
-    pub fn new(addnl_allowed: Option<char>) -> CharRefTokenizer {
+    pub fn new(addnl_allowed: Option<char>, diagnostics: bool) -> CharRefTokenizer {
         CharRefTokenizer {
             state: Begin,
             addnl_allowed: addnl_allowed,
@@ -73,6 +106,9 @@ impl CharRefTokenizer {
             name_buf_opt: None,
             name_match: None,
             name_len: 0,
+            diagnostics: diagnostics,
+            raw: if diagnostics { String::from_str("&") } else { String::new() },
+            issues: vec!(),
         }
     }
 
@@ -82,6 +118,45 @@ impl CharRefTokenizer {
         self.result.expect("get_result called before done")
     }
 
+    /// Take the diagnostics for this reference, once it's done.
+    pub fn take_diagnostics(&mut self) -> Vec<CharRefDiagnostic> {
+        if self.issues.is_empty() {
+            return vec!();
+        }
+        let chars: String = match self.result {
+            Some(ref r) => r.chars.as_slice().slice_to(r.num_chars as uint)
+                .iter().map(|&c| c).collect(),
+            None => String::new(),
+        };
+        let issues = mem::replace(&mut self.issues, vec!());
+        issues.into_iter().map(|issue| CharRefDiagnostic {
+            issue: issue,
+            text: self.raw.clone(),
+            chars: chars.clone(),
+        }).collect()
+    }
+
+    fn issue(&mut self, issue: CharRefIssue) {
+        if self.diagnostics {
+            self.issues.push(issue);
+        }
+    }
+
+    // Record a character consumed as part of a numeric reference.
+    // Named references are recorded from `name_buf` when they finish.
+    fn saw(&mut self, c: char) {
+        if self.diagnostics {
+            self.raw.push(c);
+        }
+    }
+
+    fn saw_name(&mut self, len: uint) {
+        if self.diagnostics {
+            let name = String::from_str(self.name_buf().as_slice().slice_to(len));
+            self.raw.push_str(name.as_slice());
+        }
+    }
+
     fn name_buf<'t>(&'t self) -> &'t String {
         self.name_buf_opt.as_ref()
             .expect("name_buf missing in named character reference")
@@ -135,6 +210,7 @@ impl<'sink, Sink: TokenSink> CharRefTokenizer {
 
             '#' => {
                 tokenizer.discard_char();
+                self.saw('#');
                 self.state = Octothorpe;
                 Progress
             }
@@ -152,6 +228,7 @@ impl<'sink, Sink: TokenSink> CharRefTokenizer {
         match c {
             'x' | 'X' => {
                 tokenizer.discard_char();
+                self.saw(c);
                 self.hex_marker = Some(c);
                 self.state = Numeric(16);
             }
@@ -169,13 +246,16 @@ impl<'sink, Sink: TokenSink> CharRefTokenizer {
         match to_digit(c, base as uint) {
             Some(n) => {
                 tokenizer.discard_char();
-                self.num *= base;
-                if self.num > 0x10FFFF {
-                    // We might overflow, and the character is definitely invalid.
-                    // We still parse digits and semicolon, but don't use the result.
-                    self.num_too_big = true;
+                self.saw(c);
+                // Once the number is past U+10FFFF, it's invalid whatever
+                // follows.  We still consume the digits and semicolon, but
+                // stop accumulating, so that `num` can't overflow.
+                if !self.num_too_big {
+                    self.num = self.num * base + n as u32;
+                    if self.num > 0x10FFFF {
+                        self.num_too_big = true;
+                    }
                 }
-                self.num += n as u32;
                 self.seen_digit = true;
                 Progress
             }
@@ -191,8 +271,15 @@ impl<'sink, Sink: TokenSink> CharRefTokenizer {
 
     fn do_numeric_semicolon(&mut self, tokenizer: &mut Tokenizer<'sink, Sink>) -> Status {
         match unwrap_or_return!(tokenizer.peek(), Stuck) {
-            ';' => tokenizer.discard_char(),
-            _   => tokenizer.emit_error(Slice("Semicolon missing after numeric character reference")),
+            ';' => {
+                tokenizer.discard_char();
+                self.saw(';');
+            }
+            _ => {
+                tokenizer.emit_error(MissingSemicolonAfterCharacterReference,
+                    Slice("Semicolon missing after numeric character reference"));
+                self.issue(CharRefMissingSemicolon);
+            }
         };
         self.finish_numeric(tokenizer)
     }
@@ -205,7 +292,9 @@ impl<'sink, Sink: TokenSink> CharRefTokenizer {
         }
 
         tokenizer.unconsume(unconsume);
-        tokenizer.emit_error(Slice("Numeric character reference without digits"));
+        tokenizer.emit_error(AbsenceOfDigitsInNumericCharacterReference,
+            Slice("Numeric character reference without digits"));
+        self.issue(CharRefNoDigits);
         self.finish_none()
     }
 
@@ -214,29 +303,46 @@ impl<'sink, Sink: TokenSink> CharRefTokenizer {
             from_u32(n).expect("invalid char missed by error handling cases")
         }
 
+        // A reference names one code point, so a character outside the
+        // BMP is written as one reference, as in `&#x1F600;`.  A surrogate
+        // pair written as two references is two errors, not one character.
+        let out_of_range = Some((CharRefOutOfRange, CharacterReferenceOutsideUnicodeRange));
+        let control = Some((CharRefControl, ControlCharacterReference));
+        let noncharacter = Some((CharRefNoncharacter, NoncharacterCharacterReference));
         let (c, error) = match self.num {
-            n if (n > 0x10FFFF) || self.num_too_big => ('\ufffd', true),
-            0x00 | 0xD800...0xDFFF => ('\ufffd', true),
+            _ if self.num_too_big => ('\ufffd', out_of_range),
+            0x00 => ('\ufffd', Some((CharRefOutOfRange, NullCharacterReference))),
+            0xD800...0xDFFF => ('\ufffd', Some((CharRefSurrogate, SurrogateCharacterReference))),
 
             0x80...0x9F => match data::c1_replacements[(self.num - 0x80) as uint] {
-                Some(c) => (c, true),
-                None => (conv(self.num), true),
+                Some(c) => (c, control),
+                None => (conv(self.num), control),
             },
 
-            0x01...0x08 | 0x0B | 0x0D...0x1F | 0x7F | 0xFDD0...0xFDEF
-                => (conv(self.num), true),
+            0x01...0x08 | 0x0B | 0x0D...0x1F | 0x7F
+                => (conv(self.num), control),
+
+            0xFDD0...0xFDEF => (conv(self.num), noncharacter),
 
             n if (n & 0xFFFE) == 0xFFFE
-                => (conv(n), true),
+                => (conv(n), noncharacter),
 
-            n => (conv(n), false),
+            n => (conv(n), None),
         };
 
-        if error {
-            let msg = format_if!(tokenizer.opts.exact_errors,
-                "Invalid numeric character reference",
-                "Invalid numeric character reference value 0x{:06X}", self.num);
-            tokenizer.emit_error(msg);
+        match error {
+            Some((issue, kind)) => {
+                let msg = if self.num_too_big {
+                    Slice("Numeric character reference beyond U+10FFFF")
+                } else {
+                    format_if!(tokenizer.opts.exact_errors,
+                        "Invalid numeric character reference",
+                        "Invalid numeric character reference value 0x{:06X}", self.num)
+                };
+                tokenizer.emit_error(kind, msg);
+                self.issue(issue);
+            }
+            None => (),
         }
 
         self.finish_one(c)
@@ -266,7 +372,10 @@ impl<'sink, Sink: TokenSink> CharRefTokenizer {
         let msg = format_if!(tokenizer.opts.exact_errors,
             "Invalid character reference",
             "Invalid character reference &{:s}", self.name_buf().as_slice());
-        tokenizer.emit_error(msg);
+        tokenizer.emit_error(UnknownNamedCharacterReference, msg);
+        let len = self.name_buf().len();
+        self.saw_name(len);
+        self.issue(CharRefUnknownName);
     }
 
     fn unconsume_name(&mut self, tokenizer: &mut Tokenizer<'sink, Sink>) {
@@ -327,16 +436,21 @@ impl<'sink, Sink: TokenSink> CharRefTokenizer {
                 // this next character is in fact a U+003D EQUALS SIGN
                 // character (=), then this is a parse error"
 
+                self.saw_name(name_len);
                 let unconsume_all = match (self.addnl_allowed, last_matched, next_after) {
                     (_, ';', _) => false,
                     (Some(_), _, Some('=')) => {
-                        tokenizer.emit_error(Slice("Equals sign after character reference in attribute"));
+                        tokenizer.emit_error(OtherError,
+                            Slice("Equals sign after character reference in attribute"));
+                        self.issue(CharRefEqualsInAttribute);
                         true
                     }
                     (Some(_), _, Some(c)) if is_ascii_alnum(c) => true,
                     _ => {
-                        tokenizer.emit_error(Slice("Character reference does not end with semicolon"));
-                        false
+                        tokenizer.emit_error(MissingSemicolonAfterCharacterReference,
+                            Slice("Character reference does not end with semicolon"));
+                        self.issue(CharRefMissingSemicolon);
+                        tokenizer.opts.strict_char_refs
                     }
                 };
 
@@ -377,7 +491,9 @@ impl<'sink, Sink: TokenSink> CharRefTokenizer {
                     => drop(self.unconsume_numeric(tokenizer)),
 
                 Numeric(_) | NumericSemicolon => {
-                    tokenizer.emit_error(Slice("EOF in numeric character reference"));
+                    tokenizer.emit_error(MissingSemicolonAfterCharacterReference,
+                        Slice("EOF in numeric character reference"));
+                    self.issue(CharRefMissingSemicolon);
                     self.finish_numeric(tokenizer);
                 }
 
@@ -390,7 +506,9 @@ impl<'sink, Sink: TokenSink> CharRefTokenizer {
 
                 Octothorpe => {
                     tokenizer.unconsume(String::from_char(1, '#'));
-                    tokenizer.emit_error(Slice("EOF after '#' in character reference"));
+                    tokenizer.emit_error(AbsenceOfDigitsInNumericCharacterReference,
+                        Slice("EOF after '#' in character reference"));
+                    self.issue(CharRefNoDigits);
                     self.finish_none();
                 }
             }
@@ -0,0 +1,238 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A token sink which extracts text for full-text indexing, without
+//! building a DOM.
+//!
+//! Element nesting is tracked from start and end tags alone, without
+//! the tree builder's error recovery, so the path reported for text in
+//! malformed documents is approximate.
+
+use core::prelude::*;
+
+use tokenizer::interface::{TokenSink, Token, TagToken, CharacterTokens, TagKind, StartTag, EndTag};
+use tokenizer::states;
+
+use collections::MutableSeq;
+use collections::vec::Vec;
+
+use string_cache::Atom;
+
+/// Where a run of text came from.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct TextContext {
+    /// Inside a `<script>` element.
+    pub in_script: bool,
+
+    /// Inside a `<style>` element.
+    pub in_style: bool,
+
+    /// Inside a `<title>` element.
+    pub in_title: bool,
+
+    /// The value of an `alt` attribute, rather than character data.
+    pub alt_text: bool,
+
+    /// A tag which separates words, such as `<p>` or `<br>`, occurred
+    /// between the previous run of text and this one.
+    pub word_boundary: bool,
+}
+
+/// Receives text from an `Indexer`.
+pub trait IndexSink {
+    /// Process a run of text.  `path` holds the local names of the
+    /// enclosing elements, outermost first.
+    fn text(&mut self, text: &str, context: TextContext, path: &[Atom]);
+}
+
+/// Wraps an `IndexSink` so that it can be given to the tokenizer.
+pub struct Indexer<Sink> {
+    sink: Sink,
+    open_elems: Vec<Atom>,
+    next_state: Option<states::State>,
+    boundary: bool,
+}
+
+// Elements which never have an end tag.
+fn is_void(name: &Atom) -> bool {
+    match *name {
+        atom!(area) | atom!(base) | atom!(br) | atom!(col) | atom!(embed)
+            | atom!(hr) | atom!(img) | atom!(input) | atom!(keygen) | atom!(link)
+            | atom!(meta) | atom!(param) | atom!(source) | atom!(track)
+            | atom!(wbr) => true,
+        _ => false,
+    }
+}
+
+// Elements whose tags separate the surrounding text into words.
+// Everything else (`<b>`, `<a>`, ...) may occur inside a word.
+fn is_word_boundary(name: &Atom) -> bool {
+    match *name {
+        atom!(address) | atom!(article) | atom!(aside) | atom!(blockquote)
+            | atom!(br) | atom!(caption) | atom!(dd) | atom!(div) | atom!(dl)
+            | atom!(dt) | atom!(fieldset) | atom!(figcaption) | atom!(figure)
+            | atom!(footer) | atom!(form) | atom!(h1) | atom!(h2) | atom!(h3)
+            | atom!(h4) | atom!(h5) | atom!(h6) | atom!(header) | atom!(hr)
+            | atom!(img) | atom!(li) | atom!(main) | atom!(nav) | atom!(ol)
+            | atom!(p) | atom!(pre) | atom!(section) | atom!(table) | atom!(td)
+            | atom!(th) | atom!(title) | atom!(tr) | atom!(ul) => true,
+        _ => false,
+    }
+}
+
+impl<Sink: IndexSink> Indexer<Sink> {
+    pub fn new(sink: Sink) -> Indexer<Sink> {
+        Indexer {
+            sink: sink,
+            open_elems: vec!(),
+            next_state: None,
+            boundary: false,
+        }
+    }
+
+    /// Get back the wrapped sink.
+    pub fn unwrap(self) -> Sink {
+        self.sink
+    }
+
+    fn is_open(&self, name: Atom) -> bool {
+        self.open_elems.contains(&name)
+    }
+
+    fn context(&self, alt_text: bool) -> TextContext {
+        TextContext {
+            in_script: self.is_open(atom!(script)),
+            in_style: self.is_open(atom!(style)),
+            in_title: self.is_open(atom!(title)),
+            alt_text: alt_text,
+            word_boundary: self.boundary,
+        }
+    }
+
+    fn text(&mut self, text: &str, alt_text: bool) {
+        let context = self.context(alt_text);
+        self.sink.text(text, context, self.open_elems.as_slice());
+        self.boundary = false;
+    }
+}
+
+impl<Sink: IndexSink> TokenSink for Indexer<Sink> {
+    fn wants_text_slices(&self) -> bool {
+        true
+    }
+
+    fn process_text(&mut self, text: &str) {
+        self.text(text, false);
+    }
+
+    fn process_token(&mut self, token: Token) {
+        let tag = match token {
+            CharacterTokens(text) => return self.text(text.as_slice(), false),
+            TagToken(tag) => tag,
+            _ => return,
+        };
+
+        if is_word_boundary(&tag.name) {
+            self.boundary = true;
+        }
+
+        match tag.kind {
+            StartTag => {
+                // Alt text is a separate run of words.
+                for attr in tag.attrs.iter() {
+                    if attr.name.local == atom!(alt) && !attr.value.is_empty() {
+                        self.boundary = true;
+                        self.text(attr.value.as_slice(), true);
+                        self.boundary = true;
+                    }
+                }
+
+                // Index <noscript> content as markup.
+                self.next_state = states::State::after_start_tag(&tag.name, false);
+
+                if !tag.self_closing && !is_void(&tag.name) {
+                    self.open_elems.push(tag.name);
+                }
+            }
+
+            EndTag => {
+                // Pop up to the matching start tag, if there is one.
+                match self.open_elems.iter().rposition(|n| *n == tag.name) {
+                    Some(idx) => self.open_elems.truncate(idx),
+                    None => (),
+                }
+            }
+        }
+    }
+
+    fn query_state_change(&mut self) -> Option<states::State> {
+        self.next_state.take()
+    }
+
+    // Only for alt text.
+    fn wants_attrs(&mut self, kind: TagKind, _name: &str) -> bool {
+        kind == StartTag
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{IndexSink, Indexer, TextContext};
+
+    use tokenizer::Tokenizer;
+
+    use core::default::Default;
+    use collections::MutableSeq;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    use string_cache::Atom;
+
+    struct Collect {
+        runs: Vec<(String, TextContext, uint)>,
+    }
+
+    impl IndexSink for Collect {
+        fn text(&mut self, text: &str, context: TextContext, path: &[Atom]) {
+            self.runs.push((String::from_str(text), context, path.len()));
+        }
+    }
+
+    fn index(input: &str) -> Vec<(String, TextContext, uint)> {
+        let mut indexer = Indexer::new(Collect { runs: vec!() });
+        {
+            let mut tok = Tokenizer::new(&mut indexer, Default::default());
+            tok.feed(String::from_str(input));
+            tok.end();
+        }
+        indexer.unwrap().runs
+    }
+
+    #[test]
+    fn contexts() {
+        let runs = index("<title>T</title><p>a<img alt=pic>b</p><script>if (x) {}</script>");
+        let texts: Vec<&str> = runs.iter().map(|&(ref t, _, _)| t.as_slice()).collect();
+        assert_eq!(texts, vec!("T", "a", "pic", "b", "if (x) {}"));
+
+        let (_, ref title, depth) = runs[0];
+        assert!(title.in_title && !title.alt_text);
+        assert_eq!(depth, 1);
+
+        let (_, ref alt, _) = runs[2];
+        assert!(alt.alt_text && alt.word_boundary);
+
+        let (_, ref b, _) = runs[3];
+        assert!(b.word_boundary);
+
+        let (_, ref script, depth) = runs[4];
+        assert!(script.in_script && !script.in_title);
+        assert_eq!(depth, 1);
+    }
+}
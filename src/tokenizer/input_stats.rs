@@ -0,0 +1,128 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Statistics on the characters the tokenizer reads.
+
+use core::prelude::*;
+
+use core::char;
+
+/// Counts of the input characters read by one tokenizer, gathered as
+/// they're preprocessed, so they cost no extra pass over the input.
+///
+/// Characters are counted after newline normalization, so a CR LF pair
+/// counts as one.  A character is text if the tokenizer read it in the
+/// data, RCDATA, RAWTEXT, script data, PLAINTEXT or CDATA section
+/// states, and markup otherwise.  That puts the `<` which starts a tag,
+/// and the `&` which starts a character reference, on the text side.
+#[deriving(PartialEq, Eq, Clone, Show, Default)]
+pub struct InputStats {
+    /// Characters read.
+    pub chars: uint,
+
+    /// UTF-8 bytes of the characters read.
+    pub bytes: uint,
+
+    /// UTF-8 bytes of the characters read as text.
+    pub text_bytes: uint,
+
+    /// Characters outside ASCII.
+    pub non_ascii_chars: uint,
+
+    /// Character references which resolved to something, in text or in
+    /// attribute values.
+    pub char_refs: uint,
+}
+
+impl InputStats {
+    pub fn new() -> InputStats {
+        InputStats {
+            chars: 0,
+            bytes: 0,
+            text_bytes: 0,
+            non_ascii_chars: 0,
+            char_refs: 0,
+        }
+    }
+
+    /// Count one character.
+    pub fn record_char(&mut self, c: char, text: bool) {
+        let len = char::len_utf8_bytes(c);
+        self.chars += 1;
+        self.bytes += len;
+        if text {
+            self.text_bytes += len;
+        }
+        if len > 1 {
+            self.non_ascii_chars += 1;
+        }
+    }
+
+    /// Count a run of characters.
+    pub fn record_str(&mut self, s: &str, text: bool) {
+        self.chars += s.char_len();
+        self.bytes += s.len();
+        if text {
+            self.text_bytes += s.len();
+        }
+        // Every character outside ASCII has exactly one lead byte.
+        self.non_ascii_chars += s.bytes().filter(|&b| b >= 0xC0).count();
+    }
+
+    /// Take back the count of characters which were put back to be read
+    /// again.
+    pub fn unrecord_str(&mut self, s: &str, text: bool) {
+        let mut undo = InputStats::new();
+        undo.record_str(s, text);
+        self.chars -= undo.chars;
+        self.bytes -= undo.bytes;
+        self.text_bytes -= undo.text_bytes;
+        self.non_ascii_chars -= undo.non_ascii_chars;
+    }
+
+    /// UTF-8 bytes of the characters read as markup.
+    pub fn markup_bytes(&self) -> uint {
+        self.bytes - self.text_bytes
+    }
+
+    /// The fraction of characters outside ASCII, or 0 if none were read.
+    pub fn non_ascii_ratio(&self) -> f64 {
+        if self.chars == 0 {
+            0.0
+        } else {
+            self.non_ascii_chars as f64 / self.chars as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::InputStats;
+
+    #[test]
+    fn chars_and_runs_agree() {
+        let text = "a☃b😀\n";
+        let mut by_char = InputStats::new();
+        for c in text.chars() {
+            by_char.record_char(c, true);
+        }
+        let mut by_run = InputStats::new();
+        by_run.record_str(text, true);
+
+        assert_eq!(by_char, by_run);
+        assert_eq!(by_run.chars, 5);
+        assert_eq!(by_run.bytes, 10);
+        assert_eq!(by_run.non_ascii_chars, 2);
+
+        by_run.unrecord_str("😀\n", true);
+        assert_eq!(by_run.chars, 3);
+        assert_eq!(by_run.markup_bytes(), 0);
+    }
+}
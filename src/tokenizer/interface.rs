@@ -25,6 +25,11 @@ pub struct Doctype {
     pub public_id: Option<String>,
     pub system_id: Option<String>,
     pub force_quirks: bool,
+
+    /// The doctype as written, from `<!` to `>`, if
+    /// `TokenizerOpts::raw_doctype` is set.  Newlines are normalized, as
+    /// in the rest of the input.
+    pub raw: Option<String>,
 }
 
 impl Doctype {
@@ -34,6 +39,7 @@ impl Doctype {
             public_id: None,
             system_id: None,
             force_quirks: false,
+            raw: None,
         }
     }
 }
@@ -50,6 +56,26 @@ pub struct Attribute {
     pub value: String,
 }
 
+/// A range of the input, as byte offsets from the start of the first
+/// input fed to the tokenizer, not counting a byte order mark.  Input
+/// inserted with `Tokenizer::insert_at_current_position` counts too.
+/// Runs from `start` up to but not including `end`.
+#[deriving(PartialEq, Eq, Clone, Show, Default)]
+pub struct Span {
+    pub start: uint,
+    pub end: uint,
+}
+
+/// Where an attribute's name and value are in the input.
+#[deriving(PartialEq, Eq, Clone, Show, Default)]
+pub struct AttrSpan {
+    pub name: Span,
+
+    /// The value, without any quotes around it.  For an attribute with
+    /// no value, this is empty, at the end of the name.
+    pub value: Span,
+}
+
 #[deriving(PartialEq, Eq, Clone, Show)]
 pub enum TagKind {
     StartTag,
@@ -63,6 +89,21 @@ pub struct Tag {
     pub name: Atom,
     pub self_closing: bool,
     pub attrs: Vec<Attribute>,
+
+    /// With `TokenizerOpts::lazy_attrs`, the unparsed text of the
+    /// attributes when the sink didn't want them split up, in which case
+    /// `attrs` is empty.  It runs from after the tag name to before the
+    /// closing `>`, less the `/` of a self-closing tag.  Use
+    /// `tokenizer::parse_raw_attrs` to get the attributes later.
+    pub raw_attrs: Option<String>,
+
+    /// With `TokenizerOpts::attr_spans`, where each of `attrs` is in the
+    /// input, in the same order.  Otherwise empty.
+    pub attr_spans: Vec<AttrSpan>,
+
+    /// Did the tag have an attribute name more than once?  Which of the
+    /// duplicates are in `attrs` depends on `TokenizerOpts::duplicate_attrs`.
+    pub had_duplicate_attrs: bool,
 }
 
 impl Tag {
@@ -80,6 +121,48 @@ impl Tag {
 
         self_attrs == other_attrs
     }
+
+    /// Borrow the tag as a `TagView`.
+    pub fn view<'a>(&'a self) -> TagView<'a> {
+        TagView {
+            kind: self.kind,
+            name: &self.name,
+            self_closing: self.self_closing,
+            attrs: self.attrs.as_slice(),
+            raw_attrs: self.raw_attrs.as_ref().map(|s| s.as_slice()),
+            attr_spans: self.attr_spans.as_slice(),
+            had_duplicate_attrs: self.had_duplicate_attrs,
+        }
+    }
+}
+
+/// A tag token borrowed from the tokenizer, for sinks which only look
+/// at it.  The fields are as in `Tag`.  See
+/// `TokenSink::process_tag_view`.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct TagView<'a> {
+    pub kind: TagKind,
+    pub name: &'a Atom,
+    pub self_closing: bool,
+    pub attrs: &'a [Attribute],
+    pub raw_attrs: Option<&'a str>,
+    pub attr_spans: &'a [AttrSpan],
+    pub had_duplicate_attrs: bool,
+}
+
+impl<'a> TagView<'a> {
+    /// Copy the tag into an owned `Tag`.
+    pub fn to_tag(&self) -> Tag {
+        Tag {
+            kind: self.kind,
+            name: self.name.clone(),
+            self_closing: self.self_closing,
+            attrs: self.attrs.to_vec(),
+            raw_attrs: self.raw_attrs.map(|s| String::from_str(s)),
+            attr_spans: self.attr_spans.to_vec(),
+            had_duplicate_attrs: self.had_duplicate_attrs,
+        }
+    }
 }
 
 #[deriving(PartialEq, Eq, Clone, Show)]
@@ -87,21 +170,315 @@ pub enum Token {
     DoctypeToken(Doctype),
     TagToken(Tag),
     CommentToken(String),
+
+    /// Part of a comment, when `TokenizerOpts::comment_chunk_len` is set.
+    /// The comment continues with any further chunks, and ends with a
+    /// `CommentToken` holding the rest of the text (possibly empty).
+    CommentChunkToken(String),
+
     CharacterTokens(String),
     NullCharacterToken,
     EOFToken,
     ParseError(MaybeOwned<'static>),
 }
 
+/// What was wrong with a character reference.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum CharRefIssue {
+    /// No semicolon at the end, as in the legacy `&amp` or `&#38`.
+    CharRefMissingSemicolon,
+
+    /// `&#` or `&#x` with no digits after it.
+    CharRefNoDigits,
+
+    /// A numeric reference to zero, or to a number beyond U+10FFFF.  The
+    /// result is U+FFFD.
+    CharRefOutOfRange,
+
+    /// A numeric reference to a surrogate, U+D800 to U+DFFF.  The result
+    /// is U+FFFD, even for a pair such as `&#xD83D;&#xDE00;`.
+    CharRefSurrogate,
+
+    /// A numeric reference to a control character.  References to
+    /// 0x80 to 0x9F mostly resolve to Windows-1252 characters instead.
+    CharRefControl,
+
+    /// A numeric reference to a noncharacter, such as U+FFFE.
+    CharRefNoncharacter,
+
+    /// A name followed by a semicolon, such as `&bogus;`, which isn't a
+    /// known character reference.
+    CharRefUnknownName,
+
+    /// In an attribute value, a named reference without a semicolon
+    /// followed by `=`, as in `?a=1&copy=2`.  It's left as text.
+    CharRefEqualsInAttribute,
+}
+
+/// A problem with a character reference, as reported to
+/// `TokenSink::char_ref_diagnostic`.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct CharRefDiagnostic {
+    pub issue: CharRefIssue,
+
+    /// The reference as written, starting with `&`, through the last
+    /// character which was consumed as part of it.
+    pub text: String,
+
+    /// The characters it resolved to.  Empty if it was left as text.
+    pub chars: String,
+}
+
+/// What kind of parse error happened, for classifying errors without
+/// parsing their messages.  Where the spec has a name for the error,
+/// the variant has the same name, and `code` gives the spec's spelling.
+/// The tokenizer reports these through `TokenSink::classified_parse_error`,
+/// and the tree builder through `TreeSink::classified_parse_error`.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum ParseErrorKind {
+    AbruptClosingOfEmptyComment,
+    AbruptDoctypePublicIdentifier,
+    AbruptDoctypeSystemIdentifier,
+    AbsenceOfDigitsInNumericCharacterReference,
+    CharacterReferenceOutsideUnicodeRange,
+    ControlCharacterInInputStream,
+    ControlCharacterReference,
+    DuplicateAttribute,
+    EndTagWithAttributes,
+    EndTagWithTrailingSolidus,
+    EofBeforeTagName,
+    EofInComment,
+    EofInDoctype,
+    EofInScriptHtmlCommentLikeText,
+    EofInTag,
+    IncorrectlyClosedComment,
+    IncorrectlyOpenedComment,
+    InvalidCharacterSequenceAfterDoctypeName,
+    InvalidFirstCharacterOfTagName,
+    MissingAttributeValue,
+    MissingDoctypeName,
+    MissingDoctypePublicIdentifier,
+    MissingDoctypeSystemIdentifier,
+    MissingEndTagName,
+    MissingQuoteBeforeDoctypePublicIdentifier,
+    MissingQuoteBeforeDoctypeSystemIdentifier,
+    MissingSemicolonAfterCharacterReference,
+    MissingWhitespaceAfterDoctypePublicKeyword,
+    MissingWhitespaceAfterDoctypeSystemKeyword,
+    MissingWhitespaceBeforeDoctypeName,
+    MissingWhitespaceBetweenAttributes,
+    MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers,
+    NoncharacterCharacterReference,
+    NoncharacterInInputStream,
+    NonVoidHtmlElementStartTagWithTrailingSolidus,
+    NullCharacterReference,
+    SurrogateCharacterReference,
+    SurrogateInInputStream,
+    UnexpectedCharacterAfterDoctypeSystemIdentifier,
+    UnexpectedCharacterInAttributeName,
+    UnexpectedCharacterInUnquotedAttributeValue,
+    UnexpectedEqualsSignBeforeAttributeName,
+    UnexpectedNullCharacter,
+    UnexpectedQuestionMarkInsteadOfTagName,
+    UnexpectedSolidusInTag,
+    UnknownNamedCharacterReference,
+
+    /// One of the limits in `TokenizerOpts` or `TreeBuilderOpts` was
+    /// reached.  Not a spec error.
+    LimitExceeded,
+
+    /// An error in tree construction.  The spec doesn't name these.
+    TreeConstructionError,
+
+    /// Any other error, including one which came as a plain `ParseError`
+    /// token.
+    OtherError,
+}
+
+impl ParseErrorKind {
+    /// The spec's name for the error, such as `"eof-in-tag"`.  The kinds
+    /// which aren't in the spec get names which can't clash with it.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            AbruptClosingOfEmptyComment => "abrupt-closing-of-empty-comment",
+            AbruptDoctypePublicIdentifier => "abrupt-doctype-public-identifier",
+            AbruptDoctypeSystemIdentifier => "abrupt-doctype-system-identifier",
+            AbsenceOfDigitsInNumericCharacterReference
+                => "absence-of-digits-in-numeric-character-reference",
+            CharacterReferenceOutsideUnicodeRange => "character-reference-outside-unicode-range",
+            ControlCharacterInInputStream => "control-character-in-input-stream",
+            ControlCharacterReference => "control-character-reference",
+            DuplicateAttribute => "duplicate-attribute",
+            EndTagWithAttributes => "end-tag-with-attributes",
+            EndTagWithTrailingSolidus => "end-tag-with-trailing-solidus",
+            EofBeforeTagName => "eof-before-tag-name",
+            EofInComment => "eof-in-comment",
+            EofInDoctype => "eof-in-doctype",
+            EofInScriptHtmlCommentLikeText => "eof-in-script-html-comment-like-text",
+            EofInTag => "eof-in-tag",
+            IncorrectlyClosedComment => "incorrectly-closed-comment",
+            IncorrectlyOpenedComment => "incorrectly-opened-comment",
+            InvalidCharacterSequenceAfterDoctypeName
+                => "invalid-character-sequence-after-doctype-name",
+            InvalidFirstCharacterOfTagName => "invalid-first-character-of-tag-name",
+            MissingAttributeValue => "missing-attribute-value",
+            MissingDoctypeName => "missing-doctype-name",
+            MissingDoctypePublicIdentifier => "missing-doctype-public-identifier",
+            MissingDoctypeSystemIdentifier => "missing-doctype-system-identifier",
+            MissingEndTagName => "missing-end-tag-name",
+            MissingQuoteBeforeDoctypePublicIdentifier
+                => "missing-quote-before-doctype-public-identifier",
+            MissingQuoteBeforeDoctypeSystemIdentifier
+                => "missing-quote-before-doctype-system-identifier",
+            MissingSemicolonAfterCharacterReference
+                => "missing-semicolon-after-character-reference",
+            MissingWhitespaceAfterDoctypePublicKeyword
+                => "missing-whitespace-after-doctype-public-keyword",
+            MissingWhitespaceAfterDoctypeSystemKeyword
+                => "missing-whitespace-after-doctype-system-keyword",
+            MissingWhitespaceBeforeDoctypeName => "missing-whitespace-before-doctype-name",
+            MissingWhitespaceBetweenAttributes => "missing-whitespace-between-attributes",
+            MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers
+                => "missing-whitespace-between-doctype-public-and-system-identifiers",
+            NoncharacterCharacterReference => "noncharacter-character-reference",
+            NoncharacterInInputStream => "noncharacter-in-input-stream",
+            NonVoidHtmlElementStartTagWithTrailingSolidus
+                => "non-void-html-element-start-tag-with-trailing-solidus",
+            NullCharacterReference => "null-character-reference",
+            SurrogateCharacterReference => "surrogate-character-reference",
+            SurrogateInInputStream => "surrogate-in-input-stream",
+            UnexpectedCharacterAfterDoctypeSystemIdentifier
+                => "unexpected-character-after-doctype-system-identifier",
+            UnexpectedCharacterInAttributeName => "unexpected-character-in-attribute-name",
+            UnexpectedCharacterInUnquotedAttributeValue
+                => "unexpected-character-in-unquoted-attribute-value",
+            UnexpectedEqualsSignBeforeAttributeName
+                => "unexpected-equals-sign-before-attribute-name",
+            UnexpectedNullCharacter => "unexpected-null-character",
+            UnexpectedQuestionMarkInsteadOfTagName
+                => "unexpected-question-mark-instead-of-tag-name",
+            UnexpectedSolidusInTag => "unexpected-solidus-in-tag",
+            UnknownNamedCharacterReference => "unknown-named-character-reference",
+            LimitExceeded => "x-limit-exceeded",
+            TreeConstructionError => "x-tree-construction",
+            OtherError => "x-other",
+        }
+    }
+}
+
 /// Types which can receive tokens from the tokenizer.
 pub trait TokenSink {
     /// Process a token.
     fn process_token(&mut self, token: Token);
 
-    /// The tokenizer will call this after emitting any start tag.
-    /// This allows the tree builder to change the tokenizer's state.
-    /// By default no state changes occur.
+    /// Return true to be given tags as a `TagView`, through
+    /// `process_tag_view`, rather than as an owned `TagToken`.  This
+    /// saves moving the tag out of the tokenizer, which can then reuse
+    /// its storage for the next tag.  Asked before each tag.  By default
+    /// tags are owned.
+    fn wants_tag_views(&self) -> bool {
+        false
+    }
+
+    /// Process a tag, when `wants_tag_views` returned true.  The view
+    /// is only valid during the call.  By default it's copied into a
+    /// `Tag` for `process_token`.
+    fn process_tag_view(&mut self, tag: TagView) {
+        self.process_token(TagToken(tag.to_tag()));
+    }
+
+    /// Return true to be given runs of text through `process_text`,
+    /// borrowed straight from the input, rather than copied into a
+    /// `CharacterTokens`.  Asked before each run.  Text which needed
+    /// rewriting, such as CR LF pairs and character references, still
+    /// comes as `CharacterTokens`.  By default all text is owned.
+    fn wants_text_slices(&self) -> bool {
+        false
+    }
+
+    /// Process a parse error, knowing what kind it is.  By default the
+    /// kind is dropped, and the message goes to `process_token` as a
+    /// `ParseError`.
+    fn classified_parse_error(&mut self, _kind: ParseErrorKind, msg: MaybeOwned<'static>) {
+        self.process_token(ParseError(msg));
+    }
+
+    /// Process a run of text, when `wants_text_slices` returned true.
+    /// The text is only valid during the call.  By default it's copied
+    /// into a `CharacterTokens` for `process_token`.
+    fn process_text(&mut self, text: &str) {
+        self.process_token(CharacterTokens(String::from_str(text)));
+    }
+
+    /// The tokenizer will call this after emitting any tag, comment or
+    /// doctype.  This allows the tree builder to change the tokenizer's
+    /// state.  By default no state changes occur.
+    ///
+    /// A state returned here takes precedence over the state the
+    /// tokenizer would otherwise enter after the token, e.g. `Data` after
+    /// a comment's `-->`.  It's not called after character tokens, which
+    /// are often emitted partway through a construct, or after parse
+    /// errors or EOF.
+    ///
+    /// Any state may be returned.  In particular, returning
+    /// `states::Plaintext` makes everything after the tag, up to the end
+    /// of input, into character tokens, as for `<plaintext>`.  There is
+    /// no way back out, so the tokenizer won't call this again.  Input
+    /// fed afterwards is handled the same way, and `end` emits any text
+    /// still buffered before `EOFToken`.
     fn query_state_change(&mut self) -> Option<states::State> {
         None
     }
+
+    /// The tokenizer will call this after `query_state_change` returns
+    /// a new state.  A sink which switches to a raw data state without
+    /// going through an ordinary start tag can use this to name the end
+    /// tag which will leave that state, e.g. `</script>`.  By default the
+    /// name of the last start tag emitted is used.
+    fn query_last_start_tag(&mut self) -> Option<Atom> {
+        None
+    }
+
+    /// The tokenizer will call this after each token.  Return true to
+    /// stop tokenizing, e.g. after an unrecoverable error in the sink.
+    /// All further input is ignored, and `end` does not emit EOF.
+    fn aborted(&self) -> bool {
+        false
+    }
+
+    /// The tokenizer will call this after each token.  Return true to
+    /// pause tokenizing, e.g. when a queue of tokens is full.  Input fed
+    /// afterwards is buffered until `Tokenizer::resume`.  The tokenizer
+    /// stops at the end of its current step, so a few more tokens may
+    /// arrive first, such as the rest of a run of text.  Once `end` has
+    /// started, the rest of the input is tokenized without pausing.
+    /// By default tokenizing never pauses.
+    fn paused(&self) -> bool {
+        false
+    }
+
+    /// The tokenizer will call this before each token, including parse
+    /// errors, when `TokenizerOpts::track_lines` is set.  `line` counts
+    /// from 1.  For a tag it's the line of the `<`; for anything else,
+    /// the line where the token ends.  By default it's ignored.
+    fn token_line(&mut self, _line: uint) { }
+
+    /// When `TokenizerOpts::lazy_attrs` is set, the tokenizer will call
+    /// this on reaching the attributes of a tag named `name`, before it's
+    /// interned.  Return false to get the attributes unparsed, in `Tag::raw_attrs`,
+    /// which is much faster.  By default they're left unparsed.
+    fn wants_attrs(&mut self, _kind: TagKind, _name: &str) -> bool {
+        false
+    }
+
+    /// Report a problem with a character reference, in more detail than
+    /// the `ParseError` token which precedes this.  Only called when
+    /// `TokenizerOpts::char_ref_diagnostics` is set.  By default the
+    /// diagnostic is ignored.
+    fn char_ref_diagnostic(&mut self, _diag: CharRefDiagnostic) { }
+
+    /// The tokenizer will call this from `Tokenizer::end`, after emitting
+    /// `EOFToken`, so that a sink can flush anything it buffered.  It's
+    /// not called if the sink aborted.  By default it does nothing.
+    fn end(&mut self) { }
 }
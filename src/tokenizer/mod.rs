@@ -11,16 +11,51 @@
 
 use core::prelude::*;
 
-pub use self::interface::{Doctype, Attribute, TagKind, StartTag, EndTag, Tag};
-pub use self::interface::{Token, DoctypeToken, TagToken, CommentToken};
+pub use self::interface::{Doctype, Attribute, TagKind, StartTag, EndTag, Tag, TagView};
+pub use self::interface::{Span, AttrSpan};
+pub use self::interface::{Token, DoctypeToken, TagToken, CommentToken, CommentChunkToken};
 pub use self::interface::{CharacterTokens, NullCharacterToken, EOFToken, ParseError};
 pub use self::interface::TokenSink;
+pub use self::interface::{CharRefDiagnostic, CharRefIssue, CharRefMissingSemicolon};
+pub use self::interface::{CharRefNoDigits, CharRefOutOfRange, CharRefControl};
+pub use self::interface::{CharRefNoncharacter, CharRefUnknownName, CharRefEqualsInAttribute};
+pub use self::interface::CharRefSurrogate;
+pub use self::interface::{ParseErrorKind, AbruptClosingOfEmptyComment};
+pub use self::interface::{AbruptDoctypePublicIdentifier, AbruptDoctypeSystemIdentifier};
+pub use self::interface::AbsenceOfDigitsInNumericCharacterReference;
+pub use self::interface::CharacterReferenceOutsideUnicodeRange;
+pub use self::interface::{ControlCharacterInInputStream, ControlCharacterReference};
+pub use self::interface::{DuplicateAttribute, EndTagWithAttributes, EndTagWithTrailingSolidus};
+pub use self::interface::{EofBeforeTagName, EofInComment, EofInDoctype};
+pub use self::interface::{EofInScriptHtmlCommentLikeText, EofInTag, IncorrectlyClosedComment};
+pub use self::interface::{IncorrectlyOpenedComment, InvalidCharacterSequenceAfterDoctypeName};
+pub use self::interface::{InvalidFirstCharacterOfTagName, MissingAttributeValue};
+pub use self::interface::{MissingDoctypeName, MissingDoctypePublicIdentifier};
+pub use self::interface::{MissingDoctypeSystemIdentifier, MissingEndTagName};
+pub use self::interface::MissingQuoteBeforeDoctypePublicIdentifier;
+pub use self::interface::MissingQuoteBeforeDoctypeSystemIdentifier;
+pub use self::interface::MissingSemicolonAfterCharacterReference;
+pub use self::interface::MissingWhitespaceAfterDoctypePublicKeyword;
+pub use self::interface::MissingWhitespaceAfterDoctypeSystemKeyword;
+pub use self::interface::{MissingWhitespaceBeforeDoctypeName, MissingWhitespaceBetweenAttributes};
+pub use self::interface::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers;
+pub use self::interface::{NoncharacterCharacterReference, NoncharacterInInputStream};
+pub use self::interface::{NonVoidHtmlElementStartTagWithTrailingSolidus, NullCharacterReference};
+pub use self::interface::{SurrogateCharacterReference, SurrogateInInputStream};
+pub use self::interface::UnexpectedCharacterAfterDoctypeSystemIdentifier;
+pub use self::interface::UnexpectedCharacterInAttributeName;
+pub use self::interface::UnexpectedCharacterInUnquotedAttributeValue;
+pub use self::interface::{UnexpectedEqualsSignBeforeAttributeName, UnexpectedNullCharacter};
+pub use self::interface::{UnexpectedQuestionMarkInsteadOfTagName, UnexpectedSolidusInTag};
+pub use self::interface::{UnknownNamedCharacterReference, LimitExceeded, TreeConstructionError};
+pub use self::interface::OtherError;
 
 use self::states::{RawLessThanSign, RawEndTagOpen, RawEndTagName};
 use self::states::{Rcdata, Rawtext, ScriptData, ScriptDataEscaped};
 use self::states::{Escaped, DoubleEscaped};
 use self::states::{Unquoted, SingleQuoted, DoubleQuoted};
 use self::states::{DoctypeIdKind, Public, System};
+use self::states::{LazyBeforeName, LazyName, LazyBeforeValue, LazyValue, LazySelfClosing};
 
 use self::char_ref::{CharRef, CharRefTokenizer};
 
@@ -32,6 +67,7 @@ use util::smallcharset::SmallCharSet;
 use core::mem::replace;
 use core::default::Default;
 use alloc::boxed::Box;
+use alloc::arc::Arc;
 use collections::{MutableSeq, MutableMap};
 use collections::vec::Vec;
 use collections::string::String;
@@ -40,11 +76,54 @@ use collections::treemap::TreeMap;
 
 use string_cache::{Atom, QualName};
 
+pub use self::atom_stats::AtomStats;
+pub use self::atom_table::AtomTable;
+pub use self::input_stats::InputStats;
+pub use self::index::{TextContext, IndexSink, Indexer};
+pub use self::security::{ScanResult, KeepScanning, RejectDocument};
+pub use self::security::{SecurityHooks, SecurityScanner};
+pub use self::queue::TokenQueue;
+
 pub mod states;
 mod interface;
+mod atom_stats;
+mod atom_table;
+mod input_stats;
+mod index;
+mod security;
+mod queue;
 mod char_ref;
 mod buffer_queue;
 
+// Doctype errors which the spec names after the identifier they're about.
+fn missing_whitespace_after_keyword(kind: DoctypeIdKind) -> ParseErrorKind {
+    match kind {
+        Public => MissingWhitespaceAfterDoctypePublicKeyword,
+        System => MissingWhitespaceAfterDoctypeSystemKeyword,
+    }
+}
+
+fn missing_identifier(kind: DoctypeIdKind) -> ParseErrorKind {
+    match kind {
+        Public => MissingDoctypePublicIdentifier,
+        System => MissingDoctypeSystemIdentifier,
+    }
+}
+
+fn missing_quote(kind: DoctypeIdKind) -> ParseErrorKind {
+    match kind {
+        Public => MissingQuoteBeforeDoctypePublicIdentifier,
+        System => MissingQuoteBeforeDoctypeSystemIdentifier,
+    }
+}
+
+fn abrupt_identifier(kind: DoctypeIdKind) -> ParseErrorKind {
+    match kind {
+        Public => AbruptDoctypePublicIdentifier,
+        System => AbruptDoctypeSystemIdentifier,
+    }
+}
+
 fn option_push(opt_str: &mut Option<String>, c: char) {
     match *opt_str {
         Some(ref mut s) => s.push(c),
@@ -60,6 +139,41 @@ fn append_strings(lhs: &mut String, rhs: String) {
     }
 }
 
+/// What to do when a tag has more than one attribute with the same name.
+///
+/// A parse error is signalled in every case.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum DuplicateAttrPolicy {
+    /// Keep the first attribute and drop the rest, as the spec requires.
+    KeepFirst,
+
+    /// Keep the value of the last attribute, at the position of the first.
+    KeepLast,
+
+    /// Keep every attribute, in source order.  Sinks will then see
+    /// repeated names in `Tag::attrs`, which is useful for linting
+    /// but not spec-conforming.
+    KeepAll,
+}
+
+/// Where to keep carriage returns from the input.
+///
+/// The spec converts every CR and CRLF to a single LF before
+/// tokenizing.  Each flag turns that off for one kind of content, so
+/// that e.g. a `<textarea>` default value can round-trip exactly.  None
+/// of this is spec-conforming.
+#[deriving(PartialEq, Eq, Clone, Show, Default)]
+pub struct PreserveCr {
+    /// Character data, including `<textarea>`, `<script>` and friends.
+    pub text: bool,
+
+    /// Quoted attribute values.
+    pub attr_values: bool,
+
+    /// Comments and bogus comments.
+    pub comments: bool,
+}
+
 /// Tokenizer options, with an impl for `Default`.
 #[deriving(Clone)]
 pub struct TokenizerOpts {
@@ -71,12 +185,99 @@ pub struct TokenizerOpts {
     /// of the stream?  Default: true
     pub discard_bom: bool,
 
+    /// How to handle duplicate attributes.  Default: `KeepFirst`
+    pub duplicate_attrs: DuplicateAttrPolicy,
+
+    /// Maximum length of a comment, in bytes.  The comment is truncated
+    /// there with a parse error, so that a hostile document can't make
+    /// us buffer an enormous comment.  Default: None (no limit)
+    pub max_comment_len: Option<uint>,
+
+    /// Deliver comments in pieces of about this many bytes, as
+    /// `CommentChunkToken`s, rather than buffering the whole comment.
+    /// Bogus comments are split too.  This only bounds memory for a
+    /// `TokenSink` which handles the chunks itself.  The tree builder
+    /// joins them back together, since `TreeSink::create_comment` takes
+    /// the whole text; use `max_comment_len` to bound that.
+    /// Default: None (no splitting)
+    pub comment_chunk_len: Option<uint>,
+
+    /// Maximum length of an attribute name, in bytes.  Longer names are
+    /// truncated with a parse error.  Default: None (no limit)
+    pub max_attr_name_len: Option<uint>,
+
+    /// Maximum number of attributes on one tag.  The rest are dropped
+    /// with a parse error.  This also bounds the time spent checking for
+    /// duplicate attributes.  Default: None (no limit)
+    pub max_attr_count: Option<uint>,
+
+    /// Maximum length, in bytes, of a tag name, an attribute value, or a
+    /// DOCTYPE name or identifier.  Longer ones are truncated with a
+    /// parse error.  Comments and attribute names have their own limits,
+    /// and text is never buffered whole.  Default: None (no limit)
+    pub max_token_size: Option<uint>,
+
     /// Keep a record of how long we spent in each state?  Printed
     /// when `end()` is called.  Default: false
     pub profile: bool,
 
-    /// Initial state override.  Only the test runner should use
-    /// a non-`None` value!
+    /// Log each state transition at info level through the `log` crate?
+    /// Unlike the `trace_tokenizer` cfg, this needs no rebuild; set a
+    /// logger to receive the messages.  Ignored when built as a C
+    /// library.  Default: false
+    pub trace: bool,
+
+    /// Keep statistics on the tag and attribute names we intern?  See
+    /// `Tokenizer::atom_stats`.  Default: false
+    pub atom_stats: bool,
+
+    /// Keep statistics on the input characters?  See
+    /// `Tokenizer::input_stats`.  Default: false
+    pub input_stats: bool,
+
+    /// Names to look up before interning them in the shared table.
+    /// Default: None
+    pub atom_table: Option<Arc<AtomTable>>,
+
+    /// Where to keep CR characters rather than normalizing newlines.
+    /// Default: normalize everywhere
+    pub preserve_cr: PreserveCr,
+
+    /// Report each problem with a character reference to
+    /// `TokenSink::char_ref_diagnostic`?  Default: false
+    pub char_ref_diagnostics: bool,
+
+    /// Leave named character references which don't end with a semicolon
+    /// as text, e.g. `&amp` rather than `&`, to match an XML pipeline?  The
+    /// parse error is reported either way.  Default: false
+    pub strict_char_refs: bool,
+
+    /// Check every use of the fast path for runs of ordinary characters
+    /// against the slow, one-character-at-a-time path, and fail if they
+    /// disagree?  Very slow; meant for tests and fuzzing.  Default: false
+    pub check_fast_path: bool,
+
+    /// Count lines, and tell the sink which line each token is on by
+    /// calling `TokenSink::token_line` before the token.  Default: false
+    pub track_lines: bool,
+
+    /// Skip over the attributes of tags whose sink doesn't want them,
+    /// as decided by `TokenSink::wants_attrs`, leaving their text in
+    /// `Tag::raw_attrs`.  Meant for scanning which only needs tag names.
+    /// Default: false
+    pub lazy_attrs: bool,
+
+    /// Keep the source text of each DOCTYPE, in `Doctype::raw`, so that
+    /// junk the tokenizer would otherwise drop, such as an internal
+    /// subset, survives.  Default: false
+    pub raw_doctype: bool,
+
+    /// Record where each attribute's name and value are in the input, in
+    /// `Tag::attr_spans`.  Default: false
+    pub attr_spans: bool,
+
+    /// Initial state override.  Only the test runner and the fragment
+    /// parsing driver should use a non-`None` value!
     pub initial_state: Option<states::State>,
 
     /// Last start tag.  Only the test runner should use a
@@ -89,7 +290,25 @@ impl Default for TokenizerOpts {
         TokenizerOpts {
             exact_errors: false,
             discard_bom: true,
+            duplicate_attrs: KeepFirst,
+            max_comment_len: None,
+            comment_chunk_len: None,
+            max_attr_name_len: None,
+            max_attr_count: None,
+            max_token_size: None,
             profile: false,
+            trace: false,
+            atom_stats: false,
+            input_stats: false,
+            atom_table: None,
+            preserve_cr: Default::default(),
+            char_ref_diagnostics: false,
+            strict_char_refs: false,
+            check_fast_path: false,
+            track_lines: false,
+            lazy_attrs: false,
+            raw_doctype: false,
+            attr_spans: false,
             initial_state: None,
             last_start_tag_name: None,
         }
@@ -128,6 +347,10 @@ pub struct Tokenizer<'sink, Sink:'sink> {
     /// Should we reconsume the current input character?
     reconsume: bool,
 
+    /// Line of the current input character, counting from 1.  Only kept
+    /// up to date if `opts.track_lines` is set.
+    current_line: uint,
+
     /// Did we just consume \r, translating it to \n?  In that case we need
     /// to ignore the next character if it's \n.
     ignore_lf: bool,
@@ -148,21 +371,56 @@ pub struct Tokenizer<'sink, Sink:'sink> {
     /// Current tag attributes.
     current_tag_attrs: Vec<Attribute>,
 
+    /// Unparsed attributes of the current tag, in lazy attribute mode.
+    current_tag_raw_attrs: Option<String>,
+
+    /// Line the current tag started on, if `opts.track_lines` is set.
+    current_tag_line: uint,
+
     /// Current attribute name.
     current_attr_name: String,
 
     /// Current attribute value.
     current_attr_value: String,
 
+    /// Where the current tag's attributes are, if `opts.attr_spans` is set.
+    current_tag_attr_spans: Vec<AttrSpan>,
+
+    /// Where the current attribute is, so far.
+    current_attr_span: AttrSpan,
+
     /// Current comment.
     current_comment: String,
 
+    /// Bytes of the current comment already emitted as chunks.
+    comment_chunked: uint,
+
+    /// Have we dropped characters from the current comment, tag name,
+    /// attribute or doctype because of a length limit?  Used to report
+    /// the error once.
+    truncating: bool,
+
+    /// Have we dropped attributes from the current tag because of
+    /// `opts.max_attr_count`?  Used to report the error once.
+    dropping_attrs: bool,
+
+    /// Did the current tag have an attribute name more than once?
+    current_tag_had_duplicate_attrs: bool,
+
     /// Current doctype token.
     current_doctype: Doctype,
 
+    /// Source text of the current doctype so far, if `opts.raw_doctype`
+    /// is set and we're in one.
+    raw_doctype: Option<String>,
+
     /// Last start tag name, for use in checking "appropriate end tag".
     last_start_tag_name: Option<Atom>,
 
+    /// State requested by the sink after the last tag, comment or
+    /// doctype.  Applied at the end of the step which emitted it.
+    sink_state: Option<states::State>,
+
     /// The "temporary buffer" mentioned in the spec.
     temp_buf: String,
 
@@ -171,6 +429,60 @@ pub struct Tokenizer<'sink, Sink:'sink> {
 
     /// Record of how many ns we spent in the token sink.
     time_in_sink: u64,
+
+    /// Statistics on interned names, if enabled.
+    atom_stats: Option<AtomStats>,
+
+    /// Statistics on input characters, if enabled.
+    input_stats: Option<InputStats>,
+
+    /// Has the sink asked us to stop?
+    aborted: bool,
+
+    /// Has the sink asked us to wait?  Input is buffered until `resume`.
+    paused: bool,
+
+    /// Was `end` called while we were paused?
+    end_pending: bool,
+
+    /// Input inserted at the current position while we were paused, to be
+    /// tokenized before the rest when we resume.
+    inserted: String,
+}
+
+/// A saved tokenizer position, from `Tokenizer::checkpoint`.
+#[deriving(Clone)]
+pub struct Checkpoint {
+    state: states::State,
+    input_buffers: BufferQueue,
+    wait_for: Option<uint>,
+    at_eof: bool,
+    char_ref_tokenizer: Option<Box<CharRefTokenizer>>,
+    current_char: char,
+    reconsume: bool,
+    current_line: uint,
+    ignore_lf: bool,
+    discard_bom: bool,
+    current_tag_kind: TagKind,
+    current_tag_name: String,
+    current_tag_self_closing: bool,
+    current_tag_attrs: Vec<Attribute>,
+    current_tag_raw_attrs: Option<String>,
+    current_tag_line: uint,
+    current_attr_name: String,
+    current_attr_value: String,
+    current_tag_attr_spans: Vec<AttrSpan>,
+    current_attr_span: AttrSpan,
+    current_comment: String,
+    comment_chunked: uint,
+    truncating: bool,
+    dropping_attrs: bool,
+    current_tag_had_duplicate_attrs: bool,
+    current_doctype: Doctype,
+    raw_doctype: Option<String>,
+    last_start_tag_name: Option<Atom>,
+    temp_buf: String,
+    inserted: String,
 }
 
 impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
@@ -184,6 +496,8 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
             .map(|s| Atom::from_slice(s.as_slice()));
         let state = *opts.initial_state.as_ref().unwrap_or(&states::Data);
         let discard_bom = opts.discard_bom;
+        let atom_stats = if opts.atom_stats { Some(AtomStats::new()) } else { None };
+        let input_stats = if opts.input_stats { Some(InputStats::new()) } else { None };
         Tokenizer {
             opts: opts,
             sink: sink,
@@ -194,26 +508,167 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
             at_eof: false,
             current_char: '\0',
             reconsume: false,
+            current_line: 1,
             ignore_lf: false,
             discard_bom: discard_bom,
             current_tag_kind: StartTag,
             current_tag_name: empty_str(),
             current_tag_self_closing: false,
             current_tag_attrs: vec!(),
+            current_tag_raw_attrs: None,
+            current_tag_line: 1,
             current_attr_name: empty_str(),
             current_attr_value: empty_str(),
+            current_tag_attr_spans: vec!(),
+            current_attr_span: Default::default(),
             current_comment: empty_str(),
+            comment_chunked: 0,
+            truncating: false,
+            dropping_attrs: false,
+            current_tag_had_duplicate_attrs: false,
             current_doctype: Doctype::new(),
+            raw_doctype: None,
             last_start_tag_name: start_tag_name,
+            sink_state: None,
             temp_buf: empty_str(),
             state_profile: TreeMap::new(),
             time_in_sink: 0,
+            atom_stats: atom_stats,
+            input_stats: input_stats,
+            aborted: false,
+            paused: false,
+            end_pending: false,
+            inserted: String::new(),
+        }
+    }
+
+    /// Statistics on the tag and attribute names interned so far, if
+    /// `TokenizerOpts::atom_stats` is set.
+    pub fn atom_stats<'a>(&'a self) -> Option<&'a AtomStats> {
+        self.atom_stats.as_ref()
+    }
+
+    /// Statistics on the input characters read so far, if
+    /// `TokenizerOpts::input_stats` is set.
+    pub fn input_stats<'a>(&'a self) -> Option<&'a InputStats> {
+        self.input_stats.as_ref()
+    }
+
+    // Are we in a state which reads text, for `InputStats`?
+    fn reading_text(&self) -> bool {
+        match self.state {
+            states::Data | states::Plaintext | states::RawData(_) | states::CdataSection => true,
+            _ => false,
+        }
+    }
+
+    fn intern(&mut self, name: &str) -> Atom {
+        match self.atom_stats {
+            Some(ref mut stats) => stats.record(name),
+            None => (),
+        }
+        match self.opts.atom_table.as_ref().and_then(|t| t.get(name)) {
+            Some(atom) => atom,
+            None => Atom::from_slice(name),
+        }
+    }
+
+    /// Set the name of the last start tag, which determines the
+    /// "appropriate end tag" that leaves the RCDATA, RAWTEXT and script
+    /// data states.
+    pub fn set_last_start_tag(&mut self, name: Atom) {
+        self.last_start_tag_name = Some(name);
+    }
+
+    /// Save where the tokenizer is: its state, the input it hasn't
+    /// tokenized yet, and any tag, comment, doctype or character
+    /// reference it's partway through.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            state: self.state,
+            input_buffers: self.input_buffers.clone(),
+            wait_for: self.wait_for,
+            at_eof: self.at_eof,
+            char_ref_tokenizer: self.char_ref_tokenizer.clone(),
+            current_char: self.current_char,
+            reconsume: self.reconsume,
+            current_line: self.current_line,
+            ignore_lf: self.ignore_lf,
+            discard_bom: self.discard_bom,
+            current_tag_kind: self.current_tag_kind.clone(),
+            current_tag_name: self.current_tag_name.clone(),
+            current_tag_self_closing: self.current_tag_self_closing,
+            current_tag_attrs: self.current_tag_attrs.clone(),
+            current_tag_raw_attrs: self.current_tag_raw_attrs.clone(),
+            current_tag_line: self.current_tag_line,
+            current_attr_name: self.current_attr_name.clone(),
+            current_attr_value: self.current_attr_value.clone(),
+            current_tag_attr_spans: self.current_tag_attr_spans.clone(),
+            current_attr_span: self.current_attr_span.clone(),
+            current_comment: self.current_comment.clone(),
+            comment_chunked: self.comment_chunked,
+            truncating: self.truncating,
+            dropping_attrs: self.dropping_attrs,
+            current_tag_had_duplicate_attrs: self.current_tag_had_duplicate_attrs,
+            current_doctype: self.current_doctype.clone(),
+            raw_doctype: self.raw_doctype.clone(),
+            last_start_tag_name: self.last_start_tag_name.clone(),
+            temp_buf: self.temp_buf.clone(),
+            inserted: self.inserted.clone(),
         }
     }
 
+    /// Go back to a checkpoint, for speculative tokenizing.  Input fed
+    /// since the checkpoint is dropped, so feed whatever should come
+    /// next.  Tokens already given to the sink stay given, and
+    /// statistics, pausing and aborting aren't affected.
+    pub fn rewind(&mut self, checkpoint: Checkpoint) {
+        let Checkpoint {
+            state, input_buffers, wait_for, at_eof, char_ref_tokenizer,
+            current_char, reconsume, current_line, ignore_lf, discard_bom,
+            current_tag_kind, current_tag_name, current_tag_self_closing,
+            current_tag_attrs, current_tag_raw_attrs, current_tag_line,
+            current_attr_name, current_attr_value, current_tag_attr_spans,
+            current_attr_span, current_comment, comment_chunked, truncating,
+            dropping_attrs, current_tag_had_duplicate_attrs, current_doctype,
+            raw_doctype, last_start_tag_name, temp_buf, inserted,
+        } = checkpoint;
+
+        self.state = state;
+        self.input_buffers = input_buffers;
+        self.wait_for = wait_for;
+        self.at_eof = at_eof;
+        self.char_ref_tokenizer = char_ref_tokenizer;
+        self.current_char = current_char;
+        self.reconsume = reconsume;
+        self.current_line = current_line;
+        self.ignore_lf = ignore_lf;
+        self.discard_bom = discard_bom;
+        self.current_tag_kind = current_tag_kind;
+        self.current_tag_name = current_tag_name;
+        self.current_tag_self_closing = current_tag_self_closing;
+        self.current_tag_attrs = current_tag_attrs;
+        self.current_tag_raw_attrs = current_tag_raw_attrs;
+        self.current_tag_line = current_tag_line;
+        self.current_attr_name = current_attr_name;
+        self.current_attr_value = current_attr_value;
+        self.current_tag_attr_spans = current_tag_attr_spans;
+        self.current_attr_span = current_attr_span;
+        self.current_comment = current_comment;
+        self.comment_chunked = comment_chunked;
+        self.truncating = truncating;
+        self.dropping_attrs = dropping_attrs;
+        self.current_tag_had_duplicate_attrs = current_tag_had_duplicate_attrs;
+        self.current_doctype = current_doctype;
+        self.raw_doctype = raw_doctype;
+        self.last_start_tag_name = last_start_tag_name;
+        self.temp_buf = temp_buf;
+        self.inserted = inserted;
+    }
+
     /// Feed an input string into the tokenizer.
     pub fn feed(&mut self, input: String) {
-        if input.len() == 0 {
+        if input.len() == 0 || self.aborted {
             return;
         }
 
@@ -225,16 +680,145 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
         };
 
         self.input_buffers.push_back(input, pos);
+        if !self.paused {
+            self.run();
+        }
+    }
+
+    /// Did the sink stop tokenization by returning true from
+    /// `TokenSink::aborted`?
+    pub fn is_aborted(&self) -> bool {
+        self.aborted
+    }
+
+    /// Is the tokenizer waiting for `resume`, because the sink returned
+    /// true from `TokenSink::paused`?  A producer applying backpressure
+    /// should hold off feeding more input until it isn't.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Stop tokenizing until `resume`, as the sink can by returning true
+    /// from `TokenSink::paused`.  Input fed meanwhile is buffered.  Does
+    /// nothing once `end` has started.
+    pub fn pause(&mut self) {
+        if !self.at_eof {
+            self.paused = true;
+        }
+    }
+
+    /// Carry on after tokenization was paused, e.g. once the sink has
+    /// room for more tokens.  This tokenizes the input buffered in the
+    /// meantime, and finishes up if `end` was called, unless the sink
+    /// pauses again.
+    pub fn resume(&mut self) {
+        if !self.paused || self.aborted {
+            return;
+        }
+        self.paused = false;
+        let inserted = replace(&mut self.inserted, String::new());
+        self.input_buffers.push_front(inserted);
         self.run();
+        if !self.paused && self.end_pending {
+            self.end_pending = false;
+            self.finish();
+        }
+    }
+
+    /// Insert input at the current position, ahead of anything fed but not
+    /// yet tokenized, as for `document.write`.  While paused, e.g. after a
+    /// `</script>`, successive insertions are kept in order, and tokenized
+    /// on `resume`.  Otherwise the input is tokenized right away.
+    pub fn insert_at_current_position(&mut self, input: String) {
+        if input.len() == 0 || self.aborted || self.at_eof {
+            return;
+        }
+        if self.paused {
+            self.inserted.push_str(input.as_slice());
+        } else {
+            self.input_buffers.push_front(input);
+            self.run();
+        }
+    }
+
+    /// Get the sink, e.g. to take tokens out of a `TokenQueue`.
+    pub fn sink_mut<'a>(&'a mut self) -> &'a mut Sink {
+        &mut *self.sink
     }
 
     fn process_token(&mut self, token: Token) {
+        if self.opts.track_lines {
+            let line = match token {
+                TagToken(_) => self.current_tag_line,
+                _ => self.current_line,
+            };
+            self.sink.token_line(line);
+        }
         if self.opts.profile {
             let (_, dt) = time!(self.sink.process_token(token));
             self.time_in_sink += dt;
         } else {
             self.sink.process_token(token);
         }
+        self.after_token();
+    }
+
+    // Hand the sink a view of the current tag, and clear the tag's
+    // attributes, keeping their storage for the next tag.
+    fn process_tag_view(&mut self, name: Atom) {
+        if self.opts.track_lines {
+            self.sink.token_line(self.current_tag_line);
+        }
+        {
+            let view = TagView {
+                kind: self.current_tag_kind,
+                name: &name,
+                self_closing: self.current_tag_self_closing,
+                attrs: self.current_tag_attrs.as_slice(),
+                raw_attrs: self.current_tag_raw_attrs.as_ref().map(|s| s.as_slice()),
+                attr_spans: self.current_tag_attr_spans.as_slice(),
+                had_duplicate_attrs: self.current_tag_had_duplicate_attrs,
+            };
+            if self.opts.profile {
+                let (_, dt) = time!(self.sink.process_tag_view(view));
+                self.time_in_sink += dt;
+            } else {
+                self.sink.process_tag_view(view);
+            }
+        }
+        self.current_tag_attrs.truncate(0);
+        self.current_tag_raw_attrs = None;
+        self.current_tag_attr_spans.truncate(0);
+        self.current_tag_had_duplicate_attrs = false;
+        self.after_token();
+    }
+
+    // Check whether the sink wants to stop or wait, after a token.
+    fn after_token(&mut self) {
+        if self.sink.aborted() {
+            self.aborted = true;
+        }
+        // There's no pausing once `end` has started.
+        if !self.at_eof && self.sink.paused() {
+            self.paused = true;
+        }
+    }
+
+    // Should a CR in the current state be kept as it is?
+    fn preserve_cr(&self) -> bool {
+        let p = &self.opts.preserve_cr;
+        match self.state {
+            states::Data | states::RawData(_) | states::Plaintext => p.text,
+
+            states::AttributeValue(DoubleQuoted)
+                | states::AttributeValue(SingleQuoted) => p.attr_values,
+
+            states::CommentStart | states::CommentStartDash | states::Comment
+                | states::CommentEndDash | states::CommentEnd | states::CommentEndBang
+                | states::BogusComment => p.comments,
+
+            _ => false,
+        }
     }
 
     //§ preprocessing-the-input-stream
@@ -248,20 +832,35 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
             }
         }
 
-        if c == '\r' {
+        if c == '\r' && !self.preserve_cr() {
             self.ignore_lf = true;
             c = '\n';
         }
 
-        if self.opts.exact_errors && match c as u32 {
-            0x01...0x08 | 0x0B | 0x0E...0x1F | 0x7F...0x9F | 0xFDD0...0xFDEF => true,
-            n if (n & 0xFFFE) == 0xFFFE => true,
-            _ => false,
-        } {
-            // format_if!(true) will still use the static error when built for C.
-            let msg = format_if!(true, "Bad character",
-                "Bad character {:?}", c);
-            self.emit_error(msg);
+        if c == '\n' && self.opts.track_lines {
+            self.current_line += 1;
+        }
+
+        let bad_kind = match c as u32 {
+            0x01...0x08 | 0x0B | 0x0E...0x1F | 0x7F...0x9F => Some(ControlCharacterInInputStream),
+            0xFDD0...0xFDEF => Some(NoncharacterInInputStream),
+            n if (n & 0xFFFE) == 0xFFFE => Some(NoncharacterInInputStream),
+            _ => None,
+        };
+        match bad_kind {
+            Some(kind) if self.opts.exact_errors => {
+                // format_if!(true) will still use the static error when built for C.
+                let msg = format_if!(true, "Bad character",
+                    "Bad character {:?}", c);
+                self.emit_error(kind, msg);
+            }
+            _ => (),
+        }
+
+        let text = self.reading_text();
+        match self.input_stats {
+            Some(ref mut stats) => stats.record_char(c, text),
+            None => (),
         }
 
         h5e_debug!("got character {:?}", c);
@@ -276,8 +875,13 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
             self.reconsume = false;
             Some(self.current_char)
         } else {
-            self.input_buffers.next()
-                .and_then(|c| self.get_preprocessed_char(c))
+            let c = self.input_buffers.next()
+                .and_then(|c| self.get_preprocessed_char(c));
+            match (c, self.raw_doctype.as_mut()) {
+                (Some(c), Some(raw)) => raw.push(c),
+                _ => (),
+            }
+            c
         }
     }
 
@@ -290,6 +894,14 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
             return self.get_char().map(|x| FromSet(x));
         }
 
+        if self.opts.check_fast_path {
+            self.checked_pop_except_from(set)
+        } else {
+            self.fast_pop_except_from(set)
+        }
+    }
+
+    fn fast_pop_except_from(&mut self, set: SmallCharSet) -> Option<SetResult> {
         let d = self.input_buffers.pop_except_from(set);
         h5e_debug!("got characters {}", d);
         match d {
@@ -298,8 +910,67 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
             // NB: We don't set self.current_char for a run of characters not
             // in the set.  It shouldn't matter for the codepaths that use
             // this.
-            _ => d
+            _ => {
+                match d {
+                    Some(NotFromSet(ref s)) if self.opts.track_lines
+                        => self.current_line += s.as_slice().bytes().filter(|&b| b == b'\n').count(),
+                    _ => (),
+                }
+                let text = self.reading_text();
+                match (&d, self.input_stats.as_mut()) {
+                    (&Some(NotFromSet(ref s)), Some(stats)) => stats.record_str(s.as_slice(), text),
+                    _ => (),
+                }
+                d
+            }
+        }
+    }
+
+    // Take the fast path, then rewind and replay the same characters
+    // through `get_char`.  Both must consume the same input and leave the
+    // same preprocessing state behind.  The fast path's result and state
+    // are kept, so this mode tokenizes exactly like the normal one.
+    fn checked_pop_except_from(&mut self, set: SmallCharSet) -> Option<SetResult> {
+        let start = self.input_buffers.clone();
+        let start_char = self.current_char;
+        let start_line = self.current_line;
+
+        let fast = self.fast_pop_except_from(set);
+        let fast_input = replace(&mut self.input_buffers, start);
+        let fast_ignore_lf = replace(&mut self.ignore_lf, false);
+        let fast_char = replace(&mut self.current_char, start_char);
+        let fast_line = replace(&mut self.current_line, start_line);
+
+        // The fast path has counted these characters already.
+        let stats = self.input_stats.take();
+
+        let expected: Vec<char> = match fast {
+            None => vec!(),
+            Some(FromSet(c)) => vec!(c),
+            Some(NotFromSet(ref s)) => s.as_slice().chars().collect(),
+        };
+        for &c in expected.iter() {
+            match self.get_char() {
+                Some(d) if d == c => (),
+                slow => fail!("tokenizer fast path diverged in state {:?}: fast {}, slow {}",
+                    self.state, fast, slow),
+            }
+        }
+        match fast {
+            Some(NotFromSet(ref s)) if s.as_slice().chars().any(|c| set.contains_char(c))
+                => fail!("tokenizer fast path returned {} for a set it overlaps", fast),
+            _ => (),
+        }
+
+        if self.input_buffers != fast_input || self.ignore_lf != fast_ignore_lf
+                || self.current_line != fast_line {
+            fail!("tokenizer fast path diverged in state {:?}: {} left different input",
+                self.state, fast);
         }
+
+        self.input_stats = stats;
+        self.current_char = fast_char;
+        fast
     }
 
     // If fewer than n characters are available, return None.
@@ -324,11 +995,21 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
             Some(s) => {
                 if p(s.as_slice()) {
                     h5e_debug!("lookahead: condition satisfied by {:?}", s);
+                    match self.raw_doctype {
+                        Some(ref mut raw) => raw.push_str(s.as_slice()),
+                        None => (),
+                    }
+                    let text = self.reading_text();
+                    match self.input_stats {
+                        Some(ref mut stats) => stats.record_str(s.as_slice(), text),
+                        None => (),
+                    }
                     // FIXME: set current input character?
                     Some(true)
                 } else {
                     h5e_debug!("lookahead: condition not satisfied by {:?}", s);
-                    self.unconsume(s);
+                    // Not counted in the stats, so put back directly.
+                    self.input_buffers.unconsume(s);
                     Some(false)
                 }
             }
@@ -342,6 +1023,7 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
                 let state = self.state;
                 let old_sink = self.time_in_sink;
                 let (run, mut dt) = time!(self.step());
+                self.apply_sink_state();
                 dt -= (self.time_in_sink - old_sink);
                 let new = match self.state_profile.find_mut(&state) {
                     Some(x) => {
@@ -354,28 +1036,88 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
                     // do this here because of borrow shenanigans
                     self.state_profile.insert(state, dt);
                 }
-                if !run { break; }
+                self.trace_state_change(state);
+                if !run || self.aborted || self.paused { break; }
             }
         } else {
-            while self.step() {
+            loop {
+                let state = self.state;
+                let run = self.step();
+                self.apply_sink_state();
+                self.trace_state_change(state);
+                if !run || self.aborted || self.paused { break; }
             }
         }
     }
 
+    fn trace_state_change(&self, old: states::State) {
+        if self.opts.trace && self.state != old {
+            h5e_info!("tokenizer: {:?} -> {:?}", old, self.state);
+        }
+    }
+
+    // The kind of error for an unexpected `current_char`.
+    fn bad_char_kind(&self) -> ParseErrorKind {
+        match (self.state, self.current_char) {
+            (_, '\0') => UnexpectedNullCharacter,
+            (states::TagOpen, '?') => UnexpectedQuestionMarkInsteadOfTagName,
+            (states::TagOpen, _) => InvalidFirstCharacterOfTagName,
+            (states::EndTagOpen, '>') => MissingEndTagName,
+            (states::EndTagOpen, _) => InvalidFirstCharacterOfTagName,
+            (states::BeforeAttributeName, '=') => UnexpectedEqualsSignBeforeAttributeName,
+            (states::BeforeAttributeName, _) | (states::AttributeName, _)
+                | (states::AfterAttributeName, _) => UnexpectedCharacterInAttributeName,
+            (states::BeforeAttributeValue, '>') => MissingAttributeValue,
+            (states::BeforeAttributeValue, _) | (states::AttributeValue(Unquoted), _)
+                => UnexpectedCharacterInUnquotedAttributeValue,
+            (states::AfterAttributeValueQuoted, _) => MissingWhitespaceBetweenAttributes,
+            (states::SelfClosingStartTag, _) => UnexpectedSolidusInTag,
+            (states::CommentStart, '>') | (states::CommentStartDash, '>')
+                => AbruptClosingOfEmptyComment,
+            (states::CommentEnd, '!') => IncorrectlyClosedComment,
+            (states::MarkupDeclarationOpen, _) => IncorrectlyOpenedComment,
+            _ => OtherError,
+        }
+    }
+
     fn bad_char_error(&mut self) {
+        let kind = self.bad_char_kind();
         let msg = format_if!(
             self.opts.exact_errors,
             "Bad character",
             "Saw {:?} in state {:?}", self.current_char, self.state);
-        self.emit_error(msg);
+        self.emit_error(kind, msg);
     }
 
     fn bad_eof_error(&mut self) {
+        let kind = match self.state {
+            states::TagOpen | states::EndTagOpen => EofBeforeTagName,
+
+            states::RawData(ScriptDataEscaped(_)) | states::ScriptDataEscapedDash(_)
+                | states::ScriptDataEscapedDashDash(_) => EofInScriptHtmlCommentLikeText,
+
+            states::TagName | states::BeforeAttributeName | states::AttributeName
+                | states::AfterAttributeName | states::BeforeAttributeValue
+                | states::AttributeValue(_) | states::AfterAttributeValueQuoted
+                | states::SelfClosingStartTag | states::LazyAttributes(_) => EofInTag,
+
+            states::CommentStart | states::CommentStartDash | states::Comment
+                | states::CommentEndDash | states::CommentEnd | states::CommentEndBang
+                => EofInComment,
+
+            states::Doctype | states::BeforeDoctypeName | states::DoctypeName
+                | states::AfterDoctypeName | states::AfterDoctypeKeyword(_)
+                | states::BeforeDoctypeIdentifier(_) | states::DoctypeIdentifierDoubleQuoted(_)
+                | states::DoctypeIdentifierSingleQuoted(_) | states::AfterDoctypeIdentifier(_)
+                | states::BetweenDoctypePublicAndSystemIdentifiers => EofInDoctype,
+
+            _ => OtherError,
+        };
         let msg = format_if!(
             self.opts.exact_errors,
             "Unexpected EOF",
             "Saw EOF in state {:?}", self.state);
-        self.emit_error(msg);
+        self.emit_error(kind, msg);
     }
 
     fn emit_char(&mut self, c: char) {
@@ -390,41 +1132,106 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
         self.process_token(CharacterTokens(b));
     }
 
+    // If the sink takes borrowed text, hand it the characters up to the
+    // next one in `set` without copying them.  False if there were none,
+    // or we need the slow path.
+    fn emit_text_run(&mut self, set: SmallCharSet) -> bool {
+        if self.opts.exact_errors || self.opts.check_fast_path || self.reconsume
+                || self.ignore_lf || !self.sink.wants_text_slices() {
+            return false;
+        }
+
+        let len = {
+            let run = self.input_buffers.peek_except_from(set);
+            if run.is_empty() {
+                return false;
+            }
+            if self.opts.track_lines {
+                self.current_line += run.bytes().filter(|&b| b == b'\n').count();
+                self.sink.token_line(self.current_line);
+            }
+            match self.input_stats {
+                Some(ref mut stats) => stats.record_str(run, true),
+                None => (),
+            }
+            if self.opts.profile {
+                let (_, dt) = time!(self.sink.process_text(run));
+                self.time_in_sink += dt;
+            } else {
+                self.sink.process_text(run);
+            }
+            run.len()
+        };
+        self.input_buffers.advance(len);
+        self.after_token();
+        true
+    }
+
     fn emit_current_tag(&mut self) {
         self.finish_attribute();
 
         let name = replace(&mut self.current_tag_name, String::new());
-        let name = Atom::from_slice(name.as_slice());
+        let name = self.intern(name.as_slice());
 
         match self.current_tag_kind {
             StartTag => {
                 self.last_start_tag_name = Some(name.clone());
             }
             EndTag => {
-                if !self.current_tag_attrs.is_empty() {
-                    self.emit_error(Slice("Attributes on an end tag"));
+                let raw_attrs = self.current_tag_raw_attrs.as_ref()
+                    .map_or(false, |raw| !raw.as_slice().trim_left().is_empty());
+                if !self.current_tag_attrs.is_empty() || raw_attrs {
+                    self.emit_error(EndTagWithAttributes, Slice("Attributes on an end tag"));
                 }
                 if self.current_tag_self_closing {
-                    self.emit_error(Slice("Self-closing end tag"));
+                    self.emit_error(EndTagWithTrailingSolidus, Slice("Self-closing end tag"));
                 }
             }
         }
 
-        let token = TagToken(Tag { kind: self.current_tag_kind,
-            name: name,
-            self_closing: self.current_tag_self_closing,
-            attrs: replace(&mut self.current_tag_attrs, vec!()),
-        });
-        self.process_token(token);
+        if self.sink.wants_tag_views() {
+            self.process_tag_view(name);
+        } else {
+            let token = TagToken(Tag { kind: self.current_tag_kind,
+                name: name,
+                self_closing: self.current_tag_self_closing,
+                attrs: replace(&mut self.current_tag_attrs, vec!()),
+                raw_attrs: self.current_tag_raw_attrs.take(),
+                attr_spans: replace(&mut self.current_tag_attr_spans, vec!()),
+                had_duplicate_attrs: replace(&mut self.current_tag_had_duplicate_attrs, false),
+            });
+            self.process_token(token);
+        }
+        self.query_state_change();
+    }
 
-        if self.current_tag_kind == StartTag {
-            match self.sink.query_state_change() {
-                None => (),
-                Some(s) => self.state = s,
+    // Ask the sink whether it wants a state change after the token just
+    // emitted.  This happens after every tag, comment and doctype, but
+    // not after character tokens, which are often emitted partway
+    // through a construct, e.g. `<` before `/` in script data.
+    fn query_state_change(&mut self) {
+        match self.sink.query_state_change() {
+            None => (),
+            Some(s) => {
+                self.sink_state = Some(s);
+                match self.sink.query_last_start_tag() {
+                    None => (),
+                    Some(name) => self.last_start_tag_name = Some(name),
+                }
             }
         }
     }
 
+    // The sink's state takes precedence over the one the tokenizer
+    // moved to after emitting the token, so apply it once the step which
+    // emitted the token is over.
+    fn apply_sink_state(&mut self) {
+        match self.sink_state.take() {
+            None => (),
+            Some(s) => self.state = s,
+        }
+    }
+
     fn emit_temp_buf(&mut self) {
         // FIXME: Make sure that clearing on emit is spec-compatible.
         let buf = replace(&mut self.temp_buf, empty_str());
@@ -438,19 +1245,188 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
 
     fn emit_current_comment(&mut self) {
         let comment = replace(&mut self.current_comment, empty_str());
+        self.truncating = false;
+        self.comment_chunked = 0;
         self.process_token(CommentToken(comment));
+        self.query_state_change();
+    }
+
+    fn clear_comment(&mut self) {
+        self.current_comment.truncate(0);
+        self.truncating = false;
+        self.comment_chunked = 0;
+    }
+
+    // Hand off the comment so far, if it's grown past the chunk size.
+    fn maybe_emit_comment_chunk(&mut self) {
+        match self.opts.comment_chunk_len {
+            Some(n) if self.current_comment.len() >= n => {
+                let chunk = replace(&mut self.current_comment, empty_str());
+                self.comment_chunked += chunk.len();
+                self.process_token(CommentChunkToken(chunk));
+            }
+            _ => (),
+        }
+    }
+
+    // Would appending `n` bytes take us over `limit`?  Reports the
+    // error the first time it happens for the current comment, tag name,
+    // attribute or doctype.
+    fn over_limit(&mut self, len: uint, n: uint, limit: Option<uint>) -> bool {
+        match limit {
+            Some(max) if len + n > max => {
+                if !self.truncating {
+                    self.truncating = true;
+                    self.emit_error(LimitExceeded, Slice("Token exceeds length limit; truncating"));
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn push_comment(&mut self, c: char) {
+        let len = self.comment_chunked + self.current_comment.len();
+        let limit = self.opts.max_comment_len;
+        if !self.over_limit(len, c.len_utf8_bytes(), limit) {
+            self.current_comment.push(c);
+            self.maybe_emit_comment_chunk();
+        }
+    }
+
+    fn append_comment(&mut self, s: &str) {
+        let len = self.comment_chunked + self.current_comment.len();
+        let limit = self.opts.max_comment_len;
+        if !self.over_limit(len, s.len(), limit) {
+            self.current_comment.push_str(s);
+            self.maybe_emit_comment_chunk();
+            return;
+        }
+
+        // Keep as much of it as fits.
+        let mut end = limit.unwrap() - len;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.current_comment.push_str(s.slice_to(end));
+        self.maybe_emit_comment_chunk();
+    }
+
+    fn push_attr_name(&mut self, c: char) {
+        let (len, limit) = (self.current_attr_name.len(), self.opts.max_attr_name_len);
+        if !self.over_limit(len, c.len_utf8_bytes(), limit) {
+            self.current_attr_name.push(c);
+        }
+        self.extend_attr_name_span();
+    }
+
+    fn push_tag_name(&mut self, c: char) {
+        let (len, limit) = (self.current_tag_name.len(), self.opts.max_token_size);
+        if !self.over_limit(len, c.len_utf8_bytes(), limit) {
+            self.current_tag_name.push(c);
+        }
+    }
+
+    fn push_attr_value(&mut self, c: char) {
+        let (len, limit) = (self.current_attr_value.len(), self.opts.max_token_size);
+        if !self.over_limit(len, c.len_utf8_bytes(), limit) {
+            self.current_attr_value.push(c);
+        }
+    }
+
+    fn append_attr_value(&mut self, s: String) {
+        let (len, limit) = (self.current_attr_value.len(), self.opts.max_token_size);
+        if !self.over_limit(len, s.len(), limit) {
+            append_strings(&mut self.current_attr_value, s);
+            return;
+        }
+
+        // Keep as much of a run of characters as fits.
+        let mut end = limit.unwrap() - len;
+        while !s.as_slice().is_char_boundary(end) {
+            end -= 1;
+        }
+        self.current_attr_value.push_str(s.as_slice().slice_to(end));
+    }
+
+    // Where the current input character starts.  Not meaningful after a
+    // run of characters from `pop_except_from`.
+    fn current_char_offset(&self) -> uint {
+        self.input_buffers.consumed() - self.current_char.len_utf8_bytes()
+    }
+
+    // The current attribute name runs up to here, and the value (if any)
+    // hasn't started.
+    fn extend_attr_name_span(&mut self) {
+        if self.opts.attr_spans {
+            let end = self.input_buffers.consumed();
+            self.current_attr_span.name.end = end;
+            self.current_attr_span.value = Span { start: end, end: end };
+        }
+    }
+
+    // The value starts just after the current character, which is a
+    // quote, or else with it.
+    fn start_attr_value(&mut self, quoted: bool) {
+        if self.opts.attr_spans {
+            let start = if quoted {
+                self.input_buffers.consumed()
+            } else {
+                self.current_char_offset()
+            };
+            self.current_attr_span.value = Span { start: start, end: start };
+        }
+    }
+
+    // The value ends just before the current character.
+    fn end_attr_value(&mut self) {
+        if self.opts.attr_spans {
+            self.current_attr_span.value.end = self.current_char_offset();
+        }
     }
 
     fn discard_tag(&mut self) {
         self.current_tag_name = String::new();
         self.current_tag_self_closing = false;
-        self.current_tag_attrs = vec!();
+        // Keep the storage left by `process_tag_view`.
+        self.current_tag_attrs.truncate(0);
+        self.current_tag_raw_attrs = None;
+        self.current_tag_attr_spans.truncate(0);
+        self.dropping_attrs = false;
+        self.current_tag_had_duplicate_attrs = false;
+    }
+
+    // In lazy attribute mode, ask the sink whether to skip over this
+    // tag's attributes.
+    fn lazy_attrs(&mut self) -> bool {
+        if !self.opts.lazy_attrs
+                || self.sink.wants_attrs(self.current_tag_kind, self.current_tag_name.as_slice()) {
+            return false;
+        }
+        self.current_tag_raw_attrs = Some(String::new());
+        true
+    }
+
+    fn push_raw_attrs(&mut self, c: char) {
+        match self.current_tag_raw_attrs {
+            Some(ref mut raw) => raw.push(c),
+            None => fail!("no raw attributes"),
+        }
+    }
+
+    fn append_raw_attrs(&mut self, s: String) {
+        match self.current_tag_raw_attrs {
+            Some(ref mut raw) => raw.push_str(s.as_slice()),
+            None => fail!("no raw attributes"),
+        }
     }
 
     fn create_tag(&mut self, kind: TagKind, c: char) {
         self.discard_tag();
+        self.truncating = false;
         self.current_tag_name.push(c);
         self.current_tag_kind = kind;
+        self.current_tag_line = self.current_line;
     }
 
     fn have_appropriate_end_tag(&self) -> bool {
@@ -465,7 +1441,12 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
     fn create_attribute(&mut self, c: char) {
         self.finish_attribute();
 
+        self.truncating = false;
         self.current_attr_name.push(c);
+        if self.opts.attr_spans {
+            self.current_attr_span.name.start = self.current_char_offset();
+        }
+        self.extend_attr_name_span();
     }
 
     fn finish_attribute(&mut self) {
@@ -478,30 +1459,90 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
         // FIXME: linear time search, do we care?
         let dup = {
             let name = self.current_attr_name.as_slice();
-            self.current_tag_attrs.iter().any(|a| a.name.local.as_slice() == name)
+            self.current_tag_attrs.iter().position(|a| a.name.local.as_slice() == name)
         };
 
-        if dup {
-            self.emit_error(Slice("Duplicate attribute"));
-            self.current_attr_name.truncate(0);
-            self.current_attr_value.truncate(0);
-        } else {
-            let name = replace(&mut self.current_attr_name, String::new());
-            self.current_tag_attrs.push(Attribute {
-                // The tree builder will adjust the namespace if necessary.
-                // This only happens in foreign elements.
-                name: QualName::new(ns!(""), Atom::from_slice(name.as_slice())),
-                value: replace(&mut self.current_attr_value, empty_str()),
-            });
+        match dup {
+            None => (),
+            Some(i) => {
+                self.emit_error(DuplicateAttribute, Slice("Duplicate attribute"));
+                self.current_tag_had_duplicate_attrs = true;
+                match self.opts.duplicate_attrs {
+                    KeepFirst => {
+                        self.current_attr_name.truncate(0);
+                        self.current_attr_value.truncate(0);
+                        return;
+                    }
+                    KeepLast => {
+                        self.current_attr_name.truncate(0);
+                        let value = replace(&mut self.current_attr_value, empty_str());
+                        self.current_tag_attrs.get_mut(i).value = value;
+                        if self.opts.attr_spans {
+                            self.current_tag_attr_spans.get_mut(i).value
+                                = self.current_attr_span.value.clone();
+                        }
+                        return;
+                    }
+                    KeepAll => (),
+                }
+            }
+        }
+
+        match self.opts.max_attr_count {
+            Some(max) if self.current_tag_attrs.len() >= max => {
+                if !self.dropping_attrs {
+                    self.dropping_attrs = true;
+                    self.emit_error(LimitExceeded, Slice("Too many attributes; dropping the rest"));
+                }
+                self.current_attr_name.truncate(0);
+                self.current_attr_value.truncate(0);
+                return;
+            }
+            _ => (),
+        }
+
+        let name = replace(&mut self.current_attr_name, String::new());
+        let name = self.intern(name.as_slice());
+        self.current_tag_attrs.push(Attribute {
+            // The tree builder will adjust the namespace if necessary.
+            // This only happens in foreign elements.
+            name: QualName::new(ns!(""), name),
+            value: replace(&mut self.current_attr_value, empty_str()),
+        });
+        if self.opts.attr_spans {
+            self.current_tag_attr_spans.push(self.current_attr_span.clone());
         }
     }
 
-    fn emit_current_doctype(&mut self) {
-        let doctype = replace(&mut self.current_doctype, Doctype::new());
-        self.process_token(DoctypeToken(doctype));
+    fn create_doctype(&mut self) {
+        self.current_doctype = Doctype::new();
+        self.truncating = false;
     }
 
-    fn doctype_id<'a>(&'a mut self, kind: DoctypeIdKind) -> &'a mut Option<String> {
+    fn push_doctype_name(&mut self, c: char) {
+        let len = self.current_doctype.name.as_ref().map_or(0, |n| n.len());
+        let limit = self.opts.max_token_size;
+        if !self.over_limit(len, c.len_utf8_bytes(), limit) {
+            option_push(&mut self.current_doctype.name, c);
+        }
+    }
+
+    fn push_doctype_id(&mut self, kind: DoctypeIdKind, c: char) {
+        let len = self.doctype_id(kind).as_ref().map_or(0, |id| id.len());
+        let limit = self.opts.max_token_size;
+        if !self.over_limit(len, c.len_utf8_bytes(), limit) {
+            option_push(self.doctype_id(kind), c);
+        }
+    }
+
+    fn emit_current_doctype(&mut self) {
+        let mut doctype = replace(&mut self.current_doctype, Doctype::new());
+        doctype.raw = self.raw_doctype.take();
+        self.process_token(DoctypeToken(doctype));
+        self.query_state_change();
+    }
+
+    fn doctype_id<'a>(&'a mut self, kind: DoctypeIdKind) -> &'a mut Option<String> {
         match kind {
             Public => &mut self.current_doctype.public_id,
             System => &mut self.current_doctype.system_id,
@@ -519,7 +1560,8 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
     fn consume_char_ref(&mut self, addnl_allowed: Option<char>) {
         // NB: The char ref tokenizer assumes we have an additional allowed
         // character iff we're tokenizing in an attribute value.
-        self.char_ref_tokenizer = Some(box CharRefTokenizer::new(addnl_allowed));
+        self.char_ref_tokenizer = Some(box CharRefTokenizer::new(addnl_allowed,
+            self.opts.char_ref_diagnostics));
     }
 
     fn emit_eof(&mut self) {
@@ -539,12 +1581,41 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
         assert!(c.is_some());
     }
 
-    fn unconsume(&mut self, buf: String) {
-        self.input_buffers.push_front(buf);
+    // Put back characters from `get_char`, to be read again.  They've
+    // been preprocessed, so undo what that did.
+    fn unconsume(&mut self, mut buf: String) {
+        let text = self.reading_text();
+        match self.input_stats {
+            Some(ref mut stats) => stats.unrecord_str(buf.as_slice(), text),
+            None => (),
+        }
+        if self.opts.track_lines {
+            self.current_line -= buf.as_slice().chars().filter(|&c| c == '\n').count();
+        }
+
+        // If the last character was a CR, it became a LF, and we're set to
+        // skip a LF after it.  Put back the CR instead, or the LF we put
+        // back is what gets skipped.
+        if self.ignore_lf && buf.as_slice().ends_with("\n") {
+            let len = buf.len();
+            buf.truncate(len - 1);
+            buf.push('\r');
+            self.ignore_lf = false;
+        }
+        self.input_buffers.unconsume(buf);
     }
 
-    fn emit_error(&mut self, error: MaybeOwned<'static>) {
-        self.process_token(ParseError(error));
+    fn emit_error(&mut self, kind: ParseErrorKind, error: MaybeOwned<'static>) {
+        if self.opts.track_lines {
+            self.sink.token_line(self.current_line);
+        }
+        if self.opts.profile {
+            let (_, dt) = time!(self.sink.classified_parse_error(kind, error));
+            self.time_in_sink += dt;
+        } else {
+            self.sink.classified_parse_error(kind, error);
+        }
+        self.after_token();
     }
 }
 //§ END
@@ -553,27 +1624,32 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
 macro_rules! shorthand (
     ( $me:expr : emit $c:expr                    ) => ( $me.emit_char($c);                                   );
     ( $me:expr : create_tag $kind:expr $c:expr   ) => ( $me.create_tag($kind, $c);                           );
-    ( $me:expr : push_tag $c:expr                ) => ( $me.current_tag_name.push($c);                       );
+    ( $me:expr : push_tag $c:expr                ) => ( $me.push_tag_name($c);                               );
     ( $me:expr : discard_tag                     ) => ( $me.discard_tag();                                   );
     ( $me:expr : push_temp $c:expr               ) => ( $me.temp_buf.push($c);                               );
     ( $me:expr : emit_temp                       ) => ( $me.emit_temp_buf();                                 );
     ( $me:expr : clear_temp                      ) => ( $me.clear_temp_buf();                                );
     ( $me:expr : create_attr $c:expr             ) => ( $me.create_attribute($c);                            );
-    ( $me:expr : push_name $c:expr               ) => ( $me.current_attr_name.push($c);                      );
-    ( $me:expr : push_value $c:expr              ) => ( $me.current_attr_value.push($c);                     );
-    ( $me:expr : append_value $c:expr            ) => ( append_strings(&mut $me.current_attr_value, $c);     );
-    ( $me:expr : push_comment $c:expr            ) => ( $me.current_comment.push($c);                        );
-    ( $me:expr : append_comment $c:expr          ) => ( $me.current_comment.push_str($c);                    );
+    ( $me:expr : push_name $c:expr               ) => ( $me.push_attr_name($c);                              );
+    ( $me:expr : push_value $c:expr              ) => ( $me.push_attr_value($c);                             );
+    ( $me:expr : append_value $c:expr            ) => ( $me.append_attr_value($c);                           );
+    ( $me:expr : start_value $quoted:expr        ) => ( $me.start_attr_value($quoted);                       );
+    ( $me:expr : end_value                       ) => ( $me.end_attr_value();                                );
+    ( $me:expr : push_raw_attrs $c:expr          ) => ( $me.push_raw_attrs($c);                              );
+    ( $me:expr : append_raw_attrs $c:expr        ) => ( $me.append_raw_attrs($c);                            );
+    ( $me:expr : push_comment $c:expr            ) => ( $me.push_comment($c);                                );
+    ( $me:expr : append_comment $c:expr          ) => ( $me.append_comment($c);                              );
     ( $me:expr : emit_comment                    ) => ( $me.emit_current_comment();                          );
-    ( $me:expr : clear_comment                   ) => ( $me.current_comment.truncate(0);                     );
-    ( $me:expr : create_doctype                  ) => ( $me.current_doctype = Doctype::new();                );
-    ( $me:expr : push_doctype_name $c:expr       ) => ( option_push(&mut $me.current_doctype.name, $c);      );
-    ( $me:expr : push_doctype_id $k:expr $c:expr ) => ( option_push($me.doctype_id($k), $c);                 );
+    ( $me:expr : clear_comment                   ) => ( $me.clear_comment();                                 );
+    ( $me:expr : create_doctype                  ) => ( $me.create_doctype();                                );
+    ( $me:expr : push_doctype_name $c:expr       ) => ( $me.push_doctype_name($c);                           );
+    ( $me:expr : push_doctype_id $k:expr $c:expr ) => ( $me.push_doctype_id($k, $c);                        );
     ( $me:expr : clear_doctype_id $k:expr        ) => ( $me.clear_doctype_id($k);                            );
     ( $me:expr : force_quirks                    ) => ( $me.current_doctype.force_quirks = true;             );
     ( $me:expr : emit_doctype                    ) => ( $me.emit_current_doctype();                          );
     ( $me:expr : error                           ) => ( $me.bad_char_error();                                );
     ( $me:expr : error_eof                       ) => ( $me.bad_eof_error();                                 );
+    ( $me:expr : error_msg $k:expr $m:expr       ) => ( $me.emit_error($k, Slice($m));                       );
 )
 
 // Tracing of tokenizer actions.  This adds significant bloat and compile time,
@@ -610,7 +1686,8 @@ macro_rules! go (
     ( $me:expr : consume_char_ref             ) => ({ $me.consume_char_ref(None); return true;         });
     ( $me:expr : consume_char_ref $addnl:expr ) => ({ $me.consume_char_ref(Some($addnl)); return true; });
 
-    // We have a default next state after emitting a tag, but the sink can override.
+    // We have a default next state after emitting a tag, but the sink can
+    // override it; see `apply_sink_state`.
     ( $me:expr : emit_tag $s:ident ) => ({
         $me.state = states::$s;
         $me.emit_current_tag();
@@ -643,6 +1720,17 @@ macro_rules! pop_except_from ( ($me:expr, $set:expr) => (
     unwrap_or_return!($me.pop_except_from($set), false)
 ))
 
+// Like `pop_except_from!`, in a state which emits the characters not
+// in the set.  A sink which takes borrowed text gets them straight from
+// the input, and we go round the state's loop again.
+macro_rules! pop_text_except_from ( ($me:expr, $set:expr) => ({
+    let set = $set;
+    if $me.emit_text_run(set) {
+        continue;
+    }
+    pop_except_from!($me, set)
+}))
+
 // NB: if you use this after get_char!(self) then the first char is still
 // consumed no matter what!
 macro_rules! lookahead_and_consume ( ($me:expr, $n:expr, $pred:expr) => (
@@ -679,7 +1767,7 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
         match self.state {
             //§ data-state
             states::Data => loop {
-                match pop_except_from!(self, small_char_set!('\r' '\0' '&' '<')) {
+                match pop_text_except_from!(self, small_char_set!('\r' '\0' '&' '<')) {
                     FromSet('\0') => go!(self: error; emit '\0'),
                     FromSet('&')  => go!(self: consume_char_ref),
                     FromSet('<')  => go!(self: to TagOpen),
@@ -690,7 +1778,7 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
 
             //§ rcdata-state
             states::RawData(Rcdata) => loop {
-                match pop_except_from!(self, small_char_set!('\r' '\0' '&' '<')) {
+                match pop_text_except_from!(self, small_char_set!('\r' '\0' '&' '<')) {
                     FromSet('\0') => go!(self: error; emit '\ufffd'),
                     FromSet('&') => go!(self: consume_char_ref),
                     FromSet('<') => go!(self: to RawLessThanSign Rcdata),
@@ -701,7 +1789,7 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
 
             //§ rawtext-state
             states::RawData(Rawtext) => loop {
-                match pop_except_from!(self, small_char_set!('\r' '\0' '<')) {
+                match pop_text_except_from!(self, small_char_set!('\r' '\0' '<')) {
                     FromSet('\0') => go!(self: error; emit '\ufffd'),
                     FromSet('<') => go!(self: to RawLessThanSign Rawtext),
                     FromSet(c) => go!(self: emit c),
@@ -711,7 +1799,7 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
 
             //§ script-data-state
             states::RawData(ScriptData) => loop {
-                match pop_except_from!(self, small_char_set!('\r' '\0' '<')) {
+                match pop_text_except_from!(self, small_char_set!('\r' '\0' '<')) {
                     FromSet('\0') => go!(self: error; emit '\ufffd'),
                     FromSet('<') => go!(self: to RawLessThanSign ScriptData),
                     FromSet(c) => go!(self: emit c),
@@ -721,7 +1809,7 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
 
             //§ script-data-escaped-state
             states::RawData(ScriptDataEscaped(Escaped)) => loop {
-                match pop_except_from!(self, small_char_set!('\r' '\0' '-' '<')) {
+                match pop_text_except_from!(self, small_char_set!('\r' '\0' '-' '<')) {
                     FromSet('\0') => go!(self: error; emit '\ufffd'),
                     FromSet('-') => go!(self: emit '-'; to ScriptDataEscapedDash Escaped),
                     FromSet('<') => go!(self: to RawLessThanSign ScriptDataEscaped Escaped),
@@ -732,7 +1820,7 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
 
             //§ script-data-double-escaped-state
             states::RawData(ScriptDataEscaped(DoubleEscaped)) => loop {
-                match pop_except_from!(self, small_char_set!('\r' '\0' '-' '<')) {
+                match pop_text_except_from!(self, small_char_set!('\r' '\0' '-' '<')) {
                     FromSet('\0') => go!(self: error; emit '\ufffd'),
                     FromSet('-') => go!(self: emit '-'; to ScriptDataEscapedDash DoubleEscaped),
                     FromSet('<') => go!(self: emit '<'; to RawLessThanSign ScriptDataEscaped DoubleEscaped),
@@ -743,7 +1831,7 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
 
             //§ plaintext-state
             states::Plaintext => loop {
-                match pop_except_from!(self, small_char_set!('\r' '\0')) {
+                match pop_text_except_from!(self, small_char_set!('\r' '\0')) {
                     FromSet('\0') => go!(self: error; emit '\ufffd'),
                     FromSet(c)    => go!(self: emit c),
                     NotFromSet(b) => self.emit_chars(b),
@@ -773,8 +1861,11 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
 
             //§ tag-name-state
             states::TagName => loop { match get_char!(self) {
-                '\t' | '\n' | '\x0C' | ' '
-                     => go!(self: to BeforeAttributeName),
+                '\t' | '\n' | '\x0C' | ' ' => if self.lazy_attrs() {
+                    go!(self: to LazyAttributes LazyBeforeName);
+                } else {
+                    go!(self: to BeforeAttributeName);
+                },
                 '/'  => go!(self: to SelfClosingStartTag),
                 '>'  => go!(self: emit_tag Data),
                 '\0' => go!(self: error; push_tag '\ufffd'),
@@ -952,22 +2043,23 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
             //§ before-attribute-value-state
             states::BeforeAttributeValue => loop { match get_char!(self) {
                 '\t' | '\n' | '\x0C' | ' ' => (),
-                '"'  => go!(self: to AttributeValue DoubleQuoted),
-                '&'  => go!(self: reconsume AttributeValue Unquoted),
-                '\'' => go!(self: to AttributeValue SingleQuoted),
-                '\0' => go!(self: error; push_value '\ufffd'; to AttributeValue Unquoted),
+                '"'  => go!(self: start_value true; to AttributeValue DoubleQuoted),
+                '&'  => go!(self: start_value false; reconsume AttributeValue Unquoted),
+                '\'' => go!(self: start_value true; to AttributeValue SingleQuoted),
+                '\0' => go!(self: error; start_value false; push_value '\ufffd';
+                            to AttributeValue Unquoted),
                 '>'  => go!(self: error; emit_tag Data),
                 c => {
                     go_match!(self: c,
                         '<' | '=' | '`' => error);
-                    go!(self: push_value c; to AttributeValue Unquoted);
+                    go!(self: start_value false; push_value c; to AttributeValue Unquoted);
                 }
             }},
 
             //§ attribute-value-(double-quoted)-state
             states::AttributeValue(DoubleQuoted) => loop {
                 match pop_except_from!(self, small_char_set!('\r' '"' '&' '\0')) {
-                    FromSet('"')  => go!(self: to AfterAttributeValueQuoted),
+                    FromSet('"')  => go!(self: end_value; to AfterAttributeValueQuoted),
                     FromSet('&')  => go!(self: consume_char_ref '"'),
                     FromSet('\0') => go!(self: error; push_value '\ufffd'),
                     FromSet(c)    => go!(self: push_value c),
@@ -978,7 +2070,7 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
             //§ attribute-value-(single-quoted)-state
             states::AttributeValue(SingleQuoted) => loop {
                 match pop_except_from!(self, small_char_set!('\r' '\'' '&' '\0')) {
-                    FromSet('\'') => go!(self: to AfterAttributeValueQuoted),
+                    FromSet('\'') => go!(self: end_value; to AfterAttributeValueQuoted),
                     FromSet('&')  => go!(self: consume_char_ref '\''),
                     FromSet('\0') => go!(self: error; push_value '\ufffd'),
                     FromSet(c)    => go!(self: push_value c),
@@ -990,9 +2082,9 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
             states::AttributeValue(Unquoted) => loop {
                 match pop_except_from!(self, small_char_set!('\r' '\t' '\n' '\x0C' ' ' '&' '>' '\0')) {
                     FromSet('\t') | FromSet('\n') | FromSet('\x0C') | FromSet(' ')
-                     => go!(self: to BeforeAttributeName),
+                     => go!(self: end_value; to BeforeAttributeName),
                     FromSet('&')  => go!(self: consume_char_ref '>'),
-                    FromSet('>')  => go!(self: emit_tag Data),
+                    FromSet('>')  => go!(self: end_value; emit_tag Data),
                     FromSet('\0') => go!(self: error; push_value '\ufffd'),
                     FromSet(c) => {
                         go_match!(self: c,
@@ -1021,6 +2113,85 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
                 _ => go!(self: error; reconsume BeforeAttributeName),
             }},
 
+            // The lazy attribute states aren't in the spec.  They find the
+            // `>` which ends the tag just where the attribute states would,
+            // and keep everything before it as text.  Parse errors are left
+            // for whoever parses that text.
+            states::LazyAttributes(LazyBeforeName) => loop { match get_char!(self) {
+                '/' => go!(self: to LazyAttributes LazySelfClosing),
+                '>' => go!(self: emit_tag Data),
+                c => {
+                    go!(self: push_raw_attrs c);
+                    match c {
+                        '\t' | '\n' | '\x0C' | ' ' => (),
+                        _ => go!(self: to LazyAttributes LazyName),
+                    }
+                }
+            }},
+
+            // Covers both the attribute name state and the after
+            // attribute name state.
+            states::LazyAttributes(LazyName) => loop {
+                match pop_except_from!(self, small_char_set!('\r' '/' '=' '>')) {
+                    FromSet('/')  => go!(self: to LazyAttributes LazySelfClosing),
+                    FromSet('=')  => go!(self: push_raw_attrs '='; to LazyAttributes LazyBeforeValue),
+                    FromSet('>')  => go!(self: emit_tag Data),
+                    FromSet(c)    => go!(self: push_raw_attrs c),
+                    NotFromSet(b) => go!(self: append_raw_attrs b),
+                }
+            },
+
+            states::LazyAttributes(LazyBeforeValue) => loop { match get_char!(self) {
+                '>' => go!(self: emit_tag Data),
+                c => {
+                    go!(self: push_raw_attrs c);
+                    match c {
+                        '\t' | '\n' | '\x0C' | ' ' => (),
+                        '"'  => go!(self: to LazyAttributes LazyValue DoubleQuoted),
+                        '\'' => go!(self: to LazyAttributes LazyValue SingleQuoted),
+                        _    => go!(self: to LazyAttributes LazyValue Unquoted),
+                    }
+                }
+            }},
+
+            states::LazyAttributes(LazyValue(DoubleQuoted)) => loop {
+                match pop_except_from!(self, small_char_set!('\r' '"')) {
+                    FromSet('"')  => go!(self: push_raw_attrs '"'; to LazyAttributes LazyBeforeName),
+                    FromSet(c)    => go!(self: push_raw_attrs c),
+                    NotFromSet(b) => go!(self: append_raw_attrs b),
+                }
+            },
+
+            states::LazyAttributes(LazyValue(SingleQuoted)) => loop {
+                match pop_except_from!(self, small_char_set!('\r' '\'')) {
+                    FromSet('\'') => go!(self: push_raw_attrs '\''; to LazyAttributes LazyBeforeName),
+                    FromSet(c)    => go!(self: push_raw_attrs c),
+                    NotFromSet(b) => go!(self: append_raw_attrs b),
+                }
+            },
+
+            states::LazyAttributes(LazyValue(Unquoted)) => loop {
+                match pop_except_from!(self, small_char_set!('\r' '\t' '\n' '\x0C' ' ' '>')) {
+                    FromSet('>')  => go!(self: emit_tag Data),
+                    FromSet(c) => {
+                        go!(self: push_raw_attrs c);
+                        match c {
+                            '\t' | '\n' | '\x0C' | ' ' => go!(self: to LazyAttributes LazyBeforeName),
+                            _ => (),
+                        }
+                    }
+                    NotFromSet(b) => go!(self: append_raw_attrs b),
+                }
+            },
+
+            states::LazyAttributes(LazySelfClosing) => loop { match get_char!(self) {
+                '>' => {
+                    self.current_tag_self_closing = true;
+                    go!(self: emit_tag Data);
+                }
+                _ => go!(self: push_raw_attrs '/'; reconsume LazyAttributes LazyBeforeName),
+            }},
+
             //§ comment-start-state
             states::CommentStart => loop { match get_char!(self) {
                 '-'  => go!(self: to CommentStartDash),
@@ -1072,14 +2243,18 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
             states::Doctype => loop { match get_char!(self) {
                 '\t' | '\n' | '\x0C' | ' '
                     => go!(self: to BeforeDoctypeName),
-                _   => go!(self: error; reconsume BeforeDoctypeName),
+                '>' => go!(self: reconsume BeforeDoctypeName),
+                _   => go!(self: error_msg MissingWhitespaceBeforeDoctypeName
+                              "Missing whitespace before doctype name";
+                              reconsume BeforeDoctypeName),
             }},
 
             //§ before-doctype-name-state
             states::BeforeDoctypeName => loop { match get_char!(self) {
                 '\t' | '\n' | '\x0C' | ' ' => (),
                 '\0' => go!(self: error; create_doctype; push_doctype_name '\ufffd'; to DoctypeName),
-                '>'  => go!(self: error; create_doctype; force_quirks; emit_doctype; to Data),
+                '>'  => go!(self: error_msg MissingDoctypeName "Missing doctype name";
+                            create_doctype; force_quirks; emit_doctype; to Data),
                 c    => go!(self: create_doctype; push_doctype_name (lower_ascii(c)); to DoctypeName),
             }},
 
@@ -1102,7 +2277,9 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
                     match get_char!(self) {
                         '\t' | '\n' | '\x0C' | ' ' => (),
                         '>' => go!(self: emit_doctype; to Data),
-                        _   => go!(self: error; force_quirks; to BogusDoctype),
+                        _   => go!(self: error_msg InvalidCharacterSequenceAfterDoctypeName
+                                      "Invalid keyword after doctype name";
+                                      force_quirks; to BogusDoctype),
                     }
                 }
             },
@@ -1111,10 +2288,18 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
             states::AfterDoctypeKeyword(kind) => loop { match get_char!(self) {
                 '\t' | '\n' | '\x0C' | ' '
                      => go!(self: to BeforeDoctypeIdentifier kind),
-                '"'  => go!(self: error; clear_doctype_id kind; to DoctypeIdentifierDoubleQuoted kind),
-                '\'' => go!(self: error; clear_doctype_id kind; to DoctypeIdentifierSingleQuoted kind),
-                '>'  => go!(self: error; force_quirks; emit_doctype; to Data),
-                _    => go!(self: error; force_quirks; to BogusDoctype),
+                '"'  => go!(self: error_msg (missing_whitespace_after_keyword(kind))
+                            "Missing whitespace after doctype keyword";
+                            clear_doctype_id kind; to DoctypeIdentifierDoubleQuoted kind),
+                '\'' => go!(self: error_msg (missing_whitespace_after_keyword(kind))
+                            "Missing whitespace after doctype keyword";
+                            clear_doctype_id kind; to DoctypeIdentifierSingleQuoted kind),
+                '>'  => go!(self: error_msg (missing_identifier(kind))
+                            "Missing doctype identifier";
+                            force_quirks; emit_doctype; to Data),
+                _    => go!(self: error_msg (missing_quote(kind))
+                            "Missing quote before doctype identifier";
+                            force_quirks; to BogusDoctype),
             }},
 
             //§ before-doctype-public-identifier-state before-doctype-system-identifier-state
@@ -1122,15 +2307,21 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
                 '\t' | '\n' | '\x0C' | ' ' => (),
                 '"'  => go!(self: clear_doctype_id kind; to DoctypeIdentifierDoubleQuoted kind),
                 '\'' => go!(self: clear_doctype_id kind; to DoctypeIdentifierSingleQuoted kind),
-                '>'  => go!(self: error; force_quirks; emit_doctype; to Data),
-                _    => go!(self: error; force_quirks; to BogusDoctype),
+                '>'  => go!(self: error_msg (missing_identifier(kind))
+                            "Missing doctype identifier";
+                            force_quirks; emit_doctype; to Data),
+                _    => go!(self: error_msg (missing_quote(kind))
+                            "Missing quote before doctype identifier";
+                            force_quirks; to BogusDoctype),
             }},
 
             //§ doctype-public-identifier-(double-quoted)-state doctype-system-identifier-(double-quoted)-state
             states::DoctypeIdentifierDoubleQuoted(kind) => loop { match get_char!(self) {
                 '"'  => go!(self: to AfterDoctypeIdentifier kind),
                 '\0' => go!(self: error; push_doctype_id kind '\ufffd'),
-                '>'  => go!(self: error; force_quirks; emit_doctype; to Data),
+                '>'  => go!(self: error_msg (abrupt_identifier(kind))
+                            "Abrupt end of doctype identifier";
+                            force_quirks; emit_doctype; to Data),
                 c    => go!(self: push_doctype_id kind c),
             }},
 
@@ -1138,7 +2329,9 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
             states::DoctypeIdentifierSingleQuoted(kind) => loop { match get_char!(self) {
                 '\'' => go!(self: to AfterDoctypeIdentifier kind),
                 '\0' => go!(self: error; push_doctype_id kind '\ufffd'),
-                '>'  => go!(self: error; force_quirks; emit_doctype; to Data),
+                '>'  => go!(self: error_msg (abrupt_identifier(kind))
+                            "Abrupt end of doctype identifier";
+                            force_quirks; emit_doctype; to Data),
                 c    => go!(self: push_doctype_id kind c),
             }},
 
@@ -1147,16 +2340,24 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
                 '\t' | '\n' | '\x0C' | ' '
                      => go!(self: to BetweenDoctypePublicAndSystemIdentifiers),
                 '>'  => go!(self: emit_doctype; to Data),
-                '"'  => go!(self: error; clear_doctype_id System; to DoctypeIdentifierDoubleQuoted System),
-                '\'' => go!(self: error; clear_doctype_id System; to DoctypeIdentifierSingleQuoted System),
-                _    => go!(self: error; force_quirks; to BogusDoctype),
+                '"'  => go!(self: error_msg MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers
+                            "Missing whitespace between doctype identifiers";
+                            clear_doctype_id System; to DoctypeIdentifierDoubleQuoted System),
+                '\'' => go!(self: error_msg MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers
+                            "Missing whitespace between doctype identifiers";
+                            clear_doctype_id System; to DoctypeIdentifierSingleQuoted System),
+                _    => go!(self: error_msg MissingQuoteBeforeDoctypeSystemIdentifier
+                            "Missing quote before doctype identifier";
+                            force_quirks; to BogusDoctype),
             }},
 
             //§ after-doctype-system-identifier-state
             states::AfterDoctypeIdentifier(System) => loop { match get_char!(self) {
                 '\t' | '\n' | '\x0C' | ' ' => (),
                 '>' => go!(self: emit_doctype; to Data),
-                _   => go!(self: error; to BogusDoctype),
+                _   => go!(self: error_msg UnexpectedCharacterAfterDoctypeSystemIdentifier
+                           "Unexpected character after doctype system identifier";
+                           to BogusDoctype),
             }},
 
             //§ between-doctype-public-and-system-identifiers-state
@@ -1165,7 +2366,9 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
                 '>'  => go!(self: emit_doctype; to Data),
                 '"'  => go!(self: clear_doctype_id System; to DoctypeIdentifierDoubleQuoted System),
                 '\'' => go!(self: clear_doctype_id System; to DoctypeIdentifierSingleQuoted System),
-                _    => go!(self: error; force_quirks; to BogusDoctype),
+                _    => go!(self: error_msg MissingQuoteBeforeDoctypeSystemIdentifier
+                            "Missing quote before doctype identifier";
+                            force_quirks; to BogusDoctype),
             }},
 
             //§ bogus-doctype-state
@@ -1183,9 +2386,18 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
 
             //§ markup-declaration-open-state
             states::MarkupDeclarationOpen => loop {
+                let mut keyword = None;
                 if lookahead_and_consume!(self, 2, |s| s == "--") {
                     go!(self: clear_comment; to CommentStart);
-                } else if lookahead_and_consume!(self, 7, |s| s.eq_ignore_ascii_case("doctype")) {
+                } else if lookahead_and_consume!(self, 7, |s| {
+                    keyword = Some(String::from_str(s));
+                    s.eq_ignore_ascii_case("doctype")
+                }) {
+                    if self.opts.raw_doctype {
+                        let mut raw = String::from_str("<!");
+                        raw.push_str(keyword.unwrap().as_slice());
+                        self.raw_doctype = Some(raw);
+                    }
                     go!(self: to Doctype);
                 } else {
                     // FIXME: CDATA, requires "adjusted current node" from tree builder
@@ -1209,6 +2421,7 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
 
         let progress = match outcome {
             char_ref::Done => {
+                self.report_char_ref(&mut *tok);
                 self.process_char_ref(tok.get_result());
                 return true;
             }
@@ -1221,12 +2434,23 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
         progress
     }
 
+    fn report_char_ref(&mut self, tok: &mut CharRefTokenizer) {
+        for diag in tok.take_diagnostics().into_iter() {
+            self.sink.char_ref_diagnostic(diag);
+        }
+    }
+
     fn process_char_ref(&mut self, char_ref: CharRef) {
         let CharRef { mut chars, mut num_chars } = char_ref;
 
         if num_chars == 0 {
             chars[0] = '&';
             num_chars = 1;
+        } else {
+            match self.input_stats {
+                Some(ref mut stats) => stats.char_refs += 1,
+                None => (),
+            }
         }
 
         for i in range(0, num_chars) {
@@ -1243,26 +2467,46 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
         }
     }
 
-    /// Indicate that we have reached the end of the input.
+    /// Indicate that we have reached the end of the input.  If the sink
+    /// has paused tokenization, this takes effect on `resume`.
     pub fn end(&mut self) {
+        if self.aborted {
+            return;
+        }
+        if self.paused {
+            self.end_pending = true;
+            return;
+        }
+        self.finish();
+    }
+
+    fn finish(&mut self) {
+        // If we're waiting for lookahead, we're not gonna get it.  Set
+        // `at_eof` before anything else, so that the sink can't pause us
+        // from here on, even for errors in an unfinished char ref.
+        self.wait_for = None;
+        self.at_eof = true;
+
         // Handle EOF in the char ref sub-tokenizer, if there is one.
         // Do this first because it might un-consume stuff.
         match self.char_ref_tokenizer.take() {
             None => (),
             Some(mut tok) => {
                 tok.end_of_file(self);
+                self.report_char_ref(&mut *tok);
                 self.process_char_ref(tok.get_result());
             }
         }
 
         // Process all remaining buffered input.
-        // If we're waiting for lookahead, we're not gonna get it.
-        self.wait_for = None;
-        self.at_eof = true;
         self.run();
 
-        while self.eof_step() {
-            // loop
+        while !self.aborted && self.eof_step() {
+            self.apply_sink_state();
+        }
+
+        if !self.aborted {
+            self.sink.end();
         }
 
         if self.opts.profile {
@@ -1305,7 +2549,8 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
             | states::BeforeAttributeName | states::AttributeName
             | states::AfterAttributeName | states::BeforeAttributeValue
             | states::AttributeValue(_) | states::AfterAttributeValueQuoted
-            | states::SelfClosingStartTag | states::ScriptDataEscapedDash(_)
+            | states::SelfClosingStartTag | states::LazyAttributes(_)
+            | states::ScriptDataEscapedDash(_)
             | states::ScriptDataEscapedDashDash(_)
                 => go!(self: error_eof; to Data),
 
@@ -1365,6 +2610,45 @@ impl<'sink, Sink: TokenSink> Tokenizer<'sink, Sink> {
     }
 }
 
+struct AttrCollector {
+    attrs: Vec<Attribute>,
+}
+
+impl TokenSink for AttrCollector {
+    fn process_token(&mut self, token: Token) {
+        match token {
+            TagToken(tag) => self.attrs = tag.attrs,
+            _ => (),
+        }
+    }
+}
+
+/// Split up the `Tag::raw_attrs` left by lazy attribute mode, just as
+/// the tokenizer would have with `opts`.  Parse errors are dropped, and
+/// so are the options which report or trace.
+pub fn parse_raw_attrs(raw: &str, opts: TokenizerOpts) -> Vec<Attribute> {
+    let mut sink = AttrCollector { attrs: vec!() };
+    {
+        let mut tok = Tokenizer::new(&mut sink, TokenizerOpts {
+            lazy_attrs: false,
+            track_lines: false,
+            profile: false,
+            trace: false,
+            atom_stats: false,
+            input_stats: false,
+            initial_state: None,
+            last_start_tag_name: None,
+            .. opts
+        });
+        let mut text = String::from_str("<x ");
+        text.push_str(raw);
+        text.push('>');
+        tok.feed(text);
+        tok.end();
+    }
+    sink.attrs
+}
+
 #[cfg(test)]
 #[allow(non_snake_case)]
 mod test {
@@ -1372,7 +2656,19 @@ mod test {
     use collections::vec::Vec;
     use collections::string::String;
     use collections::slice::CloneableVector;
+    use collections::str::{MaybeOwned, StrAllocating};
+    use core::default::Default;
     use super::{option_push, append_strings}; // private items
+    use super::{Tokenizer, TokenSink, Token, ParseError, TokenizerOpts};
+    use super::{CommentToken, CommentChunkToken, TagToken, CharacterTokens, PreserveCr};
+    use super::{EOFToken, EndTag, Tag, TagView, TagKind, parse_raw_attrs, states};
+    use super::{Doctype, DoctypeToken, Span, AttrSpan, InputStats};
+    use super::{CharRefDiagnostic, CharRefIssue, CharRefMissingSemicolon, CharRefControl};
+    use super::{CharRefOutOfRange, CharRefUnknownName, CharRefEqualsInAttribute};
+    use super::{CharRefSurrogate, CharRefNoncharacter, ParseErrorKind};
+    use super::{DuplicateAttrPolicy, KeepFirst, KeepLast, KeepAll};
+
+    use string_cache::Atom;
 
     #[test]
     fn push_to_None_gives_singleton() {
@@ -1414,4 +2710,897 @@ mod test {
         let ptr_new = lhs.into_bytes()[0] as *const u8;
         assert_eq!(ptr_old, ptr_new);
     }
+
+    struct ErrorLogger {
+        errors: Vec<MaybeOwned<'static>>,
+    }
+
+    impl TokenSink for ErrorLogger {
+        fn process_token(&mut self, token: Token) {
+            match token {
+                ParseError(e) => self.errors.push(e),
+                _ => (),
+            }
+        }
+    }
+
+    fn errors(input: &str) -> Vec<String> {
+        let mut sink = ErrorLogger { errors: vec!() };
+        {
+            let mut tok = Tokenizer::new(&mut sink, Default::default());
+            tok.feed(String::from_str(input));
+            tok.end();
+        }
+        sink.errors.into_iter().map(|e| e.into_string()).collect()
+    }
+
+    #[test]
+    fn doctype_missing_whitespace() {
+        assert_eq!(errors("<!DOCTYPEhtml>"),
+            vec!(String::from_str("Missing whitespace before doctype name")));
+        assert_eq!(errors("<!DOCTYPE html PUBLIC\"x\">"),
+            vec!(String::from_str("Missing whitespace after doctype keyword")));
+        assert_eq!(errors("<!DOCTYPE html PUBLIC \"x\"\"y\">"),
+            vec!(String::from_str("Missing whitespace between doctype identifiers")));
+    }
+
+    #[test]
+    fn doctype_missing_name() {
+        // Only the missing name is an error, not the missing whitespace.
+        assert_eq!(errors("<!DOCTYPE>"), vec!(String::from_str("Missing doctype name")));
+    }
+
+    struct ErrorKindLogger {
+        codes: Vec<&'static str>,
+    }
+
+    impl TokenSink for ErrorKindLogger {
+        fn process_token(&mut self, _token: Token) { }
+
+        fn classified_parse_error(&mut self, kind: ParseErrorKind, _msg: MaybeOwned<'static>) {
+            self.codes.push(kind.code());
+        }
+    }
+
+    fn error_codes(input: &str) -> Vec<&'static str> {
+        let mut sink = ErrorKindLogger { codes: vec!() };
+        {
+            let mut tok = Tokenizer::new(&mut sink, Default::default());
+            tok.feed(String::from_str(input));
+            tok.end();
+        }
+        sink.codes
+    }
+
+    #[test]
+    fn error_kinds() {
+        assert_eq!(error_codes("\0"), vec!("unexpected-null-character"));
+        assert_eq!(error_codes("<?x>"), vec!("unexpected-question-mark-instead-of-tag-name"));
+        assert_eq!(error_codes("</>"), vec!("missing-end-tag-name"));
+        assert_eq!(error_codes("<a b='1'c>"), vec!("missing-whitespace-between-attributes"));
+        assert_eq!(error_codes("<a b=1 b=2>"), vec!("duplicate-attribute"));
+        assert_eq!(error_codes("</a b>"), vec!("end-tag-with-attributes"));
+        assert_eq!(error_codes("<!-->"), vec!("abrupt-closing-of-empty-comment"));
+        assert_eq!(error_codes("<a b"), vec!("eof-in-tag"));
+        assert_eq!(error_codes("<!DOCTYPE>"), vec!("missing-doctype-name"));
+        assert_eq!(error_codes("<!DOCTYPE html PUBLIC>"),
+            vec!("missing-doctype-public-identifier"));
+        assert_eq!(error_codes("<!DOCTYPE html SYSTEM x>"),
+            vec!("missing-quote-before-doctype-system-identifier"));
+        assert_eq!(error_codes("&#0;&#xD800;&#x110000;&#;"), vec!(
+            "null-character-reference", "surrogate-character-reference",
+            "character-reference-outside-unicode-range",
+            "absence-of-digits-in-numeric-character-reference"));
+        assert_eq!(error_codes("&bogus;&#65"), vec!(
+            "unknown-named-character-reference",
+            "missing-semicolon-after-character-reference"));
+    }
+
+    struct DoctypeLogger {
+        doctypes: Vec<Doctype>,
+    }
+
+    impl TokenSink for DoctypeLogger {
+        fn process_token(&mut self, token: Token) {
+            match token {
+                DoctypeToken(dt) => self.doctypes.push(dt),
+                _ => (),
+            }
+        }
+    }
+
+    struct LimitLogger {
+        log: Vec<String>,
+    }
+
+    impl TokenSink for LimitLogger {
+        fn process_token(&mut self, token: Token) {
+            let line = match token {
+                TagToken(tag) => {
+                    let mut line = format!("<{}", tag.name.as_slice());
+                    for attr in tag.attrs.iter() {
+                        line.push_str(format!(" {}={}", attr.name.local.as_slice(),
+                            attr.value).as_slice());
+                    }
+                    line.push('>');
+                    line
+                }
+                DoctypeToken(dt) => format!("!{} {}", dt.name, dt.public_id),
+                CommentToken(c) => format!("<!--{}-->", c),
+                ParseError(e) => e.into_string(),
+                _ => return,
+            };
+            self.log.push(line);
+        }
+    }
+
+    #[test]
+    fn size_limits() {
+        let mut sink = LimitLogger { log: vec!() };
+        {
+            let mut tok = Tokenizer::new(&mut sink, TokenizerOpts {
+                max_attr_count: Some(2),
+                max_token_size: Some(4),
+                .. Default::default()
+            });
+            tok.feed(String::from_str(concat!(
+                "<abcdefg x=123456 y=\"1234&amp;56\" z=1 w=2>",
+                "<!DOCTYPE htmlhtml PUBLIC \"abcdefg\">",
+                "<p a=1 b=2 a=3 c=4><i v=\"\u00e9\u00e9\u00e9\">")));
+            tok.end();
+        }
+        let trunc = "Token exceeds length limit; truncating";
+        let too_many = "Too many attributes; dropping the rest";
+        let expected = [
+            trunc, trunc, trunc, too_many, "<abcd x=1234 y=1234>",
+            trunc, "!Some(html) Some(abcd)",
+            "Duplicate attribute", too_many, "<p a=1 b=2>",
+            trunc, "<i v=\u00e9\u00e9>",
+        ];
+        let expected: Vec<String> = expected.iter().map(|s| String::from_str(*s)).collect();
+        assert_eq!(sink.log, expected);
+    }
+
+    #[test]
+    fn comment_and_attr_name_limits() {
+        let mut sink = LimitLogger { log: vec!() };
+        {
+            let mut tok = Tokenizer::new(&mut sink, TokenizerOpts {
+                max_comment_len: Some(5),
+                max_attr_name_len: Some(3),
+                .. Default::default()
+            });
+            tok.feed(String::from_str(concat!(
+                "<!--abcdefg--><!--abc--!x--><!--ab-->",
+                "<p abcdef=1 xy=2>")));
+            tok.end();
+        }
+        let trunc = "Token exceeds length limit; truncating";
+        let expected = [
+            trunc, "<!--abcde-->",
+            "Bad character", trunc, "<!--abc---->",
+            "<!--ab-->",
+            trunc, "<p abc=1 xy=2>",
+        ];
+        let expected: Vec<String> = expected.iter().map(|s| String::from_str(*s)).collect();
+        assert_eq!(sink.log, expected);
+    }
+
+    #[test]
+    fn atom_stats() {
+        let mut sink = DoctypeLogger { doctypes: vec!() };
+        let mut tok = Tokenizer::new(&mut sink, TokenizerOpts {
+            atom_stats: true,
+            .. Default::default()
+        });
+        tok.feed(String::from_str("<p class=a><p class=b id=c>x</p><custom-el data-x=1 class=d>"));
+        tok.end();
+
+        // End tags count too.
+        let stats = tok.atom_stats().expect("no atom stats");
+        assert_eq!(stats.total(), 9);
+        assert_eq!(stats.distinct(), 5);
+        assert_eq!(stats.total_bytes(), "pclassidcustom-eldata-x".len());
+        assert_eq!(stats.most_frequent(3), vec!(
+            (String::from_str("class"), 3), (String::from_str("p"), 3),
+            (String::from_str("custom-el"), 1)));
+    }
+
+    #[test]
+    fn no_atom_stats_by_default() {
+        let mut sink = DoctypeLogger { doctypes: vec!() };
+        let tok = Tokenizer::new(&mut sink, Default::default());
+        assert!(tok.atom_stats().is_none());
+    }
+
+    #[test]
+    fn raw_doctype() {
+        let mut sink = DoctypeLogger { doctypes: vec!() };
+        {
+            let mut tok = Tokenizer::new(&mut sink, TokenizerOpts {
+                raw_doctype: true,
+                .. Default::default()
+            });
+            tok.feed(String::from_str("<!doctype  html pub"));
+            tok.feed(String::from_str("lic \"x\" [junk]\r\n>"));
+            tok.feed(String::from_str("<!DOCTYPE"));
+            tok.end();
+        }
+        let raws: Vec<Option<String>> = sink.doctypes.into_iter().map(|dt| dt.raw).collect();
+        assert_eq!(raws, vec!(
+            Some(String::from_str("<!doctype  html public \"x\" [junk]\n>")),
+            Some(String::from_str("<!DOCTYPE"))));
+    }
+
+    struct CommentLogger {
+        comments: Vec<Token>,
+    }
+
+    impl TokenSink for CommentLogger {
+        fn process_token(&mut self, token: Token) {
+            match token {
+                CommentToken(_) | CommentChunkToken(_) => self.comments.push(token),
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn comment_chunks() {
+        let mut sink = CommentLogger { comments: vec!() };
+        {
+            let mut tok = Tokenizer::new(&mut sink, TokenizerOpts {
+                comment_chunk_len: Some(4),
+                .. Default::default()
+            });
+            tok.feed(String::from_str("<!--abcdefghij--><?xyzw>"));
+            tok.end();
+        }
+        assert_eq!(sink.comments, vec!(
+            CommentChunkToken(String::from_str("abcd")),
+            CommentChunkToken(String::from_str("efgh")),
+            CommentToken(String::from_str("ij")),
+            CommentChunkToken(String::from_str("?xyz")),
+            CommentToken(String::from_str("w"))));
+    }
+
+    struct AbortAfterTwo {
+        seen: uint,
+        ended: bool,
+    }
+
+    impl TokenSink for AbortAfterTwo {
+        fn process_token(&mut self, _token: Token) {
+            self.seen += 1;
+        }
+
+        fn aborted(&self) -> bool {
+            self.seen >= 2
+        }
+
+        fn end(&mut self) {
+            self.ended = true;
+        }
+    }
+
+    #[test]
+    fn sink_abort() {
+        let mut sink = AbortAfterTwo { seen: 0, ended: false };
+        {
+            let mut tok = Tokenizer::new(&mut sink, Default::default());
+            tok.feed(String::from_str("<a><b><c>"));
+            assert!(tok.is_aborted());
+            tok.feed(String::from_str("<d>"));
+            tok.end();
+        }
+        assert_eq!(sink.seen, 2);
+        assert!(!sink.ended);
+    }
+
+    struct EndLogger {
+        events: Vec<&'static str>,
+    }
+
+    impl TokenSink for EndLogger {
+        fn process_token(&mut self, token: Token) {
+            self.events.push(match token {
+                EOFToken => "eof",
+                _ => "token",
+            });
+        }
+
+        fn end(&mut self) {
+            self.events.push("end");
+        }
+    }
+
+    #[test]
+    fn sink_end() {
+        let mut sink = EndLogger { events: vec!() };
+        {
+            let mut tok = Tokenizer::new(&mut sink, Default::default());
+            tok.feed(String::from_str("<a>x"));
+            tok.end();
+        }
+        assert_eq!(sink.events, vec!("token", "token", "eof", "end"));
+    }
+
+    struct TextLogger {
+        text: String,
+    }
+
+    impl TokenSink for TextLogger {
+        fn process_token(&mut self, token: Token) {
+            match token {
+                CharacterTokens(s) => self.text.push_str(s.as_slice()),
+                TagToken(t) => for attr in t.attrs.iter() {
+                    self.text.push_str(attr.value.as_slice());
+                },
+                _ => (),
+            }
+        }
+    }
+
+    fn cr_text(preserve_cr: PreserveCr) -> String {
+        let mut sink = TextLogger { text: String::new() };
+        {
+            let mut tok = Tokenizer::new(&mut sink, TokenizerOpts {
+                preserve_cr: preserve_cr,
+                .. Default::default()
+            });
+            tok.feed(String::from_str("a\r\nb<x y='c\r\nd\re'>"));
+            tok.end();
+        }
+        sink.text
+    }
+
+    #[test]
+    fn preserve_cr() {
+        assert_eq!(cr_text(Default::default()).as_slice(), "a\nbc\nd\ne");
+        assert_eq!(cr_text(PreserveCr { attr_values: true, .. Default::default() }).as_slice(),
+            "a\nbc\r\nd\re");
+        assert_eq!(cr_text(PreserveCr { text: true, .. Default::default() }).as_slice(),
+            "a\r\nbc\nd\ne");
+    }
+
+    fn char_ref_text(input: &str, strict: bool) -> String {
+        let mut sink = TextLogger { text: String::new() };
+        {
+            let mut tok = Tokenizer::new(&mut sink, TokenizerOpts {
+                strict_char_refs: strict,
+                .. Default::default()
+            });
+            tok.feed(String::from_str(input));
+            tok.end();
+        }
+        sink.text
+    }
+
+    #[test]
+    fn check_fast_path_non_ascii() {
+        let input = "\u00e9t\u00e9 &amp; \u4e2d<p title='\u00e9\u00e9'>\u00e9\u4e2d";
+        let text = |check: bool| {
+            let mut sink = TextLogger { text: String::new() };
+            {
+                let mut tok = Tokenizer::new(&mut sink, TokenizerOpts {
+                    check_fast_path: check,
+                    .. Default::default()
+                });
+                tok.feed(String::from_str(input));
+                tok.end();
+            }
+            sink.text
+        };
+        assert_eq!(text(true), text(false));
+        assert_eq!(text(true).as_slice(),
+            "\u00e9t\u00e9 & \u4e2d\u00e9\u00e9\u00e9\u4e2d");
+    }
+
+    struct TextSliceLogger {
+        text: String,
+        slices: uint,
+    }
+
+    impl TokenSink for TextSliceLogger {
+        fn process_token(&mut self, token: Token) {
+            match token {
+                CharacterTokens(s) => self.text.push_str(s.as_slice()),
+                _ => (),
+            }
+        }
+
+        fn wants_text_slices(&self) -> bool {
+            true
+        }
+
+        fn process_text(&mut self, text: &str) {
+            self.slices += 1;
+            self.text.push_str(text);
+        }
+    }
+
+    #[test]
+    fn text_slices() {
+        let chunks = ["abc\r\nd", "ef&amp;ghi<p>j", "kl"];
+        let mut sink = TextSliceLogger { text: String::new(), slices: 0 };
+        {
+            let mut tok = Tokenizer::new(&mut sink, Default::default());
+            for chunk in chunks.iter() {
+                tok.feed(String::from_str(*chunk));
+            }
+            tok.end();
+        }
+        assert_eq!(sink.text, chunks_text(chunks.as_slice()));
+        // "abc", "ef", "ghi", "j" and "kl".  The newline, the "d" read
+        // while skipping the LF after a CR, and the "&" are owned.
+        assert_eq!(sink.slices, 5);
+    }
+
+    #[test]
+    fn checkpoint_and_rewind() {
+        let mut sink = TextLogger { text: String::new() };
+        {
+            let mut tok = Tokenizer::new(&mut sink, Default::default());
+            tok.feed(String::from_str("<a href='x&am"));
+            let saved = tok.checkpoint();
+            tok.feed(String::from_str("p;y'>b"));
+            tok.rewind(saved.clone());
+            tok.feed(String::from_str("p=z'>c"));
+            tok.rewind(saved);
+            tok.end();
+        }
+        // What was emitted before rewinding stays emitted.  The tag which
+        // was open at the checkpoint is dropped at EOF, the last time.
+        assert_eq!(sink.text.as_slice(), "x&ybx&amp=zc");
+    }
+
+    // Pauses after every token, if `pause` is set.
+    struct PausingLogger {
+        log: String,
+        pause: bool,
+    }
+
+    impl TokenSink for PausingLogger {
+        fn process_token(&mut self, token: Token) {
+            match token {
+                CharacterTokens(s) => self.log.push_str(s.as_slice()),
+                ParseError(_) => self.log.push('!'),
+                EOFToken => self.log.push('$'),
+                _ => (),
+            }
+        }
+
+        fn paused(&self) -> bool {
+            self.pause
+        }
+    }
+
+    fn pausing_text(chunks: &[&str], pause: bool) -> String {
+        let mut sink = PausingLogger { log: String::new(), pause: pause };
+        {
+            let mut tok = Tokenizer::new(&mut sink, Default::default());
+            for chunk in chunks.iter() {
+                tok.feed(String::from_str(*chunk));
+            }
+            tok.end();
+            while tok.is_paused() {
+                tok.resume();
+            }
+        }
+        sink.log
+    }
+
+    #[test]
+    fn pause_in_char_ref() {
+        // Pausing partway through a character reference, and ending while
+        // paused, comes out as if nothing had paused.
+        let inputs = ["a&amp", "b&#", "c&#x4", "d&notit", "e&bogus", "f&", "<p title='&am"];
+        for input in inputs.iter() {
+            let whole = pausing_text(&[*input], false);
+            assert_eq!(pausing_text(&[*input], true), whole);
+            for i in range(1, input.len()) {
+                assert_eq!(pausing_text(&[input.slice_to(i), input.slice_from(i)], true), whole);
+            }
+        }
+        assert_eq!(pausing_text(&["b&#"], false).as_slice(), "b!&#$");
+
+        // Once `end` has started, the sink can't pause, even for errors
+        // in a character reference cut short by EOF.
+        let mut sink = PausingLogger { log: String::new(), pause: true };
+        {
+            let mut tok = Tokenizer::new(&mut sink, Default::default());
+            tok.feed(String::from_str("&#"));
+            assert!(!tok.is_paused());
+            tok.end();
+            assert!(!tok.is_paused());
+        }
+        assert_eq!(sink.log.as_slice(), "!&#$");
+    }
+
+    fn chunks_text(chunks: &[&str]) -> String {
+        let mut sink = TextLogger { text: String::new() };
+        {
+            let mut tok = Tokenizer::new(&mut sink, Default::default());
+            for chunk in chunks.iter() {
+                tok.feed(String::from_str(*chunk));
+            }
+            tok.end();
+        }
+        sink.text
+    }
+
+    #[test]
+    fn char_refs_split_across_feeds() {
+        let inputs = [
+            "a&amp;b &amp c &notit; &#x41;&#65 &#x; &bogus; &",
+            "<a title='&amp;&amp=&ampx&not;&notin&#x3d;' b=&copy c=\"&lt\">",
+            "&amp\rx&not\r\ny&#65\r",
+        ];
+        for input in inputs.iter() {
+            let whole = chunks_text(&[*input]);
+            for i in range(1, input.len()) {
+                assert_eq!(chunks_text(&[input.slice_to(i), input.slice_from(i)]), whole);
+            }
+            let chars: Vec<String> = input.chars().map(|c| String::from_char(1, c)).collect();
+            let chars: Vec<&str> = chars.iter().map(|c| c.as_slice()).collect();
+            assert_eq!(chunks_text(chars.as_slice()), whole);
+        }
+
+        // A CR read while looking for the end of a name is put back as a CR.
+        assert_eq!(chunks_text(&[inputs[2]]).as_slice(), "&\nx\xac\nyA\n");
+    }
+
+    #[test]
+    fn strict_char_refs() {
+        let input = "&amp; &amp &notin; &notit <a title='&copy; &copy 2014'>";
+        assert_eq!(char_ref_text(input, false).as_slice(), "& & \u2209 \xacit \xa9 \xa9 2014");
+        assert_eq!(char_ref_text(input, true).as_slice(), "& &amp \u2209 &notit \xa9 &copy 2014");
+    }
+
+    /// Switches to PLAINTEXT after `<x>`, and records what follows.
+    struct PlaintextAfterX {
+        text: Option<String>,
+        saw_eof: bool,
+    }
+
+    impl TokenSink for PlaintextAfterX {
+        fn process_token(&mut self, token: Token) {
+            match token {
+                TagToken(ref t) if t.name == atom!(x) && self.text.is_none()
+                    => self.text = Some(String::new()),
+                CharacterTokens(s) => match self.text {
+                    Some(ref mut text) => text.push_str(s.as_slice()),
+                    None => (),
+                },
+                EOFToken => self.saw_eof = true,
+                _ => (),
+            }
+        }
+
+        fn query_state_change(&mut self) -> Option<states::State> {
+            match self.text {
+                Some(_) => Some(states::Plaintext),
+                None => None,
+            }
+        }
+    }
+
+    #[test]
+    fn sink_plaintext() {
+        let mut sink = PlaintextAfterX { text: None, saw_eof: false };
+        {
+            let mut tok = Tokenizer::new(&mut sink, Default::default());
+            tok.feed(String::from_str("<y>a<x>b</"));
+            tok.feed(String::from_str("x><y>&amp;\r\n"));
+            tok.end();
+        }
+        assert_eq!(sink.text, Some(String::from_str("b</x><y>&amp;\n")));
+        assert!(sink.saw_eof);
+    }
+
+    /// Switches to RAWTEXT after the first end tag, and to PLAINTEXT
+    /// after a comment, and records the text.
+    struct SwitchAfterEndTagAndComment {
+        next: Option<states::State>,
+        switched_raw: bool,
+        text: String,
+    }
+
+    impl TokenSink for SwitchAfterEndTagAndComment {
+        fn process_token(&mut self, token: Token) {
+            match token {
+                TagToken(ref t) if t.kind == EndTag && !self.switched_raw => {
+                    self.switched_raw = true;
+                    self.next = Some(states::RawData(states::Rawtext));
+                }
+                CommentToken(_) => self.next = Some(states::Plaintext),
+                CharacterTokens(s) => self.text.push_str(s.as_slice()),
+                _ => (),
+            }
+        }
+
+        fn query_state_change(&mut self) -> Option<states::State> {
+            self.next.take()
+        }
+
+        fn query_last_start_tag(&mut self) -> Option<Atom> {
+            Some(atom!(x))
+        }
+    }
+
+    #[test]
+    fn sink_state_after_end_tag_and_comment() {
+        let mut sink = SwitchAfterEndTagAndComment {
+            next: None,
+            switched_raw: false,
+            text: String::new(),
+        };
+        {
+            let mut tok = Tokenizer::new(&mut sink, Default::default());
+            tok.feed(String::from_str("</x><b>&amp;</x><!--c--><y>&amp;"));
+            tok.end();
+        }
+        assert_eq!(sink.text.as_slice(), "<b>&amp;<y>&amp;");
+    }
+
+    /// Wants the attributes of `<a>` only.
+    struct LazyTagLogger {
+        tags: Vec<Tag>,
+    }
+
+    impl TokenSink for LazyTagLogger {
+        fn process_token(&mut self, token: Token) {
+            match token {
+                TagToken(t) => self.tags.push(t),
+                _ => (),
+            }
+        }
+
+        fn wants_attrs(&mut self, _kind: TagKind, name: &str) -> bool {
+            name == "a"
+        }
+    }
+
+    #[test]
+    fn lazy_attrs() {
+        let mut sink = LazyTagLogger { tags: vec!() };
+        {
+            let mut tok = Tokenizer::new(&mut sink, TokenizerOpts {
+                lazy_attrs: true,
+                .. Default::default()
+            });
+            tok.feed(String::from_str("<p class=\"x>"));
+            tok.feed(String::from_str("y\" id=z /><a href=u title='t'><br/><img src=a/>"));
+            tok.end();
+        }
+        let tags = sink.tags;
+        assert_eq!(tags.len(), 4);
+
+        assert!(tags[0].attrs.is_empty() && tags[0].self_closing);
+        let raw = tags[0].raw_attrs.clone().unwrap();
+        assert_eq!(raw.as_slice(), "class=\"x>y\" id=z ");
+        let attrs = parse_raw_attrs(raw.as_slice(), Default::default());
+        let values: Vec<&str> = attrs.iter().map(|a| a.value.as_slice()).collect();
+        assert_eq!(values, vec!("x>y", "z"));
+
+        assert_eq!(tags[1].attrs.len(), 2);
+        assert_eq!(tags[1].raw_attrs, None);
+        assert!(tags[2].self_closing && tags[2].raw_attrs.is_none());
+        assert!(!tags[3].self_closing);
+        assert_eq!(tags[3].raw_attrs, Some(String::from_str("src=a/")));
+    }
+
+    /// Takes tags as views, and keeps a copy of each.
+    struct TagViewLogger {
+        tags: Vec<Tag>,
+    }
+
+    impl TokenSink for TagViewLogger {
+        fn process_token(&mut self, token: Token) {
+            match token {
+                TagToken(_) => fail!("got an owned tag"),
+                _ => (),
+            }
+        }
+
+        fn wants_tag_views(&self) -> bool {
+            true
+        }
+
+        fn process_tag_view(&mut self, tag: TagView) {
+            self.tags.push(tag.to_tag());
+        }
+    }
+
+    #[test]
+    fn tag_views() {
+        let input = "<p class=x id=y><br/><a href=u>t</a x=1><p z>";
+        let opts = TokenizerOpts { lazy_attrs: true, .. Default::default() };
+
+        let mut owned = LazyTagLogger { tags: vec!() };
+        {
+            let mut tok = Tokenizer::new(&mut owned, opts.clone());
+            tok.feed(String::from_str(input));
+            tok.end();
+        }
+        let mut viewed = TagViewLogger { tags: vec!() };
+        {
+            let mut tok = Tokenizer::new(&mut viewed, opts);
+            tok.feed(String::from_str(input));
+            tok.end();
+        }
+
+        // The storage kept between views leaves nothing behind.
+        assert_eq!(viewed.tags.len(), 5);
+        assert_eq!(viewed.tags, owned.tags);
+        assert_eq!(viewed.tags[0].view().to_tag(), viewed.tags[0]);
+    }
+
+    #[test]
+    fn attr_spans() {
+        let mut sink = LazyTagLogger { tags: vec!() };
+        {
+            let mut tok = Tokenizer::new(&mut sink, TokenizerOpts {
+                attr_spans: true,
+                .. Default::default()
+            });
+            tok.feed(String::from_str("<a id=x  cla"));
+            tok.feed(String::from_str("ss=\"b c\" hidden data-x='&am"));
+            tok.feed(String::from_str("p;y' id=z\r\ntitle=t>"));
+            tok.end();
+        }
+        let spans = |name_start, name_end, value_start, value_end| AttrSpan {
+            name: Span { start: name_start, end: name_end },
+            value: Span { start: value_start, end: value_end },
+        };
+        // The second `id` is dropped, and its span with it.
+        assert_eq!(sink.tags[0].attrs.len(), 5);
+        assert_eq!(sink.tags[0].attr_spans, vec!(
+            spans(3, 5, 6, 7),
+            spans(9, 14, 16, 19),
+            spans(21, 27, 27, 27),
+            spans(28, 34, 36, 42),
+            spans(50, 55, 56, 57)));
+    }
+
+    fn duplicate_attrs(policy: DuplicateAttrPolicy) -> Vec<(Vec<(String, String)>, bool)> {
+        let mut sink = LazyTagLogger { tags: vec!() };
+        {
+            let mut tok = Tokenizer::new(&mut sink, TokenizerOpts {
+                duplicate_attrs: policy,
+                .. Default::default()
+            });
+            tok.feed(String::from_str("<a x=1 y=2 x=3 x=4><a x=5>"));
+            tok.end();
+        }
+        sink.tags.iter().map(|t| {
+            let attrs = t.attrs.iter().map(|a|
+                (String::from_str(a.name.local.as_slice()), a.value.clone())).collect();
+            (attrs, t.had_duplicate_attrs)
+        }).collect()
+    }
+
+    #[test]
+    fn duplicate_attr_policies() {
+        fn attrs(attrs: &[(&str, &str)]) -> Vec<(String, String)> {
+            attrs.iter().map(|&(n, v)| (String::from_str(n), String::from_str(v))).collect()
+        }
+        let second = (attrs([("x", "5")]), false);
+
+        assert_eq!(duplicate_attrs(KeepFirst),
+            vec!((attrs([("x", "1"), ("y", "2")]), true), second.clone()));
+        assert_eq!(duplicate_attrs(KeepLast),
+            vec!((attrs([("x", "4"), ("y", "2")]), true), second.clone()));
+        assert_eq!(duplicate_attrs(KeepAll),
+            vec!((attrs([("x", "1"), ("y", "2"), ("x", "3"), ("x", "4")]), true), second));
+    }
+
+    struct CharRefLogger {
+        diags: Vec<CharRefDiagnostic>,
+    }
+
+    impl TokenSink for CharRefLogger {
+        fn process_token(&mut self, _: Token) { }
+
+        fn char_ref_diagnostic(&mut self, diag: CharRefDiagnostic) {
+            self.diags.push(diag);
+        }
+    }
+
+    fn char_ref_diags(input: &str) -> Vec<(CharRefIssue, String, String)> {
+        let mut sink = CharRefLogger { diags: vec!() };
+        {
+            let mut tok = Tokenizer::new(&mut sink, TokenizerOpts {
+                char_ref_diagnostics: true,
+                .. Default::default()
+            });
+            tok.feed(String::from_str(input));
+            tok.end();
+        }
+        sink.diags.into_iter().map(|d| (d.issue, d.text, d.chars)).collect()
+    }
+
+    #[test]
+    fn char_ref_diagnostics() {
+        let d = |issue, text: &str, chars: &str|
+            (issue, String::from_str(text), String::from_str(chars));
+
+        assert_eq!(char_ref_diags("&amp; &#65; &#x41;"), vec!());
+        assert_eq!(char_ref_diags("&amp x"), vec!(d(CharRefMissingSemicolon, "&amp", "&")));
+        assert_eq!(char_ref_diags("&#x80;"), vec!(d(CharRefControl, "&#x80;", "\u20ac")));
+        assert_eq!(char_ref_diags("&#1114112;"),
+            vec!(d(CharRefOutOfRange, "&#1114112;", "\ufffd")));
+        assert_eq!(char_ref_diags("&#1 "), vec!(
+            d(CharRefMissingSemicolon, "&#1", "\x01"),
+            d(CharRefControl, "&#1", "\x01")));
+        assert_eq!(char_ref_diags("&bogus;"), vec!(d(CharRefUnknownName, "&bogus;", "")));
+        assert_eq!(char_ref_diags("<a href='?x&copy=1'>"),
+            vec!(d(CharRefEqualsInAttribute, "&copy", "")));
+    }
+
+    #[test]
+    fn numeric_char_refs_outside_bmp() {
+        let d = |issue, text: &str, chars: &str|
+            (issue, String::from_str(text), String::from_str(chars));
+
+        // Characters outside the BMP are one reference each.
+        assert_eq!(char_ref_text("&#x1F600;&#128512;&#x10000;&#X1f4a9;", false).as_slice(),
+            "\U0001f600\U0001f600\U00010000\U0001f4a9");
+        assert_eq!(char_ref_diags("&#x1F600; &#x0001F600; &#x10FFFD;"), vec!());
+        assert_eq!(char_ref_diags("&#x10FFFF;"),
+            vec!(d(CharRefNoncharacter, "&#x10FFFF;", "\U0010ffff")));
+        assert_eq!(char_ref_diags("&#x110000;"),
+            vec!(d(CharRefOutOfRange, "&#x110000;", "\ufffd")));
+        assert_eq!(char_ref_diags("&#0;"), vec!(d(CharRefOutOfRange, "&#0;", "\ufffd")));
+
+        // Surrogates are replaced one by one, so a pair isn't joined up.
+        assert_eq!(char_ref_text("&#xD83D;&#xDE00;", false).as_slice(), "\ufffd\ufffd");
+        assert_eq!(char_ref_diags("&#xD800;&#57343;"), vec!(
+            d(CharRefSurrogate, "&#xD800;", "\ufffd"),
+            d(CharRefSurrogate, "&#57343;", "\ufffd")));
+
+        // Digits past U+10FFFF are all consumed, however many there are.
+        assert_eq!(char_ref_text("&#x100000000000000041;x", false).as_slice(), "\ufffdx");
+        assert_eq!(char_ref_diags("&#99999999999999999999;"),
+            vec!(d(CharRefOutOfRange, "&#99999999999999999999;", "\ufffd")));
+        assert_eq!(errors("&#x100000000000000041;"),
+            vec!(String::from_str("Numeric character reference beyond U+10FFFF")));
+        assert_eq!(errors("&#xD800;"),
+            vec!(String::from_str("Invalid numeric character reference")));
+
+        // A reference split between feeds comes out the same.
+        assert_eq!(chunks_text(&["a&#x1F", "600;b"]).as_slice(), "a\U0001f600b");
+        assert_eq!(chunks_text(&["&#xD8", "3D;"]).as_slice(), "\ufffd");
+    }
+
+    fn input_stats(input: &[&str], check_fast_path: bool) -> InputStats {
+        let mut sink = CharRefLogger { diags: vec!() };
+        let mut tok = Tokenizer::new(&mut sink, TokenizerOpts {
+            input_stats: true,
+            check_fast_path: check_fast_path,
+            .. Default::default()
+        });
+        for piece in input.iter() {
+            tok.feed(String::from_str(*piece));
+        }
+        tok.end();
+        tok.input_stats().unwrap().clone()
+    }
+
+    #[test]
+    fn input_stats_counts() {
+        // `&zz` is read as a possible character reference, put back, and
+        // read again as text.  It's counted once.
+        let input = ["<p title='&amp;'>é&l", "t;&zz</p>\r\n"];
+        let stats = input_stats(input.as_slice(), false);
+        assert_eq!(stats, InputStats {
+            chars: 30,
+            bytes: 31,
+            text_bytes: 12,
+            non_ascii_chars: 1,
+            char_refs: 2,
+        });
+        assert_eq!(stats.markup_bytes(), 19);
+        assert_eq!(input_stats(input.as_slice(), true), stats);
+    }
 }
@@ -0,0 +1,139 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A bounded queue of tokens, for consumers which run at their own pace.
+//!
+//! `TokenQueue` pauses the tokenizer when it fills up.  The consumer
+//! takes tokens out through `Tokenizer::sink_mut`, and calls
+//! `Tokenizer::resume` when there's room again:
+//!
+//! ```rust
+//! let mut queue = TokenQueue::new(256);
+//! let mut tok = Tokenizer::new(&mut queue, Default::default());
+//! tok.feed(chunk);
+//! loop {
+//!     let next = tok.sink_mut().pop();
+//!     match next {
+//!         Some(token) => consumer.process(token),
+//!         None if tok.is_paused() => tok.resume(),
+//!         None => break,
+//!     }
+//! }
+//! ```
+//!
+//! Since tokens are consumed after the tokenizer has moved on, the
+//! consumer can't change the tokenizer's state, as a tree builder does
+//! for `<script>` and the like.  So this suits consumers which work from
+//! the tokens alone.
+
+use core::prelude::*;
+
+use tokenizer::interface::{TokenSink, Token};
+
+use collections::{Deque, RingBuf};
+
+/// A `TokenSink` which holds tokens until they're taken out, and pauses
+/// the tokenizer while it holds `capacity` or more.
+pub struct TokenQueue {
+    tokens: RingBuf<Token>,
+    capacity: uint,
+}
+
+impl TokenQueue {
+    pub fn new(capacity: uint) -> TokenQueue {
+        assert!(capacity > 0, "a TokenQueue needs room for a token");
+        TokenQueue {
+            tokens: RingBuf::new(),
+            capacity: capacity,
+        }
+    }
+
+    /// Take out the oldest token.
+    pub fn pop(&mut self) -> Option<Token> {
+        self.tokens.pop_front()
+    }
+
+    /// How many tokens are waiting.
+    pub fn len(&self) -> uint {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Is the queue at or over capacity?  It can go over by the tokens
+    /// the tokenizer emits before it notices.
+    pub fn is_full(&self) -> bool {
+        self.tokens.len() >= self.capacity
+    }
+}
+
+impl TokenSink for TokenQueue {
+    fn process_token(&mut self, token: Token) {
+        self.tokens.push(token);
+    }
+
+    fn paused(&self) -> bool {
+        self.is_full()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::TokenQueue;
+
+    use tokenizer::{Tokenizer, Token, TagToken, EOFToken};
+
+    use core::cmp;
+    use core::default::Default;
+    use collections::MutableSeq;
+    use collections::string::String;
+
+    fn tag_names(tokens: &[Token]) -> String {
+        let mut names = String::new();
+        for token in tokens.iter() {
+            match *token {
+                TagToken(ref tag) => names.push_str(tag.name.as_slice()),
+                _ => (),
+            }
+        }
+        names
+    }
+
+    #[test]
+    fn backpressure() {
+        let mut queue = TokenQueue::new(2);
+        let mut seen = vec!();
+        let mut most = 0;
+        {
+            let mut tok = Tokenizer::new(&mut queue, Default::default());
+            tok.feed(String::from_str("<a><b><c><d>"));
+            assert!(tok.is_paused());
+            tok.feed(String::from_str("<e>"));
+            tok.end();
+
+            loop {
+                most = cmp::max(most, tok.sink_mut().len());
+                let next = tok.sink_mut().pop();
+                match next {
+                    Some(token) => seen.push(token),
+                    None if tok.is_paused() => tok.resume(),
+                    None => break,
+                }
+            }
+            assert!(!tok.is_paused());
+        }
+
+        assert_eq!(most, 2);
+        assert_eq!(tag_names(seen.as_slice()).as_slice(), "abcde");
+        assert_eq!(seen.last(), Some(&EOFToken));
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Hooks for content-security scanning while a document is tokenized.
+//!
+//! `SecurityScanner` wraps any `TokenSink` and reports inline event
+//! handlers, `javascript:` URLs and inline `<script>` bodies to a
+//! `SecurityHooks` as they stream past.  A hook can reject the
+//! document, which stops tokenizing before the wrapped sink sees the
+//! offending token.
+//!
+//! Like `Indexer`, this works from the tokens alone, so a `<script>` is
+//! recognized by its start and end tags, whatever the tree builder
+//! would make of them.  Lines are only known when the tokenizer's
+//! `track_lines` option is set; otherwise they are all 1.
+
+use core::prelude::*;
+
+use tokenizer::interface::{TokenSink, Token, TagToken, CharacterTokens, EOFToken};
+use tokenizer::interface::{TagView, TagKind, StartTag, EndTag, Attribute, CharRefDiagnostic};
+use tokenizer::interface::ParseErrorKind;
+use tokenizer::states;
+
+use util::str::AsciiExt;
+
+use collections::string::String;
+use collections::str::MaybeOwned;
+
+use string_cache::Atom;
+
+/// Whether to go on with a document, as decided by `SecurityHooks`.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum ScanResult {
+    KeepScanning,
+    RejectDocument,
+}
+
+/// Callbacks for `SecurityScanner`.  Each defaults to `KeepScanning`.
+/// `line` is where the tag starts; for a script, its `<script>` tag.
+pub trait SecurityHooks {
+    /// An event handler attribute, such as `onclick`, on element `elem`.
+    fn event_handler(&mut self, _elem: &Atom, _attr: &Attribute, _line: uint) -> ScanResult {
+        KeepScanning
+    }
+
+    /// A URL attribute, such as `href`, whose value is a `javascript:`
+    /// URL.
+    fn javascript_url(&mut self, _elem: &Atom, _attr: &Attribute, _line: uint) -> ScanResult {
+        KeepScanning
+    }
+
+    /// The text of a `<script>` element, when it's not empty.  Called at
+    /// the end tag, or at EOF for an unclosed script.
+    fn inline_script(&mut self, _text: &str, _line: uint) -> ScanResult {
+        KeepScanning
+    }
+}
+
+/// Wraps a `TokenSink`, passing each token by a `SecurityHooks` first.
+pub struct SecurityScanner<'h, Sink, Hooks:'h> {
+    sink: Sink,
+    hooks: &'h mut Hooks,
+    line: uint,
+
+    /// Text of the `<script>` we're in, and the line of its start tag.
+    script: Option<(String, uint)>,
+
+    rejected: bool,
+}
+
+// Attributes whose value is a URL.
+fn is_url_attr(name: &str) -> bool {
+    match name {
+        "action" | "background" | "cite" | "codebase" | "data" | "formaction"
+            | "href" | "icon" | "longdesc" | "manifest" | "poster" | "src"
+            | "xlink:href" => true,
+        _ => false,
+    }
+}
+
+// Does this URL have the `javascript:` scheme?  Like a URL parser, ignore
+// leading spaces and control characters, and tabs and newlines anywhere.
+fn is_javascript_url(value: &str) -> bool {
+    let scheme: String = value.chars()
+        .skip_while(|&c| c <= ' ')
+        .filter(|&c| c != '\t' && c != '\n' && c != '\r')
+        .take(11)
+        .collect();
+    scheme.as_slice().eq_ignore_ascii_case("javascript:")
+}
+
+impl<'h, Sink: TokenSink, Hooks: SecurityHooks> SecurityScanner<'h, Sink, Hooks> {
+    pub fn new(sink: Sink, hooks: &'h mut Hooks) -> SecurityScanner<'h, Sink, Hooks> {
+        SecurityScanner {
+            sink: sink,
+            hooks: hooks,
+            line: 1,
+            script: None,
+            rejected: false,
+        }
+    }
+
+    /// Did a hook reject the document?
+    pub fn rejected(&self) -> bool {
+        self.rejected
+    }
+
+    /// Get back the wrapped sink.
+    pub fn unwrap(self) -> Sink {
+        self.sink
+    }
+
+    fn scan_tag(&mut self, tag: &TagView) -> ScanResult {
+        match tag.kind {
+            StartTag => {
+                for attr in tag.attrs.iter() {
+                    let name = attr.name.local.as_slice();
+                    let result = if name.len() > 2 && name.starts_with("on") {
+                        self.hooks.event_handler(tag.name, attr, self.line)
+                    } else if is_url_attr(name) && is_javascript_url(attr.value.as_slice()) {
+                        self.hooks.javascript_url(tag.name, attr, self.line)
+                    } else {
+                        KeepScanning
+                    };
+                    if result == RejectDocument {
+                        return result;
+                    }
+                }
+                if *tag.name == atom!(script) {
+                    self.script = Some((String::new(), self.line));
+                }
+                KeepScanning
+            }
+
+            EndTag if *tag.name == atom!(script) => self.end_script(),
+            EndTag => KeepScanning,
+        }
+    }
+
+    fn end_script(&mut self) -> ScanResult {
+        match self.script.take() {
+            Some((ref text, line)) if !text.is_empty()
+                => self.hooks.inline_script(text.as_slice(), line),
+            _ => KeepScanning,
+        }
+    }
+}
+
+impl<'h, Sink: TokenSink, Hooks: SecurityHooks> TokenSink for SecurityScanner<'h, Sink, Hooks> {
+    fn process_token(&mut self, token: Token) {
+        let result = match token {
+            TagToken(ref tag) => self.scan_tag(&tag.view()),
+            CharacterTokens(ref text) => {
+                match self.script {
+                    Some((ref mut script, _)) => script.push_str(text.as_slice()),
+                    None => (),
+                }
+                KeepScanning
+            }
+            EOFToken => self.end_script(),
+            _ => KeepScanning,
+        };
+
+        if result == RejectDocument {
+            self.rejected = true;
+            return;
+        }
+        self.sink.process_token(token);
+    }
+
+    fn wants_tag_views(&self) -> bool {
+        self.sink.wants_tag_views()
+    }
+
+    fn process_tag_view(&mut self, tag: TagView) {
+        if self.scan_tag(&tag) == RejectDocument {
+            self.rejected = true;
+            return;
+        }
+        self.sink.process_tag_view(tag);
+    }
+
+    fn wants_text_slices(&self) -> bool {
+        self.sink.wants_text_slices()
+    }
+
+    fn process_text(&mut self, text: &str) {
+        match self.script {
+            Some((ref mut script, _)) => script.push_str(text),
+            None => (),
+        }
+        self.sink.process_text(text);
+    }
+
+    fn query_state_change(&mut self) -> Option<states::State> {
+        self.sink.query_state_change()
+    }
+
+    fn query_last_start_tag(&mut self) -> Option<Atom> {
+        self.sink.query_last_start_tag()
+    }
+
+    fn aborted(&self) -> bool {
+        self.rejected || self.sink.aborted()
+    }
+
+    fn paused(&self) -> bool {
+        self.sink.paused()
+    }
+
+    fn token_line(&mut self, line: uint) {
+        self.line = line;
+        self.sink.token_line(line);
+    }
+
+    fn wants_attrs(&mut self, kind: TagKind, name: &str) -> bool {
+        kind == StartTag || self.sink.wants_attrs(kind, name)
+    }
+
+    fn char_ref_diagnostic(&mut self, diag: CharRefDiagnostic) {
+        self.sink.char_ref_diagnostic(diag);
+    }
+
+    fn classified_parse_error(&mut self, kind: ParseErrorKind, msg: MaybeOwned<'static>) {
+        self.sink.classified_parse_error(kind, msg);
+    }
+
+    fn end(&mut self) {
+        if !self.rejected {
+            self.sink.end();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{SecurityHooks, SecurityScanner, ScanResult, KeepScanning, RejectDocument};
+
+    use tokenizer::{Tokenizer, TokenizerOpts, TokenSink, Token, TagToken, Attribute};
+
+    use core::default::Default;
+    use collections::MutableSeq;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    use string_cache::Atom;
+
+    struct Recorder {
+        found: Vec<(&'static str, String, uint)>,
+        reject_scripts: bool,
+    }
+
+    impl SecurityHooks for Recorder {
+        fn event_handler(&mut self, _elem: &Atom, attr: &Attribute, line: uint) -> ScanResult {
+            self.found.push(("handler", String::from_str(attr.name.local.as_slice()), line));
+            KeepScanning
+        }
+
+        fn javascript_url(&mut self, _elem: &Atom, attr: &Attribute, line: uint) -> ScanResult {
+            self.found.push(("url", attr.value.clone(), line));
+            KeepScanning
+        }
+
+        fn inline_script(&mut self, text: &str, line: uint) -> ScanResult {
+            self.found.push(("script", String::from_str(text), line));
+            if self.reject_scripts { RejectDocument } else { KeepScanning }
+        }
+    }
+
+    struct TagCounter {
+        tags: uint,
+    }
+
+    impl TokenSink for TagCounter {
+        fn process_token(&mut self, token: Token) {
+            match token {
+                TagToken(_) => self.tags += 1,
+                _ => (),
+            }
+        }
+    }
+
+    fn scan(input: &str, reject_scripts: bool) -> (Vec<(&'static str, String, uint)>, uint) {
+        let mut hooks = Recorder { found: vec!(), reject_scripts: reject_scripts };
+        let tags = {
+            let mut scanner = SecurityScanner::new(TagCounter { tags: 0 }, &mut hooks);
+            {
+                let mut tok = Tokenizer::new(&mut scanner, TokenizerOpts {
+                    track_lines: true,
+                    .. Default::default()
+                });
+                tok.feed(String::from_str(input));
+                tok.end();
+            }
+            scanner.unwrap().tags
+        };
+        (hooks.found, tags)
+    }
+
+    #[test]
+    fn hooks() {
+        let (found, tags) = scan(concat!(
+            "<a href=' JavaScript:go()' title='javascript:no'>\n",
+            "<img\nonerror=x src=a.png>\n",
+            "<script>one\ntwo</script><script></script><p onclick=y>"), false);
+        assert_eq!(found, vec!(
+            ("url", String::from_str(" JavaScript:go()"), 1),
+            ("handler", String::from_str("onerror"), 2),
+            ("script", String::from_str("one\ntwo"), 4),
+            ("handler", String::from_str("onclick"), 5)));
+        assert_eq!(tags, 7);
+    }
+
+    #[test]
+    fn reject() {
+        let (found, tags) = scan("<script>evil()</script><p>", true);
+        assert_eq!(found.len(), 1);
+        // The scanner never passed on `</script>` or `<p>`.
+        assert_eq!(tags, 1);
+    }
+}
@@ -9,11 +9,20 @@
 
 //! Tokenizer states.
 //!
-//! This is public for use by the tokenizer tests.  Other library
+//! This is public for use by the tokenizer tests, and by tools which
+//! show or set the tokenizer's state.  They should use the names from
+//! `State::name` rather than `{:?}`, which can change.  Other library
 //! users should not have to care about this.
 
 use core::prelude::*;
 
+use core::fmt;
+
+#[cfg(not(for_c))]
+use std::from_str::FromStr;
+
+use string_cache::Atom;
+
 #[deriving(PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub enum ScriptEscapeKind {
     Escaped,
@@ -41,6 +50,17 @@ pub enum AttrValueKind {
     DoubleQuoted,
 }
 
+/// States for `TokenizerOpts::lazy_attrs`, which find the end of a tag
+/// without splitting up its attributes.  These aren't in the spec.
+#[deriving(PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
+pub enum LazyAttrKind {
+    LazyBeforeName,
+    LazyName,
+    LazyBeforeValue,
+    LazyValue(AttrValueKind),
+    LazySelfClosing,
+}
+
 #[deriving(PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub enum State {
     Data,
@@ -64,6 +84,7 @@ pub enum State {
     AttributeValue(AttrValueKind),
     AfterAttributeValueQuoted,
     SelfClosingStartTag,
+    LazyAttributes(LazyAttrKind),
     BogusComment,
     MarkupDeclarationOpen,
     CommentStart,
@@ -85,3 +106,220 @@ pub enum State {
     BogusDoctype,
     CdataSection,
 }
+
+/// Every state, in the order of the `State` enum.
+pub static ALL_STATES: &'static [State] = &[
+    Data,
+    Plaintext,
+    TagOpen,
+    EndTagOpen,
+    TagName,
+    RawData(Rcdata),
+    RawData(Rawtext),
+    RawData(ScriptData),
+    RawData(ScriptDataEscaped(Escaped)),
+    RawData(ScriptDataEscaped(DoubleEscaped)),
+    RawLessThanSign(Rcdata),
+    RawLessThanSign(Rawtext),
+    RawLessThanSign(ScriptData),
+    RawLessThanSign(ScriptDataEscaped(Escaped)),
+    RawLessThanSign(ScriptDataEscaped(DoubleEscaped)),
+    RawEndTagOpen(Rcdata),
+    RawEndTagOpen(Rawtext),
+    RawEndTagOpen(ScriptData),
+    RawEndTagOpen(ScriptDataEscaped(Escaped)),
+    RawEndTagOpen(ScriptDataEscaped(DoubleEscaped)),
+    RawEndTagName(Rcdata),
+    RawEndTagName(Rawtext),
+    RawEndTagName(ScriptData),
+    RawEndTagName(ScriptDataEscaped(Escaped)),
+    RawEndTagName(ScriptDataEscaped(DoubleEscaped)),
+    ScriptDataEscapeStart(Escaped),
+    ScriptDataEscapeStart(DoubleEscaped),
+    ScriptDataEscapeStartDash,
+    ScriptDataEscapedDash(Escaped),
+    ScriptDataEscapedDash(DoubleEscaped),
+    ScriptDataEscapedDashDash(Escaped),
+    ScriptDataEscapedDashDash(DoubleEscaped),
+    ScriptDataDoubleEscapeEnd,
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValue(DoubleQuoted),
+    AttributeValue(SingleQuoted),
+    AttributeValue(Unquoted),
+    AfterAttributeValueQuoted,
+    SelfClosingStartTag,
+    LazyAttributes(LazyBeforeName),
+    LazyAttributes(LazyName),
+    LazyAttributes(LazyBeforeValue),
+    LazyAttributes(LazyValue(DoubleQuoted)),
+    LazyAttributes(LazyValue(SingleQuoted)),
+    LazyAttributes(LazyValue(Unquoted)),
+    LazyAttributes(LazySelfClosing),
+    BogusComment,
+    MarkupDeclarationOpen,
+    CommentStart,
+    CommentStartDash,
+    Comment,
+    CommentEndDash,
+    CommentEnd,
+    CommentEndBang,
+    Doctype,
+    BeforeDoctypeName,
+    DoctypeName,
+    AfterDoctypeName,
+    AfterDoctypeKeyword(Public),
+    BeforeDoctypeIdentifier(Public),
+    DoctypeIdentifierDoubleQuoted(Public),
+    DoctypeIdentifierSingleQuoted(Public),
+    AfterDoctypeIdentifier(Public),
+    AfterDoctypeKeyword(System),
+    BeforeDoctypeIdentifier(System),
+    DoctypeIdentifierDoubleQuoted(System),
+    DoctypeIdentifierSingleQuoted(System),
+    AfterDoctypeIdentifier(System),
+    BetweenDoctypePublicAndSystemIdentifiers,
+    BogusDoctype,
+    CdataSection,
+];
+
+impl State {
+    /// A stable identifier for the state, for tools such as trace viewers
+    /// and test generators.  It's the id of the state's section in the
+    /// spec, less the `-state` suffix, e.g. `rcdata-end-tag-open`.  The
+    /// lazy attribute states, which aren't in the spec, start with
+    /// `lazy-`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Data                                              => "data",
+            Plaintext                                         => "plaintext",
+            TagOpen                                           => "tag-open",
+            EndTagOpen                                        => "end-tag-open",
+            TagName                                           => "tag-name",
+            RawData(Rcdata)                                   => "rcdata",
+            RawData(Rawtext)                                  => "rawtext",
+            RawData(ScriptData)                               => "script-data",
+            RawData(ScriptDataEscaped(Escaped))               => "script-data-escaped",
+            RawData(ScriptDataEscaped(DoubleEscaped))         => "script-data-double-escaped",
+            RawLessThanSign(Rcdata)                           => "rcdata-less-than-sign",
+            RawLessThanSign(Rawtext)                          => "rawtext-less-than-sign",
+            RawLessThanSign(ScriptData)                       => "script-data-less-than-sign",
+            RawLessThanSign(ScriptDataEscaped(Escaped))       => "script-data-escaped-less-than-sign",
+            RawLessThanSign(ScriptDataEscaped(DoubleEscaped)) => "script-data-double-escaped-less-than-sign",
+            RawEndTagOpen(Rcdata)                             => "rcdata-end-tag-open",
+            RawEndTagOpen(Rawtext)                            => "rawtext-end-tag-open",
+            RawEndTagOpen(ScriptData)                         => "script-data-end-tag-open",
+            RawEndTagOpen(ScriptDataEscaped(Escaped))         => "script-data-escaped-end-tag-open",
+            RawEndTagOpen(ScriptDataEscaped(DoubleEscaped))   => "script-data-double-escaped-end-tag-open",
+            RawEndTagName(Rcdata)                             => "rcdata-end-tag-name",
+            RawEndTagName(Rawtext)                            => "rawtext-end-tag-name",
+            RawEndTagName(ScriptData)                         => "script-data-end-tag-name",
+            RawEndTagName(ScriptDataEscaped(Escaped))         => "script-data-escaped-end-tag-name",
+            RawEndTagName(ScriptDataEscaped(DoubleEscaped))   => "script-data-double-escaped-end-tag-name",
+            ScriptDataEscapeStart(Escaped)                    => "script-data-escape-start",
+            ScriptDataEscapeStart(DoubleEscaped)              => "script-data-double-escape-start",
+            ScriptDataEscapeStartDash                         => "script-data-escape-start-dash",
+            ScriptDataEscapedDash(Escaped)                    => "script-data-escaped-dash",
+            ScriptDataEscapedDash(DoubleEscaped)              => "script-data-double-escaped-dash",
+            ScriptDataEscapedDashDash(Escaped)                => "script-data-escaped-dash-dash",
+            ScriptDataEscapedDashDash(DoubleEscaped)          => "script-data-double-escaped-dash-dash",
+            ScriptDataDoubleEscapeEnd                         => "script-data-double-escape-end",
+            BeforeAttributeName                               => "before-attribute-name",
+            AttributeName                                     => "attribute-name",
+            AfterAttributeName                                => "after-attribute-name",
+            BeforeAttributeValue                              => "before-attribute-value",
+            AttributeValue(DoubleQuoted)                      => "attribute-value-(double-quoted)",
+            AttributeValue(SingleQuoted)                      => "attribute-value-(single-quoted)",
+            AttributeValue(Unquoted)                          => "attribute-value-(unquoted)",
+            AfterAttributeValueQuoted                         => "after-attribute-value-(quoted)",
+            SelfClosingStartTag                               => "self-closing-start-tag",
+            LazyAttributes(LazyBeforeName)                    => "lazy-before-attribute-name",
+            LazyAttributes(LazyName)                          => "lazy-attribute-name",
+            LazyAttributes(LazyBeforeValue)                   => "lazy-before-attribute-value",
+            LazyAttributes(LazyValue(DoubleQuoted))           => "lazy-attribute-value-(double-quoted)",
+            LazyAttributes(LazyValue(SingleQuoted))           => "lazy-attribute-value-(single-quoted)",
+            LazyAttributes(LazyValue(Unquoted))               => "lazy-attribute-value-(unquoted)",
+            LazyAttributes(LazySelfClosing)                   => "lazy-self-closing-start-tag",
+            BogusComment                                      => "bogus-comment",
+            MarkupDeclarationOpen                             => "markup-declaration-open",
+            CommentStart                                      => "comment-start",
+            CommentStartDash                                  => "comment-start-dash",
+            Comment                                           => "comment",
+            CommentEndDash                                    => "comment-end-dash",
+            CommentEnd                                        => "comment-end",
+            CommentEndBang                                    => "comment-end-bang",
+            Doctype                                           => "doctype",
+            BeforeDoctypeName                                 => "before-doctype-name",
+            DoctypeName                                       => "doctype-name",
+            AfterDoctypeName                                  => "after-doctype-name",
+            AfterDoctypeKeyword(Public)                       => "after-doctype-public-keyword",
+            BeforeDoctypeIdentifier(Public)                   => "before-doctype-public-identifier",
+            DoctypeIdentifierDoubleQuoted(Public)             => "doctype-public-identifier-(double-quoted)",
+            DoctypeIdentifierSingleQuoted(Public)             => "doctype-public-identifier-(single-quoted)",
+            AfterDoctypeIdentifier(Public)                    => "after-doctype-public-identifier",
+            AfterDoctypeKeyword(System)                       => "after-doctype-system-keyword",
+            BeforeDoctypeIdentifier(System)                   => "before-doctype-system-identifier",
+            DoctypeIdentifierDoubleQuoted(System)             => "doctype-system-identifier-(double-quoted)",
+            DoctypeIdentifierSingleQuoted(System)             => "doctype-system-identifier-(single-quoted)",
+            AfterDoctypeIdentifier(System)                    => "after-doctype-system-identifier",
+            BetweenDoctypePublicAndSystemIdentifiers          => "between-doctype-public-and-system-identifiers",
+            BogusDoctype                                      => "bogus-doctype",
+            CdataSection                                      => "cdata-section",
+        }
+    }
+
+    /// The state with the given `name`, if any.
+    pub fn from_name(name: &str) -> Option<State> {
+        ALL_STATES.iter().find(|s| s.name() == name).map(|s| s.clone())
+    }
+
+    /// The state the tree builder puts the tokenizer in after a HTML start
+    /// tag named `name`, outside foreign content, or `None` if it stays in
+    /// `Data`.  For token sinks which guess the state without a tree
+    /// builder.  `<noscript>` holds raw text only if `scripting` is set,
+    /// as with `TreeBuilderOpts::scripting_enabled`.
+    pub fn after_start_tag(name: &Atom, scripting: bool) -> Option<State> {
+        match *name {
+            atom!(title) | atom!(textarea) => Some(RawData(Rcdata)),
+            atom!(style) | atom!(xmp) | atom!(iframe) | atom!(noembed)
+                | atom!(noframes) => Some(RawData(Rawtext)),
+            atom!(noscript) if scripting => Some(RawData(Rawtext)),
+            atom!(script) => Some(RawData(ScriptData)),
+            atom!(plaintext) => Some(Plaintext),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Show for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+#[cfg(not(for_c))]
+impl FromStr for State {
+    fn from_str(name: &str) -> Option<State> {
+        State::from_name(name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{State, ALL_STATES, RawEndTagOpen, Rcdata, AttributeValue, DoubleQuoted};
+
+    #[test]
+    fn names() {
+        for (i, state) in ALL_STATES.iter().enumerate() {
+            assert_eq!(State::from_name(state.name()), Some(state.clone()));
+            assert!(ALL_STATES.slice_from(i + 1).iter().all(|s| s.name() != state.name()));
+        }
+        assert_eq!(RawEndTagOpen(Rcdata).name(), "rcdata-end-tag-open");
+        assert_eq!(format!("{}", AttributeValue(DoubleQuoted)).as_slice(),
+            "attribute-value-(double-quoted)");
+        assert_eq!(State::from_name("Data"), None);
+    }
+}
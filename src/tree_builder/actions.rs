@@ -17,16 +17,21 @@ use core::prelude::*;
 use tree_builder::types::*;
 use tree_builder::tag_sets::*;
 use tree_builder::interface::{TreeSink, QuirksMode, NodeOrText, AppendNode, AppendText};
+use tree_builder::interface::ElemProvenance;
 use tree_builder::rules::TreeBuilderStep;
+use tree_builder::adjust::{adjust_svg_tag_name, adjust_svg_attribute, adjust_mathml_attribute};
+use tree_builder::adjust::adjust_foreign_attribute;
 
-use tokenizer::{Attribute, Tag};
+use tokenizer::{Attribute, Tag, StartTag, EndTag};
+use tokenizer::{ParseErrorKind, TreeConstructionError, LimitExceeded};
 use tokenizer::states::{RawData, RawKind};
 
-use util::str::AsciiExt;
+use util::str::{AsciiExt, is_ascii_whitespace};
 
 #[cfg(not(for_c))]
 use util::str::to_escaped_string;
 
+use core::cmp;
 use core::mem::replace;
 use core::iter::{Rev, Enumerate};
 use core::slice;
@@ -34,9 +39,9 @@ use core::fmt::Show;
 use collections::MutableSeq;
 use collections::vec::Vec;
 use collections::string::String;
-use collections::str::Slice;
+use collections::str::{MaybeOwned, Slice};
 
-use string_cache::{Atom, QualName};
+use string_cache::{Atom, QualName, Namespace};
 
 pub struct ActiveFormattingIter<'a, Handle> {
     iter: Rev<Enumerate<slice::Items<'a, FormatEntry<Handle>>>>,
@@ -56,22 +61,45 @@ pub enum PushFlag {
     NoPush,
 }
 
+// Where the adoption agency algorithm puts the new formatting element in
+// the list of active formatting elements.
+enum Bookmark<Handle> {
+    ReplaceFormatting(Handle),
+    InsertFormattingAfter(Handle),
+}
+
 // These go in a trait so that we can control visibility.
+//
+// None of these may recurse over the stack of open elements or the list
+// of active formatting elements.  Both can be as deep as the document is,
+// and a hostile document can be very deep, so walk them with loops.
 pub trait TreeBuilderActions<Handle> {
+    fn parse_error(&mut self, msg: MaybeOwned<'static>);
+    fn error_of_kind(&mut self, kind: ParseErrorKind, msg: MaybeOwned<'static>);
     fn unexpected<T: Show>(&mut self, thing: &T) -> ProcessResult;
     fn assert_named(&mut self, node: Handle, name: Atom);
     fn clear_active_formatting_to_marker(&mut self);
     fn create_formatting_element_for(&mut self, tag: Tag) -> Handle;
-    fn append_text(&mut self, text: String) -> ProcessResult;
+    fn append_text(&mut self, split: SplitStatus, text: String) -> ProcessResult;
+    fn text_after_body(&mut self, text: String) -> ProcessResult;
+    fn whitespace_in_frameset(&mut self, text: String) -> ProcessResult;
     fn append_comment(&mut self, text: String) -> ProcessResult;
     fn append_comment_to_doc(&mut self, text: String) -> ProcessResult;
     fn append_comment_to_html(&mut self, text: String) -> ProcessResult;
     fn insert_appropriately(&mut self, child: NodeOrText<Handle>);
+    fn appropriate_target(&mut self) -> Handle;
+    fn at_depth_limit(&self) -> bool;
+    fn report_depth_limit(&mut self);
+    fn insert_appropriately_in(&mut self, target: Handle, child: NodeOrText<Handle>);
     fn insert_phantom(&mut self, name: Atom) -> Handle;
     fn insert_and_pop_element_for(&mut self, tag: Tag) -> Handle;
     fn insert_element_for(&mut self, tag: Tag) -> Handle;
-    fn insert_element(&mut self, push: PushFlag, name: Atom, attrs: Vec<Attribute>) -> Handle;
-    fn create_root(&mut self, attrs: Vec<Attribute>);
+    fn insert_element(&mut self, push: PushFlag, implied: bool, name: QualName,
+        attrs: Vec<Attribute>) -> Handle;
+    fn insert_foreign_element(&mut self, tag: Tag, ns: Namespace) -> ProcessResult;
+    fn create_root(&mut self, attrs: Vec<Attribute>, implied: bool);
+    fn will_foster_parent(&self) -> bool;
+    fn will_foster_parent_into(&self, target: Handle) -> bool;
     fn close_the_cell(&mut self);
     fn reset_insertion_mode(&mut self) -> InsertionMode;
     fn process_chars_in_table(&mut self, token: Token) -> ProcessResult;
@@ -88,18 +116,27 @@ pub trait TreeBuilderActions<Handle> {
     fn in_scope_named(&self, scope: TagSet, name: Atom) -> bool;
     fn current_node_named(&self, name: Atom) -> bool;
     fn html_elem_named(&self, elem: Handle, name: Atom) -> bool;
+    fn in_html_elem_named(&self, name: Atom) -> bool;
     fn elem_in(&self, elem: Handle, set: TagSet) -> bool;
     fn in_scope(&self, scope: TagSet, pred: |Handle| -> bool) -> bool;
     fn check_body_end(&mut self);
     fn body_elem(&mut self) -> Option<Handle>;
     fn html_elem(&self) -> Handle;
     fn reconstruct_formatting(&mut self);
+    fn is_marker_or_open(&self, entry: &FormatEntry<Handle>) -> bool;
+    fn position_in_active_formatting(&self, element: &Handle) -> Option<uint>;
     fn remove_from_stack(&mut self, elem: &Handle);
     fn pop(&mut self) -> Handle;
     fn push(&mut self, elem: &Handle);
+    fn trace_action(&self, action: &'static str, elem: &Handle);
     fn adoption_agency(&mut self, subject: Atom);
+    fn process_end_tag_in_body(&mut self, tag: Tag);
     fn current_node_in(&self, set: TagSet) -> bool;
     fn current_node(&self) -> Handle;
+    fn adjusted_current_node(&self) -> Handle;
+    fn chars_in_foreign_content(&self) -> bool;
+    fn is_foreign(&self, token: &Token) -> bool;
+    fn pop_to_html_or_integration_point(&mut self);
     fn parse_raw_data(&mut self, tag: Tag, k: RawKind);
     fn to_raw_text_mode(&mut self, k: RawKind);
     fn stop_parsing(&mut self) -> ProcessResult;
@@ -111,11 +148,31 @@ pub trait TreeBuilderActions<Handle> {
 impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
     TreeBuilderActions<Handle> for super::TreeBuilder<'sink, Handle, Sink> {
 
+    fn parse_error(&mut self, msg: MaybeOwned<'static>) {
+        self.error_of_kind(TreeConstructionError, msg);
+    }
+
+    fn error_of_kind(&mut self, kind: ParseErrorKind, msg: MaybeOwned<'static>) {
+        if self.opts.error_comments {
+            let mut text = String::from_str("parse error: ");
+            text.push_str(kind.code());
+            let comment = self.sink.create_comment(text);
+            if self.open_elems.is_empty() {
+                let target = self.doc_handle.clone();
+                self.sink.append(target, AppendNode(comment));
+            } else {
+                self.insert_appropriately(AppendNode(comment));
+            }
+        }
+        self.sink.classified_parse_error(kind, msg);
+    }
+
     fn unexpected<T: Show>(&mut self, _thing: &T) -> ProcessResult {
-        self.sink.parse_error(format_if!(
+        let msg = format_if!(
             self.opts.exact_errors,
             "Unexpected token",
-            "Unexpected token {} in insertion mode {}", to_escaped_string(_thing), self.mode));
+            "Unexpected token {} in insertion mode {}", to_escaped_string(_thing), self.mode);
+        self.parse_error(msg);
         Done
     }
 
@@ -132,11 +189,16 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
     }
 
     fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        let mode = self.sink.query_quirks_override(mode).unwrap_or(mode);
         self.quirks_mode = mode;
         self.sink.set_quirks_mode(mode);
     }
 
     fn stop_parsing(&mut self) -> ProcessResult {
+        match self.auditor.take() {
+            Some(mut auditor) => auditor.finish(self.sink),
+            None => (),
+        }
         h5e_warn!("stop_parsing not implemented, full speed ahead!");
         Done
     }
@@ -168,22 +230,157 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
         set(self.sink.elem_name(self.current_node()))
     }
 
+    //§ adjusted-current-node
+    fn adjusted_current_node(&self) -> Handle {
+        match (self.open_elems.len(), self.context_elem.as_ref()) {
+            (1, Some(ctx)) => ctx.clone(),
+            _ => self.current_node(),
+        }
+    }
+    //§ END
+
+    // Would a character token be handled by the rules for parsing
+    // tokens in foreign content?
+    fn chars_in_foreign_content(&self) -> bool {
+        self.is_foreign(&CharacterTokens(NotSplit, String::new()))
+    }
+
+    //§ tree-construction
+    // Should this token be handled by the rules for parsing tokens in
+    // foreign content, rather than those of the current insertion mode?
+    fn is_foreign(&self, token: &Token) -> bool {
+        if self.open_elems.is_empty() {
+            return false;
+        }
+
+        let is_chars = match *token {
+            CharacterTokens(..) | NullCharacterToken => true,
+            _ => false,
+        };
+        let start_tag = match *token {
+            TagToken(Tag { kind: StartTag, ref name, .. }) => Some(name.clone()),
+            EOFToken => return false,
+            _ => None,
+        };
+
+        let name = self.sink.elem_name(self.adjusted_current_node());
+        if name.ns == ns!(HTML) {
+            return false;
+        }
+
+        if mathml_text_integration_point(name.clone()) {
+            match start_tag {
+                Some(ref tag) if tag.as_slice() != "mglyph"
+                    && tag.as_slice() != "malignmark" => return false,
+                _ => if is_chars { return false; },
+            }
+        }
+
+        if name.ns == ns!(MathML) && name.local.as_slice() == "annotation-xml" {
+            match start_tag {
+                Some(atom!(svg)) => return false,
+                _ => (),
+            }
+        }
+
+        // FIXME: MathML annotation-xml with a HTML encoding attribute.
+        if svg_html_integration_point(name) && (is_chars || start_tag.is_some()) {
+            return false;
+        }
+
+        true
+    }
+    //§ END
+
+    fn pop_to_html_or_integration_point(&mut self) {
+        loop {
+            let name = self.sink.elem_name(self.current_node());
+            if name.ns == ns!(HTML)
+                    || mathml_text_integration_point(name.clone())
+                    || svg_html_integration_point(name) {
+                return;
+            }
+            self.pop();
+        }
+    }
+
+    fn will_foster_parent(&self) -> bool {
+        self.will_foster_parent_into(self.current_node())
+    }
+
+    fn will_foster_parent_into(&self, target: Handle) -> bool {
+        declare_tag_set!(foster_target = table tbody tfoot thead tr)
+        self.foster_parenting && self.elem_in(target, foster_target)
+    }
+
     // Insert at the "appropriate place for inserting a node".
     fn insert_appropriately(&mut self, child: NodeOrText<Handle>) {
-        declare_tag_set!(foster_target = table tbody tfoot thead tr)
-        let target = self.current_node();
-        if !(self.foster_parenting && self.elem_in(target.clone(), foster_target)) {
+        let target = self.appropriate_target();
+        self.insert_appropriately_in(target, child);
+    }
+
+    // The target for `insert_appropriately`, before foster parenting and
+    // templates are taken into account.
+    fn appropriate_target(&mut self) -> Handle {
+        match self.opts.max_tree_depth {
+            // Too deep: insert into the deepest element allowed instead.
+            Some(max) if self.open_elems.len() > max => {
+                self.report_depth_limit();
+                self.open_elems[cmp::max(max, 1) - 1].clone()
+            }
+            _ => self.current_node(),
+        }
+    }
+
+    // Is the stack of open elements as deep as `opts.max_tree_depth`?
+    fn at_depth_limit(&self) -> bool {
+        match self.opts.max_tree_depth {
+            Some(max) => self.open_elems.len() >= max,
+            None => false,
+        }
+    }
+
+    fn report_depth_limit(&mut self) {
+        if !self.depth_limited {
+            self.depth_limited = true;
+            self.error_of_kind(LimitExceeded, Slice("Tree depth limit reached; flattening"));
+        }
+    }
+
+    // The same, with `target` as the "override target".
+    fn insert_appropriately_in(&mut self, target: Handle, child: NodeOrText<Handle>) {
+        self.trace_action("insert into", &target);
+        if !self.will_foster_parent_into(target.clone()) {
             // No foster parenting (the common case).
+            if self.html_elem_named(target.clone(), atom!(template)) {
+                let contents = self.sink.get_template_contents(target);
+                return self.sink.append(contents, child);
+            }
             return self.sink.append(target, child);
         }
 
         // Foster parenting
-        // FIXME: <template>
-        let last_table = self.open_elems.iter()
-            .enumerate()
-            .rev()
-            .filter(|&(_, e)| self.html_elem_named(e.clone(), atom!(table)))
-            .next();
+        let (last_template, last_table) = {
+            let last_named = |name: Atom| self.open_elems.iter()
+                .enumerate()
+                .rev()
+                .filter(|&(_, e)| self.html_elem_named(e.clone(), name.clone()))
+                .next()
+                .map(|(i, e)| (i, e.clone()));
+            (last_named(atom!(template)), last_named(atom!(table)))
+        };
+
+        // A template which is more recent than the last table takes the
+        // node into its contents.
+        match (last_template, last_table.as_ref()) {
+            (Some((tmpl_idx, template)), table) => {
+                if table.map_or(true, |&(idx, _)| tmpl_idx > idx) {
+                    let contents = self.sink.get_template_contents(template);
+                    return self.sink.append(contents, child);
+                }
+            }
+            _ => (),
+        }
 
         match last_table {
             None => {
@@ -191,46 +388,295 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                 self.sink.append(html_elem, child);
             }
             Some((idx, last_table)) => {
-                // Try inserting "inside last table's parent node, immediately before last table"
-                match self.sink.append_before_sibling(last_table.clone(), child) {
-                    Ok(()) => (),
-
-                    // If last_table has no parent, we regain ownership of the child.
-                    // Insert "inside previous element, after its last child (if any)"
-                    Err(child) => {
-                        let previous_element = self.open_elems[idx-1].clone();
-                        self.sink.append(previous_element, child);
-                    }
-                }
+                // Insert "inside last table's parent node, immediately before
+                // last table", or if it has no parent, "inside previous element,
+                // after its last child (if any)".
+                let previous_element = self.open_elems[idx-1].clone();
+                self.sink.append_based_on_parent_node(last_table,
+                    previous_element, child);
             }
         }
     }
 
+    //§ adoption-agency-algorithm
     fn adoption_agency(&mut self, subject: Atom) {
-        // FIXME: this is not right
-        if self.current_node_named(subject) {
+        // 1.
+        if self.current_node_named(subject.clone())
+                && self.position_in_active_formatting(&self.current_node()).is_none() {
             self.pop();
+            return;
+        }
+
+        // 2. 3. 4.
+        for _ in range(0u, 8) {
+            // 5.
+            let maybe_fmt_entry = self.active_formatting_end_to_marker()
+                .filter(|&(_, _, tag)| tag.name == subject)
+                .next()
+                .map(|(i, h, t)| (i, h.clone(), t.clone()));
+
+            let (fmt_elem_index, fmt_elem, fmt_elem_tag) = match maybe_fmt_entry {
+                None => return self.process_end_tag_in_body(Tag {
+                    kind: EndTag,
+                    name: subject.clone(),
+                    self_closing: false,
+                    attrs: vec!(),
+                    raw_attrs: None,
+                    attr_spans: vec!(),
+                    had_duplicate_attrs: false,
+                }),
+                Some(x) => x,
+            };
+
+            // 6.
+            // Can't use unwrap_or_return!() due to rust-lang/rust#16617.
+            let maybe_stack_index = self.open_elems.iter()
+                .rposition(|n| self.sink.same_node(n.clone(), fmt_elem.clone()));
+            let fmt_elem_stack_index = match maybe_stack_index {
+                None => {
+                    self.parse_error(Slice("Formatting element not open"));
+                    self.active_formatting.remove(fmt_elem_index);
+                    return;
+                }
+                Some(i) => i,
+            };
+
+            // 7.
+            if !self.in_scope(default_scope, |n| self.sink.same_node(n, fmt_elem.clone())) {
+                self.parse_error(Slice("Formatting element not in scope"));
+                return;
+            }
+
+            // 8.
+            if !self.sink.same_node(self.current_node(), fmt_elem.clone()) {
+                self.parse_error(Slice("Formatting element not current node"));
+            }
+
+            // 9.
+            let maybe_furthest_block = self.open_elems.iter()
+                .enumerate()
+                .skip(fmt_elem_stack_index)
+                .find(|&(_, elem)| self.elem_in(elem.clone(), special_tag))
+                .map(|(i, h)| (i, h.clone()));
+
+            let (furthest_block_index, furthest_block) = match maybe_furthest_block {
+                // 10.
+                None => {
+                    self.open_elems.truncate(fmt_elem_stack_index);
+                    self.active_formatting.remove(fmt_elem_index);
+                    return;
+                }
+                Some(x) => x,
+            };
+
+            // 11.
+            let common_ancestor = self.open_elems[fmt_elem_stack_index - 1].clone();
+
+            // 12.
+            let mut bookmark = ReplaceFormatting(fmt_elem.clone());
+
+            // 13.
+            let mut node_index = furthest_block_index;
+            let mut last_node = furthest_block.clone();
+            let mut inner_counter = 0u;
+            loop {
+                inner_counter += 1;
+                node_index -= 1;
+                let node = self.open_elems[node_index].clone();
+
+                if self.sink.same_node(node.clone(), fmt_elem.clone()) {
+                    break;
+                }
+
+                // Past the third time round, nodes are dropped from the list
+                // of active formatting elements, and so from the stack too.
+                if inner_counter > 3 {
+                    match self.position_in_active_formatting(&node) {
+                        Some(i) => { self.active_formatting.remove(i); }
+                        None => (),
+                    }
+                }
+
+                let node_formatting_index = match self.position_in_active_formatting(&node) {
+                    Some(i) => i,
+                    None => {
+                        self.open_elems.remove(node_index);
+                        continue;
+                    }
+                };
+
+                // Replace the node with a new element for the same tag.
+                let tag = match self.active_formatting[node_formatting_index] {
+                    Element(_, ref t) => t.clone(),
+                    Marker => fail!("Found marker during adoption agency"),
+                };
+                let node = self.sink.create_element(
+                    QualName::new(ns!(HTML), tag.name.clone()), tag.attrs.clone());
+                *self.open_elems.get_mut(node_index) = node.clone();
+                *self.active_formatting.get_mut(node_formatting_index)
+                    = Element(node.clone(), tag);
+
+                if self.sink.same_node(last_node.clone(), furthest_block.clone()) {
+                    bookmark = InsertFormattingAfter(node.clone());
+                }
+
+                self.sink.remove_from_parent(last_node.clone());
+                self.sink.append(node.clone(), AppendNode(last_node.clone()));
+                last_node = node;
+            }
+
+            // 14.
+            self.sink.remove_from_parent(last_node.clone());
+            self.insert_appropriately_in(common_ancestor, AppendNode(last_node));
+
+            // 15. 16. 17.
+            let new_element = self.sink.create_element(
+                QualName::new(ns!(HTML), fmt_elem_tag.name.clone()), fmt_elem_tag.attrs.clone());
+            let new_entry = Element(new_element.clone(), fmt_elem_tag);
+            self.sink.reparent_children(furthest_block.clone(), new_element.clone());
+            self.sink.append(furthest_block.clone(), AppendNode(new_element.clone()));
+
+            // 18.
+            match bookmark {
+                ReplaceFormatting(to_replace) => {
+                    let index = self.position_in_active_formatting(&to_replace)
+                        .expect("bookmark not in the list of active formatting elements");
+                    *self.active_formatting.get_mut(index) = new_entry;
+                }
+                InsertFormattingAfter(previous) => {
+                    let index = self.position_in_active_formatting(&previous)
+                        .expect("bookmark not in the list of active formatting elements");
+                    self.active_formatting.insert(index + 1, new_entry);
+                    let old_index = self.position_in_active_formatting(&fmt_elem)
+                        .expect("formatting element not in the list of active formatting elements");
+                    self.active_formatting.remove(old_index);
+                }
+            }
+
+            // 19.
+            self.remove_from_stack(&fmt_elem);
+            let new_furthest_block_index = self.open_elems.iter()
+                .position(|n| self.sink.same_node(n.clone(), furthest_block.clone()))
+                .expect("furthest block not on the stack of open elements");
+            self.open_elems.insert(new_furthest_block_index + 1, new_element);
         }
     }
+    //§ END
+
+    // The "any other end tag" rule of "in body", which the adoption agency
+    // algorithm falls back on.
+    fn process_end_tag_in_body(&mut self, tag: Tag) {
+        // Look back for a matching open element.
+        let mut match_idx = None;
+        for (i, elem) in self.open_elems.iter().enumerate().rev() {
+            if self.html_elem_named(elem.clone(), tag.name.clone()) {
+                match_idx = Some(i);
+                break;
+            }
+
+            if self.elem_in(elem.clone(), special_tag) {
+                self.parse_error(Slice("Found special tag while closing generic tag"));
+                return;
+            }
+        }
+
+        // Can't use unwrap_or_return!() due to rust-lang/rust#16617.
+        let match_idx = match match_idx {
+            None => {
+                // I believe this is impossible, because the root
+                // <html> element is in special_tag.
+                self.unexpected(&tag);
+                return;
+            }
+            Some(x) => x,
+        };
+
+        self.generate_implied_end_except(tag.name.clone());
+
+        if match_idx != self.open_elems.len() - 1 {
+            // mis-nested tags
+            self.unexpected(&tag);
+        }
+        self.open_elems.truncate(match_idx);
+    }
 
     fn push(&mut self, elem: &Handle) {
+        self.trace_action("push", elem);
         self.open_elems.push(elem.clone());
     }
 
     fn pop(&mut self) -> Handle {
-        self.open_elems.pop().expect("no current element")
+        let elem = self.open_elems.pop().expect("no current element");
+        self.trace_action("pop", &elem);
+        elem
+    }
+
+    #[cfg(for_c)]
+    fn trace_action(&self, _action: &'static str, _elem: &Handle) {
+    }
+
+    #[cfg(not(for_c))]
+    fn trace_action(&self, action: &'static str, elem: &Handle) {
+        if self.opts.trace {
+            let name = self.sink.elem_name(elem.clone());
+            h5e_info!("tree builder: {} <{}>", action, name.local.as_slice());
+        }
     }
 
     fn remove_from_stack(&mut self, elem: &Handle) {
+        self.trace_action("remove", elem);
         let mut open_elems = replace(&mut self.open_elems, vec!());
         open_elems.retain(|x| !self.sink.same_node(elem.clone(), x.clone()));
         self.open_elems = open_elems;
     }
 
+    fn is_marker_or_open(&self, entry: &FormatEntry<Handle>) -> bool {
+        match *entry {
+            Marker => true,
+            Element(ref node, _) => self.open_elems.iter().rev()
+                .any(|n| self.sink.same_node(n.clone(), node.clone())),
+        }
+    }
+
+    fn position_in_active_formatting(&self, element: &Handle) -> Option<uint> {
+        self.active_formatting.iter().position(|entry| match *entry {
+            Marker => false,
+            Element(ref node, _) => self.sink.same_node(node.clone(), element.clone()),
+        })
+    }
+
+    //§ reconstruct-the-active-formatting-elements
     /// Reconstruct the active formatting elements.
     fn reconstruct_formatting(&mut self) {
-        // FIXME
+        {
+            let last = unwrap_or_return!(self.active_formatting.last(), ());
+            if self.is_marker_or_open(last) {
+                return;
+            }
+        }
+
+        // Rewind to just after the last entry which is a marker or still
+        // open, or to the start of the list.
+        let mut entry_index = self.active_formatting.len() - 1;
+        while entry_index > 0 {
+            if self.is_marker_or_open(&self.active_formatting[entry_index - 1]) {
+                break;
+            }
+            entry_index -= 1;
+        }
+
+        // Then create an element for each entry from there on.
+        for i in range(entry_index, self.active_formatting.len()) {
+            let tag = match self.active_formatting[i] {
+                Element(_, ref t) => t.clone(),
+                Marker => fail!("Found marker during formatting element reconstruction"),
+            };
+            let elem = self.insert_element(Push, true,
+                QualName::new(ns!(HTML), tag.name.clone()), tag.attrs.clone());
+            *self.active_formatting.get_mut(i) = Element(elem, tag);
+        }
     }
+    //§ END
 
     /// Get the first element on the stack, which will be the <html> element.
     fn html_elem(&self) -> Handle {
@@ -258,16 +704,19 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
             dd dt li optgroup option p rp rt tbody td tfoot th
             thead tr body html)
 
-        for elem in self.open_elems.iter() {
-            let name = self.sink.elem_name(elem.clone());
-            if !body_end_ok(name.clone()) {
-                self.sink.parse_error(format_if!(self.opts.exact_errors,
+        // FIXME: Do we keep checking after finding one bad tag?
+        // The spec suggests not.
+        let bad = self.open_elems.iter()
+            .map(|elem| self.sink.elem_name(elem.clone()))
+            .find(|name| !body_end_ok(name.clone()));
+        match bad {
+            Some(name) => {
+                let msg = format_if!(self.opts.exact_errors,
                     "Unexpected open tag at end of body",
-                    "Unexpected open tag {} at end of body", name));
-                // FIXME: Do we keep checking after finding one bad tag?
-                // The spec suggests not.
-                return;
+                    "Unexpected open tag {} at end of body", name);
+                self.parse_error(msg);
             }
+            None => (),
         }
     }
 
@@ -294,6 +743,10 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
         self.sink.elem_name(elem) == QualName::new(ns!(HTML), name)
     }
 
+    fn in_html_elem_named(&self, name: Atom) -> bool {
+        self.open_elems.iter().any(|elem| self.html_elem_named(elem.clone(), name.clone()))
+    }
+
     fn current_node_named(&self, name: Atom) -> bool {
         self.html_elem_named(self.current_node(), name)
     }
@@ -353,9 +806,10 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
     // Signal an error if it was not the first one.
     fn expect_to_close(&mut self, name: Atom) {
         if self.pop_until_named(name.clone()) != 1 {
-            self.sink.parse_error(format_if!(self.opts.exact_errors,
+            let msg = format_if!(self.opts.exact_errors,
                 "Unexpected open element",
-                "Unexpected open element while closing {}", name));
+                "Unexpected open element while closing {}", name);
+            self.parse_error(msg);
         }
     }
 
@@ -395,21 +849,25 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
             self.orig_mode = Some(self.mode);
             Reprocess(InTableText, token)
         } else {
-            self.sink.parse_error(format_if!(self.opts.exact_errors,
+            let msg = format_if!(self.opts.exact_errors,
                 "Unexpected characters in table",
-                "Unexpected characters {} in table", to_escaped_string(&token)));
+                "Unexpected characters {} in table", to_escaped_string(&token));
+            self.parse_error(msg);
             self.foster_parent_in_body(token)
         }
     }
 
     fn reset_insertion_mode(&mut self) -> InsertionMode {
         for (i, node) in self.open_elems.iter().enumerate().rev() {
+            let last = i == 0u;
+            let node = match (last, self.context_elem.as_ref()) {
+                (true, Some(ctx)) => ctx,
+                _ => node,
+            };
             let name = match self.sink.elem_name(node.clone()) {
                 QualName { ns: ns!(HTML), local } => local,
                 _ => continue,
             };
-            let last = i == 0u;
-            // FIXME: fragment case context element
             match name {
                 // FIXME: <select> sub-steps
                 atom!(select) => return InSelect,
@@ -428,7 +886,8 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                     Some(_) => return AfterHead,
                 },
 
-                atom!(template) => fail!("FIXME: <template> not implemented"),
+                atom!(template) => return *self.template_modes.last()
+                    .expect("template element without a template insertion mode"),
 
                 _ => (),
             }
@@ -439,15 +898,54 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
     fn close_the_cell(&mut self) {
         self.generate_implied_end(cursory_implied_end);
         if self.pop_until(td_th) != 1 {
-            self.sink.parse_error(Slice("expected to close <td> or <th> with cell"));
+            self.parse_error(Slice("expected to close <td> or <th> with cell"));
         }
     }
 
-    fn append_text(&mut self, text: String) -> ProcessResult {
-        self.insert_appropriately(AppendText(text));
+    fn append_text(&mut self, split: SplitStatus, text: String) -> ProcessResult {
+        let target = self.appropriate_target();
+        if self.will_foster_parent_into(target.clone()) {
+            self.insert_appropriately_in(target, AppendText(text));
+            return Done;
+        }
+
+        // As in `insert_appropriately_in`, but keeping the split status.
+        let target = if self.html_elem_named(target.clone(), atom!(template)) {
+            self.sink.get_template_contents(target)
+        } else {
+            target
+        };
+        self.sink.append_text_with_status(target, text, split);
         Done
     }
 
+    // Text after </body>, without splitting off leading whitespace.  All
+    // of it goes in <body> either way; the parse error just comes before
+    // the whitespace rather than after.
+    fn text_after_body(&mut self, text: String) -> ProcessResult {
+        if text.as_slice().chars().all(is_ascii_whitespace) {
+            return self.step(InBody, CharacterTokens(Whitespace, text));
+        }
+        let token = CharacterTokens(NotWhitespace, text);
+        self.unexpected(&token);
+        Reprocess(InBody, token)
+    }
+
+    // Keep the whitespace from text in a frameset, in one pass.  Splitting
+    // would copy the rest of the text at every change between whitespace
+    // and the ignored characters.  Reports one parse error however many
+    // characters are dropped.
+    fn whitespace_in_frameset(&mut self, text: String) -> ProcessResult {
+        let ws: String = text.as_slice().chars().filter(|&c| is_ascii_whitespace(c)).collect();
+        if ws.len() != text.len() {
+            self.unexpected(&CharacterTokens(NotWhitespace, text));
+        }
+        if ws.is_empty() {
+            return Done;
+        }
+        self.append_text(Whitespace, ws)
+    }
+
     fn append_comment(&mut self, text: String) -> ProcessResult {
         let comment = self.sink.create_comment(text);
         self.insert_appropriately(AppendNode(comment));
@@ -469,17 +967,40 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
     }
 
     //§ creating-and-inserting-nodes
-    fn create_root(&mut self, attrs: Vec<Attribute>) {
+    fn create_root(&mut self, attrs: Vec<Attribute>, implied: bool) {
         let elem = self.sink.create_element(qualname!(HTML, html), attrs);
         self.push(&elem);
-        self.sink.append(self.doc_handle.clone(), AppendNode(elem));
+        self.sink.append(self.doc_handle.clone(), AppendNode(elem.clone()));
+        self.sink.element_provenance(elem, ElemProvenance {
+            parser_inserted: true,
+            implied: implied,
+            fostered: false,
+        });
         // FIXME: application cache selection algorithm
     }
 
-    fn insert_element(&mut self, push: PushFlag, name: Atom, attrs: Vec<Attribute>)
-            -> Handle {
-        let elem = self.sink.create_element(QualName::new(ns!(HTML), name), attrs);
+    fn insert_element(&mut self, push: PushFlag, implied: bool, name: QualName,
+            attrs: Vec<Attribute>) -> Handle {
+        let fostered = self.will_foster_parent();
+        // Only pay for the copy when auditing.  The audits are all about
+        // HTML elements.  An implied element, such as a formatting element
+        // reconstructed by the parser, was already audited as written.
+        let audit_attrs = match self.auditor {
+            Some(_) if name.ns == ns!(HTML) && !implied => Some(attrs.clone()),
+            _ => None,
+        };
+        let elem = self.sink.create_element(name.clone(), attrs);
         self.insert_appropriately(AppendNode(elem.clone()));
+        match (self.auditor.as_mut(), audit_attrs) {
+            (Some(auditor), Some(attrs)) => auditor.element(self.sink,
+                self.open_elems.as_slice(), &elem, &name.local, attrs.as_slice()),
+            _ => (),
+        }
+        self.sink.element_provenance(elem.clone(), ElemProvenance {
+            parser_inserted: true,
+            implied: implied,
+            fostered: fostered,
+        });
         match push {
             Push => self.push(&elem),
             NoPush => (),
@@ -489,15 +1010,53 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
     }
 
     fn insert_element_for(&mut self, tag: Tag) -> Handle {
-        self.insert_element(Push, tag.name, tag.attrs)
+        self.insert_element(Push, false, QualName::new(ns!(HTML), tag.name), tag.attrs)
     }
 
     fn insert_and_pop_element_for(&mut self, tag: Tag) -> Handle {
-        self.insert_element(NoPush, tag.name, tag.attrs)
+        self.insert_element(NoPush, false, QualName::new(ns!(HTML), tag.name), tag.attrs)
     }
 
     fn insert_phantom(&mut self, name: Atom) -> Handle {
-        self.insert_element(Push, name, vec!())
+        self.insert_element(Push, true, QualName::new(ns!(HTML), name), vec!())
+    }
+
+    // Insert a foreign element, adjusting its attribute names and, for
+    // SVG, its tag name.  A self-closing tag is popped right away, and
+    // acknowledged.
+    fn insert_foreign_element(&mut self, tag: Tag, ns: Namespace) -> ProcessResult {
+        let Tag { name, attrs, self_closing, .. } = tag;
+        let name = match ns {
+            ns!(SVG) => adjust_svg_tag_name(&name).unwrap_or(name),
+            _ => name,
+        };
+
+        let attrs: Vec<Attribute> = attrs.into_iter().map(|mut attr| {
+            if attr.name.ns == ns!("") {
+                let adjusted = match ns {
+                    ns!(SVG) => adjust_svg_attribute(&attr.name.local),
+                    ns!(MathML) => adjust_mathml_attribute(&attr.name.local),
+                    _ => None,
+                };
+                match adjusted {
+                    Some(local) => attr.name.local = local,
+                    None => (),
+                }
+                match adjust_foreign_attribute(&attr.name.local) {
+                    Some(name) => attr.name = name,
+                    None => (),
+                }
+            }
+            attr
+        }).collect();
+
+        self.insert_element(Push, false, QualName::new(ns, name), attrs);
+        if self_closing {
+            self.pop();
+            DoneAckSelfClosing
+        } else {
+            Done
+        }
     }
     //§ END
 
@@ -516,7 +1075,8 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
             self.active_formatting.remove(first_match.expect("matches with no index"));
         }
 
-        let elem = self.insert_element(Push, tag.name.clone(), tag.attrs.clone());
+        let elem = self.insert_element(Push, false,
+            QualName::new(ns!(HTML), tag.name.clone()), tag.attrs.clone());
         self.active_formatting.push(Element(elem.clone(), tag));
         elem
     }
@@ -0,0 +1,223 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The spec's name adjustments for foreign (SVG and MathML) content.
+//!
+//! The tokenizer lowercases every tag and attribute name, but SVG has
+//! camelCase names like `foreignObject` and `viewBox`, and some foreign
+//! attributes belong in the XLink, XML or XMLNS namespace.  These are the
+//! tables from the spec which restore them, for serializers, sanitizers
+//! and anything else which wants to agree with the tree builder.
+//!
+//! Each table maps a lowercase name to its adjusted form, and is sorted
+//! by the lowercase name.
+
+use core::prelude::*;
+
+use string_cache::{Atom, QualName};
+
+//§ parsing-main-inforeign
+/// SVG element names, as adjusted by "any other start tag" in foreign
+/// content.
+pub static SVG_TAG_NAMES: &'static [(&'static str, &'static str)] = &[
+    ("altglyph", "altGlyph"),
+    ("altglyphdef", "altGlyphDef"),
+    ("altglyphitem", "altGlyphItem"),
+    ("animatecolor", "animateColor"),
+    ("animatemotion", "animateMotion"),
+    ("animatetransform", "animateTransform"),
+    ("clippath", "clipPath"),
+    ("feblend", "feBlend"),
+    ("fecolormatrix", "feColorMatrix"),
+    ("fecomponenttransfer", "feComponentTransfer"),
+    ("fecomposite", "feComposite"),
+    ("feconvolvematrix", "feConvolveMatrix"),
+    ("fediffuselighting", "feDiffuseLighting"),
+    ("fedisplacementmap", "feDisplacementMap"),
+    ("fedistantlight", "feDistantLight"),
+    ("fedropshadow", "feDropShadow"),
+    ("feflood", "feFlood"),
+    ("fefunca", "feFuncA"),
+    ("fefuncb", "feFuncB"),
+    ("fefuncg", "feFuncG"),
+    ("fefuncr", "feFuncR"),
+    ("fegaussianblur", "feGaussianBlur"),
+    ("feimage", "feImage"),
+    ("femerge", "feMerge"),
+    ("femergenode", "feMergeNode"),
+    ("femorphology", "feMorphology"),
+    ("feoffset", "feOffset"),
+    ("fepointlight", "fePointLight"),
+    ("fespecularlighting", "feSpecularLighting"),
+    ("fespotlight", "feSpotLight"),
+    ("fetile", "feTile"),
+    ("feturbulence", "feTurbulence"),
+    ("foreignobject", "foreignObject"),
+    ("glyphref", "glyphRef"),
+    ("lineargradient", "linearGradient"),
+    ("radialgradient", "radialGradient"),
+    ("textpath", "textPath"),
+];
+
+//§ adjust-svg-attributes
+/// SVG attribute names.
+pub static SVG_ATTRIBUTES: &'static [(&'static str, &'static str)] = &[
+    ("attributename", "attributeName"),
+    ("attributetype", "attributeType"),
+    ("basefrequency", "baseFrequency"),
+    ("baseprofile", "baseProfile"),
+    ("calcmode", "calcMode"),
+    ("clippathunits", "clipPathUnits"),
+    ("diffuseconstant", "diffuseConstant"),
+    ("edgemode", "edgeMode"),
+    ("filterunits", "filterUnits"),
+    ("glyphref", "glyphRef"),
+    ("gradienttransform", "gradientTransform"),
+    ("gradientunits", "gradientUnits"),
+    ("kernelmatrix", "kernelMatrix"),
+    ("kernelunitlength", "kernelUnitLength"),
+    ("keypoints", "keyPoints"),
+    ("keysplines", "keySplines"),
+    ("keytimes", "keyTimes"),
+    ("lengthadjust", "lengthAdjust"),
+    ("limitingconeangle", "limitingConeAngle"),
+    ("markerheight", "markerHeight"),
+    ("markerunits", "markerUnits"),
+    ("markerwidth", "markerWidth"),
+    ("maskcontentunits", "maskContentUnits"),
+    ("maskunits", "maskUnits"),
+    ("numoctaves", "numOctaves"),
+    ("pathlength", "pathLength"),
+    ("patterncontentunits", "patternContentUnits"),
+    ("patterntransform", "patternTransform"),
+    ("patternunits", "patternUnits"),
+    ("pointsatx", "pointsAtX"),
+    ("pointsaty", "pointsAtY"),
+    ("pointsatz", "pointsAtZ"),
+    ("preservealpha", "preserveAlpha"),
+    ("preserveaspectratio", "preserveAspectRatio"),
+    ("primitiveunits", "primitiveUnits"),
+    ("refx", "refX"),
+    ("refy", "refY"),
+    ("repeatcount", "repeatCount"),
+    ("repeatdur", "repeatDur"),
+    ("requiredextensions", "requiredExtensions"),
+    ("requiredfeatures", "requiredFeatures"),
+    ("specularconstant", "specularConstant"),
+    ("specularexponent", "specularExponent"),
+    ("spreadmethod", "spreadMethod"),
+    ("startoffset", "startOffset"),
+    ("stddeviation", "stdDeviation"),
+    ("stitchtiles", "stitchTiles"),
+    ("surfacescale", "surfaceScale"),
+    ("systemlanguage", "systemLanguage"),
+    ("tablevalues", "tableValues"),
+    ("targetx", "targetX"),
+    ("targety", "targetY"),
+    ("textlength", "textLength"),
+    ("viewbox", "viewBox"),
+    ("viewtarget", "viewTarget"),
+    ("xchannelselector", "xChannelSelector"),
+    ("ychannelselector", "yChannelSelector"),
+    ("zoomandpan", "zoomAndPan"),
+];
+
+//§ adjust-mathml-attributes
+/// MathML attribute names.
+pub static MATHML_ATTRIBUTES: &'static [(&'static str, &'static str)] = &[
+    ("definitionurl", "definitionURL"),
+];
+
+fn lookup(table: &'static [(&'static str, &'static str)], name: &Atom) -> Option<Atom> {
+    let name = name.as_slice();
+    table.iter()
+        .find(|&&(from, _)| from == name)
+        .map(|&(_, to)| Atom::from_slice(to))
+}
+
+/// The adjusted local name of an SVG element, if it needs one.
+pub fn adjust_svg_tag_name(local: &Atom) -> Option<Atom> {
+    lookup(SVG_TAG_NAMES, local)
+}
+
+/// The adjusted local name of an attribute on an SVG element, if it
+/// needs one.
+pub fn adjust_svg_attribute(local: &Atom) -> Option<Atom> {
+    lookup(SVG_ATTRIBUTES, local)
+}
+
+/// The adjusted local name of an attribute on a MathML element, if it
+/// needs one.
+pub fn adjust_mathml_attribute(local: &Atom) -> Option<Atom> {
+    lookup(MATHML_ATTRIBUTES, local)
+}
+
+//§ adjust-foreign-attributes
+/// The namespaced name of an attribute on any foreign element, if it
+/// has one.  E.g. `xlink:href` becomes `href` in the XLink namespace.
+///
+/// `QualName` doesn't keep a prefix, so the serializer supplies it again
+/// from the namespace.
+pub fn adjust_foreign_attribute(local: &Atom) -> Option<QualName> {
+    let (ns, local) = match local.as_slice() {
+        "xlink:actuate" => (ns!(XLink), "actuate"),
+        "xlink:arcrole" => (ns!(XLink), "arcrole"),
+        "xlink:href" => (ns!(XLink), "href"),
+        "xlink:role" => (ns!(XLink), "role"),
+        "xlink:show" => (ns!(XLink), "show"),
+        "xlink:title" => (ns!(XLink), "title"),
+        "xlink:type" => (ns!(XLink), "type"),
+        "xml:base" => (ns!(XML), "base"),
+        "xml:lang" => (ns!(XML), "lang"),
+        "xml:space" => (ns!(XML), "space"),
+        "xmlns" => (ns!(XMLNS), "xmlns"),
+        "xmlns:xlink" => (ns!(XMLNS), "xlink"),
+        _ => return None,
+    };
+    Some(QualName::new(ns, Atom::from_slice(local)))
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{SVG_TAG_NAMES, SVG_ATTRIBUTES, MATHML_ATTRIBUTES};
+    use super::{adjust_svg_tag_name, adjust_svg_attribute, adjust_mathml_attribute};
+    use super::adjust_foreign_attribute;
+
+    use util::str::AsciiExt;
+
+    use string_cache::{Atom, QualName};
+
+    fn atom(s: &str) -> Atom {
+        Atom::from_slice(s)
+    }
+
+    #[test]
+    fn tables_sorted_and_lowercase() {
+        for table in [SVG_TAG_NAMES, SVG_ATTRIBUTES, MATHML_ATTRIBUTES].iter() {
+            for pair in table.windows(2) {
+                assert!(pair[0].val0() < pair[1].val0());
+            }
+            for &(from, to) in table.iter() {
+                assert_eq!(from, to.to_ascii_lower().as_slice());
+            }
+        }
+    }
+
+    #[test]
+    fn adjust() {
+        assert_eq!(adjust_svg_tag_name(&atom("foreignobject")), Some(atom("foreignObject")));
+        assert_eq!(adjust_svg_tag_name(&atom("circle")), None);
+        assert_eq!(adjust_svg_attribute(&atom("viewbox")), Some(atom("viewBox")));
+        assert_eq!(adjust_mathml_attribute(&atom("definitionurl")), Some(atom("definitionURL")));
+        assert_eq!(adjust_foreign_attribute(&atom("xlink:href")),
+            Some(QualName::new(ns!(XLink), atom("href"))));
+        assert_eq!(adjust_foreign_attribute(&atom("href")), None);
+    }
+}
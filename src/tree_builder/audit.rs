@@ -0,0 +1,129 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Accessibility checks which can be made while the tree is built.
+//!
+//! Enabled by `TreeBuilderOpts::audit`.  Issues are reported through
+//! `TreeSink::audit`.
+
+use core::prelude::*;
+
+use tree_builder::interface::{TreeSink, ImgWithoutAlt, DuplicateId};
+use tree_builder::interface::{UnlabeledLabel, LabelForMissingId};
+
+use tokenizer::Attribute;
+
+use util::str::AsciiExt;
+
+use core::mem::replace;
+use collections::{MutableSeq, Set, MutableSet};
+use collections::vec::Vec;
+use collections::string::String;
+use collections::treemap::TreeSet;
+
+use string_cache::{Atom, QualName};
+
+struct LabelRecord<Handle> {
+    elem: Handle,
+    for_id: Option<String>,
+    has_control: bool,
+}
+
+pub struct Auditor<Handle> {
+    /// Every `id` seen so far.
+    ids: TreeSet<String>,
+
+    /// Every `<label>`, checked at the end of parsing.
+    labels: Vec<LabelRecord<Handle>>,
+}
+
+fn attr_value<'a>(attrs: &'a [Attribute], name: Atom) -> Option<&'a String> {
+    attrs.iter().find(|at| at.name == QualName::new(ns!(""), name.clone()))
+        .map(|at| &at.value)
+}
+
+// https://html.spec.whatwg.org/#category-label
+fn is_labelable(name: &Atom, attrs: &[Attribute]) -> bool {
+    match *name {
+        atom!(button) | atom!(keygen) | atom!(meter) | atom!(output)
+            | atom!(progress) | atom!(select) | atom!(textarea) => true,
+        atom!(input) => !attr_value(attrs, atom!(type))
+            .map_or(false, |t| t.as_slice().eq_ignore_ascii_case("hidden")),
+        _ => false,
+    }
+}
+
+impl<Handle: Clone> Auditor<Handle> {
+    pub fn new() -> Auditor<Handle> {
+        Auditor {
+            ids: TreeSet::new(),
+            labels: vec!(),
+        }
+    }
+
+    /// Check a newly inserted HTML element.  `open_elems` should not yet
+    /// include the element itself.
+    pub fn element<Sink: TreeSink<Handle>>(&mut self,
+            sink: &mut Sink,
+            open_elems: &[Handle],
+            elem: &Handle,
+            name: &Atom,
+            attrs: &[Attribute]) {
+        match attr_value(attrs, atom!(id)) {
+            Some(id) => if !self.ids.insert(id.clone()) {
+                sink.audit(DuplicateId(elem.clone(), id.clone()));
+            },
+            None => (),
+        }
+
+        if *name == atom!(img) && attr_value(attrs, atom!(alt)).is_none() {
+            sink.audit(ImgWithoutAlt(elem.clone()));
+        }
+
+        if *name == atom!(label) {
+            self.labels.push(LabelRecord {
+                elem: elem.clone(),
+                for_id: attr_value(attrs, atom!(for)).map(|v| v.clone()),
+                has_control: false,
+            });
+        }
+
+        if is_labelable(name, attrs) {
+            let label = open_elems.iter().rev()
+                .find(|e| sink.elem_name((*e).clone()) == qualname!(HTML, label));
+            match label {
+                Some(label) => {
+                    let labels = self.labels.iter_mut().rev();
+                    for rec in labels {
+                        if sink.same_node(rec.elem.clone(), label.clone()) {
+                            rec.has_control = true;
+                            break;
+                        }
+                    }
+                }
+                None => (),
+            }
+        }
+    }
+
+    /// Report issues which can only be decided once the whole document
+    /// has been seen.
+    pub fn finish<Sink: TreeSink<Handle>>(&mut self, sink: &mut Sink) {
+        for rec in replace(&mut self.labels, vec!()).into_iter() {
+            match rec.for_id {
+                Some(id) => if !self.ids.contains(&id) {
+                    sink.audit(LabelForMissingId(rec.elem, id));
+                },
+                None => if !rec.has_control {
+                    sink.audit(UnlabeledLabel(rec.elem));
+                },
+            }
+        }
+    }
+}
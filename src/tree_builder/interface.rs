@@ -12,13 +12,17 @@
 
 use core::prelude::*;
 
-use tokenizer::Attribute;
+use tree_builder::types::{InsertionMode, Token, StepOutcome, SplitStatus};
+
+use tokenizer::{Attribute, ParseErrorKind};
+
+use util::str::AsciiExt;
 
 use collections::vec::Vec;
 use collections::string::String;
 use collections::str::MaybeOwned;
 
-use string_cache::QualName;
+use string_cache::{Atom, QualName};
 
 /// A document's quirks mode.
 #[deriving(PartialEq, Eq, Clone, Hash, Show)]
@@ -28,6 +32,57 @@ pub enum QuirksMode {
     NoQuirks,
 }
 
+//§ case-sensitivity-of-selectors
+impl QuirksMode {
+    /// Do class and ID selectors match case-insensitively (for ASCII)?
+    /// This is the case only in quirks mode.
+    pub fn class_and_id_case_insensitive(&self) -> bool {
+        *self == Quirks
+    }
+
+    /// Does a class or ID selector match a class name or ID in a
+    /// document with this quirks mode?
+    pub fn class_or_id_matches(&self, selector: &str, value: &str) -> bool {
+        if self.class_and_id_case_insensitive() {
+            selector.eq_ignore_ascii_case(value)
+        } else {
+            selector == value
+        }
+    }
+}
+
+/// Does a type selector match an element with the given name?  Selectors
+/// match HTML elements without regard to ASCII case; the parser has
+/// already lowercased their names.  Other elements must match exactly.
+pub fn local_name_matches(elem: &QualName, selector: &str) -> bool {
+    if elem.ns == ns!(HTML) {
+        elem.local.as_slice().eq_ignore_ascii_case(selector)
+    } else {
+        elem.local.as_slice() == selector
+    }
+}
+
+/// Do attribute selectors compare the value of `attr` on `elem` without
+/// regard to ASCII case?  True for a fixed list of attributes on HTML
+/// elements, in every quirks mode.
+pub fn attr_value_case_insensitive(elem: &QualName, attr: &QualName) -> bool {
+    if elem.ns != ns!(HTML) || attr.ns != ns!("") {
+        return false;
+    }
+    match attr.local.as_slice() {
+        "accept" | "accept-charset" | "align" | "alink" | "axis" | "bgcolor"
+            | "charset" | "checked" | "clear" | "codetype" | "color" | "compact"
+            | "declare" | "defer" | "dir" | "direction" | "disabled" | "enctype"
+            | "face" | "frame" | "hreflang" | "http-equiv" | "lang" | "language"
+            | "link" | "media" | "method" | "multiple" | "nohref" | "noresize"
+            | "noshade" | "nowrap" | "readonly" | "rel" | "rev" | "rules"
+            | "scope" | "scrolling" | "selected" | "shape" | "target" | "text"
+            | "type" | "valign" | "valuetype" | "vlink" => true,
+        _ => false,
+    }
+}
+//§ END
+
 /// Something which can be inserted into the DOM.
 ///
 /// Adjacent sibling text nodes are merged into a single node, so
@@ -37,15 +92,74 @@ pub enum NodeOrText<Handle> {
     AppendText(String),
 }
 
+/// What the parser should do after a `</script>`, as decided by
+/// `TreeSink::complete_script`.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum ScriptAction {
+    /// Carry on parsing.
+    Continue,
+
+    /// Stop until `TreeBuilder::script_completed`, e.g. to fetch and run
+    /// the script, which may write more input at the insertion point.
+    Pause,
+}
+
+/// Where an element came from, as reported to `TreeSink::element_provenance`.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct ElemProvenance {
+    /// Was the element created by the parser, rather than by a script?
+    /// Always true for now.
+    pub parser_inserted: bool,
+
+    /// Was the element synthesized by the parser, with no corresponding
+    /// start tag in the source?  For example, an implied `<tbody>` or
+    /// `<head>`.
+    pub implied: bool,
+
+    /// Was the element moved out of a table by foster parenting?
+    pub fostered: bool,
+}
+
+/// An accessibility problem found while building the tree.
+///
+/// Only reported when `TreeBuilderOpts::audit` is set.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum AuditIssue<Handle> {
+    /// An `<img>` with no `alt` attribute.
+    ImgWithoutAlt(Handle),
+
+    /// A `<label>` with no `for` attribute and no labelable control
+    /// inside it.
+    UnlabeledLabel(Handle),
+
+    /// A `<label for=...>` naming an id which no element has.
+    LabelForMissingId(Handle, String),
+
+    /// An element whose `id` was already used by an earlier element.
+    DuplicateId(Handle, String),
+}
+
 /// Types which can process tree modifications from the tree builder.
 ///
 /// `Handle` is a reference to a DOM node.  The tree builder requires
 /// that a `Handle` implements `Clone` to get another reference to
 /// the same node.
+///
+/// When parsing a fragment, the caller supplies handles for the context
+/// element and (optionally) the form element pointer.  The tree builder
+/// will pass these back to `elem_name` and `same_node`, but will never
+/// insert them or otherwise modify them.
 pub trait TreeSink<Handle> {
     /// Signal a parse error.
     fn parse_error(&mut self, msg: MaybeOwned<'static>);
 
+    /// Signal a parse error of a known kind.  Errors from the tokenizer
+    /// come here too.  By default the kind is dropped and the message
+    /// goes to `parse_error`.
+    fn classified_parse_error(&mut self, _kind: ParseErrorKind, msg: MaybeOwned<'static>) {
+        self.parse_error(msg);
+    }
+
     /// Get a handle to the `Document` node.
     fn get_document(&mut self) -> Handle;
 
@@ -58,9 +172,26 @@ pub trait TreeSink<Handle> {
     /// feel free to `fail!`.
     fn elem_name(&self, target: Handle) -> QualName;
 
+    /// Get the template contents of a HTML `<template>` element: the
+    /// document fragment which holds what the parser puts inside it.
+    /// Each call for the same element must return the same node.
+    ///
+    /// Should never be called on anything else; feel free to `fail!`.
+    fn get_template_contents(&mut self, target: Handle) -> Handle;
+
     /// Set the document's quirks mode.
     fn set_quirks_mode(&mut self, mode: QuirksMode);
 
+    /// The tree builder has decided on `mode` as the document's quirks
+    /// mode, from the DOCTYPE or its absence.  Return another mode to use
+    /// that instead, e.g. for an `about:blank` document, where the
+    /// embedder knows better.  This is asked once per document, just
+    /// before `set_quirks_mode`.  The default implementation returns
+    /// `None`.
+    fn query_quirks_override(&mut self, _mode: QuirksMode) -> Option<QuirksMode> {
+        None
+    }
+
     /// Create an element.
     fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle;
 
@@ -72,8 +203,29 @@ pub trait TreeSink<Handle> {
     /// instead.
     ///
     /// The child node will not already have a parent.
+    ///
+    /// Text arrives exactly as it appears in the document, after newline
+    /// normalization and character references, however the input was
+    /// split into chunks.  The one exception is the spec's: a newline
+    /// immediately after a `<pre>`, `<listing>` or `<textarea>` start tag
+    /// is dropped, whether it was written literally, as CRLF, or as a
+    /// character reference.  So a `<textarea>`'s text children are
+    /// exactly its default value.
     fn append(&mut self, parent: Handle, child: NodeOrText<Handle>);
 
+    /// Append text as the last child of the given node, as with `append`,
+    /// along with what the tree builder already knows about it:
+    /// `Whitespace` if it's all ASCII whitespace, `NotWhitespace` if it
+    /// isn't, or `NotSplit` if it didn't need to check.  A sink which
+    /// cares can then avoid scanning the text again.
+    ///
+    /// Text which is foster parented goes to `append` or
+    /// `append_before_sibling` instead.  The default implementation calls
+    /// `append`.
+    fn append_text_with_status(&mut self, parent: Handle, text: String, _status: SplitStatus) {
+        self.append(parent, AppendText(text));
+    }
+
     /// Append a node as the sibling immediately before the given node.  If that node
     /// has no parent, do nothing and return Err(new_node).
     ///
@@ -87,9 +239,36 @@ pub trait TreeSink<Handle> {
         sibling: Handle,
         new_node: NodeOrText<Handle>) -> Result<(), NodeOrText<Handle>>;
 
+    /// Insert a foster-parented node: immediately before `element` if it
+    /// has a parent, or else as the last child of `prev_element`.  This is
+    /// the spec's "appropriate place for inserting a node" when the last
+    /// table may have been removed from the tree.
+    ///
+    /// The default implementation calls `append_before_sibling`, then
+    /// `append` if that fails.  A sink which can decide in one step
+    /// should override this.
+    fn append_based_on_parent_node(&mut self,
+            element: Handle,
+            prev_element: Handle,
+            child: NodeOrText<Handle>) {
+        match self.append_before_sibling(element, child) {
+            Ok(()) => (),
+            Err(child) => self.append(prev_element, child),
+        }
+    }
+
     /// Append a `DOCTYPE` element to the `Document` node.
     fn append_doctype_to_document(&mut self, name: String, public_id: String, system_id: String);
 
+    /// Append a `DOCTYPE` element to the `Document` node, along with its
+    /// source text, from `<!` to `>`.  Only called when
+    /// `TokenizerOpts::raw_doctype` is set.  The default implementation
+    /// drops the source text and calls `append_doctype_to_document`.
+    fn append_raw_doctype_to_document(&mut self, name: String, public_id: String,
+            system_id: String, _raw: String) {
+        self.append_doctype_to_document(name, public_id, system_id);
+    }
+
     /// Add each attribute to the given element, if no attribute
     /// with that name already exists.
     fn add_attrs_if_missing(&mut self, target: Handle, attrs: Vec<Attribute>);
@@ -97,6 +276,75 @@ pub trait TreeSink<Handle> {
     /// Detach the given node from its parent.
     fn remove_from_parent(&mut self, target: Handle);
 
+    /// Move all of `node`'s children to the end of `new_parent`'s
+    /// children, keeping their order.  Used by the adoption agency
+    /// algorithm.
+    fn reparent_children(&mut self, node: Handle, new_parent: Handle);
+
     /// Mark a HTML `<script>` element as "already started".
     fn mark_script_already_started(&mut self, node: Handle);
+
+    /// A HTML `<script>` element was closed by a `</script>` end tag, and
+    /// is ready to run.  This is only called at the end tag, even for
+    /// `<script src=...>`; to start fetching earlier, look for the `src`
+    /// attribute in `create_element`.  Return `Pause` to pause parsing
+    /// while the script is fetched and run; the input after `</script>`
+    /// waits until the caller signals that the script is done, e.g. with
+    /// `driver::Parser::resume`.  The default implementation returns
+    /// `Continue`.
+    fn complete_script(&mut self, _node: Handle) -> ScriptAction {
+        Continue
+    }
+
+    /// Describe how an element which was just inserted into the tree came
+    /// to be created.  The default implementation ignores this.
+    fn element_provenance(&mut self, _elem: Handle, _provenance: ElemProvenance) { }
+
+    /// Called after each token is processed, with the insertion mode it
+    /// was processed in and what happened.  Only called when
+    /// `TreeBuilderOpts::report_steps` is set.  The default
+    /// implementation does nothing.
+    fn step_taken(&mut self, _mode: InsertionMode, _token: &Token, _outcome: StepOutcome) { }
+
+    /// Report an accessibility issue.  The default implementation
+    /// ignores it.
+    fn audit(&mut self, _issue: AuditIssue<Handle>) { }
+
+    /// An end tag for a void element, such as `</br>`, was dropped.
+    /// Only called when `TreeBuilderOpts::report_void_end_tags` is set.
+    /// This comes right after the parse error for the same tag.  The
+    /// default implementation does nothing.
+    fn void_end_tag_ignored(&mut self, _name: Atom) { }
+
+    /// Return true to stop parsing, e.g. after an unrecoverable error
+    /// such as a failed database write.  This is checked after each
+    /// token; the rest of the input is ignored and the tree is left as
+    /// it is.  The default implementation never aborts.
+    fn aborted(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{Quirks, LimitedQuirks, NoQuirks};
+    use super::{local_name_matches, attr_value_case_insensitive};
+
+    #[test]
+    fn class_and_id() {
+        assert!(Quirks.class_or_id_matches("Foo", "fOO"));
+        assert!(!LimitedQuirks.class_or_id_matches("Foo", "fOO"));
+        assert!(!NoQuirks.class_or_id_matches("Foo", "fOO"));
+        assert!(NoQuirks.class_or_id_matches("foo", "foo"));
+    }
+
+    #[test]
+    fn names() {
+        assert!(local_name_matches(&qualname!(HTML, div), "DIV"));
+        assert!(!local_name_matches(&qualname!(SVG, title), "TITLE"));
+        assert!(attr_value_case_insensitive(&qualname!(HTML, input), &qualname!("", "type")));
+        assert!(!attr_value_case_insensitive(&qualname!(HTML, div), &qualname!("", id)));
+        assert!(!attr_value_case_insensitive(&qualname!(SVG, title), &qualname!("", "type")));
+    }
 }
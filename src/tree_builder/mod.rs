@@ -13,11 +13,25 @@ use core::prelude::*;
 
 pub use self::interface::{QuirksMode, Quirks, LimitedQuirks, NoQuirks};
 pub use self::interface::{NodeOrText, AppendNode, AppendText};
-pub use self::interface::TreeSink;
+pub use self::interface::{TreeSink, ElemProvenance};
+pub use self::interface::{ScriptAction, Continue, Pause};
+pub use self::interface::{local_name_matches, attr_value_case_insensitive};
+pub use self::types::{InsertionMode, ALL_INSERTION_MODES};
+pub use self::types::{Initial, BeforeHtml, BeforeHead, InHead, InHeadNoscript};
+pub use self::types::{AfterHead, InBody, Text, InTable, InTableText, InCaption, InColumnGroup};
+pub use self::types::{InTableBody, InRow, InCell, InSelect, InSelectInTable, InTemplate};
+pub use self::types::{AfterBody, InFrameset, AfterFrameset, AfterAfterBody, AfterAfterFrameset};
+pub use self::types::{Token, TagToken, CommentToken, CharacterTokens, NullCharacterToken, EOFToken};
+pub use self::types::{SplitStatus, NotSplit, Whitespace, NotWhitespace};
+pub use self::types::{StepOutcome, StepDone, StepAckSelfClosing, StepSplitWhitespace, StepReprocess};
+pub use self::interface::{AuditIssue, ImgWithoutAlt, UnlabeledLabel};
+pub use self::interface::{LabelForMissingId, DuplicateId};
+pub use self::text::{TextBuf, Latin1, TextAttribute, TextTreeSink, TextAdapter};
 
 use self::types::*;
 use self::actions::TreeBuilderActions;
 use self::rules::TreeBuilderStep;
+use self::audit::Auditor;
 
 use tokenizer;
 use tokenizer::{Doctype, Tag};
@@ -25,11 +39,13 @@ use tokenizer::TokenSink;
 
 use util::str::{is_ascii_whitespace, char_run};
 
+use string_cache::QualName;
+
 use core::default::Default;
 use core::mem::replace;
 use collections::vec::Vec;
 use collections::string::String;
-use collections::str::Slice;
+use collections::str::{MaybeOwned, Slice};
 use collections::{MutableSeq, Deque, RingBuf};
 
 mod interface;
@@ -38,6 +54,10 @@ mod data;
 mod types;
 mod actions;
 mod rules;
+mod audit;
+mod text;
+
+pub mod adjust;
 
 /// Tree builder options, with an impl for Default.
 #[deriving(Clone)]
@@ -57,6 +77,59 @@ pub struct TreeBuilderOpts {
 
     /// Should we drop the DOCTYPE (if any) from the tree?
     pub drop_doctype: bool,
+
+    /// Log each (insertion mode, token) step, and each push, pop and
+    /// insertion on the stack of open elements, at info level through
+    /// the `log` crate?  Ignored when built as a C library.
+    /// Default: false
+    pub trace: bool,
+
+    /// Check for accessibility issues (`<img>` without `alt`, unlabeled
+    /// `<label>`s, duplicate ids) and report them to `TreeSink::audit`?
+    /// Default: false
+    pub audit: bool,
+
+    /// Treat a self-closing tag like `<item/>` for an element which isn't
+    /// known to HTML as an empty element, the way XML would, rather than
+    /// opening an element that swallows the following content.  This
+    /// gives saner trees for XML snippets pasted into HTML.  Not
+    /// spec-conforming.  Default: false
+    pub xml_islands: bool,
+
+    /// Report each (insertion mode, token, outcome) step to
+    /// `TreeSink::step_taken`?  This costs a copy of every token.
+    /// Default: false
+    pub report_steps: bool,
+
+    /// Insert a comment with the code of each parse error into the tree,
+    /// where the error was detected, e.g. `<!--parse error:
+    /// duplicate-attribute-->`.  Useful for seeing where problems are
+    /// when the serialized tree is viewed.  Default: false
+    pub error_comments: bool,
+
+    /// Drop every end tag for a void element, such as `</br>` or
+    /// `</img>`, with a parse error, and report it to
+    /// `TreeSink::void_end_tag_ignored`.  Without this, `</br>` becomes
+    /// a `<br>` element, as the spec says.  Default: false
+    pub report_void_end_tags: bool,
+
+    /// Maximum number of elements, counting `<html>`, that a node can be
+    /// nested in.  At that depth, start tags are dropped, except for void
+    /// elements, so text and other nodes go into the deepest element
+    /// allowed, alongside its other children.  A parse error is reported
+    /// the first time.  This keeps a hostile document such as a million
+    /// nested `<div>`s from making a tree too deep for recursive code,
+    /// like a serializer or a destructor, to walk, or a stack of open
+    /// elements that grows with the input.  Default: None (no limit)
+    pub max_tree_depth: Option<uint>,
+
+    /// Split text into whitespace and the rest wherever the spec says
+    /// to?  If false, text after `</body>` is kept whole, and text in a
+    /// frameset has its whitespace picked out in one pass, with a single
+    /// parse error for the rest.  The tree is the same, but there is less
+    /// copying when a lot of text is in those places.  Compare with the
+    /// `parse ... (fast text)` benchmarks.  Default: true
+    pub exact_text_splitting: bool,
 }
 
 impl Default for TreeBuilderOpts {
@@ -67,6 +140,14 @@ impl Default for TreeBuilderOpts {
             iframe_srcdoc: false,
             fragment: false,
             drop_doctype: false,
+            trace: false,
+            audit: false,
+            xml_islands: false,
+            report_steps: false,
+            error_comments: false,
+            report_void_end_tags: false,
+            max_tree_depth: None,
+            exact_text_splitting: true,
         }
     }
 }
@@ -85,6 +166,9 @@ pub struct TreeBuilder<'sink, Handle, Sink:'sink> {
     /// Original insertion mode, used by Text and InTableText modes.
     orig_mode: Option<InsertionMode>,
 
+    /// Stack of template insertion modes, most recently added at end.
+    template_modes: Vec<InsertionMode>,
+
     /// Pending table character tokens.
     pending_table_text: Vec<(SplitStatus, String)>,
 
@@ -109,6 +193,9 @@ pub struct TreeBuilder<'sink, Handle, Sink:'sink> {
     form_elem: Option<Handle>,
     //§ END
 
+    /// The context element, if we're parsing a fragment.
+    context_elem: Option<Handle>,
+
     /// Next state change for the tokenizer, if any.
     next_tokenizer_state: Option<tokenizer::states::State>,
 
@@ -120,6 +207,20 @@ pub struct TreeBuilder<'sink, Handle, Sink:'sink> {
 
     /// Is foster parenting enabled?
     foster_parenting: bool,
+
+    /// Accessibility checker, if `opts.audit` is set.
+    auditor: Option<Auditor<Handle>>,
+
+    /// The start of a comment which the tokenizer delivered in chunks.
+    /// We only create the comment node once we've seen all of it.
+    comment_chunks: String,
+
+    /// Did the sink pause parsing at a `</script>`?  The tokenizer stays
+    /// paused until `script_completed`.
+    script_paused: bool,
+
+    /// Have we hit `opts.max_tree_depth`?  Used to report the error once.
+    depth_limited: bool,
 }
 
 impl<'sink, Handle: Clone, Sink: TreeSink<Handle>> TreeBuilder<'sink, Handle, Sink> {
@@ -128,11 +229,13 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>> TreeBuilder<'sink, Handle, Si
     /// The tree builder is also a `TokenSink`.
     pub fn new(sink: &'sink mut Sink, opts: TreeBuilderOpts) -> TreeBuilder<'sink, Handle, Sink> {
         let doc_handle = sink.get_document();
+        let auditor = if opts.audit { Some(Auditor::new()) } else { None };
         TreeBuilder {
             opts: opts,
             sink: sink,
             mode: Initial,
             orig_mode: None,
+            template_modes: vec!(),
             pending_table_text: vec!(),
             quirks_mode: NoQuirks,
             doc_handle: doc_handle,
@@ -140,10 +243,71 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>> TreeBuilder<'sink, Handle, Si
             active_formatting: vec!(),
             head_elem: None,
             form_elem: None,
+            context_elem: None,
             next_tokenizer_state: None,
             frameset_ok: true,
             ignore_lf: false,
             foster_parenting: false,
+            auditor: auditor,
+            comment_chunks: String::new(),
+            script_paused: false,
+            depth_limited: false,
+        }
+    }
+
+    /// Create a new tree builder for parsing a fragment, as for `innerHTML`.
+    ///
+    /// `context_elem` is the element whose contents we are parsing; it is
+    /// never inserted into the tree.  `form_elem` should be the nearest
+    /// `<form>` ancestor of the context element (including itself), if any.
+    ///
+    /// The fragment's nodes become children of a new root `<html>` element.
+    pub fn new_for_fragment(sink: &'sink mut Sink,
+                            context_elem: Handle,
+                            form_elem: Option<Handle>,
+                            mut opts: TreeBuilderOpts) -> TreeBuilder<'sink, Handle, Sink> {
+        opts.fragment = true;
+        let mut tb = TreeBuilder::new(sink, opts);
+        let context_elem_copy = context_elem.clone();
+        tb.context_elem = Some(context_elem);
+        tb.form_elem = form_elem;
+        tb.create_root(vec!(), true);
+        if tb.sink.elem_name(context_elem_copy) == qualname!(HTML, template) {
+            tb.template_modes.push(InTemplate);
+        }
+        tb.mode = tb.reset_insertion_mode();
+        tb
+    }
+
+    /// The script which the sink paused parsing for has finished.  Call
+    /// `Tokenizer::resume` to carry on.
+    pub fn script_completed(&mut self) {
+        self.script_paused = false;
+    }
+
+    /// The tokenizer state to start in when parsing a fragment, based on
+    /// the context element.  `Data` if we aren't parsing a fragment.
+    pub fn tokenizer_state_for_context_elem(&self) -> tokenizer::states::State {
+        use tokenizer::states::{Data, Plaintext, RawData, Rcdata, Rawtext, ScriptData};
+
+        let elem = unwrap_or_return!(self.context_elem.as_ref(), Data);
+        let name = match self.sink.elem_name(elem.clone()) {
+            QualName { ns: ns!(HTML), local } => local,
+            _ => return Data,
+        };
+        match name {
+            atom!(title) | atom!(textarea) => RawData(Rcdata),
+
+            atom!(style) | atom!(xmp) | atom!(iframe)
+                | atom!(noembed) | atom!(noframes) => RawData(Rawtext),
+
+            atom!(script) => RawData(ScriptData),
+
+            atom!(noscript) if self.opts.scripting_enabled => RawData(Rawtext),
+
+            atom!(plaintext) => Plaintext,
+
+            _ => Data,
         }
     }
 
@@ -151,8 +315,6 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>> TreeBuilder<'sink, Handle, Si
     #[cfg(not(for_c))]
     #[allow(dead_code)]
     fn dump_state(&self, label: String) {
-        use string_cache::QualName;
-
         println!("dump_state on {}", label);
         print!("    open_elems:");
         for node in self.open_elems.iter() {
@@ -173,6 +335,9 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>> TreeBuilder<'sink, Handle, Si
     fn debug_step(&self, mode: InsertionMode, token: &Token) {
         use util::str::to_escaped_string;
         h5e_debug!("processing {} in insertion mode {:?}", to_escaped_string(token), mode);
+        if self.opts.trace {
+            h5e_info!("tree builder: {} in {}", to_escaped_string(token), mode);
+        }
     }
 
     fn process_to_completion(&mut self, mut token: Token) {
@@ -186,10 +351,29 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>> TreeBuilder<'sink, Handle, Si
                 _ => false,
             };
             let mode = self.mode;
-            match self.step(mode, token) {
+            let reported = if self.opts.report_steps { Some(token.clone()) } else { None };
+            let result = if self.is_foreign(&token) {
+                self.step_foreign(token)
+            } else {
+                self.step(mode, token)
+            };
+            match reported {
+                Some(token) => {
+                    let outcome = match result {
+                        Done => StepDone,
+                        DoneAckSelfClosing => StepAckSelfClosing,
+                        SplitWhitespace(_) => StepSplitWhitespace,
+                        Reprocess(m, _) => StepReprocess(m),
+                    };
+                    self.sink.step_taken(mode, &token, outcome);
+                }
+                None => (),
+            }
+            match result {
                 Done => {
                     if is_self_closing {
-                        self.sink.parse_error(Slice("Unacknowledged self-closing tag"));
+                        self.error_of_kind(tokenizer::NonVoidHtmlElementStartTagWithTrailingSolidus,
+                            Slice("Unacknowledged self-closing tag"));
                     }
                     token = unwrap_or_return!(more_tokens.pop_front(), ());
                 }
@@ -201,18 +385,20 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>> TreeBuilder<'sink, Handle, Si
                     token = t;
                 }
                 SplitWhitespace(buf) => {
-                    let buf = buf.as_slice();
-
                     let (len, is_ws) = unwrap_or_return!(
-                        char_run(is_ascii_whitespace, buf), ());
-
-                    token = CharacterTokens(
-                        if is_ws { Whitespace } else { NotWhitespace },
-                        String::from_str(buf.slice_to(len)));
+                        char_run(is_ascii_whitespace, buf.as_slice()), ());
+                    let status = if is_ws { Whitespace } else { NotWhitespace };
 
-                    if len < buf.len() {
-                        more_tokens.push(
-                            CharacterTokens(NotSplit, String::from_str(buf.slice_from(len))));
+                    // Only the modes before <body> and around tables ask for
+                    // a split, and usually the text is all one kind.  Don't
+                    // copy it in that case.
+                    if len == buf.len() {
+                        token = CharacterTokens(status, buf);
+                    } else {
+                        token = CharacterTokens(status,
+                            String::from_str(buf.as_slice().slice_to(len)));
+                        more_tokens.push(CharacterTokens(NotSplit,
+                            String::from_str(buf.as_slice().slice_from(len))));
                     }
                 }
             }
@@ -222,51 +408,116 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>> TreeBuilder<'sink, Handle, Si
 
 impl<'sink, Handle: Clone, Sink: TreeSink<Handle>> TokenSink for TreeBuilder<'sink, Handle, Sink> {
     fn process_token(&mut self, token: tokenizer::Token) {
-        let ignore_lf = replace(&mut self.ignore_lf, false);
+        // A parse error isn't a token as far as the tree construction
+        // rules are concerned, so it mustn't use up `ignore_lf`.
+        let ignore_lf = match token {
+            tokenizer::ParseError(_) => false,
+            _ => replace(&mut self.ignore_lf, false),
+        };
 
         // Handle `ParseError` and `DoctypeToken`; convert everything else to the local `Token` type.
         let token = match token {
             tokenizer::ParseError(e) => {
-                self.sink.parse_error(e);
+                self.error_of_kind(tokenizer::OtherError, e);
                 return;
             }
 
             tokenizer::DoctypeToken(dt) => if self.mode == Initial {
                 let (err, quirk) = data::doctype_error_and_quirks(&dt, self.opts.iframe_srcdoc);
                 if err {
-                    self.sink.parse_error(format_if!(
+                    let msg = format_if!(
                         self.opts.exact_errors,
                         "Bad DOCTYPE",
-                        "Bad DOCTYPE: {}", dt));
+                        "Bad DOCTYPE: {}", dt);
+                    self.parse_error(msg);
                 }
-                let Doctype { name, public_id, system_id, force_quirks: _ } = dt;
+                let Doctype { name, public_id, system_id, force_quirks: _, raw } = dt;
                 if !self.opts.drop_doctype {
-                    self.sink.append_doctype_to_document(
-                        name.unwrap_or(String::new()),
-                        public_id.unwrap_or(String::new()),
-                        system_id.unwrap_or(String::new())
-                    );
+                    let name = name.unwrap_or(String::new());
+                    let public_id = public_id.unwrap_or(String::new());
+                    let system_id = system_id.unwrap_or(String::new());
+                    match raw {
+                        Some(raw) => self.sink.append_raw_doctype_to_document(
+                            name, public_id, system_id, raw),
+                        None => self.sink.append_doctype_to_document(
+                            name, public_id, system_id),
+                    }
                 }
                 self.set_quirks_mode(quirk);
 
                 self.mode = BeforeHtml;
                 return;
             } else {
-                self.sink.parse_error(format_if!(
+                let msg = format_if!(
                     self.opts.exact_errors,
                     "DOCTYPE in body",
-                    "DOCTYPE in insertion mode {:?}", self.mode));
+                    "DOCTYPE in insertion mode {:?}", self.mode);
+                self.parse_error(msg);
                 return;
             },
 
-            tokenizer::TagToken(x) => TagToken(x),
-            tokenizer::CommentToken(x) => CommentToken(x),
-            tokenizer::NullCharacterToken => NullCharacterToken,
+            tokenizer::TagToken(x) => if self.opts.report_void_end_tags
+                    && x.kind == tokenizer::EndTag
+                    && !self.chars_in_foreign_content()
+                    && tag_sets::void_elements(QualName::new(ns!(HTML), x.name.clone())) {
+                let msg = format_if!(
+                    self.opts.exact_errors,
+                    "End tag for void element",
+                    "End tag for void element {}", x.name.as_slice());
+                self.parse_error(msg);
+                self.sink.void_end_tag_ignored(x.name);
+                return;
+            } else if x.kind == tokenizer::StartTag && self.at_depth_limit()
+                    && !tag_sets::void_elements(QualName::new(ns!(HTML), x.name.clone())) {
+                // Any other start tag would push an element.  Dropping the
+                // whole tag, rather than just the push, keeps the insertion
+                // mode in step with the stack.
+                self.report_depth_limit();
+                return;
+            } else {
+                TagToken(x)
+            },
+            // The sink takes a comment's text all at once, so memory for
+            // it isn't bounded here.  See `TokenizerOpts::comment_chunk_len`.
+            tokenizer::CommentChunkToken(x) => {
+                self.comment_chunks.push_str(x.as_slice());
+                return;
+            }
+            tokenizer::CommentToken(x) => if self.comment_chunks.is_empty() {
+                CommentToken(x)
+            } else {
+                let mut text = replace(&mut self.comment_chunks, String::new());
+                text.push_str(x.as_slice());
+                CommentToken(text)
+            },
+            // The HTML insertion modes ignore U+0000, but foreign content
+            // keeps it as a replacement character.
+            tokenizer::NullCharacterToken => if self.chars_in_foreign_content() {
+                self.parse_error(Slice("Null character in foreign content"));
+                CharacterTokens(NotSplit, String::from_char(1, '\ufffd'))
+            } else {
+                NullCharacterToken
+            },
             tokenizer::EOFToken => EOFToken,
 
             tokenizer::CharacterTokens(mut x) => {
-                if ignore_lf && x.len() >= 1 && x.as_slice().char_at(0) == '\n' {
-                    x.remove(0);
+                if ignore_lf && x.len() >= 1 {
+                    match x.as_slice().char_at(0) {
+                        '\n' => { x.remove(0); }
+
+                        // The tokenizer may be keeping CRs.  A CR on its own
+                        // might be followed by LF in the next token.
+                        '\r' => {
+                            x.remove(0);
+                            if x.is_empty() {
+                                self.ignore_lf = true;
+                            } else if x.as_slice().char_at(0) == '\n' {
+                                x.remove(0);
+                            }
+                        }
+
+                        _ => (),
+                    }
                 }
                 if x.is_empty() {
                     return;
@@ -278,7 +529,648 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>> TokenSink for TreeBuilder<'si
         self.process_to_completion(token);
     }
 
+    fn classified_parse_error(&mut self, kind: tokenizer::ParseErrorKind,
+                              msg: MaybeOwned<'static>) {
+        self.error_of_kind(kind, msg);
+    }
+
     fn query_state_change(&mut self) -> Option<tokenizer::states::State> {
         self.next_tokenizer_state.take()
     }
+
+    fn aborted(&self) -> bool {
+        self.sink.aborted()
+    }
+
+    fn paused(&self) -> bool {
+        self.script_paused
+    }
+
+    // Tree construction needs every attribute.
+    fn wants_attrs(&mut self, _kind: tokenizer::TagKind, _name: &str) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+
+    use sink::common::{Text, Element};
+    use sink::rcdom::{RcDom, Handle};
+    use driver::{parse, one_input, ParseOpts};
+    use driver::{parse_to, parse_fragment};
+    use serialize::{serialize, SerializeOpts};
+    use tokenizer::{Attribute, Tokenizer, ParseErrorKind, DuplicateAttribute};
+    use tokenizer::{NonVoidHtmlElementStartTagWithTrailingSolidus, TreeConstructionError};
+    use super::{TreeBuilder, TreeBuilderOpts, TreeSink, QuirksMode, NodeOrText, AppendText};
+    use super::AppendNode;
+    use super::ElemProvenance;
+    use super::{Quirks, LimitedQuirks, NoQuirks};
+    use super::{InsertionMode, ALL_INSERTION_MODES, InTableBody, InHeadNoscript};
+    use super::{ScriptAction, Continue, Pause};
+    use super::{SplitStatus, NotSplit, Whitespace, NotWhitespace};
+    use super::{AuditIssue, ImgWithoutAlt, UnlabeledLabel, LabelForMissingId, DuplicateId};
+
+    use core::default::Default;
+    use collections::MutableSeq;
+    use collections::vec::Vec;
+    use collections::string::String;
+    use collections::str::MaybeOwned;
+    use std::io::MemWriter;
+
+    use string_cache::{Atom, QualName};
+
+    fn child(node: Handle, i: uint) -> Handle {
+        node.borrow().children[i].clone()
+    }
+
+    fn textarea_value(chunks: &[&str]) -> String {
+        let input: Vec<String> = chunks.iter().map(|s| String::from_str(*s)).collect();
+        let dom: RcDom = parse(input.into_iter(), Default::default());
+        let textarea = child(child(child(dom.document, 0), 1), 0);
+
+        let mut value = String::new();
+        for node in textarea.borrow().children.iter() {
+            match node.borrow().node {
+                Text(ref t) => value.push_str(t.as_slice()),
+                _ => fail!("textarea has a non-text child"),
+            }
+        }
+        value
+    }
+
+    #[test]
+    fn textarea_first_newline() {
+        assert_eq!(textarea_value(["<textarea>\nx</textarea>"]).as_slice(), "x");
+        assert_eq!(textarea_value(["<textarea>\n\nx</textarea>"]).as_slice(), "\nx");
+        assert_eq!(textarea_value(["<textarea>x\n</textarea>"]).as_slice(), "x\n");
+        assert_eq!(textarea_value(["<textarea> \nx</textarea>"]).as_slice(), " \nx");
+    }
+
+    #[test]
+    fn textarea_first_newline_split() {
+        assert_eq!(textarea_value(["<textarea>", "\nx"]).as_slice(), "x");
+        assert_eq!(textarea_value(["<textarea>\n", "\nx"]).as_slice(), "\nx");
+        assert_eq!(textarea_value(["<textarea>\r", "\nx"]).as_slice(), "x");
+        assert_eq!(textarea_value(["<textarea>\r", "\r\nx"]).as_slice(), "\nx");
+        assert_eq!(textarea_value(["<texta", "rea>", "\n", "x"]).as_slice(), "x");
+    }
+
+    #[test]
+    fn textarea_first_newline_char_ref() {
+        // A newline from a character reference is still the first
+        // token after the start tag, so it's dropped too.
+        assert_eq!(textarea_value(["<textarea>&#10;x"]).as_slice(), "x");
+        assert_eq!(textarea_value(["<textarea>&#10;&#10;x"]).as_slice(), "\nx");
+        assert_eq!(textarea_value(["<textarea>&amp;\nx"]).as_slice(), "&\nx");
+    }
+
+    fn body_with_void_end_tags(input: &str, report: bool) -> (String, uint) {
+        let dom: RcDom = parse(one_input(String::from_str(input)), ParseOpts {
+            tree_builder: TreeBuilderOpts {
+                report_void_end_tags: report,
+                .. Default::default()
+            },
+            .. Default::default()
+        });
+        let body = child(child(dom.document.clone(), 0), 1);
+        let mut out = MemWriter::new();
+        serialize(&mut out, &body, Default::default()).ok().expect("write failed");
+        (String::from_utf8(out.unwrap()).unwrap(), dom.errors.len())
+    }
+
+    #[test]
+    fn void_end_tags() {
+        let (out, _) = body_with_void_end_tags("<p>a</br>b</img>c</p>", false);
+        assert_eq!(out.as_slice(), "<p>a<br>bc</p>");
+
+        let (out, errors) = body_with_void_end_tags("<!DOCTYPE html><p>a</br>b</img>c</p>", true);
+        assert_eq!(out.as_slice(), "<p>abc</p>");
+        assert_eq!(errors, 2);
+    }
+
+    #[test]
+    fn max_tree_depth() {
+        let dom: RcDom = parse(one_input(String::from_str(
+            "<!DOCTYPE html><div><div><div><div>x</div></div></div></div>y")), ParseOpts {
+            tree_builder: TreeBuilderOpts {
+                max_tree_depth: Some(3),
+                .. Default::default()
+            },
+            .. Default::default()
+        });
+        let body = child(child(dom.document.clone(), 1), 1);
+        let mut out = MemWriter::new();
+        serialize(&mut out, &body, Default::default()).ok().expect("write failed");
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap().as_slice(),
+            "<div>x</div>y");
+        // The depth limit, and three end tags for dropped start tags.
+        assert_eq!(dom.errors.len(), 4);
+    }
+
+    #[test]
+    fn max_tree_depth_bounds_stack() {
+        let stack_len = |limit: Option<uint>| {
+            let mut sink = TextStatusSink::new();
+            let mut tb = TreeBuilder::new(&mut sink, TreeBuilderOpts {
+                max_tree_depth: limit,
+                .. Default::default()
+            });
+            let mut tok = Tokenizer::new(&mut tb, Default::default());
+            for _ in range(0, 1000u) {
+                tok.feed(String::from_str("<div><br><b>x"));
+            }
+            let len = tok.sink_mut().open_elems.len();
+            len
+        };
+        // html, body, then a div and a b for each chunk.
+        assert_eq!(stack_len(None), 2002);
+        assert_eq!(stack_len(Some(16)), 16);
+    }
+
+    fn body_with_opts(input: &str, opts: TreeBuilderOpts) -> (String, uint) {
+        let dom: RcDom = parse(one_input(String::from_str(input)), ParseOpts {
+            tree_builder: opts,
+            .. Default::default()
+        });
+        let body = child(child(dom.document.clone(), 1), 1);
+        let mut out = MemWriter::new();
+        serialize(&mut out, &body, Default::default()).ok().expect("write failed");
+        (String::from_utf8(out.unwrap()).unwrap(), dom.errors.len())
+    }
+
+    #[test]
+    fn xml_islands() {
+        let input = "<!DOCTYPE html><p><item/>a<item>b</item><legend/>c<span/>d";
+
+        let (out, errors) = body_with_opts(input, Default::default());
+        assert_eq!(out.as_slice(),
+            "<p><item>a<item>b</item><legend>c<span>d</span></legend></item></p>");
+        assert_eq!(errors, 4);
+
+        // Only the unknown element is left empty.  Known ones still get
+        // the error, and swallow what follows.
+        let (out, errors) = body_with_opts(input, TreeBuilderOpts {
+            xml_islands: true,
+            .. Default::default()
+        });
+        assert_eq!(out.as_slice(),
+            "<p><item></item>a<item>b</item><legend>c<span>d</span></legend></p>");
+        assert_eq!(errors, 3);
+    }
+
+    #[test]
+    fn error_comments() {
+        // One error from the tokenizer, and one from the tree builder
+        // once the <b> has been closed.
+        let (out, errors) = body_with_opts("<!DOCTYPE html><p><b a=1 a=2>x</p>", TreeBuilderOpts {
+            error_comments: true,
+            .. Default::default()
+        });
+        assert_eq!(out.as_slice(), concat!(
+            "<p><!--parse error: duplicate-attribute--><b a=\"1\">x</b></p>",
+            "<!--parse error: x-tree-construction-->"));
+        assert_eq!(errors, 2);
+    }
+
+    #[test]
+    fn foreign_content() {
+        let dom: RcDom = parse(one_input(String::from_str(concat!(
+            "<svg viewbox='0 0 1 1' xlink:href=#a><foreignobject><p>x</p></foreignobject>",
+            "<clippath/></svg><math definitionurl=u><mi>y</mi><p>z"))), Default::default());
+        let body = child(child(dom.document.clone(), 0), 1);
+        let mut out = MemWriter::new();
+        serialize(&mut out, &body, Default::default()).ok().expect("write failed");
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap().as_slice(), concat!(
+            "<svg viewBox=\"0 0 1 1\" xlink:href=\"#a\"><foreignObject><p>x</p></foreignObject>",
+            "<clipPath></clipPath></svg><math definitionURL=\"u\"><mi>y</mi></math><p>z</p>"));
+
+        let svg = child(body, 0);
+        match svg.borrow().node {
+            Element(ref name, ref attrs) => {
+                assert_eq!(*name, QualName::new(ns!(SVG), atom!(svg)));
+                assert_eq!(attrs[1].name, QualName::new(ns!(XLink), atom!(href)));
+            }
+            _ => fail!("expected an element"),
+        };
+    }
+
+    #[test]
+    fn foreign_breakout() {
+        // Both end tags break out at an integration point, which leaves
+        // nothing to pop.
+        assert_eq!(reserialize("<math><mi></p>", false).as_slice(),
+            "<html><head></head><body><math><mi><p></p></mi></math></body></html>");
+        assert_eq!(reserialize("<svg><desc></br>", false).as_slice(),
+            "<html><head></head><body><svg><desc><br></desc></svg></body></html>");
+
+        // In the fragment case, breakout tags are foreign like any other.
+        let dom: RcDom = parse_fragment(one_input(String::from_str("<p>a</p><b>c")),
+            QualName::new(ns!(SVG), atom!(svg)), Default::default());
+        let root = child(dom.document.clone(), 0);
+        let mut out = MemWriter::new();
+        serialize(&mut out, &root, Default::default()).ok().expect("write failed");
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap().as_slice(), "<p>a</p><b>c</b>");
+        let b = child(root, 1);
+        match b.borrow().node {
+            Element(ref name, _) => assert_eq!(name.ns, ns!(SVG)),
+            _ => fail!("expected an element"),
+        };
+    }
+
+    #[test]
+    fn image_start_tag() {
+        let dom: RcDom = parse(one_input(String::from_str(
+            "<!DOCTYPE html><image src=x><svg><image/></svg>")), Default::default());
+        // Only the HTML one is renamed, and that's a parse error.
+        assert_eq!(dom.errors.len(), 1);
+
+        let body = child(child(dom.document.clone(), 0), 1);
+        let names: Vec<QualName> = body.borrow().children.iter().map(|c| match c.borrow().node {
+            Element(ref name, _) => name.clone(),
+            _ => fail!("expected an element"),
+        }).collect();
+        assert_eq!(names, vec!(qualname!(HTML, img), qualname!(SVG, svg)));
+        match child(child(body, 1), 0).borrow().node {
+            Element(ref name, _) => assert_eq!(*name, qualname!(SVG, image)),
+            _ => fail!("expected an element"),
+        }
+    }
+
+    fn reserialize(input: &str, omit: bool) -> String {
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        let mut out = MemWriter::new();
+        serialize(&mut out, &dom, SerializeOpts {
+            omit_optional_tags: omit,
+            .. Default::default()
+        }).ok().expect("write failed");
+        String::from_utf8(out.unwrap()).unwrap()
+    }
+
+    fn reserialize_splitting(input: &str, exact: bool) -> (String, uint) {
+        let dom: RcDom = parse(one_input(String::from_str(input)), ParseOpts {
+            tree_builder: TreeBuilderOpts {
+                exact_text_splitting: exact,
+                .. Default::default()
+            },
+            .. Default::default()
+        });
+        let mut out = MemWriter::new();
+        serialize(&mut out, &dom, Default::default()).ok().expect("write failed");
+        (String::from_utf8(out.unwrap()).unwrap(), dom.errors.len())
+    }
+
+    #[test]
+    fn exact_text_splitting() {
+        // After </body>, the text goes in <body> either way.
+        let input = "<!DOCTYPE html><p>a</p></body> b c</html> d";
+        let (exact, exact_errors) = reserialize_splitting(input, true);
+        assert_eq!(reserialize_splitting(input, false), (exact, exact_errors));
+
+        // In a frameset, the whitespace is kept, with one error per token
+        // rather than one per run of other characters.
+        let input = "<!DOCTYPE html><frameset> a b <frame> c</frameset>x y";
+        let (exact, exact_errors) = reserialize_splitting(input, true);
+        assert_eq!(exact.as_slice(), concat!("<!DOCTYPE html><html><head></head>",
+            "<frameset>   <frame> </frameset> </html>"));
+        assert_eq!(exact_errors, 5);
+        assert_eq!(reserialize_splitting(input, false), (exact, 3));
+    }
+
+    #[test]
+    fn comments_after_body() {
+        // A comment after </body> goes in <html>, and one after </html> in
+        // the document.  Whitespace still goes in <body>.
+        let input = "<p>a</p></body><!--x-->\n</html><!--y-->\n<!--z-->";
+        let full = "<html><head></head><body><p>a</p>\n\n</body><!--x--></html><!--y--><!--z-->";
+        assert_eq!(reserialize(input, false).as_slice(), full);
+
+        // Leaving out optional tags mustn't move the comments when the
+        // output is parsed again.
+        let short = reserialize(input, true);
+        assert_eq!(short.as_slice(), "<html><head><body><p>a</p>\n\n</body><!--x--></html><!--y--><!--z-->");
+        assert_eq!(reserialize(short.as_slice(), false).as_slice(), full);
+
+        assert_eq!(reserialize("<frameset></frameset><!--x--></html><!--y-->", false).as_slice(),
+            "<html><head></head><frameset></frameset><!--x--></html><!--y-->");
+    }
+
+    #[test]
+    fn misnested_formatting() {
+        let body = |input: &str| {
+            let out = reserialize(input, false);
+            String::from_str(out.as_slice()
+                .slice_from("<html><head></head><body>".len())
+                .slice_to(out.len() - "<html><head></head><body></body></html>".len()))
+        };
+
+        // Adoption agency, with a furthest block.
+        assert_eq!(body("<b>1<p>2</b>3</p>").as_slice(), "<b>1</b><p><b>2</b>3</p>");
+        assert_eq!(body("<a><div><b>1<p>2</a>3").as_slice(),
+            "<a></a><div><a><b>1</b></a><b><p><a>2</a>3</p></b></div>");
+
+        // Without one, and then reconstructing the formatting elements.
+        assert_eq!(body("<b><i>1</b>2</i>3").as_slice(), "<b><i>1</i></b><i>2</i>3");
+        assert_eq!(body("<a href=x>1<a>2").as_slice(), "<a href=\"x\">1</a><a>2</a>");
+    }
+
+    #[test]
+    fn foster_parenting() {
+        assert_eq!(reserialize("<table><b>x</b><tr><td>y", false).as_slice(),
+            "<html><head></head><body><b>x</b><table><tbody><tr><td>y</td></tr></tbody></table></body></html>");
+
+        // The default append_based_on_parent_node, with and without a
+        // parent for the table.
+        let children = |node: &Handle| {
+            let mut out = MemWriter::new();
+            serialize(&mut out, node, Default::default()).ok().expect("write failed");
+            String::from_utf8(out.unwrap()).unwrap()
+        };
+        let mut sink: RcDom = Default::default();
+        let div = sink.create_element(qualname!(HTML, div), vec!());
+        let table = sink.create_element(qualname!(HTML, table), vec!());
+        sink.append(div.clone(), AppendNode(table.clone()));
+
+        let p = sink.create_element(qualname!(HTML, p), vec!());
+        sink.append_based_on_parent_node(table.clone(), div.clone(), AppendNode(p));
+        assert_eq!(children(&div).as_slice(), "<p></p><table></table>");
+
+        sink.remove_from_parent(table.clone());
+        let span = sink.create_element(qualname!(HTML, span), vec!());
+        sink.append_based_on_parent_node(table.clone(), div.clone(), AppendNode(span));
+        assert_eq!(children(&div).as_slice(), "<p></p><span></span>");
+        assert_eq!(children(&table).as_slice(), "");
+    }
+
+    #[test]
+    fn templates() {
+        // Table parts are fine directly in a template.
+        assert_eq!(reserialize("<template><tr><td>x</template>", false).as_slice(),
+            "<html><head><template><tr><td>x</td></tr></template></head><body></body></html>");
+
+        // Text goes in the contents, alongside comments.
+        assert_eq!(reserialize("<template>text</template>", false).as_slice(),
+            "<html><head><template>text</template></head><body></body></html>");
+        assert_eq!(reserialize("<template>x<!--c--></template>", false).as_slice(),
+            "<html><head><template>x<!--c--></template></head><body></body></html>");
+
+        // Nothing is foster parented out of a template in a table.
+        assert_eq!(reserialize("<table><template><p>a</template></table>", false).as_slice(),
+            "<html><head></head><body><table><template><p>a</p></template></table></body></html>");
+
+        // A form in a template doesn't set the form element pointer.
+        assert_eq!(reserialize("<template><form></form></template><form>", false).as_slice(),
+            "<html><head><template><form></form></template></head><body><form></form></body></html>");
+    }
+
+    /// Records the text appended, and the status if we're told it.
+    struct TextStatusSink {
+        names: Vec<QualName>,
+        texts: Vec<(String, Option<SplitStatus>)>,
+        pause_scripts: bool,
+        quirks_override: Option<QuirksMode>,
+        quirks_mode: Option<QuirksMode>,
+        errors: Vec<ParseErrorKind>,
+        audits: Vec<AuditIssue<uint>>,
+        provenance: Vec<(uint, ElemProvenance)>,
+    }
+
+    impl TextStatusSink {
+        fn new() -> TextStatusSink {
+            TextStatusSink {
+                names: vec!(QualName::new(ns!(""), Atom::from_slice(""))),
+                texts: vec!(),
+                pause_scripts: false,
+                quirks_override: None,
+                quirks_mode: None,
+                errors: vec!(),
+                audits: vec!(),
+                provenance: vec!(),
+            }
+        }
+
+        fn new_node(&mut self, name: QualName) -> uint {
+            self.names.push(name);
+            self.names.len() - 1
+        }
+    }
+
+    impl TreeSink<uint> for TextStatusSink {
+        fn parse_error(&mut self, _msg: MaybeOwned<'static>) { }
+        fn get_document(&mut self) -> uint { 0 }
+
+        fn classified_parse_error(&mut self, kind: ParseErrorKind, _msg: MaybeOwned<'static>) {
+            self.errors.push(kind);
+        }
+
+        fn same_node(&self, x: uint, y: uint) -> bool { x == y }
+        fn elem_name(&self, target: uint) -> QualName { self.names[target].clone() }
+        fn get_template_contents(&mut self, target: uint) -> uint { target + 1 }
+
+        fn set_quirks_mode(&mut self, mode: QuirksMode) {
+            self.quirks_mode = Some(mode);
+        }
+
+        fn query_quirks_override(&mut self, _mode: QuirksMode) -> Option<QuirksMode> {
+            self.quirks_override
+        }
+
+        fn create_element(&mut self, name: QualName, _attrs: Vec<Attribute>) -> uint {
+            let is_template = name == qualname!(HTML, template);
+            let elem = self.new_node(name);
+            if is_template {
+                self.new_node(QualName::new(ns!(""), Atom::from_slice("")));
+            }
+            elem
+        }
+
+        fn create_comment(&mut self, _text: String) -> uint {
+            self.new_node(QualName::new(ns!(""), Atom::from_slice("")))
+        }
+
+        fn append(&mut self, _parent: uint, child: NodeOrText<uint>) {
+            match child {
+                AppendText(t) => self.texts.push((t, None)),
+                _ => (),
+            }
+        }
+
+        fn append_text_with_status(&mut self, _parent: uint, text: String, status: SplitStatus) {
+            self.texts.push((text, Some(status)));
+        }
+
+        fn append_before_sibling(&mut self, _sibling: uint, _new_node: NodeOrText<uint>)
+                -> Result<(), NodeOrText<uint>> {
+            Ok(())
+        }
+
+        fn append_doctype_to_document(&mut self, _name: String, _public: String, _system: String) { }
+        fn add_attrs_if_missing(&mut self, _target: uint, _attrs: Vec<Attribute>) { }
+        fn remove_from_parent(&mut self, _target: uint) { }
+        fn reparent_children(&mut self, _node: uint, _new_parent: uint) { }
+        fn mark_script_already_started(&mut self, _node: uint) { }
+
+        fn complete_script(&mut self, _node: uint) -> ScriptAction {
+            if self.pause_scripts { Pause } else { Continue }
+        }
+
+        fn audit(&mut self, issue: AuditIssue<uint>) {
+            self.audits.push(issue);
+        }
+
+        fn element_provenance(&mut self, elem: uint, provenance: ElemProvenance) {
+            self.provenance.push((elem, provenance));
+        }
+    }
+
+    #[test]
+    fn text_whitespace_status() {
+        let mut sink = TextStatusSink::new();
+        parse_to(&mut sink, one_input(String::from_str(
+            "<!DOCTYPE html><body>a <p> </p><select> x</select>")), Default::default());
+
+        let t = |s: &str, status| (String::from_str(s), Some(status));
+        assert_eq!(sink.texts, vec!(t("a ", NotWhitespace), t(" ", Whitespace), t(" x", NotSplit)));
+    }
+
+    // Parse a deeply nested document.  Any recursion on the depth of the
+    // tree would overflow the stack here.  RcDom's destructor recurses,
+    // so use a sink with flat storage.
+    fn parse_deep(open: &str, n: uint) -> TextStatusSink {
+        let mut input = String::with_capacity(open.len() * n + 1);
+        for _ in range(0, n) {
+            input.push_str(open);
+        }
+        input.push('x');
+
+        let mut sink = TextStatusSink::new();
+        parse_to(&mut sink, one_input(input), Default::default());
+        sink
+    }
+
+    fn quirks_mode(input: &str, quirks_override: Option<QuirksMode>,
+            opts: TreeBuilderOpts) -> Option<QuirksMode> {
+        let mut sink = TextStatusSink::new();
+        sink.quirks_override = quirks_override;
+        parse_to(&mut sink, one_input(String::from_str(input)), ParseOpts {
+            tree_builder: opts,
+            .. Default::default()
+        });
+        sink.quirks_mode
+    }
+
+    #[test]
+    fn quirks_override() {
+        let srcdoc = TreeBuilderOpts { iframe_srcdoc: true, .. Default::default() };
+        assert_eq!(quirks_mode("<p>", None, Default::default()), Some(Quirks));
+        assert_eq!(quirks_mode("<p>", Some(LimitedQuirks), Default::default()),
+            Some(LimitedQuirks));
+        assert_eq!(quirks_mode("<!DOCTYPE html>", Some(Quirks), Default::default()), Some(Quirks));
+
+        // Without a DOCTYPE, a srcdoc document is still asked about.
+        assert_eq!(quirks_mode("<p>", None, srcdoc.clone()), Some(NoQuirks));
+        assert_eq!(quirks_mode("<p>", Some(Quirks), srcdoc), Some(Quirks));
+    }
+
+    #[test]
+    fn error_kinds() {
+        let mut sink = TextStatusSink::new();
+        parse_to(&mut sink, one_input(String::from_str(
+            "<!DOCTYPE html><div/></div><b c=1 c=2></b></p>")), Default::default());
+        assert_eq!(sink.errors, vec!(NonVoidHtmlElementStartTagWithTrailingSolidus,
+            DuplicateAttribute, TreeConstructionError));
+    }
+
+    fn audit(input: &str) -> Vec<String> {
+        let mut sink = TextStatusSink::new();
+        parse_to(&mut sink, one_input(String::from_str(input)), ParseOpts {
+            tree_builder: TreeBuilderOpts {
+                audit: true,
+                .. Default::default()
+            },
+            .. Default::default()
+        });
+        sink.audits.iter().map(|issue| match *issue {
+            ImgWithoutAlt(e) => format!("no alt: {}", sink.names[e].local.as_slice()),
+            UnlabeledLabel(e) => format!("unlabeled: {}", sink.names[e].local.as_slice()),
+            LabelForMissingId(e, ref id) =>
+                format!("missing {}: {}", id, sink.names[e].local.as_slice()),
+            DuplicateId(e, ref id) =>
+                format!("duplicate {}: {}", id, sink.names[e].local.as_slice()),
+        }).collect()
+    }
+
+    #[test]
+    fn audit_issues() {
+        assert_eq!(audit("<img src=x><img alt=''>"), vec!(String::from_str("no alt: img")));
+        assert_eq!(audit("<label>a</label><label>b <input></label><label><input type=hidden>"),
+            vec!(String::from_str("unlabeled: label"), String::from_str("unlabeled: label")));
+        assert_eq!(audit("<label for=a>x</label><label for=b>y</label><input id=b>"),
+            vec!(String::from_str("missing a: label")));
+        assert_eq!(audit("<p id=x><span id=y><i id=x>"),
+            vec!(String::from_str("duplicate x: i")));
+
+        // The <a> reconstructed in the second paragraph is the same one
+        // as in the source, not a second element with that id.
+        assert_eq!(audit("<p><a id=x>1<p>2"), vec!());
+    }
+
+    #[test]
+    fn element_provenance() {
+        let mut sink = TextStatusSink::new();
+        parse_to(&mut sink, one_input(String::from_str(
+            "<table><tr><td>x</td></tr><div>y</div></table>")), Default::default());
+        let flags: Vec<(&str, bool, bool)> = sink.provenance.iter()
+            .map(|&(e, ref p)| (sink.names[e].local.as_slice(), p.implied, p.fostered))
+            .collect();
+        assert_eq!(flags, vec!(
+            ("html", true, false), ("head", true, false), ("body", true, false),
+            ("table", false, false), ("tbody", true, false), ("tr", false, false),
+            ("td", false, false), ("div", false, true)));
+        assert!(sink.provenance.iter().all(|&(_, ref p)| p.parser_inserted));
+    }
+
+    #[test]
+    fn insertion_mode_names() {
+        for mode in ALL_INSERTION_MODES.iter() {
+            assert_eq!(InsertionMode::from_name(mode.name()), Some(mode.clone()));
+        }
+        assert_eq!(InTableBody.name(), "in-table-body");
+        assert_eq!(format!("{}", InHeadNoscript).as_slice(), "in-head-noscript");
+        assert_eq!(InsertionMode::from_name("InBody"), None);
+    }
+
+    #[test]
+    fn document_write() {
+        let mut sink = TextStatusSink::new();
+        sink.pause_scripts = true;
+        {
+            let mut tb = TreeBuilder::new(&mut sink, Default::default());
+            let mut tok = Tokenizer::new(&mut tb, Default::default());
+            tok.feed(String::from_str("<script>1</script><b>"));
+            assert!(tok.is_paused());
+
+            // Both writes go before the <b>, in the order they were made.
+            tok.insert_at_current_position(String::from_str("<i>"));
+            tok.insert_at_current_position(String::from_str("<u>"));
+            tok.end();
+            tok.sink_mut().script_completed();
+            tok.resume();
+            assert!(!tok.is_paused());
+        }
+        let names: Vec<&str> = sink.names.iter().map(|n| n.local.as_slice()).collect();
+        assert_eq!(names.slice_from(names.len() - 4), vec!("script", "i", "u", "b").as_slice());
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let sink = parse_deep("<span>", 100000);
+        assert!(sink.names.len() > 100000);
+        assert_eq!(sink.texts.len(), 1);
+
+        let sink = parse_deep("<table><tr><td>", 20000);
+        assert!(sink.names.len() > 4 * 20000);
+        assert_eq!(sink.texts.len(), 1);
+    }
 }
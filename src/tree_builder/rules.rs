@@ -14,26 +14,37 @@ use core::prelude::*;
 use tree_builder::types::*;
 use tree_builder::tag_sets::*;
 use tree_builder::actions::TreeBuilderActions;
-use tree_builder::interface::{TreeSink, Quirks, AppendNode};
+use tree_builder::interface::{TreeSink, Quirks, NoQuirks, AppendNode, Continue, Pause};
 
 use tokenizer::{Tag, StartTag, EndTag};
 use tokenizer::states::{Rcdata, Rawtext, ScriptData, Plaintext};
 
-use util::str::is_ascii_whitespace;
+use util::str::{AsciiExt, is_ascii_whitespace};
 
 use core::mem::replace;
 use collections::MutableSeq;
 use collections::string::String;
 use collections::str::Slice;
 
+use string_cache::QualName;
+
 fn any_not_whitespace(x: &String) -> bool {
     // FIXME: this might be much faster as a byte scan
     x.as_slice().chars().any(|c| !is_ascii_whitespace(c))
 }
 
 // This goes in a trait so that we can control visibility.
+//
+// `step` calls itself only to use another mode's rules, which never leads
+// back to the first mode for the same token, so the depth is bounded.
+// Reprocessing a token in a new mode goes through the loop in
+// `process_to_completion` instead.
 pub trait TreeBuilderStep<Handle> {
     fn step(&mut self, mode: InsertionMode, token: Token) -> ProcessResult;
+    fn step_foreign(&mut self, token: Token) -> ProcessResult;
+    fn foreign_breakout(&mut self, tag: Tag) -> ProcessResult;
+    fn foreign_start_tag(&mut self, tag: Tag) -> ProcessResult;
+    fn foreign_end_tag(&mut self, tag: Tag) -> ProcessResult;
 }
 
 #[doc(hidden)]
@@ -50,7 +61,9 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                 CharacterTokens(Whitespace, _) => Done,
                 CommentToken(text) => self.append_comment_to_doc(text),
                 token => {
-                    if !self.opts.iframe_srcdoc {
+                    if self.opts.iframe_srcdoc {
+                        self.set_quirks_mode(NoQuirks);
+                    } else {
                         self.unexpected(&token);
                         self.set_quirks_mode(Quirks);
                     }
@@ -65,7 +78,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                 CommentToken(text) => self.append_comment_to_doc(text),
 
                 tag @ <html> => {
-                    self.create_root(tag.attrs);
+                    self.create_root(tag.attrs, false);
                     self.mode = BeforeHead;
                     Done
                 }
@@ -75,7 +88,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                 tag @ </_> => self.unexpected(&tag),
 
                 token => {
-                    self.create_root(vec!());
+                    self.create_root(vec!(), true);
                     Reprocess(BeforeHead, token)
                 }
             }),
@@ -107,7 +120,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
             //§ parsing-main-inhead
             InHead => match_token!(token {
                 CharacterTokens(NotSplit, text) => SplitWhitespace(text),
-                CharacterTokens(Whitespace, text) => self.append_text(text),
+                CharacterTokens(Whitespace, text) => self.append_text(Whitespace, text),
                 CommentToken(text) => self.append_comment(text),
 
                 <html> => self.step(InBody, token),
@@ -152,8 +165,27 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
 
                 </body> </html> </br> => else,
 
-                <template> => fail!("FIXME: <template> not implemented"),
-                </template> => fail!("FIXME: <template> not implemented"),
+                tag @ <template> => {
+                    self.insert_element_for(tag);
+                    self.active_formatting.push(Marker);
+                    self.frameset_ok = false;
+                    self.mode = InTemplate;
+                    self.template_modes.push(InTemplate);
+                    Done
+                }
+
+                tag @ </template> => {
+                    if !self.in_html_elem_named(atom!(template)) {
+                        self.unexpected(&tag);
+                    } else {
+                        self.generate_implied_end(thorough_implied_end);
+                        self.expect_to_close(atom!(template));
+                        self.clear_active_formatting_to_marker();
+                        self.template_modes.pop();
+                        self.mode = self.reset_insertion_mode();
+                    }
+                    Done
+                }
 
                 <head> => self.unexpected(&token),
                 tag @ </_> => self.unexpected(&tag),
@@ -197,7 +229,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
             //§ the-after-head-insertion-mode
             AfterHead => match_token!(token {
                 CharacterTokens(NotSplit, text) => SplitWhitespace(text),
-                CharacterTokens(Whitespace, text) => self.append_text(text),
+                CharacterTokens(Whitespace, text) => self.append_text(Whitespace, text),
                 CommentToken(text) => self.append_comment(text),
 
                 <html> => self.step(InBody, token),
@@ -244,19 +276,23 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
 
                 CharacterTokens(_, text) => {
                     self.reconstruct_formatting();
-                    if any_not_whitespace(&text) {
+                    let split = if any_not_whitespace(&text) {
                         self.frameset_ok = false;
-                    }
-                    self.append_text(text)
+                        NotWhitespace
+                    } else {
+                        Whitespace
+                    };
+                    self.append_text(split, text)
                 }
 
                 CommentToken(text) => self.append_comment(text),
 
                 tag @ <html> => {
                     self.unexpected(&tag);
-                    // FIXME: <template>
-                    let top = self.html_elem();
-                    self.sink.add_attrs_if_missing(top, tag.attrs);
+                    if !self.in_html_elem_named(atom!(template)) {
+                        let top = self.html_elem();
+                        self.sink.add_attrs_if_missing(top, tag.attrs);
+                    }
                     Done
                 }
 
@@ -267,7 +303,9 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
 
                 tag @ <body> => {
                     self.unexpected(&tag);
-                    // FIXME: <template>
+                    if self.in_html_elem_named(atom!(template)) {
+                        return Done;
+                    }
                     match self.body_elem() {
                         None => (),
                         Some(node) => {
@@ -298,7 +336,9 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                 }
 
                 EOFToken => {
-                    // FIXME: <template>
+                    if !self.template_modes.is_empty() {
+                        return self.step(InTemplate, token);
+                    }
                     self.check_body_end();
                     self.stop_parsing()
                 }
@@ -308,7 +348,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                         self.check_body_end();
                         self.mode = AfterBody;
                     } else {
-                        self.sink.parse_error(Slice("</body> with no <body> in scope"));
+                        self.parse_error(Slice("</body> with no <body> in scope"));
                     }
                     Done
                 }
@@ -318,7 +358,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                         self.check_body_end();
                         Reprocess(AfterBody, token)
                     } else {
-                        self.sink.parse_error(Slice("</html> with no <body> in scope"));
+                        self.parse_error(Slice("</html> with no <body> in scope"));
                         Done
                     }
                 }
@@ -334,7 +374,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                 tag @ <h1> <h2> <h3> <h4> <h5> <h6> => {
                     self.close_p_element_in_button_scope();
                     if self.current_node_in(heading_tag) {
-                        self.sink.parse_error(Slice("nested heading tags"));
+                        self.parse_error(Slice("nested heading tags"));
                         self.pop();
                     }
                     self.insert_element_for(tag);
@@ -350,14 +390,16 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                 }
 
                 tag @ <form> => {
-                    // FIXME: <template>
-                    if self.form_elem.is_some() {
-                        self.sink.parse_error(Slice("nested forms"));
+                    // Forms inside a template don't set the form element pointer.
+                    let in_template = self.in_html_elem_named(atom!(template));
+                    if self.form_elem.is_some() && !in_template {
+                        self.parse_error(Slice("nested forms"));
                     } else {
                         self.close_p_element_in_button_scope();
                         let elem = self.insert_element_for(tag);
-                        // FIXME: <template>
-                        self.form_elem = Some(elem);
+                        if !in_template {
+                            self.form_elem = Some(elem);
+                        }
                     }
                     Done
                 }
@@ -408,7 +450,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
 
                 tag @ <button> => {
                     if self.in_scope_named(default_scope, atom!(button)) {
-                        self.sink.parse_error(Slice("nested buttons"));
+                        self.parse_error(Slice("nested buttons"));
                         self.generate_implied_end(cursory_implied_end);
                         self.pop_until_named(atom!(button));
                     }
@@ -432,32 +474,41 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                 }
 
                 </form> => {
-                    // FIXME: <template>
+                    if self.in_html_elem_named(atom!(template)) {
+                        if !self.in_scope_named(default_scope, atom!(form)) {
+                            self.parse_error(Slice("Form element not in scope on </form>"));
+                        } else {
+                            self.generate_implied_end(cursory_implied_end);
+                            self.expect_to_close(atom!(form));
+                        }
+                        return Done;
+                    }
+
                     // Can't use unwrap_or_return!() due to rust-lang/rust#16617.
                     let node = match self.form_elem.take() {
                         None => {
-                            self.sink.parse_error(Slice("Null form element pointer on </form>"));
+                            self.parse_error(Slice("Null form element pointer on </form>"));
                             return Done;
                         }
                         Some(x) => x,
                     };
                     if !self.in_scope(default_scope,
                         |n| self.sink.same_node(node.clone(), n)) {
-                        self.sink.parse_error(Slice("Form element not in scope on </form>"));
+                        self.parse_error(Slice("Form element not in scope on </form>"));
                         return Done;
                     }
                     self.generate_implied_end(cursory_implied_end);
                     let current = self.current_node();
                     self.remove_from_stack(&node);
                     if !self.sink.same_node(current, node) {
-                        self.sink.parse_error(Slice("Bad open element on </form>"));
+                        self.parse_error(Slice("Bad open element on </form>"));
                     }
                     Done
                 }
 
                 </p> => {
                     if !self.in_scope_named(button_scope, atom!(p)) {
-                        self.sink.parse_error(Slice("No <p> tag to close"));
+                        self.parse_error(Slice("No <p> tag to close"));
                         self.insert_phantom(atom!(p));
                     }
                     self.close_p_element();
@@ -473,7 +524,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                         self.generate_implied_end_except(tag.name.clone());
                         self.expect_to_close(tag.name);
                     } else {
-                        self.sink.parse_error(Slice("No matching tag to close"));
+                        self.parse_error(Slice("No matching tag to close"));
                     }
                     Done
                 }
@@ -482,33 +533,36 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                     if self.in_scope(default_scope, |n| self.elem_in(n.clone(), heading_tag)) {
                         self.generate_implied_end(cursory_implied_end);
                         if !self.current_node_named(tag.name) {
-                            self.sink.parse_error(Slice("Closing wrong heading tag"));
+                            self.parse_error(Slice("Closing wrong heading tag"));
                         }
                         self.pop_until(heading_tag);
                     } else {
-                        self.sink.parse_error(Slice("No heading tag to close"));
+                        self.parse_error(Slice("No heading tag to close"));
                     }
                     Done
                 }
 
                 tag @ <a> => {
-                    let mut to_remove = vec!();
-                    for (i, handle, _) in self.active_formatting_end_to_marker() {
+                    let mut found_a = None;
+                    for (_, handle, _) in self.active_formatting_end_to_marker() {
                         if self.html_elem_named(handle.clone(), atom!(a)) {
-                            to_remove.push((i, handle.clone()));
+                            found_a = Some(handle.clone());
+                            break;
                         }
                     }
 
-                    if !to_remove.is_empty() {
-                        self.unexpected(&tag);
-                        self.adoption_agency(atom!(a));
-                        // FIXME: quadratic time
-                        for (i, handle) in to_remove.into_iter() {
-                            self.remove_from_stack(&handle);
-                            self.active_formatting.remove(i);
-                            // We iterated backwards from the end above, so
-                            // we don't need to adjust the indices after each
-                            // removal.
+                    match found_a {
+                        None => (),
+                        Some(a) => {
+                            self.unexpected(&tag);
+                            self.adoption_agency(atom!(a));
+                            // The adoption agency leaves the element alone
+                            // if it's not in scope.
+                            match self.position_in_active_formatting(&a) {
+                                Some(i) => { self.active_formatting.remove(i); }
+                                None => (),
+                            }
+                            self.remove_from_stack(&a);
                         }
                     }
 
@@ -526,7 +580,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                 tag @ <nobr> => {
                     self.reconstruct_formatting();
                     if self.in_scope_named(default_scope, atom!(nobr)) {
-                        self.sink.parse_error(Slice("Nested <nobr>"));
+                        self.parse_error(Slice("Nested <nobr>"));
                         self.adoption_agency(atom!(nobr));
                         self.reconstruct_formatting();
                     }
@@ -574,6 +628,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                     self.step(InBody, TagToken(Tag {
                         kind: StartTag,
                         attrs: vec!(),
+                        attr_spans: vec!(),
                         ..tag
                     }))
                 }
@@ -675,8 +730,11 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                     Done
                 }
 
-                <math> => fail!("FIXME: MathML not implemented"),
-                <svg> => fail!("FIXME: SVG not implemented"),
+                tag @ <math> <svg> => {
+                    self.reconstruct_formatting();
+                    let ns = if tag.name == atom!(math) { ns!(MathML) } else { ns!(SVG) };
+                    self.insert_foreign_element(tag, ns)
+                }
 
                 <caption> <col> <colgroup> <frame> <head>
                   <tbody> <td> <tfoot> <th> <thead> <tr> => {
@@ -687,6 +745,11 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                 tag @ <_> => {
                     if self.opts.scripting_enabled && tag.name == atom!(noscript) {
                         self.parse_raw_data(tag, Rawtext);
+                    } else if self.opts.xml_islands && tag.self_closing
+                            && !other_known_html(QualName::new(ns!(HTML), tag.name.clone())) {
+                        self.reconstruct_formatting();
+                        self.insert_and_pop_element_for(tag);
+                        return DoneAckSelfClosing;
                     } else {
                         self.reconstruct_formatting();
                         self.insert_element_for(tag);
@@ -695,38 +758,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                 }
 
                 tag @ </_> => {
-                    // Look back for a matching open element.
-                    let mut match_idx = None;
-                    for (i, elem) in self.open_elems.iter().enumerate().rev() {
-                        if self.html_elem_named(elem.clone(), tag.name.clone()) {
-                            match_idx = Some(i);
-                            break;
-                        }
-
-                        if self.elem_in(elem.clone(), special_tag) {
-                            self.sink.parse_error(Slice("Found special tag while closing generic tag"));
-                            return Done;
-                        }
-                    }
-
-                    // Can't use unwrap_or_return!() due to rust-lang/rust#16617.
-                    let match_idx = match match_idx {
-                        None => {
-                            // I believe this is impossible, because the root
-                            // <html> element is in special_tag.
-                            self.unexpected(&tag);
-                            return Done;
-                        }
-                        Some(x) => x,
-                    };
-
-                    self.generate_implied_end_except(tag.name.clone());
-
-                    if match_idx != self.open_elems.len() - 1 {
-                        // mis-nested tags
-                        self.unexpected(&tag);
-                    }
-                    self.open_elems.truncate(match_idx);
+                    self.process_end_tag_in_body(tag);
                     Done
                 }
 
@@ -737,7 +769,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
 
             //§ parsing-main-incdata
             Text => match_token!(token {
-                CharacterTokens(_, text) => self.append_text(text),
+                CharacterTokens(split, text) => self.append_text(split, text),
 
                 EOFToken => {
                     self.unexpected(&token);
@@ -750,12 +782,14 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                 }
 
                 tag @ </_> => {
+                    let node = self.pop();
+                    self.mode = self.orig_mode.take_unwrap();
                     if tag.name == atom!(script) {
-                        h5e_warn!("FIXME: </script> not implemented");
+                        match self.sink.complete_script(node) {
+                            Continue => (),
+                            Pause => self.script_paused = true,
+                        }
                     }
-
-                    self.pop();
-                    self.mode = self.orig_mode.take_unwrap();
                     Done
                 }
 
@@ -846,8 +880,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
 
                 tag @ <form> => {
                     self.unexpected(&tag);
-                    // FIXME: <template>
-                    if self.form_elem.is_none() {
+                    if self.form_elem.is_none() && !self.in_html_elem_named(atom!(template)) {
                         self.form_elem = Some(self.insert_and_pop_element_for(tag));
                     }
                     Done
@@ -881,7 +914,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                     });
 
                     if contains_nonspace {
-                        self.sink.parse_error(Slice("Non-space table text"));
+                        self.parse_error(Slice("Non-space table text"));
                         for (split, text) in pending.into_iter() {
                             match self.foster_parent_in_body(CharacterTokens(split, text)) {
                                 Done => (),
@@ -890,7 +923,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
                         }
                     } else {
                         for (_, text) in pending.into_iter() {
-                            self.append_text(text);
+                            self.append_text(Whitespace, text);
                         }
                     }
 
@@ -928,7 +961,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
             //§ parsing-main-incolgroup
             InColumnGroup => match_token!(token {
                 CharacterTokens(NotSplit, text) => SplitWhitespace(text),
-                CharacterTokens(Whitespace, text) => self.append_text(text),
+                CharacterTokens(Whitespace, text) => self.append_text(Whitespace, text),
                 CommentToken(text) => self.append_comment_to_html(text),
 
                 <html> => self.step(InBody, token),
@@ -1103,7 +1136,7 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
             //§ parsing-main-inselect
             InSelect => match_token!(token {
                 NullCharacterToken => self.unexpected(&token),
-                CharacterTokens(_, text) => self.append_text(text),
+                CharacterTokens(split, text) => self.append_text(split, text),
                 CommentToken(text) => self.append_comment(text),
 
                 <html> => self.step(InBody, token),
@@ -1204,12 +1237,68 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
             }),
 
             //§ parsing-main-intemplate
-            InTemplate
-                => fail!("FIXME: <template> not implemented"),
+            InTemplate => match_token!(token {
+                CharacterTokens(_, _) => self.step(InBody, token),
+                NullCharacterToken => self.step(InBody, token),
+                CommentToken(_) => self.step(InBody, token),
+
+                <base> <basefont> <bgsound> <link> <meta> <noframes> <script>
+                  <style> <template> <title> </template> => self.step(InHead, token),
+
+                <caption> <colgroup> <tbody> <tfoot> <thead> => {
+                    self.template_modes.pop();
+                    self.template_modes.push(InTable);
+                    Reprocess(InTable, token)
+                }
+
+                <col> => {
+                    self.template_modes.pop();
+                    self.template_modes.push(InColumnGroup);
+                    Reprocess(InColumnGroup, token)
+                }
+
+                <tr> => {
+                    self.template_modes.pop();
+                    self.template_modes.push(InTableBody);
+                    Reprocess(InTableBody, token)
+                }
+
+                <td> <th> => {
+                    self.template_modes.pop();
+                    self.template_modes.push(InRow);
+                    Reprocess(InRow, token)
+                }
+
+                EOFToken => {
+                    if !self.in_html_elem_named(atom!(template)) {
+                        self.stop_parsing()
+                    } else {
+                        self.unexpected(&token);
+                        self.pop_until_named(atom!(template));
+                        self.clear_active_formatting_to_marker();
+                        self.template_modes.pop();
+                        Reprocess(self.reset_insertion_mode(), token)
+                    }
+                }
+
+                tag @ <_> => {
+                    self.template_modes.pop();
+                    self.template_modes.push(InBody);
+                    Reprocess(InBody, TagToken(tag))
+                }
+
+                tag @ </_> => self.unexpected(&tag),
+            }),
 
             //§ parsing-main-afterbody
             AfterBody => match_token!(token {
-                CharacterTokens(NotSplit, text) => SplitWhitespace(text),
+                CharacterTokens(NotSplit, text) => {
+                    if self.opts.exact_text_splitting {
+                        SplitWhitespace(text)
+                    } else {
+                        self.text_after_body(text)
+                    }
+                }
                 CharacterTokens(Whitespace, _) => self.step(InBody, token),
                 CommentToken(text) => self.append_comment_to_html(text),
 
@@ -1234,8 +1323,14 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
 
             //§ parsing-main-inframeset
             InFrameset => match_token!(token {
-                CharacterTokens(NotSplit, text) => SplitWhitespace(text),
-                CharacterTokens(Whitespace, text) => self.append_text(text),
+                CharacterTokens(NotSplit, text) => {
+                    if self.opts.exact_text_splitting {
+                        SplitWhitespace(text)
+                    } else {
+                        self.whitespace_in_frameset(text)
+                    }
+                }
+                CharacterTokens(Whitespace, text) => self.append_text(Whitespace, text),
                 CommentToken(text) => self.append_comment(text),
 
                 <html> => self.step(InBody, token),
@@ -1276,8 +1371,14 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
 
             //§ parsing-main-afterframeset
             AfterFrameset => match_token!(token {
-                CharacterTokens(NotSplit, text) => SplitWhitespace(text),
-                CharacterTokens(Whitespace, text) => self.append_text(text),
+                CharacterTokens(NotSplit, text) => {
+                    if self.opts.exact_text_splitting {
+                        SplitWhitespace(text)
+                    } else {
+                        self.whitespace_in_frameset(text)
+                    }
+                }
+                CharacterTokens(Whitespace, text) => self.append_text(Whitespace, text),
                 CommentToken(text) => self.append_comment(text),
 
                 <html> => self.step(InBody, token),
@@ -1296,7 +1397,13 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
 
             //§ the-after-after-body-insertion-mode
             AfterAfterBody => match_token!(token {
-                CharacterTokens(NotSplit, text) => SplitWhitespace(text),
+                CharacterTokens(NotSplit, text) => {
+                    if self.opts.exact_text_splitting {
+                        SplitWhitespace(text)
+                    } else {
+                        self.text_after_body(text)
+                    }
+                }
                 CharacterTokens(Whitespace, _) => self.step(InBody, token),
                 CommentToken(text) => self.append_comment_to_doc(text),
 
@@ -1327,4 +1434,104 @@ impl<'sink, Handle: Clone, Sink: TreeSink<Handle>>
             //§ END
         }
     }
+
+    //§ parsing-main-inforeign
+    fn step_foreign(&mut self, token: Token) -> ProcessResult {
+        match_token!(token {
+            NullCharacterToken => {
+                self.unexpected(&token);
+                self.append_text(NotSplit, String::from_char(1, '\ufffd'))
+            }
+
+            CharacterTokens(split, text) => {
+                if any_not_whitespace(&text) {
+                    self.frameset_ok = false;
+                }
+                self.append_text(split, text)
+            }
+
+            CommentToken(text) => self.append_comment(text),
+
+            tag @ <b> <big> <blockquote> <body> <br> <center> <code> <dd> <div> <dl>
+              <dt> <em> <embed> <h1> <h2> <h3> <h4> <h5> <h6> <head> <hr> <i>
+              <img> <li> <listing> <menu> <meta> <nobr> <ol> <p> <pre> <ruby>
+              <s> <small> <span> <strong> <strike> <sub> <sup> <table> <tt>
+              <u> <ul> <var> </br> </p> => self.foreign_breakout(tag),
+
+            tag @ <font> => {
+                let breaks_out = tag.attrs.iter().any(|a| a.name == qualname!("", color)
+                    || a.name == qualname!("", face) || a.name == qualname!("", size));
+                if breaks_out {
+                    self.foreign_breakout(tag)
+                } else {
+                    self.foreign_start_tag(tag)
+                }
+            }
+
+            tag @ <_> => self.foreign_start_tag(tag),
+
+            tag @ </_> => self.foreign_end_tag(tag),
+
+            // EOF is handled by the current insertion mode.
+            _ => fail!("impossible case in foreign content"),
+        })
+    }
+
+    // A tag which breaks out of foreign content.
+    fn foreign_breakout(&mut self, tag: Tag) -> ProcessResult {
+        self.unexpected(&tag);
+
+        // The context element isn't on the stack, so there is nothing to
+        // pop out of.  (fragment case)
+        if self.opts.fragment {
+            return match tag.kind {
+                StartTag => self.foreign_start_tag(tag),
+                EndTag => self.foreign_end_tag(tag),
+            };
+        }
+
+        // Reprocessing would bring an end tag straight back here, since
+        // only start tags and characters leave foreign content at an
+        // integration point.  So use the HTML rules directly.
+        self.pop_to_html_or_integration_point();
+        let mode = self.mode;
+        self.step(mode, TagToken(tag))
+    }
+
+    // "Any other start tag" in foreign content.
+    fn foreign_start_tag(&mut self, tag: Tag) -> ProcessResult {
+        let ns = self.sink.elem_name(self.adjusted_current_node()).ns;
+        self.insert_foreign_element(tag, ns)
+    }
+
+    // "Any other end tag" in foreign content.
+    // FIXME: </script> in SVG should run the script.
+    fn foreign_end_tag(&mut self, tag: Tag) -> ProcessResult {
+        let mut idx = self.open_elems.len() - 1;
+        let tag_name = tag.name.clone();
+        let matches = |name: QualName| {
+            name.local.as_slice().to_ascii_lower().as_slice() == tag_name.as_slice()
+        };
+
+        if !matches(self.sink.elem_name(self.open_elems[idx].clone())) {
+            self.unexpected(&tag);
+        }
+
+        loop {
+            // The bottom of the stack is reached only in the fragment case.
+            if idx == 0 {
+                return Done;
+            }
+            if matches(self.sink.elem_name(self.open_elems[idx].clone())) {
+                self.open_elems.truncate(idx);
+                return Done;
+            }
+            idx -= 1;
+            if self.sink.elem_name(self.open_elems[idx].clone()).ns == ns!(HTML) {
+                let mode = self.mode;
+                return self.step(mode, TagToken(tag));
+            }
+        }
+    }
+    //§ END
 }
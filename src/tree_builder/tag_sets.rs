@@ -51,8 +51,38 @@ macro_rules! declare_tag_set (
 #[inline(always)] pub fn empty_set(_: QualName) -> bool { false }
 #[inline(always)] pub fn full_set(_: QualName) -> bool { true }
 
-// FIXME: MathML, SVG
-declare_tag_set!(pub default_scope = applet caption html table td th marquee object template)
+//§ mathml-text-integration-point
+pub fn mathml_text_integration_point(p: QualName) -> bool {
+    match p {
+        QualName { ns: ns!(MathML), local: atom!(mi) }
+            | QualName { ns: ns!(MathML), local: atom!(mo) }
+            | QualName { ns: ns!(MathML), local: atom!(mn) }
+            | QualName { ns: ns!(MathML), local: atom!(ms) }
+            | QualName { ns: ns!(MathML), local: atom!(mtext) } => true,
+        _ => false,
+    }
+}
+
+//§ html-integration-point
+// The SVG ones only; see `TreeBuilderActions::is_foreign` for MathML.
+pub fn svg_html_integration_point(p: QualName) -> bool {
+    match p {
+        QualName { ns: ns!(SVG), local: atom!(foreignObject) }
+            | QualName { ns: ns!(SVG), local: atom!(desc) }
+            | QualName { ns: ns!(SVG), local: atom!(title) } => true,
+        _ => false,
+    }
+}
+
+// The foreign elements which bound every scope, and which are special.
+pub fn foreign_scope(p: QualName) -> bool {
+    let annotation_xml = p.ns == ns!(MathML) && p.local.as_slice() == "annotation-xml";
+    annotation_xml || mathml_text_integration_point(p.clone()) || svg_html_integration_point(p)
+}
+
+//§ the-stack-of-open-elements
+declare_tag_set!(pub default_scope = foreign_scope
+    + applet caption html table td th marquee object template)
 
 declare_tag_set!(pub list_item_scope = default_scope + ol ul)
 declare_tag_set!(pub button_scope = default_scope + button)
@@ -70,18 +100,22 @@ declare_tag_set!(pub thorough_implied_end = cursory_implied_end
 
 declare_tag_set!(pub heading_tag = h1 h2 h3 h4 h5 h6)
 
-declare_tag_set!(pub special_tag =
+declare_tag_set!(pub special_tag = foreign_scope +
     address applet area article aside base basefont bgsound blockquote body br button caption
     center col colgroup dd details dir div dl dt embed fieldset figcaption figure footer form
     frame frameset h1 h2 h3 h4 h5 h6 head header hgroup hr html iframe img input isindex li
     link listing main marquee menu menuitem meta nav noembed noframes noscript object ol p
     param plaintext pre script section select source style summary table tbody td template
     textarea tfoot th thead title tr track ul wbr xmp)
-
-#[allow(dead_code)]
-fn unused_tag_sets() {
-    // FIXME: Some tag sets are unused until we implement <template> or other stuff.
-    // Suppress the warning here.
-    thorough_implied_end(qualname!(HTML, p));
-}
 //§ END
+
+// Elements which can't have content, so never have an end tag.
+declare_tag_set!(pub void_elements =
+    area base br col embed hr img input keygen link meta param source track wbr)
+
+// HTML elements which are handled by "any other start tag" in the body,
+// for telling them apart from unknown (e.g. XML) elements.
+declare_tag_set!(pub other_known_html =
+    abbr acronym audio bdi bdo canvas cite data datalist del dfn ins kbd label
+    legend map mark meter noscript output picture progress q ruby samp slot span
+    sub sup time var video)
@@ -0,0 +1,287 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Sinks which store text in something other than `String`.
+//!
+//! The tokenizer and tree builder work with `String` internally.  A sink
+//! implementing `TextTreeSink` instead receives text node contents and
+//! attribute values as its own `TextBuf` type, converted at the boundary
+//! by `TextAdapter`.  Comments, doctypes and names are unaffected.
+
+use core::prelude::*;
+
+use tree_builder::interface::{TreeSink, QuirksMode, NodeOrText, AppendNode, AppendText};
+use tree_builder::interface::{ElemProvenance, AuditIssue, ScriptAction, Continue};
+use tree_builder::types::{InsertionMode, Token, StepOutcome};
+
+use tokenizer::{Attribute, ParseErrorKind};
+
+use core::kinds::marker::CovariantType;
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+use collections::str::MaybeOwned;
+
+use string_cache::{Atom, QualName};
+
+/// A string type which a `TextTreeSink` stores text in.
+pub trait TextBuf {
+    /// Take ownership of text produced by the parser.
+    fn from_string(s: String) -> Self;
+
+    /// Append more text, when adjacent text nodes are merged.
+    fn push_text(&mut self, s: &str);
+}
+
+impl TextBuf for String {
+    fn from_string(s: String) -> String {
+        s
+    }
+
+    fn push_text(&mut self, s: &str) {
+        self.push_str(s);
+    }
+}
+
+/// Text stored as one byte per character.
+///
+/// Characters above U+00FF can't be represented, and are replaced by `?`.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Latin1(pub Vec<u8>);
+
+impl TextBuf for Latin1 {
+    fn from_string(s: String) -> Latin1 {
+        let mut buf = Latin1(Vec::with_capacity(s.len()));
+        buf.push_text(s.as_slice());
+        buf
+    }
+
+    fn push_text(&mut self, s: &str) {
+        let Latin1(ref mut bytes) = *self;
+        for c in s.chars() {
+            bytes.push(if (c as u32) < 0x100 { c as u8 } else { b'?' });
+        }
+    }
+}
+
+/// An attribute whose value is a `TextBuf`.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct TextAttribute<T> {
+    pub name: QualName,
+    pub value: T,
+}
+
+fn convert_attrs<T: TextBuf>(attrs: Vec<Attribute>) -> Vec<TextAttribute<T>> {
+    attrs.into_iter().map(|attr| TextAttribute {
+        name: attr.name,
+        value: TextBuf::from_string(attr.value),
+    }).collect()
+}
+
+/// Like `TreeSink`, but text and attribute values arrive as `T`.
+///
+/// Methods not documented here behave exactly as in `TreeSink`.
+pub trait TextTreeSink<Handle, T: TextBuf> {
+    fn parse_error(&mut self, msg: MaybeOwned<'static>);
+    fn classified_parse_error(&mut self, _kind: ParseErrorKind, msg: MaybeOwned<'static>) {
+        self.parse_error(msg);
+    }
+    fn get_document(&mut self) -> Handle;
+    fn same_node(&self, x: Handle, y: Handle) -> bool;
+    fn elem_name(&self, target: Handle) -> QualName;
+    fn get_template_contents(&mut self, target: Handle) -> Handle;
+    fn set_quirks_mode(&mut self, mode: QuirksMode);
+    fn query_quirks_override(&mut self, _mode: QuirksMode) -> Option<QuirksMode> { None }
+    fn create_element(&mut self, name: QualName, attrs: Vec<TextAttribute<T>>) -> Handle;
+    fn create_comment(&mut self, text: String) -> Handle;
+
+    /// Append a node as the last child of the given node.
+    fn append(&mut self, parent: Handle, child: Handle);
+
+    /// Append text to the given node, merging it with a trailing text
+    /// node if there is one.
+    fn append_text(&mut self, parent: Handle, text: T);
+
+    /// Insert a node before the given node.  If that node has no parent,
+    /// do nothing and return `Err(new_node)`.
+    fn append_before_sibling(&mut self, sibling: Handle, new_node: Handle) -> Result<(), Handle>;
+
+    /// Insert text before the given node, merging it with a preceding
+    /// text node if there is one.  If that node has no parent, do nothing
+    /// and return `Err(text)`.
+    fn append_text_before_sibling(&mut self, sibling: Handle, text: T) -> Result<(), T>;
+
+    fn append_doctype_to_document(&mut self, name: String, public_id: String, system_id: String);
+    fn append_raw_doctype_to_document(&mut self, name: String, public_id: String,
+            system_id: String, _raw: String) {
+        self.append_doctype_to_document(name, public_id, system_id);
+    }
+    fn add_attrs_if_missing(&mut self, target: Handle, attrs: Vec<TextAttribute<T>>);
+    fn remove_from_parent(&mut self, target: Handle);
+    fn reparent_children(&mut self, node: Handle, new_parent: Handle);
+    fn mark_script_already_started(&mut self, node: Handle);
+    fn complete_script(&mut self, _node: Handle) -> ScriptAction { Continue }
+    fn element_provenance(&mut self, _elem: Handle, _provenance: ElemProvenance) { }
+    fn step_taken(&mut self, _mode: InsertionMode, _token: &Token, _outcome: StepOutcome) { }
+    fn audit(&mut self, _issue: AuditIssue<Handle>) { }
+    fn void_end_tag_ignored(&mut self, _name: Atom) { }
+    fn aborted(&self) -> bool { false }
+}
+
+/// Wraps a `TextTreeSink` so it can be given to the parser.
+pub struct TextAdapter<Sink, T> {
+    sink: Sink,
+    marker: CovariantType<T>,
+}
+
+impl<Sink, T> TextAdapter<Sink, T> {
+    pub fn new(sink: Sink) -> TextAdapter<Sink, T> {
+        TextAdapter {
+            sink: sink,
+            marker: CovariantType,
+        }
+    }
+
+    /// Get back the wrapped sink.
+    pub fn unwrap(self) -> Sink {
+        self.sink
+    }
+}
+
+impl<Handle, T: TextBuf, Sink: TextTreeSink<Handle, T>> TreeSink<Handle> for TextAdapter<Sink, T> {
+    fn parse_error(&mut self, msg: MaybeOwned<'static>) {
+        self.sink.parse_error(msg);
+    }
+
+    fn classified_parse_error(&mut self, kind: ParseErrorKind, msg: MaybeOwned<'static>) {
+        self.sink.classified_parse_error(kind, msg);
+    }
+
+    fn get_document(&mut self) -> Handle {
+        self.sink.get_document()
+    }
+
+    fn same_node(&self, x: Handle, y: Handle) -> bool {
+        self.sink.same_node(x, y)
+    }
+
+    fn elem_name(&self, target: Handle) -> QualName {
+        self.sink.elem_name(target)
+    }
+
+    fn get_template_contents(&mut self, target: Handle) -> Handle {
+        self.sink.get_template_contents(target)
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.sink.set_quirks_mode(mode);
+    }
+
+    fn query_quirks_override(&mut self, mode: QuirksMode) -> Option<QuirksMode> {
+        self.sink.query_quirks_override(mode)
+    }
+
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle {
+        self.sink.create_element(name, convert_attrs(attrs))
+    }
+
+    fn create_comment(&mut self, text: String) -> Handle {
+        self.sink.create_comment(text)
+    }
+
+    fn append(&mut self, parent: Handle, child: NodeOrText<Handle>) {
+        match child {
+            AppendNode(node) => self.sink.append(parent, node),
+            AppendText(text) => self.sink.append_text(parent, TextBuf::from_string(text)),
+        }
+    }
+
+    fn append_before_sibling(&mut self,
+            sibling: Handle,
+            new_node: NodeOrText<Handle>) -> Result<(), NodeOrText<Handle>> {
+        match new_node {
+            AppendNode(node) => self.sink.append_before_sibling(sibling, node)
+                .map_err(|node| AppendNode(node)),
+
+            // The tree builder wants the text back if this fails, and we
+            // can't convert a `T` into a `String`.  Foster-parented text is
+            // rare enough that copying it is fine.
+            AppendText(text) => {
+                let buf = TextBuf::from_string(text.clone());
+                match self.sink.append_text_before_sibling(sibling, buf) {
+                    Ok(()) => Ok(()),
+                    Err(_) => Err(AppendText(text)),
+                }
+            }
+        }
+    }
+
+    fn append_doctype_to_document(&mut self, name: String, public_id: String, system_id: String) {
+        self.sink.append_doctype_to_document(name, public_id, system_id);
+    }
+
+    fn append_raw_doctype_to_document(&mut self, name: String, public_id: String,
+            system_id: String, raw: String) {
+        self.sink.append_raw_doctype_to_document(name, public_id, system_id, raw);
+    }
+
+    fn add_attrs_if_missing(&mut self, target: Handle, attrs: Vec<Attribute>) {
+        self.sink.add_attrs_if_missing(target, convert_attrs(attrs));
+    }
+
+    fn remove_from_parent(&mut self, target: Handle) {
+        self.sink.remove_from_parent(target);
+    }
+
+    fn reparent_children(&mut self, node: Handle, new_parent: Handle) {
+        self.sink.reparent_children(node, new_parent);
+    }
+
+    fn mark_script_already_started(&mut self, node: Handle) {
+        self.sink.mark_script_already_started(node);
+    }
+
+    fn complete_script(&mut self, node: Handle) -> ScriptAction {
+        self.sink.complete_script(node)
+    }
+
+    fn element_provenance(&mut self, elem: Handle, provenance: ElemProvenance) {
+        self.sink.element_provenance(elem, provenance);
+    }
+
+    fn step_taken(&mut self, mode: InsertionMode, token: &Token, outcome: StepOutcome) {
+        self.sink.step_taken(mode, token, outcome);
+    }
+
+    fn audit(&mut self, issue: AuditIssue<Handle>) {
+        self.sink.audit(issue);
+    }
+
+    fn void_end_tag_ignored(&mut self, name: Atom) {
+        self.sink.void_end_tag_ignored(name);
+    }
+
+    fn aborted(&self) -> bool {
+        self.sink.aborted()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use collections::string::String;
+    use super::{TextBuf, Latin1};
+
+    #[test]
+    fn latin1() {
+        let mut buf: Latin1 = TextBuf::from_string(String::from_str("café"));
+        buf.push_text(" ☃");
+        assert_eq!(buf, Latin1(b"caf\xe9 ?".to_vec()));
+    }
+}
@@ -7,15 +7,21 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-//! Types used within the tree builder code.  Not exported to users.
+//! Types used within the tree builder code.  Only `InsertionMode`,
+//! `ALL_INSERTION_MODES`, `Token` and `StepOutcome` are exported to users.
 
 use core::prelude::*;
 
 use tokenizer::Tag;
 
+use core::fmt;
 use collections::string::String;
 
-#[deriving(PartialEq, Eq, Clone, Show)]
+#[cfg(not(for_c))]
+use std::from_str::FromStr;
+
+/// The tree builder's insertion mode.
+#[deriving(PartialEq, Eq, Clone)]
 pub enum InsertionMode {
     Initial,
     BeforeHtml,
@@ -42,6 +48,85 @@ pub enum InsertionMode {
     AfterAfterFrameset,
 }
 
+/// Every insertion mode, in the order of the `InsertionMode` enum.
+pub static ALL_INSERTION_MODES: &'static [InsertionMode] = &[
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    InHeadNoscript,
+    AfterHead,
+    InBody,
+    Text,
+    InTable,
+    InTableText,
+    InCaption,
+    InColumnGroup,
+    InTableBody,
+    InRow,
+    InCell,
+    InSelect,
+    InSelectInTable,
+    InTemplate,
+    AfterBody,
+    InFrameset,
+    AfterFrameset,
+    AfterAfterBody,
+    AfterAfterFrameset,
+];
+
+impl InsertionMode {
+    /// A stable identifier for the insertion mode, for tools such as
+    /// trace viewers.  It's the spec's name for the mode, in lower case
+    /// with hyphens, e.g. `in-table-body`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Initial            => "initial",
+            BeforeHtml         => "before-html",
+            BeforeHead         => "before-head",
+            InHead             => "in-head",
+            InHeadNoscript     => "in-head-noscript",
+            AfterHead          => "after-head",
+            InBody             => "in-body",
+            Text               => "text",
+            InTable            => "in-table",
+            InTableText        => "in-table-text",
+            InCaption          => "in-caption",
+            InColumnGroup      => "in-column-group",
+            InTableBody        => "in-table-body",
+            InRow              => "in-row",
+            InCell             => "in-cell",
+            InSelect           => "in-select",
+            InSelectInTable    => "in-select-in-table",
+            InTemplate         => "in-template",
+            AfterBody          => "after-body",
+            InFrameset         => "in-frameset",
+            AfterFrameset      => "after-frameset",
+            AfterAfterBody     => "after-after-body",
+            AfterAfterFrameset => "after-after-frameset",
+        }
+    }
+
+    /// The insertion mode with the given `name`, if any.
+    pub fn from_name(name: &str) -> Option<InsertionMode> {
+        ALL_INSERTION_MODES.iter().find(|m| m.name() == name).map(|m| m.clone())
+    }
+}
+
+impl fmt::Show for InsertionMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+#[cfg(not(for_c))]
+impl FromStr for InsertionMode {
+    fn from_str(name: &str) -> Option<InsertionMode> {
+        InsertionMode::from_name(name)
+    }
+}
+
+/// Do we know whether a run of characters is all whitespace?
 #[deriving(PartialEq, Eq, Clone, Show)]
 pub enum SplitStatus {
     NotSplit,
@@ -60,6 +145,25 @@ pub enum Token {
     EOFToken,
 }
 
+/// What the tree builder did with a token, as reported to
+/// `TreeSink::step_taken`.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum StepOutcome {
+    /// The token was consumed.
+    StepDone,
+
+    /// The token was consumed, and it was a self-closing tag that is
+    /// allowed to be self-closing.
+    StepAckSelfClosing,
+
+    /// The characters will be split into whitespace and non-whitespace
+    /// runs, which are processed separately.
+    StepSplitWhitespace,
+
+    /// The token will be processed again, in the given insertion mode.
+    StepReprocess(InsertionMode),
+}
+
 pub enum ProcessResult {
     Done,
     DoneAckSelfClosing,
@@ -23,22 +23,145 @@ impl SmallCharSet {
         0 != (self.bits & (1 << (n as uint)))
     }
 
+    /// Is this character in the set?
+    pub fn contains_char(self, c: char) -> bool {
+        (c as u32) < 64 && self.contains(c as u8)
+    }
+
     /// Count the number of bytes of characters at the beginning
     /// of `buf` which are not in the set.
     /// See `tokenizer::buffer_queue::pop_except_from`.
     pub fn nonmember_prefix_len(&self, buf: &str) -> uint {
+        let bytes = buf.as_bytes();
+        match member_words_scan(self.bits, bytes) {
+            Some(n) => return n,
+            None => (),
+        }
+
         let mut n = 0;
-        for b in buf.bytes() {
-            if b >= 64 || !self.contains(b) {
-                n += 1;
-            } else {
-                break;
+        loop {
+            // Bytes >= 64 are never in the set, so skip them in bulk.
+            n += high_prefix_len(bytes.slice_from(n));
+            if n >= bytes.len() || (bytes[n] < 64 && self.contains(bytes[n])) {
+                return n;
             }
+            n += 1;
         }
-        n
     }
 }
 
+/// Count the bytes >= 64 at the beginning of `buf`.
+///
+/// With the `simd_scan` feature, this tests a word of 8 bytes at a time.
+/// Text is mostly letters and non-ASCII, so this skips most of the input
+/// in the Data, RawData and attribute value states.
+#[cfg(feature = "simd_scan")]
+fn high_prefix_len(buf: &[u8]) -> uint {
+    static WORD: uint = 8;
+    static LOW_BITS: u64 = 0x0101010101010101;
+    static HIGH_BITS: u64 = 0x8080808080808080;
+    static TOP_TWO_BITS: u64 = 0xC0C0C0C0C0C0C0C0;
+
+    let start = buf.as_ptr() as uint;
+    let mut n = 0;
+
+    // Step one byte at a time until we can read aligned words.
+    while n < buf.len() && (start + n) % WORD != 0 {
+        if buf[n] < 64 {
+            return n;
+        }
+        n += 1;
+    }
+
+    // A byte is < 64 iff its top two bits are clear.  Find words where
+    // that's true for no byte, using the usual test for a zero byte.
+    while n + WORD <= buf.len() {
+        let word = unsafe { *(buf.as_ptr().offset(n as int) as *const u64) };
+        let top = word & TOP_TWO_BITS;
+        if (top - LOW_BITS) & !top & HIGH_BITS != 0 {
+            break;
+        }
+        n += WORD;
+    }
+
+    while n < buf.len() && buf[n] >= 64 {
+        n += 1;
+    }
+    n
+}
+
+/// Scalar fallback: the caller tests each byte itself.
+#[cfg(not(feature = "simd_scan"))]
+#[inline(always)]
+fn high_prefix_len(_buf: &[u8]) -> uint {
+    0
+}
+
+/// Count the bytes at the beginning of `buf` which are not in the set
+/// with these `bits`, or None if the set is too big to scan this way.
+///
+/// With the `simd_scan` feature, sets of up to 4 characters, which is
+/// all the tokenizer uses, are found a word of 8 bytes at a time.  A
+/// word contains a member iff XORing it with that member in every byte
+/// leaves a zero byte.  Unlike `high_prefix_len`, this doesn't stop at
+/// spaces and punctuation.
+#[cfg(feature = "simd_scan")]
+fn member_words_scan(bits: u64, buf: &[u8]) -> Option<uint> {
+    use core::num::Int;
+
+    static WORD: uint = 8;
+    static MAX_MEMBERS: uint = 4;
+    static LOW_BITS: u64 = 0x0101010101010101;
+    static HIGH_BITS: u64 = 0x8080808080808080;
+
+    let mut members = [0u8, ..MAX_MEMBERS];
+    let mut count = 0;
+    let mut rest = bits;
+    while rest != 0 {
+        if count == MAX_MEMBERS {
+            return None;
+        }
+        members[count] = rest.trailing_zeros() as u8;
+        count += 1;
+        rest &= rest - 1;
+    }
+    let members = members.slice_to(count);
+
+    let start = buf.as_ptr() as uint;
+    let mut n = 0;
+
+    // Step one byte at a time until we can read aligned words.
+    while n < buf.len() && (start + n) % WORD != 0 {
+        if members.contains(&buf[n]) {
+            return Some(n);
+        }
+        n += 1;
+    }
+
+    while n + WORD <= buf.len() {
+        let word = unsafe { *(buf.as_ptr().offset(n as int) as *const u64) };
+        let hit = members.iter().any(|&m| {
+            let x = word ^ (LOW_BITS * m as u64);
+            (x - LOW_BITS) & !x & HIGH_BITS != 0
+        });
+        if hit {
+            break;
+        }
+        n += WORD;
+    }
+
+    while n < buf.len() && !members.contains(&buf[n]) {
+        n += 1;
+    }
+    Some(n)
+}
+
+#[cfg(not(feature = "simd_scan"))]
+#[inline(always)]
+fn member_words_scan(_bits: u64, _buf: &[u8]) -> Option<uint> {
+    None
+}
+
 macro_rules! small_char_set ( ($($e:expr)+) => (
     ::util::smallcharset::SmallCharSet {
         bits: $( (1 << ($e as uint)) )|+
@@ -65,4 +188,54 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn nonmember_prefix_mixed() {
+        // Non-member bytes both above and below 64, at every alignment.
+        let text = "a quick, brown fox; \u00e9t\u00e9 \u2603 jumps over the lazy dog";
+        let set = small_char_set!('&' '<' '\0');
+        for skip in range(0, 8u) {
+            for c in ['&', '<', '\0'].iter() {
+                let mut s = String::from_char(skip, ' ');
+                s.push_str(text);
+                s.push(*c);
+                s.push_str(text);
+                assert_eq!(skip + text.len(), set.nonmember_prefix_len(s.as_slice()));
+            }
+        }
+    }
+
+    #[test]
+    fn nonmember_prefix_near_misses() {
+        // Bytes next to each member, which the word-at-a-time test must
+        // not mistake for them, and a set too big for that test.
+        let text = "%'%';=;=\x01\x01\x0c\x0e%%'';;==\x01";
+        for set in [small_char_set!('&' '<' '\r' '\0'),
+                    small_char_set!('&' '<' '\r' '\0' '>')].iter() {
+            for skip in range(0, 8u) {
+                for c in ['&', '<', '\r', '\0'].iter() {
+                    let mut s = String::from_char(skip, 'x');
+                    s.push_str(text);
+                    s.push(*c);
+                    s.push_str(text);
+                    assert_eq!(skip + text.len(), set.nonmember_prefix_len(s.as_slice()));
+                }
+            }
+        }
+    }
+
+    // Run by `make check`, which builds the tests a second time with the
+    // feature on.
+    #[test]
+    #[cfg(feature = "simd_scan")]
+    fn member_words_scan() {
+        use super::member_words_scan;
+
+        let small = small_char_set!('&' '<' '\r' '\0');
+        let big = small_char_set!('&' '<' '\r' '\0' '>');
+        let s = "spaces, and punctuation; all skipped\r";
+        assert_eq!(member_words_scan(small.bits, s.as_bytes()), Some(s.len() - 1));
+        assert_eq!(member_words_scan(small.bits, b"no members"), Some(10));
+        assert_eq!(member_words_scan(big.bits, s.as_bytes()), None);
+    }
 }
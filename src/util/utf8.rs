@@ -0,0 +1,228 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Decoding UTF-8 which arrives in arbitrary chunks.
+//!
+//! The C API uses this for the bytes it's given.
+
+use core::prelude::*;
+
+use core::cmp;
+use core::str::raw::from_utf8;
+use collections::{MutableSeq, Vec};
+use collections::string::String;
+
+/// Find where an incomplete UTF-8 sequence at the end of `bytes` begins.
+///
+/// Returns `bytes.len()` if the input ends on a character boundary.
+/// Invalid sequences are not our concern here; `decode_utf8` will replace
+/// them with U+FFFD.
+pub fn incomplete_suffix_start(bytes: &[u8]) -> uint {
+    let len = bytes.len();
+
+    // A sequence is at most 4 bytes, so look back at most 3.
+    for back in range(1, cmp::min(len, 3) + 1) {
+        let b = bytes[len - back];
+        if b & 0xC0 == 0x80 {
+            // Continuation byte; keep looking for the lead byte.
+            continue;
+        }
+
+        let needed = match b {
+            _ if b & 0x80 == 0x00 => 1,
+            _ if b & 0xE0 == 0xC0 => 2,
+            _ if b & 0xF0 == 0xE0 => 3,
+            _ if b & 0xF8 == 0xF0 => 4,
+            _ => 1,
+        };
+        return if needed > back { len - back } else { len };
+    }
+
+    len
+}
+
+/// Problems found while decoding input, each replaced by U+FFFD.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct DecodeStats {
+    /// Encoded surrogate code points (U+D800 to U+DFFF), as produced by
+    /// CESU-8 and WTF-8.  Each half of a surrogate pair counts separately.
+    pub surrogates: uint,
+
+    /// Other invalid or truncated sequences.
+    pub invalid: uint,
+}
+
+/// Decode UTF-8, replacing each invalid sequence with U+FFFD.
+///
+/// Encoded surrogates are well-formed apart from their value, so they are
+/// replaced as a unit, one U+FFFD per surrogate.  Other invalid sequences
+/// are replaced up to the first byte which couldn't continue them.
+pub fn decode_utf8(bytes: &[u8], stats: &mut DecodeStats) -> String {
+    let len = bytes.len();
+    let mut out = String::with_capacity(len);
+    let mut run_start = 0;
+    let mut i = 0;
+
+    while i < len {
+        let b = bytes[i];
+        if b < 0x80 {
+            i += 1;
+            continue;
+        }
+
+        // The range allowed for the second byte, and the total width.
+        let (lo, hi, width) = match b {
+            0xC2 ... 0xDF => (0x80, 0xBF, 2),
+            0xE0 => (0xA0, 0xBF, 3),
+            0xED => (0x80, 0xBF, 3), // surrogates checked below
+            0xE1 ... 0xEF => (0x80, 0xBF, 3),
+            0xF0 => (0x90, 0xBF, 4),
+            0xF1 ... 0xF3 => (0x80, 0xBF, 4),
+            0xF4 => (0x80, 0x8F, 4),
+            _ => (0, 0, 0),
+        };
+
+        // How many bytes of a valid sequence we have.
+        let mut good = 1;
+        while good < width && i + good < len {
+            let c = bytes[i + good];
+            let ok = if good == 1 { c >= lo && c <= hi } else { c & 0xC0 == 0x80 };
+            if !ok {
+                break;
+            }
+            good += 1;
+        }
+
+        if good == width && !(b == 0xED && bytes[i + 1] >= 0xA0) {
+            i += width;
+            continue;
+        }
+
+        if good == width {
+            stats.surrogates += 1;
+        } else {
+            stats.invalid += 1;
+        }
+        unsafe {
+            out.push_str(from_utf8(bytes.slice(run_start, i)));
+        }
+        out.push('\ufffd');
+        i += good;
+        run_start = i;
+    }
+
+    unsafe {
+        out.push_str(from_utf8(bytes.slice(run_start, len)));
+    }
+    out
+}
+
+/// Decodes UTF-8 fed in arbitrary chunks, which may split a character.
+///
+/// The bytes of an incomplete trailing sequence are held back until the
+/// next chunk arrives.
+pub struct Utf8Chunker {
+    pending: Vec<u8>,
+
+    /// Problems found in the input so far.
+    pub stats: DecodeStats,
+}
+
+impl Utf8Chunker {
+    pub fn new() -> Utf8Chunker {
+        Utf8Chunker {
+            pending: vec!(),
+            stats: DecodeStats {
+                surrogates: 0,
+                invalid: 0,
+            },
+        }
+    }
+
+    /// Decode everything up to the last complete character.
+    pub fn push(&mut self, bytes: &[u8]) -> String {
+        self.pending.push_all(bytes);
+        let split = incomplete_suffix_start(self.pending.as_slice());
+        let rest = self.pending.slice_from(split).to_vec();
+        self.pending.truncate(split);
+        let out = decode_utf8(self.pending.as_slice(), &mut self.stats);
+        self.pending = rest;
+        out
+    }
+
+    /// Decode whatever is left at the end of input.  A truncated
+    /// sequence becomes U+FFFD.
+    pub fn finish(&mut self) -> String {
+        let out = decode_utf8(self.pending.as_slice(), &mut self.stats);
+        self.pending.clear();
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{incomplete_suffix_start, Utf8Chunker, DecodeStats, decode_utf8};
+
+    test_eq!(split_empty, incomplete_suffix_start(b""), 0)
+    test_eq!(split_ascii, incomplete_suffix_start(b"abc"), 3)
+    test_eq!(split_complete_2, incomplete_suffix_start(b"a\xc3\xa9"), 3)
+    test_eq!(split_complete_4, incomplete_suffix_start(b"\xf0\x9f\x92\xa9"), 4)
+    test_eq!(split_lead_only, incomplete_suffix_start(b"a\xe2"), 1)
+    test_eq!(split_lead_and_one, incomplete_suffix_start(b"a\xe2\x98"), 1)
+    test_eq!(split_4_of_3, incomplete_suffix_start(b"\xf0\x9f\x92"), 0)
+    test_eq!(split_stray_continuation, incomplete_suffix_start(b"a\x80"), 2)
+
+    #[test]
+    fn chunker_every_split() {
+        let input = "x\u00e9\u2603\U0001f4a9y";
+        let bytes = input.as_bytes();
+        for i in range(0, bytes.len() + 1) {
+            let mut chunker = Utf8Chunker::new();
+            let mut out = chunker.push(bytes.slice_to(i));
+            out.push_str(chunker.push(bytes.slice_from(i)).as_slice());
+            out.push_str(chunker.finish().as_slice());
+            assert_eq!(out.as_slice(), input);
+        }
+    }
+
+    #[test]
+    fn chunker_truncated_at_end() {
+        let mut chunker = Utf8Chunker::new();
+        assert_eq!(chunker.push(b"a\xe2\x98").as_slice(), "a");
+        assert_eq!(chunker.finish().as_slice(), "\ufffd");
+    }
+
+    #[test]
+    fn chunker_stats_across_splits() {
+        let mut chunker = Utf8Chunker::new();
+        let mut out = chunker.push(b"a\xed\xa0");
+        out.push_str(chunker.push(b"\xbdb\xe2").as_slice());
+        out.push_str(chunker.finish().as_slice());
+        assert_eq!(out.as_slice(), "a\ufffdb\ufffd");
+        assert_eq!(chunker.stats, DecodeStats { surrogates: 1, invalid: 1 });
+    }
+
+    #[test]
+    fn decode_surrogates() {
+        let mut stats = DecodeStats { surrogates: 0, invalid: 0 };
+        // A CESU-8 surrogate pair, then a lone low surrogate.
+        let out = decode_utf8(b"a\xed\xa0\xbd\xed\xb2\xa9b\xed\xb0\x80", &mut stats);
+        assert_eq!(out.as_slice(), "a\ufffd\ufffdb\ufffd");
+        assert_eq!(stats, DecodeStats { surrogates: 3, invalid: 0 });
+    }
+
+    #[test]
+    fn decode_invalid() {
+        let mut stats = DecodeStats { surrogates: 0, invalid: 0 };
+        let out = decode_utf8(b"\x80x\xe2\x98y\xc0\xaf\xe2\x98\x83", &mut stats);
+        assert_eq!(out.as_slice(), "\ufffdx\ufffdy\ufffd\ufffd\u2603");
+        assert_eq!(stats, DecodeStats { surrogates: 0, invalid: 4 });
+    }
+}
@@ -0,0 +1,382 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Tests for the C API, run by `make check-capi`.  Each callback appends
+// a line to a log, which we compare against what we expect.  Runs of
+// characters are joined, since the tokenizer may split text anywhere.
+
+#include <stddef.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "html5ever.h"
+
+#define LOG_SIZE 16384
+
+struct log {
+    char text[LOG_SIZE];
+    size_t len;
+    int in_chars;
+};
+
+static int failures = 0;
+
+static void log_str(struct log *log, const char *str) {
+    size_t n = strlen(str);
+    if (log->len + n >= LOG_SIZE) {
+        fputs("log overflowed\n", stderr);
+        exit(1);
+    }
+    memcpy(log->text + log->len, str, n);
+    log->len += n;
+    log->text[log->len] = '\0';
+}
+
+static void log_buf(struct log *log, struct h5e_buf buf) {
+    char tmp[LOG_SIZE];
+    if (buf.data == NULL) {
+        log_str(log, "(null)");
+        return;
+    }
+    if (buf.len >= LOG_SIZE) {
+        fputs("buffer too big to log\n", stderr);
+        exit(1);
+    }
+    memcpy(tmp, buf.data, buf.len);
+    tmp[buf.len] = '\0';
+    log_str(log, tmp);
+}
+
+// Start a line for a callback other than do_chars.
+static struct log *start(void *user) {
+    struct log *log = user;
+    if (log->in_chars) {
+        log_str(log, "\n");
+        log->in_chars = 0;
+    }
+    return log;
+}
+
+static void do_doctype(void *user, struct h5e_buf name,
+        struct h5e_buf pub, struct h5e_buf sys, int force_quirks) {
+    struct log *log = start(user);
+    log_str(log, "DOCTYPE ");
+    log_buf(log, name);
+    log_str(log, " ");
+    log_buf(log, pub);
+    log_str(log, " ");
+    log_buf(log, sys);
+    log_str(log, force_quirks ? " quirks\n" : "\n");
+}
+
+static void do_start_tag(void *user, struct h5e_buf name,
+        int self_closing, size_t num_attrs) {
+    struct log *log = start(user);
+    char count[32];
+    snprintf(count, sizeof(count), " %lu", (unsigned long) num_attrs);
+    log_str(log, "START ");
+    log_buf(log, name);
+    log_str(log, count);
+    log_str(log, self_closing ? " /\n" : "\n");
+}
+
+static void do_tag_attr(void *user, struct h5e_buf name, struct h5e_buf value) {
+    struct log *log = start(user);
+    log_str(log, "  ATTR ");
+    log_buf(log, name);
+    log_str(log, "=");
+    log_buf(log, value);
+    log_str(log, "\n");
+}
+
+static void do_end_tag(void *user, struct h5e_buf name) {
+    struct log *log = start(user);
+    log_str(log, "END ");
+    log_buf(log, name);
+    log_str(log, "\n");
+}
+
+static void do_comment(void *user, struct h5e_buf text) {
+    struct log *log = start(user);
+    log_str(log, "COMMENT ");
+    log_buf(log, text);
+    log_str(log, "\n");
+}
+
+static void do_chars(void *user, struct h5e_buf text) {
+    struct log *log = user;
+    if (!log->in_chars) {
+        log_str(log, "CHARS ");
+        log->in_chars = 1;
+    }
+    log_buf(log, text);
+}
+
+static void do_null_char(void *user) {
+    log_str(start(user), "NULL\n");
+}
+
+static void do_eof(void *user) {
+    log_str(start(user), "EOF\n");
+}
+
+static void do_error(void *user, struct h5e_buf message) {
+    struct log *log = start(user);
+    log_str(log, "ERROR ");
+    log_buf(log, message);
+    log_str(log, "\n");
+}
+
+static void do_comment_chunk(void *user, struct h5e_buf text) {
+    struct log *log = start(user);
+    log_str(log, "COMMENT CHUNK ");
+    log_buf(log, text);
+    log_str(log, "\n");
+}
+
+static struct h5e_token_ops all_ops = {
+    .size = sizeof(struct h5e_token_ops),
+    .do_doctype = do_doctype,
+    .do_start_tag = do_start_tag,
+    .do_tag_attr = do_tag_attr,
+    .do_end_tag = do_end_tag,
+    .do_comment = do_comment,
+    .do_chars = do_chars,
+    .do_null_char = do_null_char,
+    .do_eof = do_eof,
+    .do_error = do_error,
+    .do_comment_chunk = do_comment_chunk,
+};
+
+static void check(const char *name, int ok) {
+    if (!ok) {
+        fprintf(stderr, "FAILED: %s\n", name);
+        failures++;
+    }
+}
+
+static void check_log(const char *name, struct log *log, const char *expected) {
+    if (strcmp(log->text, expected) != 0) {
+        fprintf(stderr, "FAILED: %s\nexpected:\n%s\ngot:\n%s\n", name, expected, log->text);
+        failures++;
+    }
+}
+
+// Tokenize `input` in chunks of `chunk` bytes, or all at once if 0,
+// logging into `log`.
+static void tokenize(struct h5e_token_ops *ops, struct log *log,
+        const char *input, size_t chunk) {
+    struct h5e_token_sink sink = { .ops = ops, .user = log };
+    struct h5e_tokenizer *tok;
+    size_t len = strlen(input), pos = 0;
+
+    memset(log, 0, sizeof(*log));
+    tok = h5e_tokenizer_new(&sink);
+    check("h5e_tokenizer_new", tok != NULL);
+    if (chunk == 0) {
+        chunk = len;
+    }
+    while (pos < len) {
+        struct h5e_buf buf;
+        buf.data = (unsigned char *) input + pos;
+        buf.len = len - pos < chunk ? len - pos : chunk;
+        check("h5e_tokenizer_feed", h5e_tokenizer_feed(tok, buf) == H5E_OK);
+        pos += buf.len;
+    }
+    check("h5e_tokenizer_end", h5e_tokenizer_end(tok) == H5E_OK);
+    h5e_tokenizer_free(tok);
+    start(log);
+}
+
+static const char *ALL_INPUT =
+    "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01//EN\">"
+    "<p class=\"a b\" id=x>Caf\xc3\xa9 &amp; \xe2\x98\x83</p>"
+    "<!-- note --><br/>x\r\ny";
+
+static const char *ALL_EXPECTED =
+    "DOCTYPE html -//W3C//DTD HTML 4.01//EN (null)\n"
+    "START p 2\n"
+    "  ATTR class=a b\n"
+    "  ATTR id=x\n"
+    "CHARS Caf\xc3\xa9 & \xe2\x98\x83\n"
+    "END p\n"
+    "COMMENT  note \n"
+    "START br 0 /\n"
+    "CHARS x\ny\n"
+    "EOF\n";
+
+static void test_version(void) {
+    struct h5e_version v = h5e_version();
+    check("ABI version", v.abi == H5E_ABI_VERSION);
+    check("tokenizer capability", (h5e_capabilities() & H5E_CAP_TOKENIZER) != 0);
+}
+
+static void test_callbacks(void) {
+    struct log log;
+    tokenize(&all_ops, &log, ALL_INPUT, 0);
+    check_log("callbacks", &log, ALL_EXPECTED);
+
+    tokenize(&all_ops, &log, "<a x=1 x=2>\x01<!doctype>", 0);
+    check_log("errors", &log,
+        "ERROR Duplicate attribute\n"
+        "START a 1\n"
+        "  ATTR x=1\n"
+        "CHARS \x01\n"
+        "ERROR Missing whitespace before doctype name\n"
+        "ERROR Missing doctype name\n"
+        "DOCTYPE (null) (null) (null) quirks\n"
+        "EOF\n");
+}
+
+static void test_null_char(void) {
+    // h5e_buf_from_cstr stops at NUL, so build the buffer by hand.
+    static unsigned char input[] = { 'a', 0, 'b' };
+    struct log log;
+    struct h5e_token_sink sink = { .ops = &all_ops, .user = &log };
+    struct h5e_buf buf = { .data = input, .len = sizeof(input) };
+    struct h5e_tokenizer *tok;
+
+    memset(&log, 0, sizeof(log));
+    tok = h5e_tokenizer_new(&sink);
+    check("feed with NUL", h5e_tokenizer_feed(tok, buf) == H5E_OK);
+    check("end with NUL", h5e_tokenizer_end(tok) == H5E_OK);
+    h5e_tokenizer_free(tok);
+    start(&log);
+    check_log("null char", &log,
+        "CHARS a\n"
+        "ERROR Bad character\n"
+        "NULL\n"
+        "CHARS b\n"
+        "EOF\n");
+}
+
+static void test_chunks(void) {
+    // Every chunk size splits tags, character references, the CRLF and
+    // the multibyte characters somewhere.
+    struct log log;
+    size_t chunk;
+    for (chunk = 1; chunk <= 16; chunk++) {
+        char name[32];
+        snprintf(name, sizeof(name), "chunks of %lu", (unsigned long) chunk);
+        tokenize(&all_ops, &log, ALL_INPUT, chunk);
+        check_log(name, &log, ALL_EXPECTED);
+    }
+}
+
+static void test_invalid_utf8(void) {
+    struct log log;
+    struct h5e_token_sink sink = { .ops = &all_ops, .user = &log };
+    struct h5e_tokenizer *tok;
+
+    memset(&log, 0, sizeof(log));
+    tok = h5e_tokenizer_new(&sink);
+    h5e_tokenizer_feed(tok, h5e_buf_from_cstr("a\xff" "b\xed\xa0\x80" "c\xe2\x98"));
+    check("surrogate count", h5e_tokenizer_surrogate_count(tok) == 1);
+    check("invalid count", h5e_tokenizer_invalid_count(tok) == 1);
+    h5e_tokenizer_end(tok);
+    check("invalid count at end", h5e_tokenizer_invalid_count(tok) == 2);
+    h5e_tokenizer_free(tok);
+    start(&log);
+    check_log("invalid UTF-8", &log,
+        "ERROR Surrogate code point in input\n"
+        "ERROR Invalid UTF-8 in input\n"
+        "CHARS a\xef\xbf\xbd" "b\xef\xbf\xbd" "c\n"
+        "ERROR Invalid UTF-8 in input\n"
+        "CHARS \xef\xbf\xbd\n"
+        "EOF\n");
+}
+
+static void test_old_ops(void) {
+    // A caller built against a header which ended at do_end_tag.  The
+    // callbacks past its size must never be called, even if the memory
+    // there holds a function pointer.
+    struct h5e_token_ops ops = all_ops;
+    struct log log;
+    ops.size = offsetof(struct h5e_token_ops, do_comment);
+    tokenize(&ops, &log, "<b>x</b><!-- c -->\x01", 0);
+    check_log("old ops", &log,
+        "START b 0\n"
+        "END b\n");
+}
+
+static void test_zero_size(void) {
+    // Without a size, no callback could ever be called.
+    struct h5e_token_ops ops = all_ops;
+    struct log log;
+    struct h5e_token_sink sink = { .ops = &ops, .user = &log };
+    ops.size = 0;
+    check("zero size rejected", h5e_tokenizer_new(&sink) == NULL);
+}
+
+static void test_no_callbacks(void) {
+    // Null callbacks are skipped.
+    struct h5e_token_ops ops = { .size = sizeof(struct h5e_token_ops) };
+    struct log log;
+    tokenize(&ops, &log, ALL_INPUT, 3);
+    check_log("no callbacks", &log, "");
+}
+
+static void test_poisoned(void) {
+    // Misuse fails without unwinding into C, and poisons the tokenizer.
+    struct log log;
+    struct h5e_token_sink sink = { .ops = &all_ops, .user = &log };
+    struct h5e_tokenizer *tok;
+
+    memset(&log, 0, sizeof(log));
+    tok = h5e_tokenizer_new(&sink);
+    check("feed before end", h5e_tokenizer_feed(tok, h5e_buf_from_cstr("a")) == H5E_OK);
+    check("end", h5e_tokenizer_end(tok) == H5E_OK);
+    check("feed after end", h5e_tokenizer_feed(tok, h5e_buf_from_cstr("b")) == H5E_FAILED);
+    check("feed when poisoned", h5e_tokenizer_feed(tok, h5e_buf_from_cstr("c")) == H5E_POISONED);
+    check("end when poisoned", h5e_tokenizer_end(tok) == H5E_POISONED);
+    h5e_tokenizer_free(tok);
+    start(&log);
+    check_log("poisoned", &log,
+        "CHARS a\n"
+        "EOF\n");
+}
+
+static void test_teardown(void) {
+    // A tokenizer can be freed without being ended, in the middle of a
+    // tag and a multibyte character, and before it's fed at all.
+    struct log log;
+    struct h5e_token_sink sink = { .ops = &all_ops, .user = &log };
+    struct h5e_tokenizer *tok;
+
+    memset(&log, 0, sizeof(log));
+    tok = h5e_tokenizer_new(&sink);
+    h5e_tokenizer_feed(tok, h5e_buf_from_cstr("x<p class=\xe2\x98"));
+    h5e_tokenizer_free(tok);
+
+    tok = h5e_tokenizer_new(&sink);
+    h5e_tokenizer_free(tok);
+    start(&log);
+    check_log("teardown", &log, "CHARS x\n");
+}
+
+int main(void) {
+    test_version();
+    test_callbacks();
+    test_null_char();
+    test_chunks();
+    test_invalid_utf8();
+    test_old_ops();
+    test_zero_size();
+    test_no_callbacks();
+    test_poisoned();
+    test_teardown();
+
+    if (failures > 0) {
+        fprintf(stderr, "%d C API test(s) failed\n", failures);
+        return 1;
+    }
+    puts("C API tests passed");
+    return 0;
+}
@@ -15,6 +15,7 @@
 extern crate test;
 extern crate serialize;
 extern crate debug;
+extern crate time;
 extern crate string_cache;
 #[phase(plugin)] extern crate string_cache_macros;
 
@@ -26,6 +27,7 @@ use test::test_main;
 
 mod tokenizer;
 mod tree_builder;
+mod perf;
 mod util;
 
 fn main() {
@@ -40,7 +42,12 @@ fn main() {
     }
 
     if os::getenv("HTML5EVER_NO_TB_TEST").is_none() {
-        tests.extend(tree_builder::tests(src_dir));
+        tests.extend(tree_builder::tests(src_dir.clone()));
+    }
+
+    // Opt-in, since timings depend on the machine and build.
+    if os::getenv("HTML5EVER_PERF_TEST").is_some() {
+        tests.extend(perf::tests(src_dir));
     }
 
     let args: Vec<String> = os::args().into_iter().collect();
@@ -0,0 +1,125 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Performance regression tests over the benchmark corpus in
+//! `data/bench`.  These only run with `HTML5EVER_PERF_TEST` set
+//! (`make check-perf`), and are only meaningful in an optimized build.
+//!
+//! There is no way to count heap allocations here, so we count tokens
+//! instead: every token owns at least one allocation.  The tokenizer
+//! should produce at most a tag token and a character token for each `<`,
+//! and a character token either side of each `&`.  A change which splits
+//! text into more tokens than that will fail.
+
+use std::{io, os};
+use std::default::Default;
+use std::path::Path;
+use std::vec::MoveItems;
+use test::{TestDesc, TestDescAndFn, DynTestName, DynTestFn};
+use time::precise_time_ns;
+
+use html5ever::tokenizer::{TokenSink, Token};
+use html5ever::sink::rcdom::RcDom;
+use html5ever::driver::{tokenize_to, one_input};
+use html5ever::parse;
+
+static FILES: &'static [&'static str] = &["lipsum.html", "lipsum-zh.html", "strong.html",
+    "tiny-fragment.html", "small-fragment.html", "medium-fragment.html"];
+
+/// Parse each file this many times when measuring throughput.
+static ITERATIONS: uint = 200;
+
+/// Default minimum throughput, in megabytes per second.  Override with
+/// `HTML5EVER_PERF_MIN_MBPS` on slow machines.
+static MIN_MBPS: f64 = 5.0;
+
+struct TokenCounter {
+    tokens: uint,
+}
+
+impl TokenSink for TokenCounter {
+    fn process_token(&mut self, _token: Token) {
+        self.tokens += 1;
+    }
+}
+
+fn min_mbps() -> f64 {
+    match os::getenv("HTML5EVER_PERF_MIN_MBPS") {
+        Some(s) => from_str(s.as_slice()).expect("bad HTML5EVER_PERF_MIN_MBPS"),
+        None => MIN_MBPS,
+    }
+}
+
+// Run `f` ITERATIONS times and return megabytes per second.
+fn throughput(input: &str, f: |String|) -> f64 {
+    let start = precise_time_ns();
+    for _ in range(0, ITERATIONS) {
+        f(String::from_str(input));
+    }
+    let secs = (precise_time_ns() - start) as f64 / 1e9;
+    (input.len() * ITERATIONS) as f64 / secs / 1e6
+}
+
+fn check_tokens(name: &str, input: &str) {
+    let markup = input.chars().filter(|&c| c == '<' || c == '&').count();
+    // Plus a final character token and EOF.
+    let max = 2 * markup + 2;
+
+    let mut counter = TokenCounter { tokens: 0 };
+    tokenize_to(&mut counter, one_input(String::from_str(input)), Default::default());
+    if counter.tokens > max {
+        fail!("{:s}: {:u} tokens, expected at most {:u}", name, counter.tokens, max);
+    }
+}
+
+fn check_throughput(name: &str, input: &str) {
+    let min = min_mbps();
+
+    let tok = throughput(input, |s| {
+        let mut counter = TokenCounter { tokens: 0 };
+        tokenize_to(&mut counter, one_input(s), Default::default());
+    });
+    if tok < min {
+        fail!("{:s}: tokenized at {:.2f} MB/s, expected at least {:.2f}", name, tok, min);
+    }
+
+    let tb = throughput(input, |s| {
+        let _: RcDom = parse(one_input(s), Default::default());
+    });
+    if tb < min {
+        fail!("{:s}: parsed at {:.2f} MB/s, expected at least {:.2f}", name, tb, min);
+    }
+}
+
+fn make_test(tests: &mut Vec<TestDescAndFn>, src_dir: &Path, name: &'static str) {
+    let mut path = src_dir.clone();
+    path.push("data/bench");
+    path.push(name);
+    let input = io::File::open(&path).read_to_string().ok().expect("can't read file");
+
+    tests.push(TestDescAndFn {
+        desc: TestDesc {
+            name: DynTestName(format!("perf: {:s}", name)),
+            ignore: false,
+            should_fail: false,
+        },
+        testfn: DynTestFn(proc() {
+            check_tokens(name, input.as_slice());
+            check_throughput(name, input.as_slice());
+        }),
+    });
+}
+
+pub fn tests(src_dir: Path) -> MoveItems<TestDescAndFn> {
+    let mut tests = vec!();
+    for &name in FILES.iter() {
+        make_test(&mut tests, &src_dir, name);
+    }
+    tests.into_iter()
+}
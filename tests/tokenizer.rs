@@ -102,6 +102,9 @@ impl TokenSink for TokenLogger {
             },
 
             TagToken(mut t) => {
+                // The test cases don't record duplicate attributes.
+                t.had_duplicate_attrs = false;
+
                 // The spec seems to indicate that one can emit
                 // erroneous end tags with attrs, but the test
                 // cases don't contain them.
@@ -196,6 +199,7 @@ fn json_to_token(js: &Json) -> Token {
             public_id: public_id.get_nullable_str(),
             system_id: system_id.get_nullable_str(),
             force_quirks: !correct.get_bool(),
+            raw: None,
         }),
 
         ("StartTag", [name, attrs, rest..]) => TagToken(Tag {
@@ -210,14 +214,20 @@ fn json_to_token(js: &Json) -> Token {
             self_closing: match rest {
                 [ref b, ..] => b.get_bool(),
                 _ => false,
-            }
+            },
+            raw_attrs: None,
+            attr_spans: vec!(),
+            had_duplicate_attrs: false,
         }),
 
         ("EndTag", [name]) => TagToken(Tag {
             kind: EndTag,
             name: Atom::from_slice(name.get_str().as_slice()),
             attrs: vec!(),
-            self_closing: false
+            self_closing: false,
+            raw_attrs: None,
+            attr_spans: vec!(),
+            had_duplicate_attrs: false,
         }),
 
         ("Comment", [txt]) => CommentToken(txt.get_str()),
@@ -366,6 +376,8 @@ fn mk_tests(tests: &mut Vec<TestDescAndFn>, path_str: &str, js: &Json) {
             let expect_toks = json_to_tokens(&expect, exact_errors);
             tests.push(mk_test(newdesc, insplits.clone(), expect_toks, TokenizerOpts {
                 exact_errors: exact_errors,
+                // The fast path is only taken without exact errors.
+                check_fast_path: !exact_errors,
                 initial_state: state,
                 last_start_tag_name: start_tag.clone(),
 